@@ -1,5 +1,7 @@
+use crate::shared::get_shared_state;
 use crate::types::Config;
 use crate::utils::load_config_from_yaml;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
 use std::sync::OnceLock;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -40,13 +42,27 @@ pub fn load_config_sled(key: &str) -> Result<Option<Arc<Config>>, String> {
         Ok(Some(bytes)) => match serde_json::from_slice::<Config>(&bytes) {
             Ok(conf) => Ok(Some(Arc::new(conf))),
             Err(e) => {
-                error!("❌ Sled 解析設定失敗: {}", e);
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ Sled 解析設定失敗: {}",
+                        "❌ Failed to parse config from sled: {}",
+                        e
+                    )
+                );
                 Err(format!("JSON 解析失敗: {}", e))
             }
         },
         Ok(None) => Ok(None),
         Err(e) => {
-            error!("❌ 讀取 Sled 設定失敗: {}", e);
+            error!(
+                "{}",
+                tr!(
+                    "❌ 讀取 Sled 設定失敗: {}",
+                    "❌ Failed to read config from sled: {}",
+                    e
+                )
+            );
             Err(format!("載入失敗: {}", e))
         }
     }
@@ -56,7 +72,14 @@ pub fn load_config_sled(key: &str) -> Result<Option<Arc<Config>>, String> {
 pub fn remove_config_sled(key: &str) {
     let db = get_sled_db();
     if let Err(e) = db.remove(key.as_bytes()) {
-        warn!("⚠️ 從 sled 移除緩存時發生錯誤: {}", e);
+        warn!(
+            "{}",
+            tr!(
+                "⚠️ 從 sled 移除緩存時發生錯誤: {}",
+                "⚠️ Failed to remove cache entry from sled: {}",
+                e
+            )
+        );
     }
     db.flush().ok();
 }
@@ -78,13 +101,31 @@ pub async fn get_cached_config() -> Arc<Config> {
                     Arc::new(conf)
                 }
                 Err(e) => {
-                    warn!("⚠️ 無法從 YAML 載入設定，回退預設: {}", e);
+                    warn!(
+                        "{}",
+                        tr!(
+                            "⚠️ 無法從 YAML 載入設定，回退預設: {}",
+                            "⚠️ Failed to load config from YAML, using defaults: {}",
+                            e
+                        )
+                    );
                     Arc::new(Config {
                         enable: Some(false),
                         models: std::collections::HashMap::new(),
                         custom_models: None,
                         api_token: None,
                         use_v1_api: None,
+                        stream_compat: None,
+                        key_stream_compat: None,
+                        key_priority: None,
+                        history_policy: None,
+                        history_summary_model: None,
+                        mcp_servers: None,
+                        scopes: None,
+                        coalesce: None,
+                        key_coalesce: None,
+                        param_policy: None,
+                        strip_footnotes: None,
                     })
                 }
             }
@@ -133,12 +174,23 @@ pub fn cache_url(original_url: &str, poe_url: &str, size_bytes: usize) {
                 debug!("✅ URL緩存已更新: {}", original_url);
             }
             Err(e) => {
-                error!("❌ 保存URL緩存失敗: {}", e);
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ 保存URL緩存失敗: {}",
+                        "❌ Failed to save URL cache: {}",
+                        e
+                    )
+                );
             }
         }
     } else {
-        error!("❌ 無法開啟URL緩存樹");
+        error!(
+            "{}",
+            tr!("❌ 無法開啟URL緩存樹", "❌ Failed to open URL cache tree")
+        );
     }
+    share_upload("url", original_url, poe_url, size_bytes);
     // 維護緩存大小
     check_and_control_cache_size();
 }
@@ -151,7 +203,14 @@ pub fn get_cached_url(original_url: &str) -> Option<(String, usize)> {
     let result = match db.open_tree(tree_name) {
         Ok(tree) => tree.get(key.as_bytes()),
         Err(e) => {
-            error!("❌ 無法開啟URL緩存樹: {}", e);
+            error!(
+                "{}",
+                tr!(
+                    "❌ 無法開啟URL緩存樹: {}",
+                    "❌ Failed to open URL cache tree: {}",
+                    e
+                )
+            );
             return None;
         }
     };
@@ -188,18 +247,42 @@ pub fn get_cached_url(original_url: &str) -> Option<(String, usize)> {
                     }
                 }
             } else {
-                error!("❌ 無效的URL緩存值格式");
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ 無效的URL緩存值格式",
+                        "❌ Invalid URL cache value format"
+                    )
+                );
             }
             None
         }
         Ok(None) => None,
         Err(e) => {
-            error!("❌ 讀取URL緩存失敗: {}", e);
+            error!(
+                "{}",
+                tr!(
+                    "❌ 讀取URL緩存失敗: {}",
+                    "❌ Failed to read URL cache: {}",
+                    e
+                )
+            );
             None
         }
     }
 }
 
+// 獲取緩存的URL，本機未命中時查詢共享狀態
+pub async fn lookup_cached_url(original_url: &str) -> Option<(String, usize)> {
+    if let Some(hit) = get_cached_url(original_url) {
+        return Some(hit);
+    }
+    let (poe_url, size) = lookup_shared_upload("url", original_url).await?;
+    debug!("🔗 共享緩存命中 URL: {}", original_url);
+    cache_url(original_url, &poe_url, size);
+    Some((poe_url, size))
+}
+
 // 刷新URL緩存的TTL
 fn refresh_url_cache_ttl(original_url: &str, poe_url: &str, size_bytes: usize) {
     cache_url(original_url, poe_url, size_bytes);
@@ -233,13 +316,73 @@ pub fn cache_base64(hash: &str, poe_url: &str, size_bytes: usize) {
                 debug!("✅ base64緩存已更新 | 哈希: {}...", hash_prefix);
             }
             Err(e) => {
-                error!("❌ 保存base64緩存失敗: {} | 哈希: {}...", e, hash_prefix);
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ 保存base64緩存失敗: {} | 哈希: {}...",
+                        "❌ Failed to save base64 cache: {} | hash: {}...",
+                        e,
+                        hash_prefix
+                    )
+                );
             }
         },
         Err(e) => {
-            error!("❌ 無法開啟base64緩存樹: {} | 哈希: {}...", e, hash_prefix);
+            error!(
+                "{}",
+                tr!(
+                    "❌ 無法開啟base64緩存樹: {} | 哈希: {}...",
+                    "❌ Failed to open base64 cache tree: {} | hash: {}...",
+                    e,
+                    hash_prefix
+                )
+            );
         }
     }
+    share_upload("base64", hash, poe_url, size_bytes);
+}
+
+// 從緩存獲取base64哈希對應的URL，本機未命中時查詢共享狀態
+pub async fn lookup_cached_base64(hash: &str) -> Option<(String, usize)> {
+    if let Some(hit) = get_cached_base64(hash) {
+        return Some(hit);
+    }
+    let (poe_url, size) = lookup_shared_upload("base64", hash).await?;
+    debug!(
+        "🔗 共享緩存命中 base64 | 哈希: {}...",
+        hash.get(..8).unwrap_or(hash)
+    );
+    cache_base64(hash, &poe_url, size);
+    Some((poe_url, size))
+}
+
+// 共享狀態中的上傳緩存鍵，URL 以雜湊縮短
+fn shared_upload_key(kind: &str, id: &str) -> String {
+    format!("upload:{}:{:x}", kind, Sha256::digest(id.as_bytes()))
+}
+
+// 於背景將上傳結果寫入共享狀態，有效期與本機緩存相同
+fn share_upload(kind: &str, id: &str, poe_url: &str, size_bytes: usize) {
+    let (Some(shared), Ok(runtime)) = (get_shared_state(), tokio::runtime::Handle::try_current())
+    else {
+        return;
+    };
+    let key = shared_upload_key(kind, id);
+    let value = serde_json::json!({ "poe_url": poe_url, "size": size_bytes }).to_string();
+    let ttl = get_url_cache_ttl();
+    runtime.spawn(async move {
+        let _ = shared.set_ex(&key, &value, ttl).await;
+    });
+}
+
+async fn lookup_shared_upload(kind: &str, id: &str) -> Option<(String, usize)> {
+    let shared = get_shared_state()?;
+    let value = shared.get(&shared_upload_key(kind, id)).await.ok()??;
+    let value: serde_json::Value = serde_json::from_str(&value).ok()?;
+    Some((
+        value["poe_url"].as_str()?.to_string(),
+        value["size"].as_u64()? as usize,
+    ))
 }
 
 // 從緩存獲取base64哈希對應的URL
@@ -252,7 +395,14 @@ pub fn get_cached_base64(hash: &str) -> Option<(String, usize)> {
     let result = match db.open_tree(tree_name) {
         Ok(tree) => tree.get(key.as_bytes()),
         Err(e) => {
-            error!("❌ 無法開啟base64緩存樹: {}", e);
+            error!(
+                "{}",
+                tr!(
+                    "❌ 無法開啟base64緩存樹: {}",
+                    "❌ Failed to open base64 cache tree: {}",
+                    e
+                )
+            );
             return None;
         }
     };
@@ -277,7 +427,14 @@ pub fn get_cached_base64(hash: &str) -> Option<(String, usize)> {
                                 debug!("✅ base64緩存命中並續期 | 哈希: {}...", hash_prefix);
                                 return Some((poe_url, size));
                             } else {
-                                error!("❌ base64緩存大小無效: {}", size_str);
+                                error!(
+                                    "{}",
+                                    tr!(
+                                        "❌ base64緩存大小無效: {}",
+                                        "❌ Invalid base64 cache size: {}",
+                                        size_str
+                                    )
+                                );
                             }
                         } else {
                             // 已過期，刪除項目
@@ -287,23 +444,48 @@ pub fn get_cached_base64(hash: &str) -> Option<(String, usize)> {
                             }
                         }
                     } else {
-                        error!("❌ base64緩存時間戳無效: {}", parts[0]);
+                        error!(
+                            "{}",
+                            tr!(
+                                "❌ base64緩存時間戳無效: {}",
+                                "❌ Invalid base64 cache timestamp: {}",
+                                parts[0]
+                            )
+                        );
                     }
                 } else {
                     error!(
-                        "❌ base64緩存格式錯誤: {} (部分數: {})",
-                        value_str,
-                        parts.len()
+                        "{}",
+                        tr!(
+                            "❌ base64緩存格式錯誤: {} (部分數: {})",
+                            "❌ Malformed base64 cache entry: {} (parts: {})",
+                            value_str,
+                            parts.len()
+                        )
                     );
                 }
             } else {
-                error!("❌ base64緩存值無法解析為字符串");
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ base64緩存值無法解析為字符串",
+                        "❌ base64 cache value is not a valid string"
+                    )
+                );
             }
             None
         }
         Ok(None) => None,
         Err(e) => {
-            error!("❌ 讀取base64緩存失敗: {} | 哈希: {}...", e, hash_prefix);
+            error!(
+                "{}",
+                tr!(
+                    "❌ 讀取base64緩存失敗: {} | 哈希: {}...",
+                    "❌ Failed to read base64 cache: {} | hash: {}...",
+                    e,
+                    hash_prefix
+                )
+            );
             None
         }
     }
@@ -322,6 +504,36 @@ pub fn estimate_base64_size(data_url: &str) -> usize {
     0
 }
 
+/// 統計緩存樹的項目數與記錄的附件大小總和
+fn tree_usage(db: &sled::Db, tree_name: &str) -> (usize, usize) {
+    let Ok(tree) = db.open_tree(tree_name) else {
+        return (0, 0);
+    };
+    tree.iter()
+        .flatten()
+        .fold((0, 0), |(entries, bytes), (_, value)| {
+            let size = std::str::from_utf8(&value)
+                .ok()
+                .and_then(|value| value.rsplit(':').next())
+                .and_then(|size| size.parse::<usize>().ok())
+                .unwrap_or(0);
+            (entries + 1, bytes + size)
+        })
+}
+
+/// 緩存資料庫統計，供 /api/admin/stats 使用
+pub fn cache_stats() -> serde_json::Value {
+    let db = get_sled_db();
+    let (url_entries, url_bytes) = tree_usage(db, "urls");
+    let (base64_entries, base64_bytes) = tree_usage(db, "base64");
+    serde_json::json!({
+        "size_on_disk": db.size_on_disk().unwrap_or(0),
+        "limit_bytes": get_url_cache_size_mb() * 1024 * 1024,
+        "urls": { "entries": url_entries, "bytes": url_bytes },
+        "base64": { "entries": base64_entries, "bytes": base64_bytes },
+    })
+}
+
 // 檢查並控制緩存大小
 fn check_and_control_cache_size() {
     let db = get_sled_db();
@@ -336,13 +548,12 @@ fn check_and_control_cache_size() {
         for (key, value) in tree.iter().flatten() {
             if let Ok(value_str) = String::from_utf8(value.to_vec()) {
                 let parts: Vec<&str> = value_str.split(':').collect();
-                if parts.len() >= 3 {
-                    if let Ok(expires_secs) = parts[0].parse::<u64>() {
-                        if let Ok(size) = parts.last().unwrap().parse::<usize>() {
-                            current_size += size;
-                            entries.push((expires_secs, "urls".to_string(), key.to_vec(), size));
-                        }
-                    }
+                if parts.len() >= 3
+                    && let Ok(expires_secs) = parts[0].parse::<u64>()
+                    && let Ok(size) = parts.last().unwrap().parse::<usize>()
+                {
+                    current_size += size;
+                    entries.push((expires_secs, "urls".to_string(), key.to_vec(), size));
                 }
             }
         }
@@ -353,13 +564,12 @@ fn check_and_control_cache_size() {
         for (key, value) in tree.iter().flatten() {
             if let Ok(value_str) = String::from_utf8(value.to_vec()) {
                 let parts: Vec<&str> = value_str.split(':').collect();
-                if parts.len() >= 3 {
-                    if let Ok(expires_secs) = parts[0].parse::<u64>() {
-                        if let Ok(size) = parts.last().unwrap().parse::<usize>() {
-                            current_size += size;
-                            entries.push((expires_secs, "base64".to_string(), key.to_vec(), size));
-                        }
-                    }
+                if parts.len() >= 3
+                    && let Ok(expires_secs) = parts[0].parse::<u64>()
+                    && let Ok(size) = parts.last().unwrap().parse::<usize>()
+                {
+                    current_size += size;
+                    entries.push((expires_secs, "base64".to_string(), key.to_vec(), size));
                 }
             }
         }
@@ -370,10 +580,14 @@ fn check_and_control_cache_size() {
         let excess_bytes = current_size - max_size_bytes;
         let mut bytes_to_free = excess_bytes + (max_size_bytes / 10); // 多釋放10%空間
         info!(
-            "⚠️ 緩存大小 ({:.2}MB) 超出限制 ({:.2}MB)，需釋放 {:.2}MB",
-            current_size as f64 / 1024.0 / 1024.0,
-            max_size_bytes as f64 / 1024.0 / 1024.0,
-            bytes_to_free as f64 / 1024.0 / 1024.0
+            "{}",
+            tr!(
+                "⚠️ 緩存大小 ({:.2}MB) 超出限制 ({:.2}MB)，需釋放 {:.2}MB",
+                "⚠️ Cache size ({:.2}MB) exceeds limit ({:.2}MB), freeing {:.2}MB",
+                current_size as f64 / 1024.0 / 1024.0,
+                max_size_bytes as f64 / 1024.0 / 1024.0,
+                bytes_to_free as f64 / 1024.0 / 1024.0
+            )
         );
 
         // 按過期時間排序（最早過期的先刪除）
@@ -386,7 +600,14 @@ fn check_and_control_cache_size() {
             }
             if let Ok(tree) = db.open_tree(&tree_name) {
                 if let Err(e) = tree.remove(&key) {
-                    error!("❌ 刪除緩存項失敗: {}", e);
+                    error!(
+                        "{}",
+                        tr!(
+                            "❌ 刪除緩存項失敗: {}",
+                            "❌ Failed to delete cache entry: {}",
+                            e
+                        )
+                    );
                 } else {
                     bytes_to_free = bytes_to_free.saturating_sub(size);
                     deleted += 1;
@@ -395,7 +616,14 @@ fn check_and_control_cache_size() {
         }
 
         if deleted > 0 {
-            info!("🗑️ 已釋放 {} 個緩存項", deleted);
+            info!(
+                "{}",
+                tr!(
+                    "🗑️ 已釋放 {} 個緩存項",
+                    "🗑️ Freed {} cache entries",
+                    deleted
+                )
+            );
         }
     }
 }
@@ -0,0 +1,206 @@
+//! 命令列介面：未指定子命令時啟動服務，另提供檢查設定、列出模型與產生金鑰等維運指令
+
+use crate::cache::get_cached_config;
+use crate::poe_client::PoeClientWrapper;
+use crate::utils::{get_config_path, load_config_from_yaml};
+use clap::{Parser, Subcommand};
+use poe_api_process::ModelInfo;
+
+#[derive(Parser)]
+#[command(name = "poe2openai", version, about = "Poe API to OpenAI API")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// 啟動 API 服務（預設）
+    Serve,
+    /// 檢查 models.yaml、轉換腳本、內容過濾規則與綁定地址，有錯誤時以非零狀態碼結束
+    CheckConfig,
+    /// 列出 Poe 可用的模型
+    ListModels {
+        /// Poe API Token，指定時使用 v1/models API；未指定時依 models.yaml 設定取得
+        #[arg(long)]
+        token: Option<String>,
+        /// 以 JSON 格式輸出
+        #[arg(long)]
+        json: bool,
+    },
+    /// 產生隨機金鑰並輸出至標準輸出，可用於 POE_POOL_ACCESS_KEYS、範圍的 access_keys 或 ADMIN_PASSWORD
+    GenKey {
+        /// 金鑰前綴
+        #[arg(long, default_value = "sk-")]
+        prefix: String,
+        /// 隨機部分的長度
+        #[arg(long, default_value_t = 48, value_parser = clap::value_parser!(u16).range(16..=256))]
+        length: u16,
+        /// 產生的數量，每行一個
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+    },
+}
+
+// 金鑰只使用英數字，方便放入環境變數與 YAML
+const KEY_ALPHABET: [char; 62] = [
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i',
+    'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'A', 'B',
+    'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U',
+    'V', 'W', 'X', 'Y', 'Z',
+];
+
+/// 執行維運子命令，返回程式結束狀態碼
+pub async fn run(command: Command) -> i32 {
+    match command {
+        Command::Serve => unreachable!("serve 由 main 處理"),
+        Command::CheckConfig => check_config(),
+        Command::ListModels { token, json } => list_models(token, json).await,
+        Command::GenKey {
+            prefix,
+            length,
+            count,
+        } => gen_key(&prefix, length as usize, count),
+    }
+}
+
+fn gen_key(prefix: &str, length: usize, count: u32) -> i32 {
+    for _ in 0..count {
+        println!("{}{}", prefix, nanoid::nanoid!(length, &KEY_ALPHABET));
+    }
+    0
+}
+
+fn check_config() -> i32 {
+    let mut failed = false;
+    let mut report = |name: &str, result: Result<String, String>| match result {
+        Ok(detail) => println!("✅ {}: {}", name, detail),
+        Err(e) => {
+            println!("❌ {}: {}", name, e);
+            failed = true;
+        }
+    };
+
+    let models_path = get_config_path("models.yaml");
+    report(
+        "models.yaml",
+        load_config_from_yaml().map(|config| {
+            if models_path.exists() {
+                tr!(
+                    "{} | 啟用: {} | 模型設定: {}",
+                    "{} | enabled: {} | model entries: {}",
+                    models_path.display(),
+                    config.enable.unwrap_or(false),
+                    config.models.len()
+                )
+            } else {
+                tr!(
+                    "{} 不存在，使用預設設定",
+                    "{} not found, using defaults",
+                    models_path.display()
+                )
+            }
+        }),
+    );
+    report(
+        &tr!("轉換腳本", "Transform script"),
+        crate::script::init_script_hooks().map(|_| match crate::script::get_script_hooks() {
+            Some(_) => tr!("已載入", "loaded"),
+            None => tr!("未設定", "not configured"),
+        }),
+    );
+    report(
+        &tr!("內容過濾規則", "Content filter"),
+        crate::filter::init_content_filter().map(|_| match crate::filter::get_content_filter() {
+            Some(_) => tr!("已載入", "loaded"),
+            None => tr!("未設定", "not configured"),
+        }),
+    );
+
+    report(
+        &tr!("串流處理階段", "Stream stages"),
+        crate::pipeline::parse_stage_list(&std::env::var("STREAM_STAGES").unwrap_or_default()).map(
+            |stages| {
+                if stages.is_empty() {
+                    tr!("未設定", "not configured")
+                } else {
+                    crate::pipeline::stage_names(&stages)
+                }
+            },
+        ),
+    );
+
+    let bind_addresses = std::env::var("BIND_ADDRESSES").unwrap_or_else(|_| {
+        format!(
+            "{}:{}",
+            std::env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
+            std::env::var("PORT").unwrap_or_else(|_| "8080".to_string())
+        )
+    });
+    let addrs = crate::parse_bind_addresses(&bind_addresses);
+    report(
+        &tr!("綁定地址", "Bind addresses"),
+        if addrs.is_empty() {
+            Err(tr!(
+                "沒有可用的綁定地址: {}",
+                "No usable bind address: {}",
+                bind_addresses
+            ))
+        } else {
+            Ok(addrs
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+                .join(", "))
+        },
+    );
+
+    if failed { 1 } else { 0 }
+}
+
+async fn list_models(token: Option<String>, json: bool) -> i32 {
+    let result: Result<Vec<ModelInfo>, String> = match token {
+        Some(token) => PoeClientWrapper::new("dummy", &token)
+            .get_v1_model_list()
+            .await
+            .map(|response| response.data)
+            .map_err(|e| {
+                tr!(
+                    "v1/models API 請求失敗: {}",
+                    "v1/models API request failed: {}",
+                    e
+                )
+            }),
+        None => {
+            let config = get_cached_config().await;
+            crate::handlers::get_models_from_api(&config).await
+        }
+    };
+    match result {
+        Ok(mut models) => {
+            models.sort_by(|a, b| a.id.cmp(&b.id));
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&models).unwrap_or_default()
+                );
+            } else {
+                for model in &models {
+                    println!("{}", model.id);
+                }
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "❌ 取得模型列表失敗: {}",
+                    "❌ Failed to fetch model list: {}",
+                    e
+                )
+            );
+            1
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! 上游連接的 DNS 緩存 (DNS_CACHE_TTL)
+//!
+//! 系統解析器不提供記錄的 TTL，緩存時間由 DNS_CACHE_TTL 指定。過期後的記錄在 DNS_CACHE_STALE
+//! 秒內仍可使用並於背景重新解析，解析失敗時也會沿用舊記錄，避免不穩定的 DNS 拖慢請求。
+//! 解析結果保留 IPv4 與 IPv6 位址，由連接器以 happy eyeballs 方式輪流嘗試
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+struct DnsCacheConfig {
+    ttl: Duration,
+    stale: Duration,
+}
+
+static DNS_CACHE_CONFIG: LazyLock<Option<DnsCacheConfig>> = LazyLock::new(|| {
+    let ttl = std::env::var("DNS_CACHE_TTL")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    if ttl == 0 {
+        return None;
+    }
+    let stale = std::env::var("DNS_CACHE_STALE")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(600);
+    info!(
+        "{}",
+        tr!(
+            "🌐 DNS 緩存: 有效 {} 秒，過期後 {} 秒內沿用舊記錄",
+            "🌐 DNS cache: fresh for {}s, stale records served for {}s",
+            ttl,
+            stale
+        )
+    );
+    Some(DnsCacheConfig {
+        ttl: Duration::from_secs(ttl),
+        stale: Duration::from_secs(stale),
+    })
+});
+
+struct CacheEntry {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+    refreshing: bool,
+}
+
+static DNS_CACHE: LazyLock<RwLock<HashMap<String, CacheEntry>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+// 解析統計
+static LOOKUPS: AtomicU64 = AtomicU64::new(0);
+static LOOKUP_FAILURES: AtomicU64 = AtomicU64::new(0);
+static LOOKUP_MICROS_TOTAL: AtomicU64 = AtomicU64::new(0);
+static LOOKUP_MICROS_MAX: AtomicU64 = AtomicU64::new(0);
+static LOOKUP_MICROS_LAST: AtomicU64 = AtomicU64::new(0);
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static STALE_HITS: AtomicU64 = AtomicU64::new(0);
+
+/// 是否啟用 DNS 緩存
+pub fn dns_cache_enabled() -> bool {
+    DNS_CACHE_CONFIG.is_some()
+}
+
+/// 以系統解析器查詢並記錄耗時
+async fn lookup(host: &str) -> std::io::Result<Vec<SocketAddr>> {
+    let start = Instant::now();
+    let result = tokio::net::lookup_host((host, 0))
+        .await
+        .map(|addrs| addrs.collect::<Vec<_>>());
+    let micros = start.elapsed().as_micros() as u64;
+    LOOKUPS.fetch_add(1, Ordering::Relaxed);
+    LOOKUP_MICROS_TOTAL.fetch_add(micros, Ordering::Relaxed);
+    LOOKUP_MICROS_MAX.fetch_max(micros, Ordering::Relaxed);
+    LOOKUP_MICROS_LAST.store(micros, Ordering::Relaxed);
+    match &result {
+        Ok(addrs) => debug!(
+            "🌐 DNS 解析 | 主機: {} | 位址數: {} | 耗時: {}μs",
+            host,
+            addrs.len(),
+            micros
+        ),
+        Err(e) => {
+            LOOKUP_FAILURES.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ DNS 解析失敗 | 主機: {} | 錯誤: {}",
+                    "⚠️ DNS lookup failed | host: {} | error: {}",
+                    host,
+                    e
+                )
+            );
+        }
+    }
+    result
+}
+
+fn store(host: &str, addrs: Vec<SocketAddr>) {
+    if let Ok(mut cache) = DNS_CACHE.write() {
+        cache.insert(
+            host.to_string(),
+            CacheEntry {
+                addrs,
+                resolved_at: Instant::now(),
+                refreshing: false,
+            },
+        );
+    }
+}
+
+/// 背景重新解析過期的記錄
+fn spawn_refresh(host: String) {
+    tokio::spawn(async move {
+        match lookup(&host).await {
+            Ok(addrs) if !addrs.is_empty() => store(&host, addrs),
+            _ => {
+                if let Ok(mut cache) = DNS_CACHE.write()
+                    && let Some(entry) = cache.get_mut(&host)
+                {
+                    entry.refreshing = false;
+                }
+            }
+        }
+    });
+}
+
+/// 查詢主機位址，優先使用緩存
+async fn resolve_cached(host: String, config: &DnsCacheConfig) -> std::io::Result<Vec<SocketAddr>> {
+    let mut stale_addrs = None;
+    if let Ok(mut cache) = DNS_CACHE.write()
+        && let Some(entry) = cache.get_mut(&host)
+    {
+        let age = entry.resolved_at.elapsed();
+        if age < config.ttl {
+            CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+            return Ok(entry.addrs.clone());
+        }
+        if age < config.ttl + config.stale {
+            STALE_HITS.fetch_add(1, Ordering::Relaxed);
+            if !entry.refreshing {
+                entry.refreshing = true;
+                spawn_refresh(host.clone());
+            }
+            return Ok(entry.addrs.clone());
+        }
+        stale_addrs = Some(entry.addrs.clone());
+    }
+
+    match lookup(&host).await {
+        Ok(addrs) if !addrs.is_empty() => {
+            store(&host, addrs.clone());
+            Ok(addrs)
+        }
+        // 解析失敗時沿用過期已久的舊記錄
+        result => match stale_addrs {
+            Some(addrs) => {
+                STALE_HITS.fetch_add(1, Ordering::Relaxed);
+                Ok(addrs)
+            }
+            None => result,
+        },
+    }
+}
+
+/// 帶緩存的 DNS 解析器，供 reqwest 客戶端使用
+pub struct CachingResolver;
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let Some(config) = DNS_CACHE_CONFIG.as_ref() else {
+                return Ok(Box::new(lookup(&host).await?.into_iter()) as Addrs);
+            };
+            let addrs = resolve_cached(host, config).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// DNS 緩存與解析耗時統計，未啟用時返回 None
+pub fn dns_stats() -> Option<serde_json::Value> {
+    DNS_CACHE_CONFIG.as_ref()?;
+    let lookups = LOOKUPS.load(Ordering::Relaxed);
+    let total = LOOKUP_MICROS_TOTAL.load(Ordering::Relaxed);
+    Some(json!({
+        "entries": DNS_CACHE.read().map(|cache| cache.len()).unwrap_or(0),
+        "cache_hits": CACHE_HITS.load(Ordering::Relaxed),
+        "stale_hits": STALE_HITS.load(Ordering::Relaxed),
+        "lookups": lookups,
+        "lookup_failures": LOOKUP_FAILURES.load(Ordering::Relaxed),
+        "lookup_ms": {
+            "avg": if lookups == 0 { 0.0 } else { total as f64 / lookups as f64 / 1000.0 },
+            "max": LOOKUP_MICROS_MAX.load(Ordering::Relaxed) as f64 / 1000.0,
+            "last": LOOKUP_MICROS_LAST.load(Ordering::Relaxed) as f64 / 1000.0,
+        },
+    }))
+}
@@ -1,324 +1,489 @@
+use crate::media::{
+    ImageOutputMode, attachment_markdown, get_image_output_mode, is_audio, is_image,
+};
+use crate::tool_schema::{StrictToolSchemas, check_tool_call};
 use crate::types::*;
 use crate::utils::{convert_poe_error_to_openai, format_bytes_length};
 use poe_api_process::{ChatEventType, ChatResponse, ChatResponseData};
 use salvo::prelude::*;
 use std::collections::HashMap;
-use tracing::{debug, error};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+/// ReplaceResponse 事件在串流模式下的處理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplaceResponseMode {
+    /// 追蹤已發送的內容，只發送替換後新增的差異部分
+    #[default]
+    Diff,
+    /// 緩衝全部正文，於完成時一次發送最終版本
+    Buffer,
+}
+
+static REPLACE_RESPONSE_MODE: OnceLock<ReplaceResponseMode> = OnceLock::new();
+
+/// 取得 ReplaceResponse 處理策略 (REPLACE_RESPONSE_MODE=diff|buffer)
+pub fn get_replace_response_mode() -> ReplaceResponseMode {
+    *REPLACE_RESPONSE_MODE.get_or_init(|| {
+        let value = std::env::var("REPLACE_RESPONSE_MODE").unwrap_or_default();
+        let mode = match value.trim().to_lowercase().as_str() {
+            "buffer" => ReplaceResponseMode::Buffer,
+            "" | "diff" => ReplaceResponseMode::Diff,
+            other => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 無效的 REPLACE_RESPONSE_MODE: {}，使用 diff",
+                        "⚠️ Invalid REPLACE_RESPONSE_MODE: {}, using diff",
+                        other
+                    )
+                );
+                ReplaceResponseMode::Diff
+            }
+        };
+        info!(
+            "{}",
+            tr!(
+                "🔄 ReplaceResponse 處理策略: {:?}",
+                "🔄 ReplaceResponse strategy: {:?}",
+                mode
+            )
+        );
+        mode
+    })
+}
+
+/// 是否以 message.metadata.suggested_replies 返回機器人的建議回覆 (SUGGESTED_REPLIES)
+pub fn suggested_replies_enabled() -> bool {
+    static ENABLED: LazyLock<bool> = LazyLock::new(|| {
+        std::env::var("SUGGESTED_REPLIES")
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+            .unwrap_or(false)
+    });
+    *ENABLED
+}
+
+// 曾改寫已發送內容的模型及其改用緩衝模式的期限
+static REWRITING_MODELS: LazyLock<Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 改寫過已發送內容的模型改用緩衝模式的時間 (REWRITE_BUFFER_TTL_SECS)，0 表示不自動切換
+fn rewrite_buffer_ttl() -> Duration {
+    static TTL: LazyLock<Duration> = LazyLock::new(|| {
+        Duration::from_secs(
+            std::env::var("REWRITE_BUFFER_TTL_SECS")
+                .ok()
+                .and_then(|s| s.trim().parse::<u64>().ok())
+                .unwrap_or(3600),
+        )
+    });
+    *TTL
+}
+
+/// 取得模型的 ReplaceResponse 處理策略，近期改寫過已發送內容的模型使用緩衝模式
+pub fn replace_response_mode_for(model: &str) -> ReplaceResponseMode {
+    let rewrites = {
+        let mut models = REWRITING_MODELS.lock().unwrap_or_else(|e| e.into_inner());
+        match models.get(model) {
+            Some(&until) if until > Instant::now() => true,
+            Some(_) => {
+                debug!("🔄 模型 {} 的緩衝模式已到期，恢復預設策略", model);
+                models.remove(model);
+                false
+            }
+            None => false,
+        }
+    };
+    if rewrites {
+        ReplaceResponseMode::Buffer
+    } else {
+        get_replace_response_mode()
+    }
+}
+
+/// 記錄會改寫已發送內容的模型，期限內的串流改用緩衝模式
+pub fn mark_rewriting_model(model: &str) {
+    let ttl = rewrite_buffer_ttl();
+    if ttl.is_zero() {
+        return;
+    }
+    let now = Instant::now();
+    let previous = REWRITING_MODELS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(model.to_string(), now + ttl);
+    if previous.is_none_or(|until| until <= now) {
+        warn!(
+            "{}",
+            tr!(
+                "⚠️ 模型 {} 會改寫已發送的內容，之後 {} 秒內的串流改用緩衝模式",
+                "⚠️ Model {} rewrites already sent content, streams in the next {} seconds use buffer mode",
+                model,
+                ttl.as_secs()
+            )
+        );
+    }
+}
+
+/// 串流正文合併設定
+#[derive(Debug, Clone, Copy)]
+pub struct StreamCoalesceConfig {
+    /// 合併間隔，0 表示逐事件直接轉發
+    pub interval: Duration,
+    /// 累積達到此大小時立即發送，0 表示只按間隔發送
+    pub max_bytes: usize,
+}
+
+static STREAM_COALESCE_CONFIG: OnceLock<StreamCoalesceConfig> = OnceLock::new();
+
+/// 取得串流正文合併設定 (STREAM_COALESCE_MS / STREAM_COALESCE_BYTES)
+pub fn get_stream_coalesce_config() -> StreamCoalesceConfig {
+    *STREAM_COALESCE_CONFIG.get_or_init(|| {
+        let interval_ms = std::env::var("STREAM_COALESCE_MS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(0);
+        let max_bytes = std::env::var("STREAM_COALESCE_BYTES")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(0);
+        if interval_ms == 0 {
+            info!(
+                "{}",
+                tr!(
+                    "📦 串流正文合併: 已禁用 (STREAM_COALESCE_MS=0)",
+                    "📦 Stream text coalescing: disabled (STREAM_COALESCE_MS=0)"
+                )
+            );
+        } else {
+            info!(
+                "{}",
+                tr!(
+                    "📦 串流正文合併: 每 {}ms 或累積 {} 發送一次",
+                    "📦 Stream text coalescing: flush every {}ms or {} buffered",
+                    interval_ms,
+                    if max_bytes == 0 {
+                        "無上限".to_string()
+                    } else {
+                        format_bytes_length(max_bytes)
+                    }
+                )
+            );
+        }
+        StreamCoalesceConfig {
+            interval: Duration::from_millis(interval_ms),
+            max_bytes,
+        }
+    })
+}
 
 // 事件積累上下文，用於收集處理事件期間的狀態
 #[derive(Debug, Clone, Default)]
 pub struct EventContext {
     pub content: String,
-    pub replace_buffer: Option<String>,
+    // 已發送給客戶端的正文，用於計算 ReplaceResponse 後的差異
+    pub sent_content: String,
     pub file_refs: HashMap<String, poe_api_process::types::FileData>,
     pub tool_calls: Vec<poe_api_process::types::ChatToolCall>,
-    is_replace_mode: bool,
+    // strict 工具的參數 schema，用於檢查工具調用參數
+    pub strict_tools: Arc<StrictToolSchemas>,
+    // 是否允許同一回合多個工具調用 (parallel_tool_calls)
+    pub single_tool_call: bool,
+    // 串流中已發送的工具調用數
+    tool_calls_sent: usize,
+    // 依收到順序記錄的附件，用於補上正文中未引用的附件
+    pub attachments: Vec<poe_api_process::types::FileData>,
+    // 以 message.images 返回的圖片 (IMAGE_OUTPUT_MODE=images|b64)
+    pub images: Vec<ImageOutput>,
+    // 以 message.videos 返回的影片
+    pub videos: Vec<VideoOutput>,
+    // 以 message.audio 返回的第一個音訊
+    pub audio: Option<AudioOutput>,
+    // 請求要求音訊輸出時，message.audio 中的音訊不再放入正文
+    pub audio_separate: bool,
+    // 收到 ReplaceResponse 後尚未發送差異
+    replace_pending: bool,
+    // ReplaceResponse 處理策略
+    pub replace_mode: ReplaceResponseMode,
+    // ReplaceResponse 改寫了已發送的內容，串流無法撤回
+    pub content_rewritten: bool,
     pub error: Option<(StatusCode, OpenAIErrorResponse)>,
     pub done: bool,
     pub completion_tokens: u32,
-    first_text_processed: bool,
     pub role_chunk_sent: bool,
     has_new_file_refs: bool,
-    pub image_urls_sent: bool,
-    // 思考相關欄位
-    pub reasoning_content: String,
-    pub in_thinking_mode: bool,
-    pub thinking_started: bool,
-    pub current_reasoning_line: String,
-    pub pending_text: String,
-    pub metadata: HashMap<String, usize>, // 用於追蹤已發送的內容長度
+    // 串流中暫緩發送、可能尚未完整的字素簇（如 ZWJ 表情序列）
+    stream_tail: String,
+    // 串流正文合併：累積中的正文與預定發送時間
+    coalesce_buffer: String,
+    coalesce_deadline: Option<Instant>,
+    // 需要在 data: [DONE] 之後發送的內容
+    pub after_done: Option<String>,
+    // 機器人的建議回覆，依收到順序排列
+    pub suggested_replies: Vec<String>,
 }
 
-impl EventContext {
-    pub fn get(&self, key: &str) -> Option<usize> {
-        self.metadata.get(key).copied()
-    }
+/// 是否為會附加在前一字元上的字元（ZWJ、變體選擇符、膚色修飾符、組合符號等）
+fn is_grapheme_extender(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x200D
+            | 0x20E3
+            | 0xFE00..=0xFE0F
+            | 0x1F3FB..=0x1F3FF
+            | 0xE0020..=0xE007F
+            | 0x0300..=0x036F
+            | 0x1AB0..=0x1AFF
+            | 0x1DC0..=0x1DFF
+            | 0x20D0..=0x20FF
+            | 0xFE20..=0xFE2F
+            | 0x3099..=0x309A
+    )
+}
 
-    pub fn insert(&mut self, key: &str, value: usize) {
-        self.metadata.insert(key.to_string(), value);
-    }
+/// 是否為可能接上修飾符或 ZWJ 的表情符號
+fn is_emoji_base(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF | 0x2300..=0x23FF | 0x2600..=0x27BF | 0x2B00..=0x2BFF
+    )
 }
 
-// 事件處理器 trait
-trait EventHandler {
-    fn handle(&self, event: &ChatResponse, ctx: &mut EventContext) -> Option<String>;
+/// 是否為區域指示符（兩個組成一面旗幟）
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
 }
 
-// 思考內容處理器
-#[derive(Clone)]
-pub struct ThinkingProcessor;
+/// 找出文本末尾可能被下一個片段延續的字素簇起點
+/// 返回 None 表示整段可以安全發送
+fn incomplete_cluster_start(text: &str) -> Option<usize> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let &(_, last) = chars.last()?;
+
+    // 旗幟：末尾區域指示符數量為奇數時，最後一個尚未配對
+    if is_regional_indicator(last) {
+        let count = chars
+            .iter()
+            .rev()
+            .take_while(|(_, c)| is_regional_indicator(*c))
+            .count();
+        return (count % 2 == 1).then(|| chars[chars.len() - 1].0);
+    }
 
-impl ThinkingProcessor {
-    // 檢測思考開始標記
-    fn detect_thinking_start(text: &str) -> Option<usize> {
-        if let Some(pos) = text.find("*Thinking...*") {
-            return Some(pos);
+    // 向前找出最後一個字素簇的起點（含以 ZWJ 連接的表情）
+    let mut start = chars.len();
+    let mut base = None;
+    while start > 0 {
+        start -= 1;
+        let c = chars[start].1;
+        if is_grapheme_extender(c) {
+            continue;
         }
-        if let Some(pos) = text.find("Thinking...") {
-            return Some(pos);
+        base = Some(c);
+        if start > 0 && chars[start - 1].1 == '\u{200D}' {
+            continue;
         }
-        None
+        break;
     }
 
-    // 處理文本並分離思考內容和普通內容
-    // 返回 (reasoning_chunk, content_chunk)
-    pub fn process_text_chunk(
-        ctx: &mut EventContext,
-        new_text: &str,
-    ) -> (Option<String>, Option<String>) {
-        ctx.pending_text.push_str(new_text);
-
-        let mut reasoning_output = None;
-        let mut content_output = None;
-
-        // 如果還沒開始思考模式，檢測是否有思考標記
-        if !ctx.thinking_started {
-            if let Some(thinking_pos) = Self::detect_thinking_start(&ctx.pending_text) {
-                debug!("🧠 思考模式開始");
-                ctx.thinking_started = true;
-                ctx.in_thinking_mode = true;
-
-                // 分離思考標記前後的內容
-                let (before_thinking, after_thinking) = ctx.pending_text.split_at(thinking_pos);
-
-                // 思考標記前的內容作為普通內容
-                if !before_thinking.trim().is_empty() {
-                    ctx.content.push_str(before_thinking);
-                    content_output = Some(before_thinking.to_string());
-                }
+    let extendable = is_grapheme_extender(last) || base.is_some_and(is_emoji_base);
+    extendable.then(|| chars[start].0)
+}
 
-                // 確定標記類型並移除完整標記
-                let after_marker = if after_thinking.starts_with("*Thinking...*") {
-                    after_thinking.strip_prefix("*Thinking...*").unwrap_or("")
-                } else if after_thinking.starts_with("Thinking...") {
-                    after_thinking.strip_prefix("Thinking...").unwrap_or("")
-                } else {
-                    after_thinking
-                };
-
-                ctx.pending_text = after_marker.to_string();
-            } else {
-                // 沒有思考標記，作為普通內容處理
-                if !ctx.pending_text.trim().is_empty() {
-                    ctx.content.push_str(&ctx.pending_text);
-                    content_output = Some(ctx.pending_text.clone());
-                    ctx.pending_text.clear();
-                }
-                return (None, content_output);
-            }
+impl EventContext {
+    /// 建立處理工具調用的上下文：strict 工具的參數 schema 與是否只保留一個工具調用
+    pub fn with_tool_options(strict_tools: Arc<StrictToolSchemas>, single_tool_call: bool) -> Self {
+        Self {
+            strict_tools,
+            single_tool_call,
+            replace_mode: get_replace_response_mode(),
+            ..Default::default()
         }
+    }
 
-        // 思考模式下處理內容
-        if ctx.thinking_started && ctx.in_thinking_mode {
-            let (reasoning_chunk, remaining_text, thinking_ended) =
-                Self::process_thinking_content(ctx);
+    /// 取出尚未發送的工具調用，index 為其在本回合中的位置
+    pub fn take_new_tool_calls(&mut self) -> Vec<ToolCallDelta> {
+        let start = self.tool_calls_sent;
+        self.tool_calls_sent = self.tool_calls.len();
+        self.tool_calls[start..]
+            .iter()
+            .enumerate()
+            .map(|(offset, call)| ToolCallDelta {
+                index: start + offset,
+                call: call.clone(),
+            })
+            .collect()
+    }
 
-            if let Some(reasoning_content) = reasoning_chunk {
-                reasoning_output = Some(reasoning_content);
-            }
+    /// 加入新的工具調用，補上缺漏或重複的 id
+    fn push_tool_call(&mut self, mut call: poe_api_process::types::ChatToolCall) {
+        if self.single_tool_call && !self.tool_calls.is_empty() {
+            debug!(
+                "🔧 parallel_tool_calls 為 false，捨棄多餘的工具調用: {}",
+                call.function.name
+            );
+            return;
+        }
+        if call.id.is_empty() || self.tool_calls.iter().any(|c| c.id == call.id) {
+            call.id = format!("call_{}", nanoid::nanoid!(24));
+        }
+        if call.r#type.is_empty() {
+            call.r#type = "function".to_string();
+        }
+        self.tool_calls.push(call);
+    }
 
-            ctx.pending_text = remaining_text;
-
-            // 如果思考結束，處理剩餘內容作為普通內容
-            if thinking_ended {
-                debug!("🧠 思考模式結束");
-                ctx.in_thinking_mode = false;
-                if !ctx.pending_text.trim().is_empty() {
-                    ctx.content.push_str(&ctx.pending_text);
-                    // 如果已經有 content_output，合併內容
-                    if let Some(existing_content) = content_output {
-                        content_output = Some(format!("{}{}", existing_content, ctx.pending_text));
-                    } else {
-                        content_output = Some(ctx.pending_text.clone());
-                    }
-                    ctx.pending_text.clear();
-                }
-            }
-        } else if ctx.thinking_started
-            && !ctx.in_thinking_mode
-            && !ctx.pending_text.trim().is_empty()
-        {
-            ctx.content.push_str(&ctx.pending_text);
-            content_output = Some(ctx.pending_text.clone());
-            ctx.pending_text.clear();
+    /// 計算尚未發送給客戶端的正文差異，並更新已發送記錄
+    /// 已發送的內容被改寫時串流無法撤回，標記 content_rewritten 而不發送差異
+    fn take_content_delta(&mut self) -> Option<String> {
+        if !self.content.starts_with(&self.sent_content) {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ ReplaceResponse 改寫了已發送的內容 | 已發送: {} | 改寫後: {}",
+                    "⚠️ ReplaceResponse rewrote already sent content | sent: {} | rewritten: {}",
+                    format_bytes_length(self.sent_content.len()),
+                    format_bytes_length(self.content.len())
+                )
+            );
+            self.content_rewritten = true;
+            self.sent_content = self.content.clone();
+            return None;
         }
+        let delta = self.content[self.sent_content.len()..].to_string();
+        self.sent_content = self.content.clone();
+        if delta.is_empty() { None } else { Some(delta) }
+    }
 
-        (reasoning_output, content_output)
+    /// 取得可安全發送的串流正文，避免將表情序列等字素簇拆到兩個 delta.content 中
+    /// 末尾可能未完整的字素簇會暫存，與下一個片段合併後再發送
+    pub fn take_stream_safe(&mut self, chunk: &str) -> Option<String> {
+        let mut text = std::mem::take(&mut self.stream_tail);
+        text.push_str(chunk);
+        if let Some(cut) = incomplete_cluster_start(&text) {
+            self.stream_tail = text.split_off(cut);
+            debug!(
+                "✂️ 暫緩發送未完整的字素簇 | 長度: {}",
+                format_bytes_length(self.stream_tail.len())
+            );
+        }
+        if text.is_empty() { None } else { Some(text) }
     }
 
-    // 處理思考模式下的內容
-    // 返回 (reasoning_chunk, remaining_text, thinking_ended)
-    fn process_thinking_content(ctx: &mut EventContext) -> (Option<String>, String, bool) {
-        let mut reasoning_chunks = Vec::new();
-        let mut thinking_ended = false;
-
-        // 按行處理，但需要考慮串流中的不完整行
-        let lines: Vec<&str> = ctx.pending_text.lines().collect();
-        let mut processed_lines = 0;
-
-        for (i, line) in lines.iter().enumerate() {
-            let trimmed = line.trim();
-
-            if trimmed.starts_with("> ") || trimmed == ">" {
-                // 思考內容行（包括空的 "> " 行）
-                let thinking_content = if trimmed == ">" {
-                    "" // 空的思考行
-                } else {
-                    trimmed.strip_prefix("> ").unwrap_or(trimmed)
-                };
-
-                // 檢查是否是完整的行（在串流中可能不完整）
-                if i == lines.len() - 1 && !ctx.pending_text.ends_with('\n') {
-                    // 最後一行且沒有換行符，可能不完整
-                    ctx.current_reasoning_line = thinking_content.to_string();
-                    break;
-                } else {
-                    // 完整的思考行
-                    let mut full_line = thinking_content.to_string();
-
-                    // 檢查後續行是否屬於同一段思考（沒有真正的換行分隔）
-                    let mut j = i + 1;
-                    while j < lines.len() {
-                        let next_line = lines[j].trim();
-                        if !next_line.starts_with("> ") && !next_line.is_empty() {
-                            // 檢查原始文本中是否有真正的換行
-                            // 使用更安全的方式查找位置，避免重複文本導致的錯誤
-                            if let Some(current_pos) = ctx.pending_text.find(line) {
-                                if let Some(relative_next_pos) =
-                                    ctx.pending_text[current_pos..].find(next_line)
-                                {
-                                    let next_pos = current_pos + relative_next_pos;
-                                    let start_pos = current_pos + line.len();
-
-                                    // 確保切片邊界正確
-                                    if start_pos <= next_pos {
-                                        let between_text = &ctx.pending_text[start_pos..next_pos];
-
-                                        if between_text.contains('\n') {
-                                            // 有真正的換行，思考內容結束
-                                            break;
-                                        } else {
-                                            // 沒有換行，是同一段內容
-                                            full_line.push_str(next_line);
-                                            j += 1;
-                                        }
-                                    } else {
-                                        // 位置計算有問題，保守處理：認為有換行
-                                        debug!(
-                                            "🧠 位置計算異常，保守處理（等待'\\n'換行） | start_pos: {} | next_pos: {}",
-                                            start_pos, next_pos
-                                        );
-                                        break;
-                                    }
-                                } else {
-                                    // 找不到下一行，認為有換行
-                                    break;
-                                }
-                            } else {
-                                // 找不到當前行，認為有換行
-                                break;
-                            }
-                        } else if next_line.is_empty() {
-                            j += 1;
-                        } else {
-                            break;
-                        }
-                    }
+    /// 取出串流結束前仍暫存的正文
+    pub fn flush_stream_tail(&mut self) -> Option<String> {
+        let tail = std::mem::take(&mut self.stream_tail);
+        if tail.is_empty() { None } else { Some(tail) }
+    }
 
-                    reasoning_chunks.push(full_line);
-                    processed_lines = j;
-                }
-            } else if trimmed.is_empty() {
-                // 空行，繼續
-                processed_lines = i + 1;
-            } else {
-                // 非思考格式的內容，思考結束
-                thinking_ended = true;
-                processed_lines = i;
-                break;
-            }
+    /// 累積串流正文，達到大小上限時返回全部累積內容
+    /// 未啟用合併時直接返回原片段
+    pub fn coalesce_content(&mut self, chunk: String) -> Option<String> {
+        let config = get_stream_coalesce_config();
+        if config.interval.is_zero() {
+            return Some(chunk);
         }
+        self.coalesce_buffer.push_str(&chunk);
+        if self.coalesce_deadline.is_none() {
+            self.coalesce_deadline = Some(Instant::now() + config.interval);
+        }
+        if config.max_bytes > 0 && self.coalesce_buffer.len() >= config.max_bytes {
+            return self.take_coalesced();
+        }
+        None
+    }
 
-        // 組合思考內容
-        let reasoning_output = if !reasoning_chunks.is_empty() {
-            let combined_reasoning = reasoning_chunks.join("\n");
-            ctx.reasoning_content.push_str(&combined_reasoning);
-            if !ctx.reasoning_content.ends_with('\n') {
-                ctx.reasoning_content.push('\n');
-            }
-            Some(combined_reasoning)
-        } else {
+    /// 取出全部累積中的串流正文
+    pub fn take_coalesced(&mut self) -> Option<String> {
+        self.coalesce_deadline = None;
+        let buffer = std::mem::take(&mut self.coalesce_buffer);
+        if buffer.is_empty() {
             None
-        };
+        } else {
+            Some(buffer)
+        }
+    }
+
+    /// 累積中的正文應發送的時間
+    pub fn coalesce_deadline(&self) -> Option<Instant> {
+        self.coalesce_deadline
+    }
 
-        // 計算剩餘文本
-        let remaining_text = if processed_lines < lines.len() {
-            lines[processed_lines..].join("\n")
-        } else if !ctx.current_reasoning_line.is_empty() && !thinking_ended {
-            // 保留未完成的思考行
-            format!("> {}", ctx.current_reasoning_line)
+    /// 產生正文中未引用附件的 Markdown
+    /// 以 message.images 返回的圖片及要求音訊輸出時的 message.audio 不再重複放入正文
+    fn unreferenced_attachments_markdown(&self) -> Option<String> {
+        let images_separate = get_image_output_mode() != ImageOutputMode::Markdown;
+        let separate_audio = self
+            .attachments
+            .iter()
+            .find(|file| is_audio(file))
+            .filter(|_| self.audio_separate)
+            .map(|file| file.url.clone());
+        let parts: Vec<String> = self
+            .attachments
+            .iter()
+            .filter(|file| !(images_separate && is_image(file)))
+            .filter(|file| separate_audio.as_ref() != Some(&file.url))
+            .filter(|file| {
+                !self.content.contains(&format!("[{}]", file.inline_ref))
+                    && !self.content.contains(&file.url)
+            })
+            .map(attachment_markdown)
+            .collect();
+        if parts.is_empty() {
+            return None;
+        }
+        let separator = if self.content.trim().is_empty() {
+            ""
         } else {
-            String::new()
+            "\n\n"
         };
+        Some(format!("{}{}", separator, parts.join("\n\n")))
+    }
 
-        (reasoning_output, remaining_text, thinking_ended)
+    /// 是否包含已知文件引用的標記
+    fn contains_file_ref(&self, text: &str) -> bool {
+        self.file_refs
+            .keys()
+            .any(|ref_id| text.contains(&format!("[{}]", ref_id)))
     }
 }
 
+// 事件處理器 trait
+trait EventHandler {
+    fn handle(&self, event: &ChatResponse, ctx: &mut EventContext) -> Option<String>;
+}
+
 // Text 事件處理器
 #[derive(Clone)]
 struct TextEventHandler;
 impl EventHandler for TextEventHandler {
     fn handle(&self, event: &ChatResponse, ctx: &mut EventContext) -> Option<String> {
         if let Some(ChatResponseData::Text { text }) = &event.data {
-            // 處理替換模式
-            if ctx.is_replace_mode && !ctx.first_text_processed {
+            let buffered = ctx.replace_mode == ReplaceResponseMode::Buffer;
+
+            // ReplaceResponse 之後的第一個 Text：追加到替換內容並發送差異
+            if ctx.replace_pending {
                 debug!("📝 合併第一個 Text 事件與 ReplaceResponse");
-                if let Some(replace_content) = &mut ctx.replace_buffer {
-                    replace_content.push_str(text);
-                    ctx.first_text_processed = true;
-
-                    // 先克隆內容，然後釋放借用
-                    let content_to_process = replace_content.clone();
-                    let _ = replace_content; // 明確釋放借用
-                    let (reasoning_output, content_output) =
-                        ThinkingProcessor::process_text_chunk(ctx, &content_to_process);
-
-                    if reasoning_output.is_some() {
-                        return Some("__REASONING_DETECTED__".to_string());
-                    }
-                    return content_output;
-                } else {
-                    // 沒有 replace_buffer，直接添加到 content
-                    ctx.content.push_str(text);
-                    return Some(text.clone());
-                }
-            } else if ctx.is_replace_mode && ctx.first_text_processed {
-                debug!("🔄 重置替換模式");
-                ctx.is_replace_mode = false;
-                ctx.first_text_processed = false;
-
-                // 將 replace_buffer 的內容移至 content
-                if let Some(replace_content) = ctx.replace_buffer.take() {
-                    ctx.content = replace_content;
+                ctx.replace_pending = false;
+                ctx.content.push_str(text);
+                if buffered {
+                    return None;
                 }
+                return ctx.take_content_delta();
             }
 
-            // 正常模式處理
-            let (reasoning_output, content_output) =
-                ThinkingProcessor::process_text_chunk(ctx, text);
+            // 思考內容由處理管線的 Poe 思考階段分離
+            ctx.content.push_str(text);
 
-            // 如果檢測到思考內容，返回特殊標記
-            if reasoning_output.is_some() {
-                // 如果同時有普通內容，需要暫存起來等待下次處理
-                if let Some(content) = content_output {
-                    // 將內容添加到 pending_text 開頭，確保下次處理時能發送
-                    ctx.pending_text = format!("{}{}", content, ctx.pending_text);
-                }
-                return Some("__REASONING_DETECTED__".to_string());
+            // 緩衝模式下正文留待完成時發送
+            if buffered {
+                return None;
             }
-
-            return content_output;
+            ctx.sent_content.push_str(text);
+            return Some(text.clone());
         }
         None
     }
@@ -336,25 +501,20 @@ impl EventHandler for FileEventHandler {
             );
             ctx.file_refs
                 .insert(file_data.inline_ref.clone(), file_data.clone());
+            ctx.attachments.push(file_data.clone());
             ctx.has_new_file_refs = true;
 
-            // 如果此時有 replace_buffer，處理它並發送
-            if !ctx.image_urls_sent && ctx.replace_buffer.is_some() {
-                // 只處理未發送過的
-                let content = ctx.replace_buffer.as_ref().unwrap();
-                if content.contains(&format!("[{}]", file_data.inline_ref)) {
-                    debug!(
-                        "🖼️ 檢測到 ReplaceResponse 包含圖片引用 [{}]，立即處理",
-                        file_data.inline_ref
-                    );
-                    // 處理這個文本中的圖片引用
-                    let mut processed = content.clone();
-                    let img_marker = format!("[{}]", file_data.inline_ref);
-                    let replacement = format!("({})", file_data.url);
-                    processed = processed.replace(&img_marker, &replacement);
-                    ctx.image_urls_sent = true; // 標記已發送
-                    return Some(processed);
-                }
+            // 推遲中的 ReplaceResponse 包含此圖片引用時，立即發送
+            if ctx.replace_pending
+                && ctx.replace_mode == ReplaceResponseMode::Diff
+                && ctx.content.contains(&format!("[{}]", file_data.inline_ref))
+            {
+                debug!(
+                    "🖼️ 檢測到 ReplaceResponse 包含圖片引用 [{}]，立即處理",
+                    file_data.inline_ref
+                );
+                ctx.replace_pending = false;
+                return ctx.take_content_delta();
             }
         }
         None
@@ -371,39 +531,26 @@ impl EventHandler for ReplaceResponseEventHandler {
                 "🔄 處理 ReplaceResponse 事件 | 長度: {}",
                 format_bytes_length(text.len())
             );
-            ctx.is_replace_mode = true;
-            ctx.replace_buffer = Some(text.clone());
-            ctx.first_text_processed = false;
-
-            // 檢查是否有文件引用需要處理
-            if !ctx.file_refs.is_empty() && text.contains('[') {
-                debug!("🔄 ReplaceResponse 可能包含圖片引用，檢查並處理");
-                // 處理這個文本中的圖片引用
-                let mut processed = text.clone();
-                let mut has_refs = false;
-
-                for (ref_id, file_data) in &ctx.file_refs {
-                    let img_marker = format!("[{}]", ref_id);
-                    if processed.contains(&img_marker) {
-                        let replacement = format!("({})", file_data.url);
-                        processed = processed.replace(&img_marker, &replacement);
-                        has_refs = true;
-                        debug!("🖼️  替換圖片引用 | ID: {} | URL: {}", ref_id, file_data.url);
-                    }
-                }
+            // 替換後的內容成為新的完整正文
+            ctx.content = text.clone();
+            ctx.replace_pending = true;
 
-                if has_refs {
-                    // 如果確實包含了圖片引用，立即返回處理後的內容
-                    debug!("✅ ReplaceResponse 含有圖片引用，立即發送處理後內容");
-                    ctx.image_urls_sent = true; // 標記已發送
-                    return Some(processed);
-                }
+            if ctx.replace_mode == ReplaceResponseMode::Buffer {
+                debug!("🔄 緩衝模式，ReplaceResponse 留待完成時發送");
+                return None;
+            }
+
+            // 已知的圖片引用可立即解析，直接發送差異
+            if ctx.contains_file_ref(text) {
+                debug!("✅ ReplaceResponse 含有圖片引用，立即發送處理後內容");
+                ctx.replace_pending = false;
+                return ctx.take_content_delta();
             }
 
-            // 推遲 ReplaceResponse 的輸出，等待後續 Text 事件
+            // 推遲 ReplaceResponse 的輸出，等待後續 Text 或 File 事件
             debug!("🔄 推遲 ReplaceResponse 的輸出，等待後續 Text 事件");
         }
-        None // 不直接發送，等待與 Text 合併
+        None
     }
 }
 
@@ -415,7 +562,39 @@ impl EventHandler for JsonEventHandler {
         debug!("📝 處理 JSON 事件");
         if let Some(ChatResponseData::ToolCalls(tool_calls)) = &event.data {
             debug!("🔧 處理工具調用，數量: {}", tool_calls.len());
-            ctx.tool_calls.extend(tool_calls.clone());
+            let mut tool_calls = tool_calls.clone();
+            for call in &mut tool_calls {
+                if let Err(reason) = check_tool_call(call, &ctx.strict_tools) {
+                    warn!(
+                        "{}",
+                        tr!(
+                            "⚠️ 工具調用參數不符合 schema | 工具: {} | 原因: {}",
+                            "⚠️ Tool call arguments do not match the schema | tool: {} | reason: {}",
+                            call.function.name,
+                            reason
+                        )
+                    );
+                    // 以 5xx 返回，客戶端可重試
+                    ctx.error = Some((
+                        StatusCode::BAD_GATEWAY,
+                        OpenAIErrorResponse {
+                            error: OpenAIError {
+                                message: format!(
+                                    "工具 {} 的調用參數不符合 schema: {}",
+                                    call.function.name, reason
+                                ),
+                                r#type: "server_error".to_string(),
+                                code: "invalid_tool_call".to_string(),
+                                param: Some("tools".to_string()),
+                            },
+                        },
+                    ));
+                    return Some("error".to_string());
+                }
+            }
+            for call in tool_calls {
+                ctx.push_tool_call(call);
+            }
             // 返回 Some，表示需要發送工具調用
             return Some("tool_calls".to_string());
         }
@@ -429,7 +608,10 @@ struct ErrorEventHandler;
 impl EventHandler for ErrorEventHandler {
     fn handle(&self, event: &ChatResponse, ctx: &mut EventContext) -> Option<String> {
         if let Some(ChatResponseData::Error { text, allow_retry }) = &event.data {
-            error!("❌ 處理錯誤事件: {}", text);
+            error!(
+                "{}",
+                tr!("❌ 處理錯誤事件: {}", "❌ Upstream error event: {}", text)
+            );
             let (status, error_response) = convert_poe_error_to_openai(text, *allow_retry);
             ctx.error = Some((status, error_response));
             return Some("error".to_string());
@@ -438,6 +620,19 @@ impl EventHandler for ErrorEventHandler {
     }
 }
 
+// SuggestedReply 事件處理器：只收集，不寫入正文
+#[derive(Clone)]
+struct SuggestedReplyEventHandler;
+impl EventHandler for SuggestedReplyEventHandler {
+    fn handle(&self, event: &ChatResponse, ctx: &mut EventContext) -> Option<String> {
+        if let Some(ChatResponseData::Text { text }) = &event.data {
+            debug!("💡 收到建議回覆: {}", text);
+            ctx.suggested_replies.push(text.clone());
+        }
+        None
+    }
+}
+
 // Done 事件處理器
 #[derive(Clone)]
 struct DoneEventHandler;
@@ -446,34 +641,36 @@ impl EventHandler for DoneEventHandler {
         debug!("✅ 處理 Done 事件");
         ctx.done = true;
 
-        // 只有當未發送過圖片URL時才處理
-        if !ctx.image_urls_sent && ctx.replace_buffer.is_some() && !ctx.file_refs.is_empty() {
-            let content = ctx.replace_buffer.as_ref().unwrap();
-            debug!("🔍 檢查完成事件時是否有未處理的圖片引用");
-            let mut processed = content.clone();
-            let mut has_refs = false;
-
-            for (ref_id, file_data) in &ctx.file_refs {
-                let img_marker = format!("[{}]", ref_id);
-                if processed.contains(&img_marker) {
-                    let replacement = format!("({})", file_data.url);
-                    processed = processed.replace(&img_marker, &replacement);
-                    has_refs = true;
-                    debug!(
-                        "🖼️ 完成前替換圖片引用 | ID: {} | URL: {}",
-                        ref_id, file_data.url
-                    );
-                }
+        // 發送仍未發送的正文（推遲中的 ReplaceResponse 或緩衝模式）
+        let buffered = ctx.replace_mode == ReplaceResponseMode::Buffer;
+        let deferred = ctx.replace_pending || buffered;
+
+        // 補上正文未引用的附件，避免客戶端只收到無法解析的引用或空白回應
+        let mut attachment_output = None;
+        if let Some(extra) = ctx.unreferenced_attachments_markdown() {
+            debug!(
+                "🖼️ 補上未引用的附件 | 長度: {}",
+                format_bytes_length(extra.len())
+            );
+            ctx.content.push_str(&extra);
+            if !deferred {
+                ctx.sent_content.push_str(&extra);
+                attachment_output = Some(extra);
             }
+        }
 
-            if has_refs {
-                debug!("✅ 完成前處理了圖片引用");
-                ctx.image_urls_sent = true; // 標記已發送
-                return Some(processed);
+        if deferred {
+            ctx.replace_pending = false;
+            if let Some(delta) = ctx.take_content_delta() {
+                debug!(
+                    "✅ 完成前發送剩餘正文 | 長度: {}",
+                    format_bytes_length(delta.len())
+                );
+                return Some(delta);
             }
         }
 
-        Some("done".to_string())
+        attachment_output.or_else(|| Some("done".to_string()))
     }
 }
 
@@ -485,6 +682,7 @@ pub struct EventHandlerManager {
     replace_handler: ReplaceResponseEventHandler,
     json_handler: JsonEventHandler,
     error_handler: ErrorEventHandler,
+    suggested_reply_handler: SuggestedReplyEventHandler,
     done_handler: DoneEventHandler,
 }
 
@@ -496,6 +694,7 @@ impl EventHandlerManager {
             replace_handler: ReplaceResponseEventHandler,
             json_handler: JsonEventHandler,
             error_handler: ErrorEventHandler,
+            suggested_reply_handler: SuggestedReplyEventHandler,
             done_handler: DoneEventHandler,
         }
     }
@@ -507,7 +706,48 @@ impl EventHandlerManager {
             ChatEventType::ReplaceResponse => self.replace_handler.handle(event, ctx),
             ChatEventType::Json => self.json_handler.handle(event, ctx),
             ChatEventType::Error => self.error_handler.handle(event, ctx),
+            ChatEventType::SuggestedReply => self.suggested_reply_handler.handle(event, ctx),
             ChatEventType::Done => self.done_handler.handle(event, ctx),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_event(event: ChatEventType, text: &str) -> ChatResponse {
+        ChatResponse {
+            event,
+            data: Some(ChatResponseData::Text {
+                text: text.to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn suggested_replies_are_collected_outside_content() {
+        let manager = EventHandlerManager::new();
+        let mut ctx = EventContext::default();
+        let events = [
+            text_event(ChatEventType::Text, "Hello"),
+            text_event(ChatEventType::SuggestedReply, "Tell me more"),
+            text_event(ChatEventType::SuggestedReply, "Give an example"),
+            ChatResponse {
+                event: ChatEventType::Done,
+                data: Some(ChatResponseData::Empty),
+            },
+        ];
+        let outputs: Vec<Option<String>> = events
+            .iter()
+            .map(|event| manager.handle(event, &mut ctx))
+            .collect();
+
+        // 建議回覆不產生輸出，也不寫入正文
+        assert_eq!(outputs[1], None);
+        assert_eq!(outputs[2], None);
+        assert_eq!(ctx.content, "Hello");
+        assert_eq!(ctx.suggested_replies, ["Tell me more", "Give an example"]);
+        assert!(ctx.done);
+    }
+}
@@ -0,0 +1,201 @@
+//! 內容過濾：以關鍵字或正則表達式檢查發送給 Poe 的使用者訊息，並可選擇套用到輸出
+
+use crate::types::{Message, OpenAiContent, OpenAiContentItem};
+use crate::utils::get_config_path;
+use regex::Regex;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+static CONTENT_FILTER: OnceLock<Option<ContentFilter>> = OnceLock::new();
+
+/// 命中規則時的處理方式
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// 以 content_filter 錯誤拒絕請求
+    #[default]
+    Reject,
+    /// 以替換文字遮蔽命中的內容
+    Mask,
+    /// 僅記錄日誌
+    Log,
+}
+
+#[derive(Deserialize)]
+struct ContentFilterFile {
+    #[serde(default)]
+    apply_to_output: bool,
+    #[serde(default)]
+    rules: Vec<FilterRuleConfig>,
+}
+
+#[derive(Deserialize)]
+struct FilterRuleConfig {
+    /// 正則表達式
+    pattern: Option<String>,
+    /// 關鍵字（不區分大小寫）
+    keyword: Option<String>,
+    #[serde(default)]
+    action: FilterAction,
+    replacement: Option<String>,
+}
+
+struct FilterRule {
+    regex: Regex,
+    action: FilterAction,
+    replacement: String,
+}
+
+pub struct ContentFilter {
+    rules: Vec<FilterRule>,
+    apply_to_output: bool,
+}
+
+fn get_content_filter_path() -> PathBuf {
+    std::env::var("CONTENT_FILTER_PATH")
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| get_config_path("content_filter.yaml"))
+}
+
+/// 啟動時載入過濾規則，規則檔格式或正則表達式錯誤時返回錯誤
+pub fn init_content_filter() -> Result<(), String> {
+    let path = get_content_filter_path();
+    let filter = if path.exists() {
+        Some(ContentFilter::load(&path)?)
+    } else {
+        None
+    };
+    let _ = CONTENT_FILTER.set(filter);
+    Ok(())
+}
+
+/// 取得已載入的內容過濾器，未設定規則時返回 None
+pub fn get_content_filter() -> Option<&'static ContentFilter> {
+    CONTENT_FILTER.get_or_init(|| None).as_ref()
+}
+
+impl ContentFilter {
+    fn load(path: &PathBuf) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("無法讀取內容過濾規則 {}: {}", path.display(), e))?;
+        let file: ContentFilterFile = serde_yaml::from_str(&contents)
+            .map_err(|e| format!("無法解析內容過濾規則 {}: {}", path.display(), e))?;
+
+        let mut rules = Vec::new();
+        for rule in file.rules {
+            let pattern = match (rule.pattern, rule.keyword) {
+                (Some(pattern), _) => pattern,
+                (None, Some(keyword)) => format!("(?i){}", regex::escape(&keyword)),
+                (None, None) => return Err("內容過濾規則缺少 pattern 或 keyword".to_string()),
+            };
+            let regex = Regex::new(&pattern)
+                .map_err(|e| format!("無效的內容過濾規則 {}: {}", pattern, e))?;
+            rules.push(FilterRule {
+                regex,
+                action: rule.action,
+                replacement: rule.replacement.unwrap_or_else(|| "***".to_string()),
+            });
+        }
+        info!(
+            "{}",
+            tr!(
+                "🛡️ 已載入內容過濾規則 {} | 規則數: {} | 套用到輸出: {}",
+                "🛡️ Loaded content filter rules {} | rules: {} | apply to output: {}",
+                path.display(),
+                rules.len(),
+                file.apply_to_output
+            )
+        );
+        Ok(Self {
+            rules,
+            apply_to_output: file.apply_to_output,
+        })
+    }
+
+    /// 對文本套用規則；遇到 reject 規則時返回 Err
+    fn apply<'a>(&self, text: &'a str, allow_reject: bool) -> Result<Cow<'a, str>, ()> {
+        let mut text = Cow::Borrowed(text);
+        for rule in &self.rules {
+            if !rule.regex.is_match(&text) {
+                continue;
+            }
+            match rule.action {
+                FilterAction::Reject if allow_reject => {
+                    warn!(
+                        "{}",
+                        tr!(
+                            "🛡️ 內容過濾規則拒絕請求 | 規則: {}",
+                            "🛡️ Content filter rejected request | rule: {}",
+                            rule.regex.as_str()
+                        )
+                    );
+                    return Err(());
+                }
+                // 輸出已開始發送無法拒絕，改為遮蔽
+                FilterAction::Reject | FilterAction::Mask => {
+                    info!(
+                        "{}",
+                        tr!(
+                            "🛡️ 內容過濾規則遮蔽內容 | 規則: {}",
+                            "🛡️ Content filter masked content | rule: {}",
+                            rule.regex.as_str()
+                        )
+                    );
+                    text = Cow::Owned(
+                        rule.regex
+                            .replace_all(&text, rule.replacement.as_str())
+                            .into_owned(),
+                    );
+                }
+                FilterAction::Log => {
+                    warn!(
+                        "{}",
+                        tr!(
+                            "🛡️ 內容過濾規則命中 | 規則: {}",
+                            "🛡️ Content filter rule matched | rule: {}",
+                            rule.regex.as_str()
+                        )
+                    );
+                }
+            }
+        }
+        Ok(text)
+    }
+
+    /// 檢查並遮蔽使用者訊息，命中 reject 規則時返回 Err
+    pub fn filter_messages(&self, messages: &mut [Message]) -> Result<(), ()> {
+        for message in messages.iter_mut().filter(|m| m.role == "user") {
+            match &mut message.content {
+                Some(OpenAiContent::Text(text)) => {
+                    if let Cow::Owned(filtered) = self.apply(text, true)? {
+                        *text = filtered;
+                    }
+                }
+                Some(OpenAiContent::Multi(items)) => {
+                    for item in items.iter_mut() {
+                        if let OpenAiContentItem::Text { text } = item
+                            && let Cow::Owned(filtered) = self.apply(text, true)?
+                        {
+                            *text = filtered;
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// 對輸出文本套用規則（需啟用 apply_to_output）
+    pub fn filter_output<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !self.apply_to_output {
+            return Cow::Borrowed(text);
+        }
+        self.apply(text, false).unwrap_or(Cow::Borrowed(text))
+    }
+}
@@ -1,20 +1,39 @@
+use super::balance::get_balances;
+use super::debug::debug_convert;
+use super::health::get_model_health;
+use super::inflight::{cancel_generation, list_generations};
+use super::replay::{list_replay_candidates, replay_completion};
+use super::runtime::{get_runtime, reset_runtime, update_runtime};
+use super::stats::get_stats;
+use super::usage::{get_usage, usage_page};
 use crate::cache::{remove_config_sled, save_config_sled};
+use crate::i18n::get_lang;
+use crate::shared::{SYNC_INTERVAL, get_shared_state};
 use crate::types::Config;
-use crate::utils::get_config_path;
+use crate::utils::{get_config_path, get_env_secret};
 use askama::Template;
 use salvo::basic_auth::{BasicAuth, BasicAuthValidator};
 use salvo::prelude::*;
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::fs;
-use tracing::info;
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// 本實例最後寫入或套用的共享設定版本
+static SHARED_CONFIG_VERSION: Mutex<Option<String>> = Mutex::new(None);
 
 #[derive(Template)]
 #[template(path = "admin.html")]
-struct AdminTemplate;
+struct AdminTemplate {
+    lang: &'static str,
+}
 
 #[handler]
 async fn admin_page(res: &mut Response) {
-    let template = AdminTemplate;
+    let template = AdminTemplate {
+        lang: get_lang().code(),
+    };
     res.render(Text::Html(template.render().unwrap()));
 }
 
@@ -33,10 +52,14 @@ async fn save_config(req: &mut Request, res: &mut Response) {
                 res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
                 res.render(Json(json!({ "error": e.to_string() })));
             } else {
-                info!("✅ models.yaml 已成功儲存。");
+                info!(
+                    "{}",
+                    tr!("✅ models.yaml 已成功儲存。", "✅ models.yaml saved.")
+                );
                 // 同步寫入 sled 快取
                 let _ = save_config_sled("models.yaml", &config);
                 invalidate_config_cache();
+                publish_shared_config(&config).await;
                 res.render(Json(json!({ "status": "success" })));
             }
         }
@@ -68,6 +91,17 @@ fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
             custom_models: Some(Vec::new()),
             api_token: None,
             use_v1_api: None,
+            stream_compat: None,
+            key_stream_compat: None,
+            key_priority: None,
+            history_policy: None,
+            history_summary_model: None,
+            mcp_servers: None,
+            scopes: None,
+            coalesce: None,
+            key_coalesce: None,
+            param_policy: None,
+            strip_footnotes: None,
         })
     }
 }
@@ -79,8 +113,114 @@ fn save_config_to_file(config: &Config) -> Result<(), Box<dyn std::error::Error>
     Ok(())
 }
 
+/// 將設定寫入共享狀態，其他實例於下次同步時套用；未設定 REDIS_URL 時不處理
+async fn publish_shared_config(config: &Config) {
+    let Some(shared) = get_shared_state() else {
+        return;
+    };
+    let Ok(json) = serde_json::to_string(config) else {
+        return;
+    };
+    let version = format!("{:x}", Sha256::digest(json.as_bytes()));
+    let mut pipe = redis::pipe();
+    pipe.set(shared.key("config"), &json)
+        .ignore()
+        .set(shared.key("config:version"), &version)
+        .ignore();
+    if shared.query_pipeline::<()>(&pipe).await.is_ok() {
+        debug!("🔗 已發布共享設定 | 版本: {}", &version[..12]);
+        *SHARED_CONFIG_VERSION
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(version);
+    }
+}
+
+/// 比對共享設定的版本，有變更時寫入本機 models.yaml 並清除緩存；
+/// 共享狀態中尚無設定時發布本機設定
+async fn sync_shared_config() {
+    let Some(shared) = get_shared_state() else {
+        return;
+    };
+    let Ok(version) = shared.get("config:version").await else {
+        return;
+    };
+    let Some(version) = version else {
+        let local = load_config().ok();
+        if let Some(config) = local {
+            publish_shared_config(&config).await;
+        }
+        return;
+    };
+    if SHARED_CONFIG_VERSION
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_deref()
+        == Some(version.as_str())
+    {
+        return;
+    }
+    let Ok(Some(json)) = shared.get("config").await else {
+        return;
+    };
+    match serde_json::from_str::<Config>(&json) {
+        Ok(config) => {
+            if let Err(e) = save_config_to_file(&config) {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 無法寫入共享設定到 models.yaml: {}",
+                        "⚠️ Failed to write the shared config to models.yaml: {}",
+                        e
+                    )
+                );
+                return;
+            }
+            let _ = save_config_sled("models.yaml", &config);
+            info!(
+                "{}",
+                tr!(
+                    "🔗 已套用共享設定 | 版本: {}",
+                    "🔗 Applied shared config | version: {}",
+                    &version[..version.len().min(12)]
+                )
+            );
+        }
+        Err(e) => warn!(
+            "{}",
+            tr!(
+                "⚠️ 共享設定無法解析，略過此版本: {}",
+                "⚠️ Failed to parse the shared config, skipping this version: {}",
+                e
+            )
+        ),
+    }
+    *SHARED_CONFIG_VERSION
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = Some(version);
+}
+
+/// 啟動共享設定的背景同步，未設定 REDIS_URL 時不處理
+pub fn spawn_shared_config_sync() {
+    if get_shared_state().is_none() {
+        return;
+    }
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(SYNC_INTERVAL);
+        loop {
+            interval.tick().await;
+            sync_shared_config().await;
+        }
+    });
+}
+
 fn invalidate_config_cache() {
-    info!("🗑️  清除 models.yaml 設定緩存...");
+    info!(
+        "{}",
+        tr!(
+            "🗑️  清除 models.yaml 設定緩存...",
+            "🗑️  Clearing models.yaml config cache..."
+        )
+    );
     remove_config_sled("models.yaml");
 }
 
@@ -89,9 +229,9 @@ pub struct AdminAuthValidator;
 impl BasicAuthValidator for AdminAuthValidator {
     async fn validate(&self, username: &str, password: &str, _depot: &mut Depot) -> bool {
         let valid_username =
-            std::env::var("ADMIN_USERNAME").unwrap_or_else(|_| "admin".to_string());
+            get_env_secret("ADMIN_USERNAME").unwrap_or_else(|| "admin".to_string());
         let valid_password =
-            std::env::var("ADMIN_PASSWORD").unwrap_or_else(|_| "123456".to_string());
+            get_env_secret("ADMIN_PASSWORD").unwrap_or_else(|| "123456".to_string());
         username == valid_username && password == valid_password
     }
 }
@@ -101,9 +241,25 @@ pub fn admin_routes() -> Router {
     Router::new()
         .hoop(auth_handler) // 加入認證中間件
         .push(Router::with_path("admin").get(admin_page))
+        .push(Router::with_path("admin/usage").get(usage_page))
         .push(
             Router::with_path("api/admin/config")
                 .get(get_config)
                 .post(save_config),
         )
+        .push(Router::with_path("api/admin/balance").get(get_balances))
+        .push(Router::with_path("api/admin/stats").get(get_stats))
+        .push(Router::with_path("api/admin/model-health").get(get_model_health))
+        .push(
+            Router::with_path("api/admin/runtime")
+                .get(get_runtime)
+                .post(update_runtime)
+                .delete(reset_runtime),
+        )
+        .push(Router::with_path("api/admin/requests").get(list_generations))
+        .push(Router::with_path("api/admin/requests/{id}/cancel").post(cancel_generation))
+        .push(Router::with_path("api/admin/usage").get(get_usage))
+        .push(Router::with_path("api/admin/completions").get(list_replay_candidates))
+        .push(Router::with_path("api/admin/replay").post(replay_completion))
+        .push(Router::with_path("debug/convert").post(debug_convert))
 }
@@ -0,0 +1,255 @@
+//! 聊天請求的併發上限與優先級排隊 (MAX_CONCURRENT_REQUESTS)
+//!
+//! 進行中的請求達到上限時，新請求依 API Key 的優先級（models.yaml 的 key_priority）排隊，
+//! 名額釋出時優先放行高優先級的請求，同一優先級依到達順序。隊列已滿時先捨棄最晚到達的低優先級請求，
+//! 沒有更低優先級的請求可捨棄時拒絕新請求；排隊超過 QUEUE_TIMEOUT_SECS 亦會被拒絕
+
+use crate::types::{OpenAIError, OpenAIErrorResponse, Priority};
+use salvo::http::StatusCode;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::{debug, info, warn};
+
+/// 被拒絕的請求建議的重試等待秒數
+const RETRY_AFTER_SECS: u64 = 1;
+
+struct AdmissionConfig {
+    max_concurrent: usize,
+    max_queued: usize,
+    queue_timeout: Duration,
+}
+
+static ADMISSION_CONFIG: LazyLock<Option<AdmissionConfig>> = LazyLock::new(|| {
+    let env = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+    };
+    let max_concurrent = env("MAX_CONCURRENT_REQUESTS").unwrap_or(0) as usize;
+    if max_concurrent == 0 {
+        return None;
+    }
+    let config = AdmissionConfig {
+        max_concurrent,
+        max_queued: env("MAX_QUEUED_REQUESTS").unwrap_or(100) as usize,
+        queue_timeout: Duration::from_secs(env("QUEUE_TIMEOUT_SECS").unwrap_or(60)),
+    };
+    info!(
+        "{}",
+        tr!(
+            "🚦 併發上限: {} 個請求 | 隊列上限: {} | 排隊逾時: {} 秒",
+            "🚦 Concurrency limit: {} requests | queue limit: {} | queue timeout: {}s",
+            config.max_concurrent,
+            config.max_queued,
+            config.queue_timeout.as_secs()
+        )
+    );
+    Some(config)
+});
+
+/// 排隊中的請求，收到 true 表示放行，false 表示被捨棄
+struct Waiter {
+    id: u64,
+    sender: oneshot::Sender<bool>,
+}
+
+#[derive(Default)]
+struct AdmissionState {
+    active: usize,
+    // 依優先級由高到低的隊列
+    queues: [VecDeque<Waiter>; 3],
+    next_id: u64,
+}
+
+impl AdmissionState {
+    fn queued(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// 釋出名額給優先級最高且仍在等待的請求
+    fn admit_next(&mut self) {
+        for queue in self.queues.iter_mut() {
+            while let Some(waiter) = queue.pop_front() {
+                if waiter.sender.send(true).is_ok() {
+                    self.active += 1;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+static ADMISSION_STATE: LazyLock<Mutex<AdmissionState>> =
+    LazyLock::new(|| Mutex::new(AdmissionState::default()));
+
+// 被拒絕的請求數（依優先級）
+static REJECTED: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+fn lock_state() -> std::sync::MutexGuard<'static, AdmissionState> {
+    ADMISSION_STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 佔用的併發名額，釋放時放行下一個排隊的請求
+pub(super) struct AdmissionPermit(());
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        let mut state = lock_state();
+        state.active = state.active.saturating_sub(1);
+        state.admit_next();
+    }
+}
+
+/// 請求被拒絕的原因
+pub(super) enum Rejection {
+    QueueFull,
+    QueueTimeout,
+}
+
+impl Rejection {
+    pub(super) fn status(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    pub(super) fn retry_after(&self) -> u64 {
+        RETRY_AFTER_SECS
+    }
+
+    pub(super) fn error_response(&self) -> OpenAIErrorResponse {
+        let (message, code) = match self {
+            Rejection::QueueFull => (
+                "The server is at capacity and the request queue is full. Please retry later.",
+                "queue_full",
+            ),
+            Rejection::QueueTimeout => (
+                "The request waited too long for a free slot. Please retry later.",
+                "queue_timeout",
+            ),
+        };
+        OpenAIErrorResponse {
+            error: OpenAIError {
+                message: message.to_string(),
+                r#type: "rate_limit_exceeded".to_string(),
+                code: code.to_string(),
+                param: None,
+            },
+        }
+    }
+}
+
+/// 取得併發名額，未設定併發上限時直接放行並返回 None
+pub(super) async fn acquire_admission(
+    priority: Priority,
+) -> Result<Option<AdmissionPermit>, Rejection> {
+    let Some(config) = ADMISSION_CONFIG.as_ref() else {
+        return Ok(None);
+    };
+    let tier = priority.tier();
+    let (id, receiver) = {
+        let mut state = lock_state();
+        if state.active < config.max_concurrent && state.queued() == 0 {
+            state.active += 1;
+            return Ok(Some(AdmissionPermit(())));
+        }
+        if state.queued() >= config.max_queued {
+            // 捨棄優先級較低的隊列中最晚到達的請求
+            let victim = state.queues[tier + 1..]
+                .iter_mut()
+                .rev()
+                .find_map(VecDeque::pop_back);
+            match victim {
+                Some(victim) => {
+                    debug!("🚦 隊列已滿，捨棄一個較低優先級的排隊請求");
+                    let _ = victim.sender.send(false);
+                }
+                None => {
+                    REJECTED[tier].fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "{}",
+                        tr!(
+                            "🚦 隊列已滿，拒絕請求 | 優先級: {:?}",
+                            "🚦 Queue full, request rejected | priority: {:?}",
+                            priority
+                        )
+                    );
+                    return Err(Rejection::QueueFull);
+                }
+            }
+        }
+        let (sender, receiver) = oneshot::channel();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.queues[tier].push_back(Waiter { id, sender });
+        debug!(
+            "🚦 請求排隊中 | 優先級: {:?} | 進行中: {} | 排隊: {}",
+            priority,
+            state.active,
+            state.queued()
+        );
+        (id, receiver)
+    };
+
+    match tokio::time::timeout(config.queue_timeout, receiver).await {
+        Ok(Ok(true)) => Ok(Some(AdmissionPermit(()))),
+        Ok(_) => {
+            REJECTED[tier].fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "{}",
+                tr!(
+                    "🚦 排隊中的請求被較高優先級的請求擠出 | 優先級: {:?}",
+                    "🚦 Queued request shed for higher-priority traffic | priority: {:?}",
+                    priority
+                )
+            );
+            Err(Rejection::QueueFull)
+        }
+        Err(_) => {
+            let mut state = lock_state();
+            let queue = &mut state.queues[tier];
+            match queue.iter().position(|w| w.id == id) {
+                Some(index) => {
+                    queue.remove(index);
+                }
+                // 逾時的同時已被放行，歸還名額
+                None => {
+                    state.active = state.active.saturating_sub(1);
+                    state.admit_next();
+                }
+            }
+            REJECTED[tier].fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "{}",
+                tr!(
+                    "🚦 請求排隊逾時 | 優先級: {:?}",
+                    "🚦 Request timed out in the queue | priority: {:?}",
+                    priority
+                )
+            );
+            Err(Rejection::QueueTimeout)
+        }
+    }
+}
+
+/// 排隊狀態，供 /api/admin/stats 使用；未設定併發上限時返回 None
+pub(super) fn admission_stats() -> Option<serde_json::Value> {
+    let config = ADMISSION_CONFIG.as_ref()?;
+    let state = lock_state();
+    let tiers = Priority::ALL.map(|priority| {
+        let tier = priority.tier();
+        json!({
+            "priority": priority,
+            "queued": state.queues[tier].len(),
+            "rejected": REJECTED[tier].load(Ordering::Relaxed),
+        })
+    });
+    Some(json!({
+        "max_concurrent": config.max_concurrent,
+        "active": state.active,
+        "queued": state.queued(),
+        "tiers": tiers,
+    }))
+}
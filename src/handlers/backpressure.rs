@@ -0,0 +1,398 @@
+//! 串流響應的背壓處理 (STREAM_BACKPRESSURE)
+//!
+//! 上游事件由背景任務讀取並放入有界緩衝區（STREAM_BUFFER_EVENTS 個片段），客戶端從緩衝區讀取。
+//! 客戶端讀取太慢使緩衝區滿時依設定處理：
+//! - pause（預設）：暫停讀取上游，直到客戶端讀走片段
+//! - disk：後續片段暫存到磁碟（STREAM_SPILL_DIR），超過 STREAM_SPILL_MAX_BYTES 時改為斷線
+//! - disconnect：以錯誤事件結束串流並中止上游請求
+//!
+//! 客戶端斷線時停止讀取上游。啟用 STREAM_RESUME_SECS 時生成本就在背景進行並保存所有事件，不套用背壓處理
+
+use crate::types::{OpenAIError, OpenAIErrorResponse};
+use crate::utils::format_bytes_length;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Policy {
+    Pause,
+    Disk,
+    Disconnect,
+}
+
+struct BackpressureConfig {
+    policy: Policy,
+    buffer_events: usize,
+    spill_dir: PathBuf,
+    spill_max_bytes: u64,
+}
+
+static BACKPRESSURE_CONFIG: LazyLock<BackpressureConfig> = LazyLock::new(|| {
+    let value = std::env::var("STREAM_BACKPRESSURE").unwrap_or_default();
+    let policy = match value.trim().to_lowercase().as_str() {
+        "" | "pause" => Policy::Pause,
+        "disk" => Policy::Disk,
+        "disconnect" => Policy::Disconnect,
+        other => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無效的 STREAM_BACKPRESSURE: {}，使用 pause",
+                    "⚠️ Invalid STREAM_BACKPRESSURE: {}, using pause",
+                    other
+                )
+            );
+            Policy::Pause
+        }
+    };
+    let config = BackpressureConfig {
+        policy,
+        buffer_events: std::env::var("STREAM_BUFFER_EVENTS")
+            .ok()
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .unwrap_or(64)
+            .max(1),
+        spill_dir: std::env::var("STREAM_SPILL_DIR")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| crate::utils::get_config_path("stream_spill")),
+        spill_max_bytes: std::env::var("STREAM_SPILL_MAX_BYTES")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(64 * 1024 * 1024),
+    };
+    if config.policy == Policy::Disk {
+        // 清除上次執行殘留的暫存檔
+        if let Ok(entries) = std::fs::read_dir(&config.spill_dir) {
+            for entry in entries.flatten() {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        info!(
+            "{}",
+            tr!(
+                "🚰 串流背壓: {:?} | 緩衝: {} 個片段 | 磁碟暫存上限: {} | 目錄: {}",
+                "🚰 Stream backpressure: {:?} | buffer: {} chunks | disk spill cap: {} | directory: {}",
+                config.policy,
+                config.buffer_events,
+                format_bytes_length(config.spill_max_bytes as usize),
+                config.spill_dir.display()
+            )
+        );
+    } else {
+        info!(
+            "{}",
+            tr!(
+                "🚰 串流背壓: {:?} | 緩衝: {} 個片段",
+                "🚰 Stream backpressure: {:?} | buffer: {} chunks",
+                config.policy,
+                config.buffer_events
+            )
+        );
+    }
+    config
+});
+
+/// 緩衝區已滿而暫停讀取上游的次數
+static PAUSED: AtomicU64 = AtomicU64::new(0);
+/// 開始暫存到磁碟的次數及暫存的位元組數
+static SPILLED: AtomicU64 = AtomicU64::new(0);
+static SPILLED_BYTES: AtomicU64 = AtomicU64::new(0);
+/// 因客戶端太慢而中斷的串流數
+static DISCONNECTED: AtomicU64 = AtomicU64::new(0);
+
+/// 磁碟暫存檔，片段以 4 位元組長度前綴依序寫入
+struct Spill {
+    path: PathBuf,
+    file: File,
+    write_pos: u64,
+    read_pos: u64,
+}
+
+impl Spill {
+    fn create(dir: &PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.spill", nanoid::nanoid!(16)));
+        let file = File::options()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file,
+            write_pos: 0,
+            read_pos: 0,
+        })
+    }
+
+    fn push(&mut self, text: &str) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.write_pos))?;
+        self.file.write_all(&(text.len() as u32).to_le_bytes())?;
+        self.file.write_all(text.as_bytes())?;
+        self.write_pos += 4 + text.len() as u64;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> std::io::Result<Option<String>> {
+        if self.read_pos >= self.write_pos {
+            return Ok(None);
+        }
+        self.file.seek(SeekFrom::Start(self.read_pos))?;
+        let mut len = [0u8; 4];
+        self.file.read_exact(&mut len)?;
+        let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut buf)?;
+        self.read_pos += 4 + buf.len() as u64;
+        Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+    }
+
+    fn is_drained(&self) -> bool {
+        self.read_pos >= self.write_pos
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Default)]
+struct BufferState {
+    memory: VecDeque<String>,
+    // 暫存中的片段，有內容時新片段一律寫入磁碟以保持順序
+    spill: Option<Spill>,
+    // 上游已結束
+    closed: bool,
+    // 客戶端太慢，串流以錯誤結束
+    overflowed: bool,
+    // 客戶端已斷線
+    consumer_gone: bool,
+}
+
+struct SseBuffer {
+    state: Mutex<BufferState>,
+    // 有新片段、上游結束或中斷時喚醒客戶端
+    readable: Notify,
+    // 客戶端讀走片段或斷線時喚醒上游讀取
+    writable: Notify,
+}
+
+impl SseBuffer {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BufferState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// 客戶端的讀取端，被丟棄時通知上游停止讀取
+struct Reader {
+    buffer: Arc<SseBuffer>,
+    finished: bool,
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.buffer.lock().consumer_gone = true;
+        self.buffer.writable.notify_one();
+    }
+}
+
+fn slow_consumer_error() -> String {
+    let error = OpenAIErrorResponse {
+        error: OpenAIError {
+            message: "The client did not read the stream fast enough; the stream was closed."
+                .to_string(),
+            r#type: "server_error".to_string(),
+            code: "slow_consumer".to_string(),
+            param: None,
+        },
+    };
+    format!("data: {}\n\n", serde_json::to_string(&error).unwrap())
+}
+
+/// 放入片段，返回 false 表示應停止讀取上游
+async fn push_chunk(buffer: &SseBuffer, config: &BackpressureConfig, text: String) -> bool {
+    let mut paused = false;
+    loop {
+        {
+            let mut state = buffer.lock();
+            if state.consumer_gone {
+                return false;
+            }
+            if state.spill.is_none() && state.memory.len() < config.buffer_events {
+                state.memory.push_back(text);
+                drop(state);
+                buffer.readable.notify_one();
+                return true;
+            }
+            match config.policy {
+                Policy::Pause => {
+                    if !paused {
+                        paused = true;
+                        PAUSED.fetch_add(1, Ordering::Relaxed);
+                        debug!("🚰 客戶端讀取太慢，暫停讀取上游");
+                    }
+                }
+                Policy::Disconnect => {
+                    state.overflowed = true;
+                    drop(state);
+                    buffer.readable.notify_one();
+                    return false;
+                }
+                Policy::Disk => {
+                    let result = match &mut state.spill {
+                        Some(spill) => Ok(spill),
+                        None => Spill::create(&config.spill_dir).map(|spill| {
+                            SPILLED.fetch_add(1, Ordering::Relaxed);
+                            debug!("🚰 客戶端讀取太慢，暫存到磁碟: {}", spill.path.display());
+                            state.spill.insert(spill)
+                        }),
+                    }
+                    .and_then(|spill| {
+                        if spill.write_pos + text.len() as u64 > config.spill_max_bytes {
+                            return Err(std::io::Error::other("spill cap exceeded"));
+                        }
+                        spill.push(&text)
+                    });
+                    if let Err(e) = result {
+                        debug!("🚰 磁碟暫存失敗或超過上限: {}", e);
+                        state.overflowed = true;
+                        drop(state);
+                        buffer.readable.notify_one();
+                        return false;
+                    }
+                    SPILLED_BYTES.fetch_add(text.len() as u64, Ordering::Relaxed);
+                    drop(state);
+                    buffer.readable.notify_one();
+                    return true;
+                }
+            }
+        }
+        buffer.writable.notified().await;
+    }
+}
+
+/// 取出下一個片段，None 表示目前沒有可讀的片段
+fn pop_chunk(state: &mut BufferState) -> Option<String> {
+    if let Some(text) = state.memory.pop_front() {
+        return Some(text);
+    }
+    let spill = state.spill.as_mut()?;
+    let text = match spill.pop() {
+        Ok(text) => text,
+        Err(e) => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 讀取串流磁碟暫存失敗: {}",
+                    "⚠️ Failed to read the stream disk spill: {}",
+                    e
+                )
+            );
+            state.overflowed = true;
+            None
+        }
+    };
+    if state.spill.as_ref().is_some_and(Spill::is_drained) {
+        debug!("🚰 磁碟暫存已讀完，恢復使用記憶體緩衝");
+        state.spill = None;
+    }
+    text
+}
+
+/// 以有界緩衝區連接上游與客戶端，依 STREAM_BACKPRESSURE 處理讀取太慢的客戶端
+pub(super) fn with_backpressure<S>(
+    id: &str,
+    upstream: S,
+) -> stream::BoxStream<'static, Result<String, Infallible>>
+where
+    S: Stream<Item = Result<String, Infallible>> + Send + 'static,
+{
+    let config = &*BACKPRESSURE_CONFIG;
+    let buffer = Arc::new(SseBuffer {
+        state: Mutex::new(BufferState::default()),
+        readable: Notify::new(),
+        writable: Notify::new(),
+    });
+
+    let producer = buffer.clone();
+    let id = id.to_string();
+    tokio::spawn(async move {
+        let mut upstream = Box::pin(upstream);
+        while let Some(Ok(text)) = upstream.next().await {
+            if !push_chunk(&producer, config, text).await {
+                break;
+            }
+        }
+        let mut state = producer.lock();
+        state.closed = true;
+        if state.overflowed {
+            DISCONNECTED.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "{}",
+                tr!(
+                    "🚰 客戶端讀取太慢，中斷串流 | ID: {} | 策略: {:?}",
+                    "🚰 Client reads too slowly, closing the stream | ID: {} | policy: {:?}",
+                    id,
+                    config.policy
+                )
+            );
+        }
+        drop(state);
+        producer.readable.notify_one();
+    });
+
+    let reader = Reader {
+        buffer,
+        finished: false,
+    };
+    stream::unfold(reader, |mut reader| async move {
+        if reader.finished {
+            return None;
+        }
+        loop {
+            {
+                let mut state = reader.buffer.lock();
+                if state.overflowed {
+                    drop(state);
+                    reader.finished = true;
+                    return Some((Ok(slow_consumer_error()), reader));
+                }
+                if let Some(text) = pop_chunk(&mut state) {
+                    drop(state);
+                    reader.buffer.writable.notify_one();
+                    return Some((Ok(text), reader));
+                }
+                if state.closed && !state.overflowed {
+                    return None;
+                }
+            }
+            reader.buffer.readable.notified().await;
+        }
+    })
+    .boxed()
+}
+
+/// 背壓處理的設定與計數，供 /api/admin/stats 使用
+pub(super) fn backpressure_stats() -> serde_json::Value {
+    let config = &*BACKPRESSURE_CONFIG;
+    json!({
+        "policy": format!("{:?}", config.policy).to_lowercase(),
+        "buffer_events": config.buffer_events,
+        "paused": PAUSED.load(Ordering::Relaxed),
+        "spilled": SPILLED.load(Ordering::Relaxed),
+        "spilled_bytes": SPILLED_BYTES.load(Ordering::Relaxed),
+        "disconnected": DISCONNECTED.load(Ordering::Relaxed),
+    })
+}
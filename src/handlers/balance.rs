@@ -0,0 +1,284 @@
+use super::pool::{mark_pool_token_exhausted, pool_status, pool_tokens, record_pool_balance};
+use crate::cache::get_cached_config;
+use crate::poe_client::{get_current_point_balance, shared_http_client};
+use crate::utils::get_env_secret;
+use chrono::Utc;
+use salvo::prelude::*;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// 已低於警告閾值的 Token（以遮罩後名稱記錄），避免重複告警
+static BELOW_THRESHOLD: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// 上游回報點數耗盡的次數
+static POINTS_EXHAUSTED: AtomicU64 = AtomicU64::new(0);
+
+/// 各 Token（遮罩後）最近一次點數耗盡告警的時間
+static EXHAUSTED_ALERTED: LazyLock<Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 同一 Token 點數耗盡告警的最短間隔
+const EXHAUSTED_ALERT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Serialize)]
+struct TokenBalance {
+    token: String,
+    source: String,
+    balance: Option<i64>,
+    below_threshold: bool,
+    error: Option<String>,
+}
+
+/// 點數警告閾值，0 表示不告警
+fn get_warn_threshold() -> i64 {
+    std::env::var("POE_BALANCE_WARN_THRESHOLD")
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(0)
+}
+
+/// 上游回報點數耗盡的累計次數
+pub(super) fn points_exhausted_count() -> u64 {
+    POINTS_EXHAUSTED.load(Ordering::Relaxed)
+}
+
+/// 上游回報帳戶點數耗盡：累計次數，並對同一 Token 節流後記錄告警及通知 Webhook
+pub fn report_points_exhausted(token: &str, model: &str) {
+    POINTS_EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+    mark_pool_token_exhausted(token);
+    let masked = mask_token(token);
+    let should_alert = {
+        let mut alerted = EXHAUSTED_ALERTED.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        match alerted.get(&masked) {
+            Some(last) if now.duration_since(*last) < EXHAUSTED_ALERT_INTERVAL => false,
+            _ => {
+                alerted.insert(masked.clone(), now);
+                true
+            }
+        }
+    };
+    if !should_alert {
+        debug!(
+            "🚫 Poe 帳戶點數耗盡（已告警） | Token: {} | 模型: {}",
+            masked, model
+        );
+        return;
+    }
+    error!(
+        "{}",
+        tr!(
+            "🚫 Poe 帳戶點數已耗盡，請求將以 insufficient_quota 拒絕 | Token: {} | 模型: {}",
+            "🚫 Poe account is out of points, requests are rejected with insufficient_quota | token: {} | model: {}",
+            masked,
+            model
+        )
+    );
+    send_alert(json!({
+        "event": "points_exhausted",
+        "token": masked,
+        "model": model,
+    }));
+}
+
+/// 告警 Webhook 位址 (POE_ALERT_WEBHOOK_URL)
+fn get_alert_webhook_url() -> Option<String> {
+    get_env_secret("POE_ALERT_WEBHOOK_URL")
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+}
+
+/// 於背景將告警以 JSON POST 至 Webhook，未設定時不處理
+fn send_alert(mut payload: serde_json::Value) {
+    let Some(url) = get_alert_webhook_url() else {
+        return;
+    };
+    payload["timestamp"] = Utc::now().timestamp().into();
+    tokio::spawn(async move {
+        let result = shared_http_client()
+            .post(&url)
+            .timeout(Duration::from_secs(10))
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => debug!("📣 告警 Webhook 已送出: {}", payload),
+            Err(e) => warn!(
+                "{}",
+                tr!(
+                    "⚠️ 告警 Webhook 送出失敗: {}",
+                    "⚠️ Failed to deliver alert webhook: {}",
+                    e
+                )
+            ),
+        }
+    });
+}
+
+/// 遮罩 Token，只保留首尾各 4 個字元
+pub(super) fn mask_token(token: &str) -> String {
+    let chars: Vec<char> = token.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    format!(
+        "{}...{}",
+        chars[..4].iter().collect::<String>(),
+        chars[chars.len() - 4..].iter().collect::<String>()
+    )
+}
+
+/// 收集需要查詢點數的 Token：models.yaml 的 api_token、POE_BALANCE_TOKENS 與 POE_TOKEN_POOL
+pub(super) async fn collect_tokens() -> Vec<(String, &'static str)> {
+    let mut tokens: Vec<(String, &'static str)> = Vec::new();
+    let config = get_cached_config().await;
+    if let Some(token) = config.api_token.as_ref().filter(|t| !t.trim().is_empty()) {
+        tokens.push((token.trim().to_string(), "models.yaml"));
+    }
+    // 逗號或換行分隔，方便以 POE_BALANCE_TOKENS_FILE 逐行列出
+    let env_tokens = get_env_secret("POE_BALANCE_TOKENS").unwrap_or_default();
+    for token in env_tokens
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+    {
+        if !tokens.iter().any(|(t, _)| t == token) {
+            tokens.push((token.to_string(), "POE_BALANCE_TOKENS"));
+        }
+    }
+    for token in pool_tokens() {
+        if !tokens.iter().any(|(t, _)| *t == token) {
+            tokens.push((token, "POE_TOKEN_POOL"));
+        }
+    }
+    tokens
+}
+
+/// 查詢所有 Token 的點數，並在跨越閾值時記錄告警
+async fn check_balances() -> Vec<TokenBalance> {
+    let threshold = get_warn_threshold();
+    let mut results = Vec::new();
+    for (token, source) in collect_tokens().await {
+        let masked = mask_token(&token);
+        match get_current_point_balance(&token).await {
+            Ok(balance) => {
+                record_pool_balance(&token, balance);
+                let below = threshold > 0 && balance < threshold;
+                let newly_crossed = {
+                    let mut state = BELOW_THRESHOLD.lock().unwrap_or_else(|e| e.into_inner());
+                    if below {
+                        state.insert(masked.clone())
+                    } else {
+                        state.remove(&masked);
+                        false
+                    }
+                };
+                if newly_crossed {
+                    warn!(
+                        "{}",
+                        tr!(
+                            "⚠️ Poe 帳戶點數低於警告閾值 | Token: {} | 剩餘: {} | 閾值: {}",
+                            "⚠️ Poe point balance below warning threshold | token: {} | remaining: {} | threshold: {}",
+                            masked,
+                            balance,
+                            threshold
+                        )
+                    );
+                    send_alert(json!({
+                        "event": "balance_below_threshold",
+                        "token": masked,
+                        "balance": balance,
+                        "threshold": threshold,
+                    }));
+                } else {
+                    debug!("💰 Poe 帳戶點數 | Token: {} | 剩餘: {}", masked, balance);
+                }
+                results.push(TokenBalance {
+                    token: masked,
+                    source: source.to_string(),
+                    balance: Some(balance),
+                    below_threshold: below,
+                    error: None,
+                });
+            }
+            Err(e) => {
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ 查詢 Poe 帳戶點數失敗 | Token: {} | 錯誤: {}",
+                        "❌ Failed to query Poe point balance | token: {} | error: {}",
+                        masked,
+                        e
+                    )
+                );
+                results.push(TokenBalance {
+                    token: masked,
+                    source: source.to_string(),
+                    balance: None,
+                    below_threshold: false,
+                    error: Some(e.to_string()),
+                });
+            }
+        }
+    }
+    results
+}
+
+#[handler]
+pub async fn get_balances(res: &mut Response) {
+    info!(
+        "{}",
+        tr!(
+            "💰 收到 Poe 帳戶點數查詢請求",
+            "💰 Received Poe point balance request"
+        )
+    );
+    let balances = check_balances().await;
+    res.render(Json(json!({
+        "threshold": get_warn_threshold(),
+        "points_exhausted": points_exhausted_count(),
+        "balances": balances,
+        "pool": pool_status(),
+    })));
+}
+
+/// 啟動背景點數檢查任務，間隔由 POE_BALANCE_CHECK_INTERVAL_SECS 控制（0 為停用）
+pub fn spawn_balance_monitor() {
+    let interval_secs = std::env::var("POE_BALANCE_CHECK_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(0);
+    if interval_secs == 0 {
+        info!(
+            "{}",
+            tr!(
+                "💰 Poe 帳戶點數背景檢查: 已禁用",
+                "💰 Poe point balance monitor: disabled"
+            )
+        );
+        return;
+    }
+    info!(
+        "{}",
+        tr!(
+            "💰 Poe 帳戶點數背景檢查: 每 {} 秒 | 警告閾值: {}",
+            "💰 Poe point balance monitor: every {} seconds | warning threshold: {}",
+            interval_secs,
+            get_warn_threshold()
+        )
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+            check_balances().await;
+        }
+    });
+}
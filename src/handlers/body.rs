@@ -0,0 +1,612 @@
+use crate::spool::{DataUrlSpool, SPOOL_MARKER, SpooledFiles};
+use futures_util::StreamExt;
+use salvo::http::header;
+use salvo::prelude::*;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::io::Write;
+use tracing::debug;
+
+/// JSON 巢狀層級上限，防止過深的結構
+const MAX_JSON_DEPTH: usize = 128;
+
+/// 讀取請求體時的錯誤
+#[derive(Debug)]
+pub(crate) enum BodyError {
+    /// 請求體超過 MAX_REQUEST_SIZE
+    TooLarge(usize),
+    /// 單個字串欄位超過 MAX_FIELD_SIZE
+    FieldTooLarge(usize),
+    /// 讀取過程中即可判定的 JSON 格式錯誤
+    Malformed { offset: usize, reason: &'static str },
+    /// 讀取請求體失敗
+    Read(std::io::Error),
+    /// 不支援的 Content-Encoding
+    UnsupportedEncoding(String),
+    /// 解壓縮失敗
+    Decode(std::io::Error),
+    /// 完整 JSON 反序列化失敗
+    Parse(serde_json::Error),
+    /// 附件寫入臨時檔案失敗
+    Spool(std::io::Error),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "請求大小超過限制 ({} bytes)", limit),
+            BodyError::FieldTooLarge(limit) => write!(f, "單個欄位大小超過限制 ({} bytes)", limit),
+            BodyError::Malformed { offset, reason } => {
+                write!(f, "JSON 格式錯誤 (位置 {}): {}", offset, reason)
+            }
+            BodyError::Read(e) => write!(f, "讀取請求體失敗: {}", e),
+            BodyError::UnsupportedEncoding(encoding) => write!(
+                f,
+                "不支援的 Content-Encoding: {}（可用: gzip, deflate, br）",
+                encoding
+            ),
+            BodyError::Decode(e) => write!(f, "請求體解壓縮失敗: {}", e),
+            BodyError::Parse(e) => write!(f, "JSON 解析失敗: {}", e),
+            BodyError::Spool(e) => write!(f, "附件暫存失敗: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+/// 增量 JSON 結構掃描器
+/// 逐塊檢查括號配對、字串、非法字元及欄位長度，讓格式錯誤或過大的請求在讀完前即被拒絕；
+/// 完整語法仍由 serde_json 在最後驗證
+struct JsonScanner {
+    stack: Vec<u8>,
+    started: bool,
+    finished: bool,
+    in_string: bool,
+    escape: bool,
+    string_len: usize,
+    max_field_size: usize,
+    offset: usize,
+}
+
+impl JsonScanner {
+    fn new(max_field_size: usize) -> Self {
+        Self {
+            stack: Vec::new(),
+            started: false,
+            finished: false,
+            in_string: false,
+            escape: false,
+            string_len: 0,
+            max_field_size,
+            offset: 0,
+        }
+    }
+
+    fn malformed(&self, reason: &'static str) -> BodyError {
+        BodyError::Malformed {
+            offset: self.offset,
+            reason,
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Result<(), BodyError> {
+        for &b in chunk {
+            self.step(b)?;
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, b: u8) -> Result<(), BodyError> {
+        if self.in_string {
+            self.string_len += 1;
+            if self.max_field_size > 0 && self.string_len > self.max_field_size {
+                return Err(BodyError::FieldTooLarge(self.max_field_size));
+            }
+            if self.escape {
+                self.escape = false;
+            } else if b == b'\\' {
+                self.escape = true;
+            } else if b == b'"' {
+                self.in_string = false;
+            } else if b < 0x20 {
+                return Err(self.malformed("字串中包含未轉義的控制字元"));
+            }
+        } else if !b.is_ascii_whitespace() {
+            if self.finished {
+                return Err(self.malformed("JSON 結束後仍有多餘內容"));
+            }
+            if !self.started {
+                if b != b'{' {
+                    return Err(self.malformed("請求體必須是 JSON 物件"));
+                }
+                self.started = true;
+            }
+            match b {
+                b'{' | b'[' => {
+                    if self.stack.len() >= MAX_JSON_DEPTH {
+                        return Err(self.malformed("JSON 巢狀層級過深"));
+                    }
+                    self.stack.push(b);
+                }
+                b'}' | b']' => {
+                    let open = if b == b'}' { b'{' } else { b'[' };
+                    if self.stack.pop() != Some(open) {
+                        return Err(self.malformed("括號不匹配"));
+                    }
+                    if self.stack.is_empty() {
+                        self.finished = true;
+                    }
+                }
+                b'"' => {
+                    self.in_string = true;
+                    self.string_len = 0;
+                }
+                b':' | b',' | b'-' | b'+' | b'.' | b'0'..=b'9' => {}
+                // true / false / null 及數字指數
+                b'a' | b'e' | b'f' | b'l' | b'n' | b'r' | b's' | b't' | b'u' | b'E' => {}
+                _ => return Err(self.malformed("無效的字元")),
+            }
+        }
+        self.offset += 1;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), BodyError> {
+        if !self.finished {
+            return Err(self.malformed("JSON 不完整"));
+        }
+        Ok(())
+    }
+}
+
+/// 接收（解壓縮後的）請求體：檢查大小與 JSON 結構後累積
+/// 啟用暫存時，超過門檻的 `"url"` base64 data URL 改為逐塊解碼寫入臨時檔案，不累積在記憶體中
+struct JsonSink {
+    scanner: JsonScanner,
+    buffer: Vec<u8>,
+    max_size: usize,
+    /// 已接收的位元組數（含已寫入臨時檔案的部分）
+    received: usize,
+    /// data URL 暫存門檻，0 表示不暫存
+    spool_threshold: usize,
+    /// 目前字串內容在 buffer 中的起點，超過門檻後即不再記錄
+    string_start: Option<usize>,
+    spooling: Option<DataUrlSpool>,
+    spooled: SpooledFiles,
+}
+
+impl JsonSink {
+    fn new(
+        max_field_size: usize,
+        capacity: usize,
+        max_size: usize,
+        spool_threshold: usize,
+    ) -> Self {
+        Self {
+            scanner: JsonScanner::new(max_field_size),
+            buffer: Vec::with_capacity(capacity),
+            max_size,
+            received: 0,
+            spool_threshold,
+            string_start: None,
+            spooling: None,
+            spooled: SpooledFiles::default(),
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) -> Result<(), BodyError> {
+        self.received += data.len();
+        if self.received > self.max_size {
+            return Err(BodyError::TooLarge(self.max_size));
+        }
+        if self.spool_threshold == 0 {
+            self.scanner.feed(data)?;
+            self.buffer.extend_from_slice(data);
+            return Ok(());
+        }
+        for &b in data {
+            let was_in_string = self.scanner.in_string;
+            self.scanner.step(b)?;
+            if let Some(spool) = &mut self.spooling {
+                let result = if self.scanner.in_string {
+                    spool.push(b)
+                } else {
+                    // 字串結束，寫入剩餘內容後保留結尾的引號
+                    self.spooling.take().map_or(Ok(()), DataUrlSpool::finish)
+                };
+                result.map_err(|e| self.spool_error(e))?;
+                if self.scanner.in_string {
+                    continue;
+                }
+            }
+            self.buffer.push(b);
+            if !self.scanner.in_string {
+                self.string_start = None;
+            } else if !was_in_string {
+                self.string_start = Some(self.buffer.len());
+            } else if let Some(start) = self.string_start
+                && self.buffer.len() - start > self.spool_threshold
+            {
+                self.string_start = None;
+                self.start_spool(start)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 字串超過門檻時，若為 `"url"` 欄位的 base64 data URL，將已累積的內容移入臨時檔案
+    /// buffer 中改為 `data:<MIME>;spool,<ID>` 引用
+    fn start_spool(&mut self, start: usize) -> Result<(), BodyError> {
+        let is_url_value = self.buffer[..start - 1]
+            .trim_ascii_end()
+            .strip_suffix(b":")
+            .is_some_and(|key| key.trim_ascii_end().ends_with(b"\"url\""));
+        let content = &self.buffer[start..];
+        if !is_url_value || !content.starts_with(b"data:") {
+            return Ok(());
+        }
+        let Some(marker) = content[..content.len().min(256)]
+            .windows(8)
+            .position(|w| w == b";base64,")
+        else {
+            return Ok(());
+        };
+        // MIME 類型仍是 JSON 原文，處理常見的 `\/` 跳脫
+        let mime_type = String::from_utf8_lossy(&content[5..marker]).replace("\\/", "/");
+        let mut spool =
+            DataUrlSpool::create(&mut self.spooled, &mime_type).map_err(BodyError::Spool)?;
+        for &b in &self.buffer[start + marker + 8..] {
+            spool.push(b).map_err(|e| self.spool_error(e))?;
+        }
+        self.buffer.truncate(start + marker);
+        self.buffer.extend_from_slice(SPOOL_MARKER.as_bytes());
+        self.buffer.extend_from_slice(spool.id().as_bytes());
+        self.spooling = Some(spool);
+        Ok(())
+    }
+
+    fn spool_error(&self, e: std::io::Error) -> BodyError {
+        if e.kind() == std::io::ErrorKind::InvalidData {
+            self.scanner.malformed("data URL 的 base64 內容無效")
+        } else {
+            BodyError::Spool(e)
+        }
+    }
+}
+
+// 供解壓縮器寫入，超過限制時立即中止解壓縮
+impl Write for JsonSink {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.push(data).map_err(std::io::Error::other)?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 依 Content-Encoding 選擇的解碼器
+enum BodyDecoder {
+    Identity(JsonSink),
+    Gzip(flate2::write::GzDecoder<JsonSink>),
+    Deflate(flate2::write::ZlibDecoder<JsonSink>),
+    Brotli(Box<brotli::DecompressorWriter<JsonSink>>),
+}
+
+/// 取回寫入 JsonSink 時產生的 BodyError，其餘視為解壓縮失敗
+fn decode_error(e: std::io::Error) -> BodyError {
+    if !e.get_ref().is_some_and(|inner| inner.is::<BodyError>()) {
+        return BodyError::Decode(e);
+    }
+    *e.into_inner()
+        .and_then(|inner| inner.downcast::<BodyError>().ok())
+        .expect("已確認為 BodyError")
+}
+
+impl BodyDecoder {
+    fn new(encoding: Option<&str>, sink: JsonSink) -> Result<Self, BodyError> {
+        let encoding = encoding
+            .map(|e| e.trim().to_lowercase())
+            .unwrap_or_default();
+        Ok(match encoding.as_str() {
+            "" | "identity" => BodyDecoder::Identity(sink),
+            "gzip" | "x-gzip" => BodyDecoder::Gzip(flate2::write::GzDecoder::new(sink)),
+            "deflate" => BodyDecoder::Deflate(flate2::write::ZlibDecoder::new(sink)),
+            "br" => BodyDecoder::Brotli(Box::new(brotli::DecompressorWriter::new(sink, 8192))),
+            _ => return Err(BodyError::UnsupportedEncoding(encoding)),
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), BodyError> {
+        match self {
+            BodyDecoder::Identity(sink) => sink.push(data),
+            BodyDecoder::Gzip(decoder) => decoder.write_all(data).map_err(decode_error),
+            BodyDecoder::Deflate(decoder) => decoder.write_all(data).map_err(decode_error),
+            BodyDecoder::Brotli(decoder) => decoder.write_all(data).map_err(decode_error),
+        }
+    }
+
+    fn finish(self) -> Result<JsonSink, BodyError> {
+        match self {
+            BodyDecoder::Identity(sink) => Ok(sink),
+            BodyDecoder::Gzip(decoder) => decoder.finish().map_err(decode_error),
+            BodyDecoder::Deflate(decoder) => decoder.finish().map_err(decode_error),
+            BodyDecoder::Brotli(mut decoder) => {
+                decoder.close().map_err(decode_error)?;
+                decoder
+                    .into_inner()
+                    .map_err(|_| BodyError::Decode(std::io::Error::other("brotli 資料不完整")))
+            }
+        }
+    }
+}
+
+/// 解壓縮後的請求體大小上限 (MAX_DECOMPRESSED_SIZE)，未設定時與 MAX_REQUEST_SIZE 相同
+fn max_decompressed_size(max_size: usize) -> usize {
+    std::env::var("MAX_DECOMPRESSED_SIZE")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(max_size)
+}
+
+/// 逐塊讀取請求體並解析為 JSON，支援 gzip、deflate、br 壓縮的請求體
+/// 超過大小限制或格式錯誤時立即停止讀取；原始位元組在解析後即釋放，不會保留在 Request 中
+pub(crate) async fn read_json_body<T: DeserializeOwned>(
+    req: &mut Request,
+    max_size: usize,
+    max_field_size: usize,
+) -> Result<T, BodyError> {
+    read_body(req, max_size, max_field_size, 0)
+        .await
+        .map(|(value, _)| value)
+}
+
+/// 同 read_json_body，但大型 base64 data URL 會在讀取時寫入臨時檔案 (ATTACHMENT_SPOOL_SIZE)
+/// 返回的 SpooledFiles 需保留至附件上傳完成，釋放時刪除未使用的暫存檔案
+pub(crate) async fn read_json_body_spooled<T: DeserializeOwned>(
+    req: &mut Request,
+    max_size: usize,
+    max_field_size: usize,
+) -> Result<(T, SpooledFiles), BodyError> {
+    read_body(
+        req,
+        max_size,
+        max_field_size,
+        crate::spool::spool_threshold(),
+    )
+    .await
+}
+
+async fn read_body<T: DeserializeOwned>(
+    req: &mut Request,
+    max_size: usize,
+    max_field_size: usize,
+    spool_threshold: usize,
+) -> Result<(T, SpooledFiles), BodyError> {
+    // 依 Content-Length 提前拒絕
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if let Some(length) = content_length
+        && length > max_size
+    {
+        return Err(BodyError::TooLarge(max_size));
+    }
+
+    let encoding = req
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let compressed = encoding
+        .as_deref()
+        .is_some_and(|e| !matches!(e.trim().to_lowercase().as_str(), "" | "identity"));
+    let sink = JsonSink::new(
+        max_field_size,
+        content_length.unwrap_or(0).min(1024 * 1024),
+        if compressed {
+            max_decompressed_size(max_size)
+        } else {
+            max_size
+        },
+        spool_threshold,
+    );
+    let mut decoder = BodyDecoder::new(encoding.as_deref(), sink)?;
+
+    let mut body = req.take_body();
+    let mut received = 0;
+    while let Some(frame) = body.next().await {
+        let Ok(data) = frame.map_err(BodyError::Read)?.into_data() else {
+            continue;
+        };
+        received += data.len();
+        if received > max_size {
+            return Err(BodyError::TooLarge(max_size));
+        }
+        decoder.write(&data)?;
+    }
+    let sink = decoder.finish()?;
+    sink.scanner.finish()?;
+    debug!(
+        "📥 請求體讀取完成 | 大小: {} bytes | 傳輸大小: {} bytes | 暫存附件: {} bytes | 編碼: {}",
+        sink.received,
+        received,
+        sink.received.saturating_sub(sink.buffer.len()),
+        encoding.as_deref().unwrap_or("identity")
+    );
+
+    let value = serde_json::from_slice::<T>(&sink.buffer).map_err(BodyError::Parse)?;
+    Ok((value, sink.spooled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::prelude::*;
+
+    const THRESHOLD: usize = 4097;
+    const PREFIX: &str = "data:image/png;base64,";
+
+    fn image_request(url: &str) -> Vec<u8> {
+        format!(
+            r#"{{"messages":[{{"role":"user","content":[{{"type":"image_url","image_url":{{"url":"{}"}}}}]}}]}}"#,
+            url
+        )
+        .into_bytes()
+    }
+
+    fn sample_image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    fn spool_sink() -> JsonSink {
+        JsonSink::new(0, 0, usize::MAX, THRESHOLD)
+    }
+
+    fn parsed_url(sink: &JsonSink) -> String {
+        let value: serde_json::Value = serde_json::from_slice(&sink.buffer).unwrap();
+        value["messages"][0]["content"][0]["image_url"]["url"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// 取出暫存的附件並返回 (MIME 類型, 檔案內容, 哈希)
+    fn take_spooled(url: &str) -> (String, Vec<u8>, String) {
+        let (mime_type, id) = url
+            .strip_prefix("data:")
+            .and_then(|rest| rest.split_once(SPOOL_MARKER))
+            .expect("應為暫存引用");
+        let attachment = crate::spool::take(id).expect("暫存附件應已完成");
+        let data = std::fs::read(&attachment.path).unwrap();
+        std::fs::remove_file(&attachment.path).unwrap();
+        (mime_type.to_string(), data, attachment.hash)
+    }
+
+    #[test]
+    fn scanner_handles_escapes_split_across_chunks() {
+        let mut scanner = JsonScanner::new(0);
+        scanner.feed(br#"{"a":"x\"#).unwrap();
+        scanner.feed(br#""}\\"#).unwrap();
+        assert!(scanner.in_string);
+        scanner.feed(br#""}"#).unwrap();
+        scanner.finish().unwrap();
+
+        let mut scanner = JsonScanner::new(0);
+        assert!(matches!(
+            scanner.feed(b"{\"a\":\"x\ny\"}"),
+            Err(BodyError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn data_url_split_across_chunks_is_spooled() {
+        let image = sample_image(6000);
+        let encoded = BASE64_STANDARD.encode(&image);
+        let url = format!("{}{}", PREFIX, encoded);
+        let mut sink = spool_sink();
+        for chunk in image_request(&url).chunks(7) {
+            sink.push(chunk).unwrap();
+        }
+        sink.scanner.finish().unwrap();
+        assert_eq!(sink.spooled.encoded_bytes(), encoded.len() as u64);
+
+        let spooled_url = parsed_url(&sink);
+        let (mime_type, data, hash) = take_spooled(&spooled_url);
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(data, image);
+        assert_eq!(hash, crate::utils::hash_base64_content(&url));
+    }
+
+    #[test]
+    fn escaped_data_url_is_spooled() {
+        let image = sample_image(6000);
+        let encoded = BASE64_STANDARD.encode(&image);
+        assert!(encoded.contains('/'));
+        // 部分編碼器跳脫 `/` 並每 76 個字元插入換行
+        let escaped = encoded
+            .as_bytes()
+            .chunks(76)
+            .map(|line| String::from_utf8_lossy(line).replace('/', "\\/"))
+            .collect::<Vec<_>>()
+            .join("\\n");
+        let mut sink = spool_sink();
+        sink.push(&image_request(&format!(
+            "data:image\\/png;base64,{}",
+            escaped
+        )))
+        .unwrap();
+
+        let (mime_type, data, _) = take_spooled(&parsed_url(&sink));
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(data, image);
+    }
+
+    #[test]
+    fn spool_threshold_boundary() {
+        // 正好等於門檻時保留在記憶體中
+        let url = format!("{}{}", PREFIX, "A".repeat(THRESHOLD - PREFIX.len()));
+        let mut sink = spool_sink();
+        sink.push(&image_request(&url)).unwrap();
+        assert_eq!(parsed_url(&sink), url);
+        assert!(sink.spooled.is_empty());
+
+        // 超過門檻一個位元組時暫存
+        let encoded = "A".repeat(THRESHOLD + 1 - PREFIX.len());
+        let mut sink = spool_sink();
+        sink.push(&image_request(&format!("{}{}", PREFIX, encoded)))
+            .unwrap();
+        let (_, data, _) = take_spooled(&parsed_url(&sink));
+        assert_eq!(data, BASE64_STANDARD.decode(&encoded).unwrap());
+    }
+
+    #[test]
+    fn non_url_fields_are_not_spooled() {
+        let text = format!("{}{}", PREFIX, "A".repeat(THRESHOLD * 2));
+        let body = format!(r#"{{"messages":[{{"role":"user","content":"{}"}}]}}"#, text);
+        let mut sink = spool_sink();
+        sink.push(body.as_bytes()).unwrap();
+        assert!(sink.spooled.is_empty());
+        assert_eq!(sink.buffer, body.as_bytes());
+    }
+
+    fn decode_compressed(encoding: &str, compressed: &[u8]) -> JsonSink {
+        let mut decoder = BodyDecoder::new(Some(encoding), spool_sink()).unwrap();
+        for chunk in compressed.chunks(97) {
+            decoder.write(chunk).unwrap();
+        }
+        decoder.finish().unwrap()
+    }
+
+    #[test]
+    fn compressed_bodies_are_spooled() {
+        let image = sample_image(6000);
+        let body = image_request(&format!("{}{}", PREFIX, BASE64_STANDARD.encode(&image)));
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&body).unwrap();
+        let sink = decode_compressed("gzip", &gzip.finish().unwrap());
+        assert_eq!(take_spooled(&parsed_url(&sink)).1, image);
+
+        let mut br = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        br.write_all(&body).unwrap();
+        let sink = decode_compressed("br", &br.into_inner());
+        assert_eq!(take_spooled(&parsed_url(&sink)).1, image);
+    }
+
+    #[test]
+    fn compressed_body_over_limit_is_rejected() {
+        let body = image_request(&"A".repeat(10_000));
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&body).unwrap();
+        let mut decoder =
+            BodyDecoder::new(Some("gzip"), JsonSink::new(0, 0, 1000, THRESHOLD)).unwrap();
+        let result = decoder
+            .write(&gzip.finish().unwrap())
+            .and_then(|_| decoder.finish().map(|_| ()));
+        assert!(matches!(result, Err(BodyError::TooLarge(1000))));
+    }
+}
@@ -1,10 +1,38 @@
+use super::admission::{AdmissionPermit, acquire_admission};
+use super::backpressure::with_backpressure;
+use super::balance::mask_token;
+use super::body::{BodyError, read_json_body_spooled};
+use super::coalesce::{Coalesced, coalesce, coalesce_key, render_shared};
+use super::health::{report_model_error, track_model_health};
+use super::inflight::track_generation;
+use super::pool::select_upstream_token;
+use super::resume::{make_resumable, resume_enabled, resume_stream};
+use super::scope::RequestScope;
+use super::stats::InFlight;
 use crate::cache::get_cached_config;
-use crate::evert::{EventContext, EventHandlerManager};
-use crate::poe_client::{PoeClientWrapper, create_chat_request};
+use crate::evert::{
+    EventContext, EventHandlerManager, mark_rewriting_model, replace_response_mode_for,
+    suggested_replies_enabled,
+};
+use crate::filter::get_content_filter;
+use crate::history::{Truncation, summarize_history, truncate_history};
+use crate::image_cache::{cached_image_response, image_cache_key, record_image_response};
+use crate::media::{MediaOutput, prepare_attachment};
+use crate::output_limit::{OutputLimit, limit_output};
+use crate::pipeline::{Pipeline, StageOutput};
+use crate::poe_client::{
+    PoeClientWrapper, apply_conversation_ids, conversation_ids_enabled, create_chat_request,
+};
+use crate::script::get_script_hooks;
+use crate::server_tools::ServerTools;
+use crate::store::{PendingStore, PendingTurn, owner_hash};
+use crate::tool_schema::StrictToolSchemas;
 use crate::types::*;
+use crate::usage::UsageKey;
 use crate::utils::{
-    convert_poe_error_to_openai, count_completion_tokens, count_message_tokens,
-    format_bytes_length, format_duration, process_message_images,
+    CONTENT_REWRITTEN_MESSAGE, convert_poe_error_to_openai, count_completion_tokens,
+    count_message_tokens, count_tool_call_tokens, format_bytes_length, format_duration,
+    is_insufficient_points, process_message_images,
 };
 use chrono::Utc;
 use futures_util::future::{self};
@@ -17,19 +45,23 @@ use salvo::prelude::*;
 use serde_json::json;
 use std::collections::HashMap;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 
 #[handler]
-pub async fn chat_completions(req: &mut Request, res: &mut Response) {
+pub async fn chat_completions(req: &mut Request, depot: &mut Depot, res: &mut Response) {
     let start_time = Instant::now();
-    info!("📝 收到新的聊天完成請求");
-
-    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
-        .unwrap_or_else(|_| "1073741824".to_string())
-        .parse()
-        .unwrap_or(1024 * 1024 * 1024);
+    let _in_flight = InFlight::request();
+    let client_ip = crate::handlers::get_client_ip(depot);
+    info!(
+        "{}",
+        tr!(
+            "📝 收到新的聊天完成請求 | 客戶端 IP: {}",
+            "📝 New chat completion request | client IP: {}",
+            client_ip
+        )
+    );
 
     // 從緩存獲取 models.yaml 配置
     let config = get_cached_config().await;
@@ -43,104 +75,283 @@ pub async fn chat_completions(req: &mut Request, res: &mut Response) {
                 debug!("🔑 驗證令牌長度: {}", stripped.len());
                 stripped.to_string()
             } else {
-                error!("❌ 無效的授權格式");
+                error!(
+                    "{}",
+                    tr!("❌ 無效的授權格式", "❌ Invalid Authorization format")
+                );
                 res.status_code(StatusCode::UNAUTHORIZED);
                 res.render(Json(json!({ "error": "無效的 Authorization" })));
                 return;
             }
         }
         None => {
-            error!("❌ 缺少授權標頭");
+            error!(
+                "{}",
+                tr!("❌ 缺少授權標頭", "❌ Missing Authorization header")
+            );
             res.status_code(StatusCode::UNAUTHORIZED);
             res.render(Json(json!({ "error": "缺少 Authorization" })));
             return;
         }
     };
 
-    // 解析請求體
-    let chat_request = match req.payload_with_max_size(max_size).await {
-        Ok(bytes) => match serde_json::from_slice::<ChatCompletionRequest>(bytes) {
-            Ok(req) => {
-                debug!(
-                    "📊 請求解析成功 | 模型: {} | 訊息數量: {} | 是否串流: {:?}",
-                    req.model,
-                    req.messages.len(),
-                    req.stream
+    // 帶有 Last-Event-ID 的重新連線，從中斷處續傳先前的串流
+    if resume_enabled()
+        && let Some(last_event_id) = req
+            .headers()
+            .get("Last-Event-ID")
+            .and_then(|v| v.to_str().ok())
+    {
+        match resume_stream(last_event_id, &owner_hash(&access_key)) {
+            Some(stream) => {
+                info!(
+                    "{}",
+                    tr!(
+                        "🔁 續傳串流 | Last-Event-ID: {}",
+                        "🔁 Resuming stream | Last-Event-ID: {}",
+                        last_event_id
+                    )
                 );
-                req
+                res.headers_mut()
+                    .insert(header::CONTENT_TYPE, "text/event-stream".parse().unwrap());
+                res.headers_mut()
+                    .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+                res.stream(stream);
             }
-            Err(e) => {
-                error!("❌ JSON 解析失敗: {}", e);
-                res.status_code(StatusCode::BAD_REQUEST);
+            None => {
+                res.status_code(StatusCode::NOT_FOUND);
                 res.render(Json(OpenAIErrorResponse {
                     error: OpenAIError {
-                        message: format!("JSON 解析失敗: {}", e),
+                        message: format!(
+                            "No resumable stream found for Last-Event-ID {}; it may have expired.",
+                            last_event_id
+                        ),
                         r#type: "invalid_request_error".to_string(),
-                        code: "parse_error".to_string(),
+                        code: "stream_not_found".to_string(),
                         param: None,
                     },
                 }));
-                return;
             }
-        },
-        Err(e) => {
-            error!("❌ 請求大小超過限制或讀取失敗: {}", e);
-            res.status_code(StatusCode::PAYLOAD_TOO_LARGE);
-            res.render(Json(OpenAIErrorResponse {
-                error: OpenAIError {
-                    message: format!("請求大小超過限制 ({} bytes) 或讀取失敗: {}", max_size, e),
-                    r#type: "invalid_request_error".to_string(),
-                    code: "payload_too_large".to_string(),
-                    param: None,
-                },
-            }));
+        }
+        return;
+    }
+
+    let scope = RequestScope::from_request(req);
+    let conversation_header = req
+        .headers()
+        .get("X-Conversation-Id")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+
+    let Some(mut chat_request) = read_chat_request(req, res).await else {
+        return;
+    };
+
+    let (display_model, original_model) = resolve_model(&config, &chat_request.model);
+    // 組織或專案限制了可用的模型時，與 OpenAI 相同以模型不存在回應
+    if !scope.allows_model(&config, &[&display_model, &original_model]) {
+        warn!(
+            "{}",
+            tr!(
+                "🏢 模型不在請求範圍允許的列表中 | 模型: {} | 組織: {} | 專案: {}",
+                "🏢 Model is not allowed for the request scope | model: {} | organization: {} | project: {}",
+                display_model,
+                scope.organization.as_deref().unwrap_or("-"),
+                scope.project.as_deref().unwrap_or("-")
+            )
+        );
+        res.status_code(StatusCode::NOT_FOUND);
+        res.render(Json(OpenAIErrorResponse {
+            error: OpenAIError {
+                message: format!(
+                    "The model `{}` does not exist or you do not have access to it.",
+                    chat_request.model
+                ),
+                r#type: "invalid_request_error".to_string(),
+                code: "model_not_found".to_string(),
+                param: Some("model".to_string()),
+            },
+        }));
+        return;
+    }
+    if !apply_param_policy(&config, &original_model, &mut chat_request, res) {
+        return;
+    }
+    apply_stream_policy(&config, &original_model, &mut chat_request);
+    if !apply_size_limits(&config, &original_model, &chat_request, res) {
+        return;
+    }
+    info!(
+        "{}",
+        tr!(
+            "🤖 使用模型: {} (原始: {})",
+            "🤖 Model: {} (original: {})",
+            display_model,
+            original_model
+        )
+    );
+
+    // 併發已滿時依 API Key 的優先級排隊，名額隨響應結束釋放
+    let priority = config
+        .key_priority
+        .as_ref()
+        .and_then(|keys| keys.get(&access_key).copied())
+        .unwrap_or_default();
+    let permit = match acquire_admission(priority).await {
+        Ok(permit) => permit,
+        Err(rejection) => {
+            res.status_code(rejection.status());
+            res.headers_mut().insert(
+                header::RETRY_AFTER,
+                rejection.retry_after().to_string().parse().unwrap(),
+            );
+            res.render(Json(rejection.error_response()));
             return;
         }
     };
 
-    // 尋找映射的原始模型名稱
-    let (display_model, original_model) = if config.enable.unwrap_or(false) {
-        let requested_model = chat_request.model.clone();
-        // 檢查當前請求的模型是否是某個映射的目標
-        let mapping_entry = config.models.iter().find(|(_, cfg)| {
-            if let Some(mapping) = &cfg.mapping {
-                mapping.to_lowercase() == requested_model.to_lowercase()
-            } else {
-                false
-            }
-        });
-        if let Some((original_name, _)) = mapping_entry {
-            // 如果找到映射，使用原始模型名稱
-            debug!("🔄 反向模型映射: {} -> {}", requested_model, original_name);
-            (requested_model, original_name.clone())
-        } else {
-            // 如果沒找到映射，檢查是否有直接映射配置
-            if let Some(model_config) = config.models.get(&requested_model) {
-                if let Some(mapped_name) = &model_config.mapping {
-                    debug!("🔄 直接模型映射: {} -> {}", requested_model, mapped_name);
-                    (requested_model.clone(), requested_model)
-                } else {
-                    // 沒有映射配置，使用原始名稱
-                    (requested_model.clone(), requested_model)
-                }
-            } else {
-                // 完全沒有相關配置，使用原始名稱
-                (requested_model.clone(), requested_model)
+    // 相同的非串流請求進行中時直接共享其回應，不再呼叫 Poe；
+    // 等待中的請求同樣經過速率限制並佔用併發名額，直到取得共享的回應
+    let coalesce_leader = if config.coalesce(&access_key) && !chat_request.stream.unwrap_or(false) {
+        let key = coalesce_key(&access_key, &original_model, &chat_request);
+        match coalesce(key).await {
+            Coalesced::Leader(leader) => Some(leader),
+            Coalesced::Shared(shared) => {
+                render_shared(&shared, res);
+                return;
             }
         }
     } else {
-        // 配置未啟用，直接使用原始名稱
-        (chat_request.model.clone(), chat_request.model.clone())
+        None
     };
-    info!("🤖 使用模型: {} (原始: {})", display_model, original_model);
 
-    // 創建客戶端
-    let client = PoeClientWrapper::new(&original_model, &access_key);
+    // 創建客戶端，範圍綁定了 Token 或使用 Token 池的金鑰時改以對應的 Token 連線 Poe
+    let upstream_key = scope
+        .upstream_token(&config, &access_key)
+        .or_else(|| select_upstream_token(&access_key));
+    let client = PoeClientWrapper::new(
+        &original_model,
+        upstream_key.as_deref().unwrap_or(&access_key),
+    );
+    // 請求的 user 與 metadata 作為標籤記錄在日誌與用量統計中
+    if chat_request.user.is_some() || chat_request.metadata.is_some() {
+        info!(
+            "{}",
+            tr!(
+                "🏷️ 請求標籤 | user: {} | metadata: {}",
+                "🏷️ Request tags | user: {} | metadata: {}",
+                chat_request.user.as_deref().unwrap_or("-"),
+                chat_request
+                    .metadata
+                    .as_ref()
+                    .map(|m| serde_json::to_string(m).unwrap_or_default())
+                    .unwrap_or_else(|| "-".to_string())
+            )
+        );
+    }
+    if !scope.is_empty() {
+        info!(
+            "{}",
+            tr!(
+                "🏢 請求範圍 | 組織: {} | 專案: {}",
+                "🏢 Request scope | organization: {} | project: {}",
+                scope.organization.as_deref().unwrap_or("-"),
+                scope.project.as_deref().unwrap_or("-")
+            )
+        );
+    }
+    let usage_key = UsageKey::new(
+        owner_hash(&access_key),
+        mask_token(&access_key),
+        &display_model,
+    )
+    .map(|key| {
+        key.with_tags(chat_request.user.as_deref(), chat_request.metadata.as_ref())
+            .with_scope(scope.organization.as_deref(), scope.project.as_deref())
+    });
+
+    // store=true 或關聯對話時，在處理附件前保留原始訊息
+    let conversation_id = chat_request
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get("conversation_id").cloned())
+        .or_else(|| conversation_header.clone())
+        .filter(|id| !id.trim().is_empty());
+    let store_requested = chat_request.store.unwrap_or(false);
+    let original_messages = if store_requested || conversation_id.is_some() {
+        let mut messages = serde_json::to_value(&chat_request.messages).unwrap_or_default();
+        // 記錄中保存附件內容本身，而非請求結束後即失效的暫存引用
+        if !chat_request.spooled.is_empty() {
+            crate::spool::resolve_refs(&mut messages);
+        }
+        messages
+    } else {
+        serde_json::Value::Null
+    };
+    let pending_store = store_requested.then(|| {
+        debug!("💾 請求要求儲存聊天完成記錄");
+        Arc::new(PendingStore {
+            owner: owner_hash(&access_key),
+            metadata: chat_request.metadata.clone().unwrap_or_default(),
+            messages: original_messages.clone(),
+        })
+    });
+    let pending_turn = conversation_id.map(|id| {
+        debug!("💬 請求關聯對話 | ID: {}", id);
+        Arc::new(PendingTurn::new(
+            owner_hash(&access_key),
+            id,
+            &original_messages,
+        ))
+    });
 
     // 處理消息中的image_url
-    let mut messages = chat_request.messages.clone();
+    // 移出消息而非複製，避免大型附件在記憶體中保留兩份
+    let mut messages = std::mem::take(&mut chat_request.messages);
+
+    // 對話歷史超出模型上下文長度時，在上傳附件前先移除或摘要較舊的訊息
+    let truncation = fit_history(
+        &config,
+        &original_model,
+        &chat_request,
+        &mut messages,
+        upstream_key.as_deref().unwrap_or(&access_key),
+    )
+    .await;
+    if let Some(truncation) = truncation {
+        info!(
+            "{}",
+            tr!(
+                "✂️ 對話歷史超出上下文長度 | 摘要訊息: {} | 移除訊息: {} | 裁剪文字: {} | 處理前: {} tokens",
+                "✂️ History exceeds the context window | summarized messages: {} | dropped messages: {} | trimmed: {} | before: {} tokens",
+                truncation.summarized,
+                truncation.dropped,
+                truncation.trimmed,
+                truncation.tokens_before
+            )
+        );
+        if truncation.dropped > 0 || truncation.trimmed {
+            res.headers_mut().insert(
+                "X-History-Truncated",
+                truncation.dropped.to_string().parse().unwrap(),
+            );
+        }
+        if truncation.summarized > 0 {
+            res.headers_mut().insert(
+                "X-History-Summarized",
+                truncation.summarized.to_string().parse().unwrap(),
+            );
+        }
+    }
+
     if let Err(e) = process_message_images(&client, &mut messages).await {
-        error!("❌ 處理文件上傳失敗: {}", e);
+        error!(
+            "{}",
+            tr!("❌ 處理文件上傳失敗: {}", "❌ File upload failed: {}", e)
+        );
+        if let Some(usage_key) = &usage_key {
+            usage_key.record_error();
+        }
         res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
         res.render(Json(OpenAIErrorResponse {
             error: OpenAIError {
@@ -161,7 +372,17 @@ pub async fn chat_completions(req: &mut Request, res: &mut Response) {
     debug!("🔄 請求模式: {}", if stream { "串流" } else { "非串流" });
 
     // 創建 chat 請求
-    let chat_request_obj = create_chat_request(&original_model, messages, &chat_request).await;
+    let mut chat_request_obj = create_chat_request(&original_model, messages, &chat_request).await;
+
+    // 以 X-Conversation-Id 或 user 對應固定的 Poe 對話識別
+    if conversation_ids_enabled() {
+        apply_conversation_ids(
+            &mut chat_request_obj,
+            &access_key,
+            chat_request.user.as_deref(),
+            conversation_header.as_deref(),
+        );
+    }
 
     // 檢查是否需要包含 usage 統計
     let include_usage = chat_request
@@ -171,11 +392,106 @@ pub async fn chat_completions(req: &mut Request, res: &mut Response) {
         .unwrap_or(false);
     debug!("📊 是否包含 usage 統計: {}", include_usage);
 
+    // 串流結尾片段相容性設定：全域設定再以 API Key 的設定覆蓋
+    let global_compat = config.stream_compat.clone().unwrap_or_default();
+    let stream_compat = match config
+        .key_stream_compat
+        .as_ref()
+        .and_then(|keys| keys.get(&access_key))
+    {
+        Some(key_compat) => global_compat.overlay(key_compat),
+        None => global_compat,
+    };
+    debug!("🔧 串流相容性設定: {:?}", stream_compat);
+
     // 創建輸出生成器
-    let output_generator =
-        OutputGenerator::new(display_model.clone(), prompt_tokens, include_usage);
+    let mut output_generator = OutputGenerator::new(
+        display_model.clone(),
+        prompt_tokens,
+        include_usage,
+        stream_compat,
+        chat_request.stop.clone().unwrap_or_default(),
+        config.strip_footnotes(&original_model),
+    );
+    output_generator.store = pending_store;
+    output_generator.conversation = pending_turn;
+    output_generator.usage = usage_key.clone();
+    output_generator.strict_tools = Arc::new(chat_request.strict_tool_schemas());
+    output_generator.single_tool_call = chat_request.parallel_tool_calls == Some(false);
+    output_generator.truncation = truncation;
+    output_generator.audio_output = chat_request.wants_audio();
+    if output_generator.audio_output {
+        debug!(
+            "🔊 要求音訊輸出 | 聲音: {:?} | 格式: {:?}",
+            chat_request
+                .audio
+                .as_ref()
+                .and_then(|audio| audio.voice.as_deref()),
+            chat_request
+                .audio
+                .as_ref()
+                .and_then(|audio| audio.format.as_deref())
+        );
+    }
+    let output_limit = output_limit(&config, &original_model, &chat_request);
+    output_generator.output_limit = output_limit.clone();
+
+    // 啟用圖片緩存的模型，相同請求在有效期內直接重播保存的結果
+    let image_cache = config.image_cache_ttl(&original_model).map(|ttl| {
+        let key = image_cache_key(&owner_hash(&access_key), &original_model, &chat_request_obj);
+        (key, ttl)
+    });
+    let cached = image_cache
+        .as_ref()
+        .and_then(|(key, _)| cached_image_response(key));
+    if image_cache.is_some() {
+        res.headers_mut().insert(
+            "X-Image-Cache",
+            if cached.is_some() { "hit" } else { "miss" }
+                .parse()
+                .unwrap(),
+        );
+    }
+    let upstream = match cached {
+        Some(events) => Ok(events),
+        None => match ServerTools::for_model(&config, &original_model).await {
+            // 啟用伺服器端工具的模型由代理執行工具循環
+            Some(server_tools) => server_tools.run(&client, chat_request_obj).await,
+            None => {
+                client
+                    .stream_request_with_retry(chat_request_obj, !stream)
+                    .await
+            }
+        }
+        .inspect_err(|e| report_model_error(&original_model, &e.to_string()))
+        .map(|events| track_model_health(&original_model, events))
+        .map(|events| match image_cache {
+            Some((key, ttl)) => record_image_response(key, ttl, events),
+            None => events,
+        }),
+    }
+    // 緩存保存完整的回應，長度上限套用在緩存之後
+    .map(|events| match output_limit {
+        Some(limit) => limit_output(events, limit),
+        None => events,
+    })
+    // 登記為進行中的生成，管理員可由 /api/admin/requests 中止
+    .map(|events| {
+        track_generation(
+            &output_generator.id,
+            &display_model,
+            stream,
+            &client_ip,
+            &access_key,
+            events,
+        )
+    });
 
-    match client.stream_request(chat_request_obj).await {
+    match upstream {
+        // 非串流響應的錯誤事件在彙整時處理，不預先等待首個事件以便盡早開始保活
+        Ok(event_stream) if !stream => {
+            handle_non_stream_response(res, event_stream, output_generator, permit).await;
+        }
         Ok(mut event_stream) => {
             let first_event = event_stream.next().await;
 
@@ -184,33 +500,13 @@ pub async fn chat_completions(req: &mut Request, res: &mut Response) {
                 data: Some(ChatResponseData::Error { text, allow_retry }),
             })) = &first_event
             {
-                let insufficient_points_msg_1 =
-                    "This bot needs more points to answer your request.";
-                let insufficient_points_msg_2 =
-                    "You do not have enough points to message this bot.";
-
-                if text.contains(insufficient_points_msg_1)
-                    || text.contains(insufficient_points_msg_2)
-                {
-                    info!("🚫 偵測到 Poe 點數不足錯誤，返回 429 狀態碼。");
-                    let status = StatusCode::TOO_MANY_REQUESTS;
-                    let body = OpenAIErrorResponse {
-                        error: OpenAIError {
-                            message: "You have exceeded your message quota for this model. Please try again later.".to_string(),
-                            r#type: "insufficient_quota".to_string(),
-                            code: "insufficient_quota".to_string(),
-                            param: None,
-                        },
-                    };
-                    res.status_code(status);
-                    res.render(Json(body));
-                    return;
-                } else {
-                    let (status, body) = convert_poe_error_to_openai(text, *allow_retry);
-                    res.status_code(status);
-                    res.render(Json(body));
-                    return;
+                if let Some(usage_key) = &usage_key {
+                    usage_key.record_error();
                 }
+                let (status, body) = convert_poe_error_to_openai(text, *allow_retry);
+                res.status_code(status);
+                res.render(Json(body));
+                return;
             }
 
             let reconstituted_stream: Pin<
@@ -221,21 +517,506 @@ pub async fn chat_completions(req: &mut Request, res: &mut Response) {
                 Box::pin(stream::empty())
             };
 
-            if stream {
-                handle_stream_response(res, reconstituted_stream, output_generator).await;
+            handle_stream_response(
+                res,
+                reconstituted_stream,
+                output_generator,
+                permit,
+                &owner_hash(&access_key),
+            )
+            .await;
+        }
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 建立串流請求失敗: {}",
+                    "❌ Failed to create stream request: {}",
+                    e
+                )
+            );
+            if let Some(usage_key) = &usage_key {
+                usage_key.record_error();
+            }
+            let text = e.to_string();
+            if is_insufficient_points(&text) {
+                let (status, body) = convert_poe_error_to_openai(&text, false);
+                res.status_code(status);
+                res.render(Json(body));
             } else {
-                handle_non_stream_response(res, reconstituted_stream, output_generator).await;
+                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+                res.render(Json(json!({ "error": text })));
             }
         }
+    }
+
+    if let Some(leader) = coalesce_leader {
+        leader.finish(res);
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "{}",
+        tr!(
+            "✅ 請求處理完成 | 耗時: {}",
+            "✅ Request completed | duration: {}",
+            format_duration(duration)
+        )
+    );
+}
+
+/// 請求體的大小上限 (MAX_REQUEST_SIZE) 與單個欄位的大小上限 (MAX_FIELD_SIZE)
+pub(super) fn body_size_limits() -> (usize, usize) {
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+    let max_field_size: usize = std::env::var("MAX_FIELD_SIZE")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    (max_size, max_field_size)
+}
+
+/// 以 OpenAI 錯誤格式回應請求體的讀取或解析錯誤
+pub(super) fn render_body_error(e: &BodyError, res: &mut Response) {
+    let (status, code) = match e {
+        BodyError::TooLarge(_) | BodyError::FieldTooLarge(_) => {
+            (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large")
+        }
+        BodyError::Read(_) => (StatusCode::BAD_REQUEST, "read_error"),
+        BodyError::UnsupportedEncoding(_) => {
+            (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_encoding")
+        }
+        BodyError::Decode(_) => (StatusCode::BAD_REQUEST, "decode_error"),
+        BodyError::Malformed { .. } | BodyError::Parse(_) => {
+            (StatusCode::BAD_REQUEST, "parse_error")
+        }
+        BodyError::Spool(_) => (StatusCode::INTERNAL_SERVER_ERROR, "spool_error"),
+    };
+    error!(
+        "{}",
+        tr!(
+            "❌ 請求體處理失敗: {}",
+            "❌ Failed to process request body: {}",
+            e
+        )
+    );
+    res.status_code(status);
+    res.render(Json(OpenAIErrorResponse {
+        error: OpenAIError {
+            message: e.to_string(),
+            r#type: "invalid_request_error".to_string(),
+            code: code.to_string(),
+            param: None,
+        },
+    }));
+}
+
+/// 讀取並解析聊天請求，依序套用 on_request 腳本與內容過濾；失敗時寫入錯誤回應並返回 None
+pub(super) async fn read_chat_request(
+    req: &mut Request,
+    res: &mut Response,
+) -> Option<ChatCompletionRequest> {
+    // 逐塊讀取並解析請求體
+    let (max_size, max_field_size) = body_size_limits();
+    // 有 on_request 腳本時先解析為 JSON 交由腳本修改
+    let parsed = match get_script_hooks().filter(|hooks| hooks.has_request_hook()) {
+        Some(hooks) => {
+            let headers: HashMap<String, String> = req
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    value
+                        .to_str()
+                        .ok()
+                        .map(|v| (name.as_str().to_string(), v.to_string()))
+                })
+                .collect();
+            match read_json_body_spooled::<serde_json::Value>(req, max_size, max_field_size).await {
+                Ok((value, spooled)) => match hooks.on_request(value, headers) {
+                    Ok(value) => serde_json::from_value::<ChatCompletionRequest>(value)
+                        .map(|request| (request, spooled))
+                        .map_err(BodyError::Parse),
+                    Err(message) => {
+                        res.status_code(StatusCode::BAD_REQUEST);
+                        res.render(Json(OpenAIErrorResponse {
+                            error: OpenAIError {
+                                message,
+                                r#type: "invalid_request_error".to_string(),
+                                code: "request_rejected".to_string(),
+                                param: None,
+                            },
+                        }));
+                        return None;
+                    }
+                },
+                Err(e) => Err(e),
+            }
+        }
+        None => {
+            read_json_body_spooled::<ChatCompletionRequest>(req, max_size, max_field_size).await
+        }
+    };
+    let mut chat_request = match parsed {
+        Ok((mut req, spooled)) => {
+            req.spooled = spooled;
+            debug!(
+                "📊 請求解析成功 | 模型: {} | 訊息數量: {} | 是否串流: {:?}",
+                req.model,
+                req.messages.len(),
+                req.stream
+            );
+            req
+        }
         Err(e) => {
-            error!("❌ 建立串流請求失敗: {}", e);
-            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            res.render(Json(json!({ "error": e.to_string() })));
+            render_body_error(&e, res);
+            return None;
         }
+    };
+
+    // 內容過濾：檢查並遮蔽使用者訊息
+    if let Some(filter) = get_content_filter()
+        && filter.filter_messages(&mut chat_request.messages).is_err()
+    {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(OpenAIErrorResponse {
+            error: OpenAIError {
+                message: "請求內容被內容過濾規則拒絕".to_string(),
+                r#type: "invalid_request_error".to_string(),
+                code: "content_filter".to_string(),
+                param: None,
+            },
+        }));
+        return None;
     }
 
-    let duration = start_time.elapsed();
-    info!("✅ 請求處理完成 | 耗時: {}", format_duration(duration));
+    Some(chat_request)
+}
+
+/// 依模型的 stream_policy 覆蓋客戶端的 stream 設定
+pub(super) fn apply_stream_policy(
+    config: &Config,
+    model: &str,
+    chat_request: &mut ChatCompletionRequest,
+) {
+    let policy = config
+        .models
+        .get(model)
+        .and_then(|model_config| model_config.stream_policy)
+        .unwrap_or_default();
+    let requested = chat_request.stream.unwrap_or(false);
+    let stream = match policy {
+        StreamPolicy::Client => return,
+        StreamPolicy::Always => true,
+        StreamPolicy::Never => false,
+    };
+    if stream != requested {
+        debug!(
+            "🌊 依模型 {} 的 stream_policy 改為{}回應",
+            model,
+            if stream { "串流" } else { "非串流" }
+        );
+        chat_request.stream = Some(stream);
+        if !stream {
+            chat_request.stream_options = None;
+        }
+    }
+}
+
+/// 超過模型的 max_input_bytes 或 max_input_tokens 時返回 (狀態碼, 錯誤代碼, 訊息)
+fn input_limit_error(
+    model_config: &ModelConfig,
+    model: &str,
+    chat_request: &ChatCompletionRequest,
+) -> Option<(StatusCode, &'static str, String)> {
+    if let Some(max_bytes) = model_config.max_input_bytes {
+        // 暫存至磁碟的附件在訊息中只剩引用，另外加回其 base64 內容的大小
+        let bytes = serde_json::to_vec(&chat_request.messages)
+            .map(|body| body.len() as u64)
+            .unwrap_or(0)
+            + chat_request.spooled.encoded_bytes();
+        if bytes > max_bytes {
+            return Some((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "request_too_large",
+                format!(
+                    "Request messages are {} bytes, exceeding the {} byte limit for model {}.",
+                    bytes, max_bytes, model
+                ),
+            ));
+        }
+    }
+    if let Some(max_tokens) = model_config.max_input_tokens {
+        let tokens = count_message_tokens(&chat_request.messages);
+        if tokens > max_tokens {
+            return Some((
+                StatusCode::BAD_REQUEST,
+                "context_length_exceeded",
+                format!(
+                    "Request messages are about {} tokens, exceeding the {} token limit for model {}. Please shorten the messages.",
+                    tokens, max_tokens, model
+                ),
+            ));
+        }
+    }
+    None
+}
+
+/// 檢查模型的 max_input_bytes 與 max_input_tokens，超過時寫入錯誤回應並返回 false
+pub(super) fn apply_size_limits(
+    config: &Config,
+    model: &str,
+    chat_request: &ChatCompletionRequest,
+    res: &mut Response,
+) -> bool {
+    let Some((status, code, message)) = config
+        .models
+        .get(model)
+        .and_then(|model_config| input_limit_error(model_config, model, chat_request))
+    else {
+        return true;
+    };
+    warn!(
+        "{}",
+        tr!(
+            "🚫 請求超過模型的輸入上限，拒絕請求 | {}",
+            "🚫 Request exceeds the model's input limit, rejecting | {}",
+            message
+        )
+    );
+    res.status_code(status);
+    res.render(Json(OpenAIErrorResponse {
+        error: OpenAIError {
+            message,
+            r#type: "invalid_request_error".to_string(),
+            code: code.to_string(),
+            param: Some("messages".to_string()),
+        },
+    }));
+    false
+}
+
+/// 模型的回覆長度上限：max_output_tokens，請求的 max_tokens 較小時以請求為準；
+/// 模型未設定 max_output_tokens 時返回 None
+fn output_limit(
+    config: &Config,
+    model: &str,
+    chat_request: &ChatCompletionRequest,
+) -> Option<Arc<OutputLimit>> {
+    let max_tokens = config.models.get(model)?.max_output_tokens?;
+    let requested = ["max_completion_tokens", "max_tokens"]
+        .iter()
+        .find_map(|name| chat_request.other.get(*name).and_then(|v| v.as_u64()))
+        .filter(|requested| *requested > 0)
+        .map_or(max_tokens, |requested| {
+            requested.min(max_tokens as u64) as u32
+        });
+    debug!("✂️ 回覆長度上限: {} tokens", requested);
+    Some(OutputLimit::new(requested))
+}
+
+/// 依 models.yaml 的 param_policy（模型設定優先於全域設定）處理 Poe 無法支援的參數
+/// 參數被拒絕時寫入 400 錯誤回應並返回 false
+pub(super) fn apply_param_policy(
+    config: &Config,
+    model: &str,
+    chat_request: &mut ChatCompletionRequest,
+    res: &mut Response,
+) -> bool {
+    let model_policy = config
+        .models
+        .get(model)
+        .and_then(|model_config| model_config.param_policy.as_ref());
+    let mut names: Vec<&String> = config
+        .param_policy
+        .iter()
+        .chain(model_policy)
+        .flat_map(|policies| policies.keys())
+        .collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        if !chat_request.has_param(name) {
+            continue;
+        }
+        let policy = model_policy
+            .and_then(|policies| policies.get(name))
+            .or_else(|| config.param_policy.as_ref()?.get(name))
+            .copied()
+            .unwrap_or_default();
+        match policy {
+            ParamPolicy::Forward => {}
+            ParamPolicy::Ignore => {
+                debug!("🧹 依參數策略移除參數: {}", name);
+                chat_request.remove_param(name);
+            }
+            ParamPolicy::Warn => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 模型 {} 不支援參數 {}，已移除",
+                        "⚠️ Model {} does not support parameter {}, removed",
+                        model,
+                        name
+                    )
+                );
+                chat_request.remove_param(name);
+            }
+            ParamPolicy::Reject => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "🚫 模型 {} 不支援參數 {}，拒絕請求",
+                        "🚫 Model {} does not support parameter {}, rejecting request",
+                        model,
+                        name
+                    )
+                );
+                res.status_code(StatusCode::BAD_REQUEST);
+                res.render(Json(OpenAIErrorResponse {
+                    error: OpenAIError {
+                        message: format!("模型 {} 不支援參數 {}", model, name),
+                        r#type: "invalid_request_error".to_string(),
+                        code: "unsupported_parameter".to_string(),
+                        param: Some(name.clone()),
+                    },
+                }));
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// 尋找映射的原始模型名稱，返回 (顯示名稱, 原始名稱)
+pub(super) fn resolve_model(config: &Config, requested_model: &str) -> (String, String) {
+    if config.enable.unwrap_or(false) {
+        let requested_model = requested_model.to_string();
+        // 檢查當前請求的模型是否是某個映射的目標
+        let mapping_entry = config.models.iter().find(|(_, cfg)| {
+            if let Some(mapping) = &cfg.mapping {
+                mapping.to_lowercase() == requested_model.to_lowercase()
+            } else {
+                false
+            }
+        });
+        if let Some((original_name, _)) = mapping_entry {
+            // 如果找到映射，使用原始模型名稱
+            debug!("🔄 反向模型映射: {} -> {}", requested_model, original_name);
+            (requested_model, original_name.clone())
+        } else {
+            // 如果沒找到映射，檢查是否有直接映射配置
+            if let Some(model_config) = config.models.get(&requested_model) {
+                if let Some(mapped_name) = &model_config.mapping {
+                    debug!("🔄 直接模型映射: {} -> {}", requested_model, mapped_name);
+                    (requested_model.clone(), requested_model)
+                } else {
+                    // 沒有映射配置，使用原始名稱
+                    (requested_model.clone(), requested_model)
+                }
+            } else {
+                // 完全沒有相關配置，使用原始名稱
+                (requested_model.clone(), requested_model)
+            }
+        }
+    } else {
+        // 配置未啟用，直接使用原始名稱
+        (requested_model.to_string(), requested_model.to_string())
+    }
+}
+
+/// 依 history_policy 將訊息截斷或摘要至模型的上下文長度，預留請求的 max_tokens 作為輸出空間
+async fn fit_history(
+    config: &Config,
+    model: &str,
+    chat_request: &ChatCompletionRequest,
+    messages: &mut Vec<Message>,
+    access_key: &str,
+) -> Option<Truncation> {
+    let reserved = ["max_completion_tokens", "max_tokens"]
+        .iter()
+        .find_map(|name| chat_request.other.get(*name).and_then(|v| v.as_u64()))
+        .unwrap_or(0);
+    let (policy, budget) = config.history_limit(model, reserved.min(u32::MAX as u64) as u32)?;
+    match policy {
+        HistoryPolicy::Summarize => {
+            summarize_history(
+                messages,
+                budget,
+                config.history_summary_model.as_deref(),
+                access_key,
+            )
+            .await
+        }
+        _ => truncate_history(messages, budget),
+    }
+}
+
+/// 以非串流方式執行一次聊天請求並返回完整響應，不儲存記錄也不套用 on_response 腳本（供管理介面重播使用）
+pub(super) async fn run_chat_request(
+    mut chat_request: ChatCompletionRequest,
+    access_key: &str,
+) -> Result<ChatCompletionResponse, (StatusCode, OpenAIErrorResponse)> {
+    let config = get_cached_config().await;
+    let (display_model, original_model) = resolve_model(&config, &chat_request.model);
+    let upstream_key = select_upstream_token(access_key);
+    let client = PoeClientWrapper::new(
+        &original_model,
+        upstream_key.as_deref().unwrap_or(access_key),
+    );
+
+    let mut messages = std::mem::take(&mut chat_request.messages);
+    let truncation = fit_history(
+        &config,
+        &original_model,
+        &chat_request,
+        &mut messages,
+        upstream_key.as_deref().unwrap_or(access_key),
+    )
+    .await;
+    if let Err(e) = process_message_images(&client, &mut messages).await {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            OpenAIErrorResponse {
+                error: OpenAIError {
+                    message: format!("處理文件上傳失敗: {}", e),
+                    r#type: "processing_error".to_string(),
+                    code: "file_processing_failed".to_string(),
+                    param: None,
+                },
+            },
+        ));
+    }
+    let prompt_tokens = count_message_tokens(&messages);
+    let chat_request_obj = create_chat_request(&original_model, messages, &chat_request).await;
+    let mut output_generator = OutputGenerator::new(
+        display_model,
+        prompt_tokens,
+        true,
+        config.stream_compat.clone().unwrap_or_default(),
+        chat_request.stop.clone().unwrap_or_default(),
+        config.strip_footnotes(&original_model),
+    );
+    output_generator.truncation = truncation;
+    output_generator.audio_output = chat_request.wants_audio();
+    output_generator.output_limit = output_limit(&config, &original_model, &chat_request);
+    let event_stream = match ServerTools::for_model(&config, &original_model).await {
+        Some(server_tools) => server_tools.run(&client, chat_request_obj).await,
+        None => {
+            client
+                .stream_request_with_retry(chat_request_obj, true)
+                .await
+        }
+    }
+    .inspect_err(|e| report_model_error(&original_model, &e.to_string()))
+    .map(|events| track_model_health(&original_model, events))
+    .map(|events| match output_generator.output_limit.clone() {
+        Some(limit) => limit_output(events, limit),
+        None => events,
+    })
+    .map_err(|e| convert_poe_error_to_openai(&e.to_string(), false))?;
+    collect_response(event_stream, &output_generator).await
 }
 
 // 處理串流響應
@@ -243,14 +1024,22 @@ async fn handle_stream_response(
     res: &mut Response,
     event_stream: Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>,
     output_generator: OutputGenerator,
+    permit: Option<AdmissionPermit>,
+    owner: &str,
 ) {
     let start_time = Instant::now();
     let id = output_generator.id.clone();
     let model = output_generator.model.clone();
     let include_usage = output_generator.include_usage;
     info!(
-        "🌊 開始處理串流響應 | ID: {} | 模型: {} | 包含使用統計: {}",
-        id, model, include_usage
+        "{}",
+        tr!(
+            "🌊 開始處理串流響應 | ID: {} | 模型: {} | 包含使用統計: {}",
+            "🌊 Streaming response started | ID: {} | model: {} | include usage: {}",
+            id,
+            model,
+            include_usage
+        )
     );
 
     // 設置串流響應的頭部
@@ -265,45 +1054,267 @@ async fn handle_stream_response(
     let processed_stream = output_generator
         .process_stream(Box::pin(event_stream))
         .await;
-    res.stream(processed_stream);
+    // 計數與併發名額隨串流一起釋放，涵蓋客戶端中途斷線
+    let in_flight = InFlight::stream();
+    let processed_stream = processed_stream.map(move |item| {
+        let _in_flight = &in_flight;
+        let _permit = &permit;
+        item
+    });
+    let processed_stream = match get_script_hooks().filter(|hooks| hooks.has_chunk_hook()) {
+        Some(hooks) => processed_stream
+            .map(move |item| item.map(|text| hooks.transform_sse(text)))
+            .boxed(),
+        None => processed_stream.boxed(),
+    };
+    // 啟用 STREAM_RESUME_SECS 時生成在背景進行，客戶端斷線後可憑 Last-Event-ID 續傳；
+    // 否則經有界緩衝區輸出，依 STREAM_BACKPRESSURE 處理讀取太慢的客戶端
+    if resume_enabled() {
+        res.stream(make_resumable(&id, owner, processed_stream));
+    } else {
+        res.stream(with_backpressure(&id, processed_stream));
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "{}",
+        tr!(
+            "✅ 串流響應處理完成 | ID: {} | 耗時: {}",
+            "✅ Streaming response completed | ID: {} | duration: {}",
+            id,
+            format_duration(duration)
+        )
+    );
+}
+
+// 處理 File 事件中的附件（影片與音訊轉存、圖片轉換），會改寫事件中的附件 URL
+async fn prepare_event_attachment(event: &mut ChatResponse, audio_data: bool) -> MediaOutput {
+    match (&event.event, &mut event.data) {
+        (ChatEventType::File, Some(ChatResponseData::File(file_data))) => {
+            prepare_attachment(file_data, audio_data).await
+        }
+        _ => MediaOutput::default(),
+    }
+}
 
-    let duration = start_time.elapsed();
-    info!(
-        "✅ 串流響應處理完成 | ID: {} | 耗時: {}",
-        id,
-        format_duration(duration)
-    );
+/// 非串流請求的總逾時與保活設定
+#[derive(Clone, Copy)]
+struct NonStreamConfig {
+    /// 等待完整響應的上限，None 表示不限制
+    timeout: Option<Duration>,
+    /// 響應未完成時發送空白保持連線的間隔，None 表示不保活
+    keepalive: Option<Duration>,
 }
 
+static NON_STREAM_CONFIG: LazyLock<NonStreamConfig> = LazyLock::new(|| {
+    let secs = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    };
+    let config = NonStreamConfig {
+        timeout: secs("NON_STREAM_TIMEOUT_SECS"),
+        keepalive: secs("NON_STREAM_KEEPALIVE_SECS"),
+    };
+    if config.timeout.is_some() || config.keepalive.is_some() {
+        info!(
+            "{}",
+            tr!(
+                "⏳ 非串流請求 | 總逾時: {} | 保活間隔: {}",
+                "⏳ Non-streaming requests | total timeout: {} | keep-alive interval: {}",
+                config
+                    .timeout
+                    .map(format_duration)
+                    .unwrap_or("-".to_string()),
+                config
+                    .keepalive
+                    .map(format_duration)
+                    .unwrap_or("-".to_string())
+            )
+        );
+    }
+    config
+});
+
+/// 序列化後的響應內容或錯誤
+type NonStreamOutcome = Result<String, (StatusCode, OpenAIErrorResponse)>;
+
 // 處理非串流響應
+// 設定 NON_STREAM_KEEPALIVE_SECS 時，超過間隔仍未完成即先以 200 開始響應，
+// 每個間隔發送一個空白字元（JSON 允許前置空白）避免中間代理因閒置而中斷連線，完成後再寫入 JSON
 async fn handle_non_stream_response(
     res: &mut Response,
-    mut event_stream: Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>,
+    event_stream: Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>,
     output_generator: OutputGenerator,
+    permit: Option<AdmissionPermit>,
 ) {
     let start_time = Instant::now();
     let id = output_generator.id.clone();
     let model = output_generator.model.clone();
     let include_usage = output_generator.include_usage;
     info!(
-        "📦 開始處理非串流響應 | ID: {} | 模型: {} | 包含使用統計: {}",
-        id, model, include_usage
+        "{}",
+        tr!(
+            "📦 開始處理非串流響應 | ID: {} | 模型: {} | 包含使用統計: {}",
+            "📦 Non-streaming response started | ID: {} | model: {} | include usage: {}",
+            id,
+            model,
+            include_usage
+        )
+    );
+
+    let config = *NON_STREAM_CONFIG;
+    let mut outcome = Box::pin(async move {
+        // 併發名額在響應完成時釋放
+        let _permit = permit;
+        let result = match config.timeout {
+            Some(timeout) => {
+                tokio::time::timeout(timeout, collect_response(event_stream, &output_generator))
+                    .await
+                    .unwrap_or_else(|_| Err(non_stream_timeout_error(timeout)))
+            }
+            None => collect_response(event_stream, &output_generator).await,
+        };
+        finish_non_stream_response(result, &output_generator)
+    });
+
+    let outcome = match config.keepalive {
+        Some(interval) => match tokio::time::timeout(interval, &mut outcome).await {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                debug!("⏳ 非串流響應尚未完成，開始發送保活空白 | ID: {}", id);
+                res.headers_mut()
+                    .insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+                res.headers_mut()
+                    .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+                // 計數隨響應一起釋放，涵蓋客戶端中途斷線
+                let in_flight = InFlight::stream();
+                let body = stream::unfold(Some(outcome), move |outcome| async move {
+                    let mut outcome = outcome?;
+                    match tokio::time::timeout(interval, &mut outcome).await {
+                        Ok(Ok(body)) => Some((Ok(body), None)),
+                        // 狀態碼已送出，錯誤只能寫在響應內容中
+                        Ok(Err((_, error_response))) => Some((
+                            Ok(serde_json::to_string(&error_response).unwrap_or_default()),
+                            None,
+                        )),
+                        Err(_) => Some((Ok(" ".to_string()), Some(outcome))),
+                    }
+                })
+                .map(move |item: Result<String, std::convert::Infallible>| {
+                    let _in_flight = &in_flight;
+                    item
+                });
+                res.stream(body);
+                return;
+            }
+        },
+        None => outcome.await,
+    };
+    match outcome {
+        Ok(body) => res.render(Text::Json(body)),
+        Err((status, error_response)) => {
+            res.status_code(status);
+            res.render(Json(error_response));
+            return;
+        }
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "{}",
+        tr!(
+            "✅ 非串流響應處理完成 | ID: {} | 耗時: {}",
+            "✅ Non-streaming response completed | ID: {} | duration: {}",
+            id,
+            format_duration(duration)
+        )
+    );
+}
+
+// 記錄完成的響應（儲存、用量統計）並套用響應腳本
+fn finish_non_stream_response(
+    result: Result<ChatCompletionResponse, (StatusCode, OpenAIErrorResponse)>,
+    output_generator: &OutputGenerator,
+) -> NonStreamOutcome {
+    let response = result.inspect_err(|_| output_generator.record_error())?;
+    output_generator.persist_completion(&response);
+    if let Some(usage_key) = &output_generator.usage {
+        let completion_tokens = response
+            .usage
+            .as_ref()
+            .and_then(|usage| usage["completion_tokens"].as_u64())
+            .unwrap_or_default();
+        usage_key.record(output_generator.prompt_tokens, completion_tokens as u32);
+    }
+    let body = match get_script_hooks().filter(|hooks| hooks.has_response_hook()) {
+        Some(hooks) => {
+            let value = serde_json::to_value(&response).unwrap_or_default();
+            serde_json::to_string(&hooks.on_response(value))
+        }
+        None => serde_json::to_string(&response),
+    };
+    Ok(body.unwrap_or_default())
+}
+
+fn non_stream_timeout_error(timeout: Duration) -> (StatusCode, OpenAIErrorResponse) {
+    warn!(
+        "{}",
+        tr!(
+            "⏰ 非串流請求超過總逾時 {}，已中止",
+            "⏰ Non-streaming request exceeded the total timeout of {}, aborted",
+            format_duration(timeout)
+        )
     );
+    (
+        StatusCode::GATEWAY_TIMEOUT,
+        OpenAIErrorResponse {
+            error: OpenAIError {
+                message: format!(
+                    "The bot did not finish responding within {} seconds.",
+                    timeout.as_secs()
+                ),
+                r#type: "timeout_error".to_string(),
+                code: "timeout".to_string(),
+                param: None,
+            },
+        },
+    )
+}
 
+// 彙整所有事件為完整的聊天完成響應，遇到錯誤事件時返回對應的狀態碼與錯誤內容
+async fn collect_response(
+    mut event_stream: Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>,
+    output_generator: &OutputGenerator,
+) -> Result<ChatCompletionResponse, (StatusCode, OpenAIErrorResponse)> {
     let handler_manager = EventHandlerManager::new();
-    let mut ctx = EventContext::default();
+    let mut ctx = output_generator.new_context();
 
     // 處理所有事件
     while let Some(result) = event_stream.next().await {
         match result {
-            Ok(event) => {
+            Ok(mut event) => {
+                let media_output =
+                    prepare_event_attachment(&mut event, output_generator.audio_output).await;
                 handler_manager.handle(&event, &mut ctx);
+                ctx.images.extend(media_output.image);
+                ctx.videos.extend(media_output.video);
+                if ctx.audio.is_none() {
+                    ctx.audio = media_output.audio;
+                }
                 // 檢查是否有錯誤
                 if let Some((status, error_response)) = &ctx.error {
-                    error!("❌ 處理錯誤: {:?}", error_response);
-                    res.status_code(*status);
-                    res.render(Json(error_response));
-                    return;
+                    error!(
+                        "{}",
+                        tr!(
+                            "❌ 處理錯誤: {:?}",
+                            "❌ Processing error: {:?}",
+                            error_response
+                        )
+                    );
+                    return Err((*status, error_response.clone()));
                 }
                 // 檢查是否完成
                 if ctx.done {
@@ -312,25 +1323,50 @@ async fn handle_non_stream_response(
                 }
             }
             Err(e) => {
-                error!("❌ 處理錯誤: {}", e);
-                let (status, error_response) = convert_poe_error_to_openai(&e.to_string(), false);
-                res.status_code(status);
-                res.render(Json(error_response));
-                return;
+                error!("{}", tr!("❌ 處理錯誤: {}", "❌ Processing error: {}", e));
+                return Err(convert_poe_error_to_openai(&e.to_string(), false));
             }
         }
     }
 
     // 創建最終響應
-    let response = output_generator.create_final_response(&mut ctx);
-    res.render(Json(response));
+    Ok(output_generator.create_final_response(&mut ctx))
+}
 
-    let duration = start_time.elapsed();
-    info!(
-        "✅ 非串流響應處理完成 | ID: {} | 耗時: {}",
-        id,
-        format_duration(duration)
-    );
+/// 串流片段：Poe 事件解碼後的結果，T 為正文，經過處理階段前為 String，之後為 StageOutput
+enum StreamPart<T> {
+    // 角色片段，已發送時略過
+    Role,
+    Text(T),
+    // 串流中斷前的正文，處理階段同時取出暫存的內容
+    Flush(T),
+    Media(Box<MediaOutput>),
+    ToolCalls(Vec<ToolCallDelta>),
+    // 結尾片段，text 為尚未發送的正文，has_text 表示 Done 事件前仍有正文
+    Finish {
+        text: T,
+        has_text: bool,
+        finish_reason: &'static str,
+        completion_tokens: u32,
+    },
+    // 已序列化的錯誤
+    Error(String),
+}
+
+impl StreamPart<StageOutput> {
+    // 錯誤與空的正文不需要先發送角色片段
+    fn needs_role(&self) -> bool {
+        match self {
+            StreamPart::Text(output) | StreamPart::Flush(output) => !output.is_empty(),
+            StreamPart::Error(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// 編碼為 SSE 的 data 行
+fn sse_data<T: serde::Serialize>(value: &T) -> String {
+    format!("data: {}\n\n", serde_json::to_string(value).unwrap())
 }
 
 // 輸出生成器 - 用於將 EventContext 轉換為最終輸出
@@ -341,16 +1377,107 @@ struct OutputGenerator {
     model: String,
     prompt_tokens: u32,
     include_usage: bool,
+    stream_compat: StreamCompatConfig,
+    store: Option<Arc<PendingStore>>,
+    conversation: Option<Arc<PendingTurn>>,
+    // 請求的停止序列，供 stop_sequences 階段使用
+    stop: Vec<String>,
+    // 移除腳註標記與結尾的來源列表 (strip_footnotes)
+    strip_footnotes: bool,
+    // 串流正文的處理階段
+    pipeline: Arc<Mutex<Pipeline>>,
+    // 用量統計 (USAGE_STATS)
+    usage: Option<UsageKey>,
+    // strict 工具的參數 schema
+    strict_tools: Arc<StrictToolSchemas>,
+    // parallel_tool_calls 為 false 時每回合只返回一個工具調用
+    single_tool_call: bool,
+    // 對話歷史的截斷或摘要結果，記錄在 usage.history_truncated
+    truncation: Option<Truncation>,
+    // 模型的回覆長度上限 (max_output_tokens)
+    output_limit: Option<Arc<OutputLimit>>,
+    // modalities 包含 audio：音訊以 base64 放入 message.audio，不再放入正文
+    audio_output: bool,
 }
 
 impl OutputGenerator {
-    fn new(model: String, prompt_tokens: u32, include_usage: bool) -> Self {
+    fn new(
+        model: String,
+        prompt_tokens: u32,
+        include_usage: bool,
+        stream_compat: StreamCompatConfig,
+        stop: Vec<String>,
+        strip_footnotes: bool,
+    ) -> Self {
+        let pipeline = Arc::new(Mutex::new(Pipeline::new(&stop, strip_footnotes)));
         Self {
             id: nanoid!(10),
             created: Utc::now().timestamp(),
             model,
             prompt_tokens,
             include_usage,
+            stream_compat,
+            store: None,
+            conversation: None,
+            stop,
+            strip_footnotes,
+            pipeline,
+            usage: None,
+            strict_tools: Arc::default(),
+            single_tool_call: false,
+            truncation: None,
+            output_limit: None,
+            audio_output: false,
+        }
+    }
+
+    // 新的事件積累上下文
+    fn new_context(&self) -> EventContext {
+        let mut ctx =
+            EventContext::with_tool_options(self.strict_tools.clone(), self.single_tool_call);
+        ctx.audio_separate = self.audio_output;
+        ctx.replace_mode = replace_response_mode_for(&self.model);
+        ctx
+    }
+
+    // 完成原因：工具調用、達到回覆長度上限或正常結束
+    fn finish_reason(&self, ctx: &EventContext) -> &'static str {
+        if !ctx.tool_calls.is_empty() {
+            "tool_calls"
+        } else if self
+            .output_limit
+            .as_ref()
+            .is_some_and(|limit| limit.reached())
+        {
+            "length"
+        } else {
+            "stop"
+        }
+    }
+
+    // 是否需要在完成後保存回應
+    fn needs_persist(&self) -> bool {
+        self.store.is_some() || self.conversation.is_some()
+    }
+
+    // 保存完成的回應 (store=true 及關聯對話)
+    fn persist_completion(&self, response: &ChatCompletionResponse) {
+        if !self.needs_persist() {
+            return;
+        }
+        let value = serde_json::to_value(response).unwrap_or_default();
+        if let Some(conversation) = &self.conversation {
+            conversation.complete(&value);
+        }
+        if let Some(store) = &self.store {
+            store.complete(value);
+        }
+    }
+
+    // 記錄一次失敗的請求
+    fn record_error(&self) {
+        if let Some(usage) = &self.usage {
+            usage.record_error();
         }
     }
 
@@ -380,8 +1507,12 @@ impl OutputGenerator {
             debug!("✅ 成功替換圖片引用");
         } else if processed.contains('[') && processed.contains(']') {
             warn!(
-                "⚠️ 文本包含可能的圖片引用格式，但未找到對應引用: {}",
-                processed
+                "{}",
+                tr!(
+                    "⚠️ 文本包含可能的圖片引用格式，但未找到對應引用: {}",
+                    "⚠️ Text looks like an image reference but no matching reference was found: {}",
+                    processed
+                )
             );
         }
 
@@ -389,26 +1520,43 @@ impl OutputGenerator {
     }
 
     // 計算 token 使用情況
+    // Poe 不回傳用量，completion_tokens 由正文、思考內容及工具調用在本地估算
     fn calculate_tokens(&self, ctx: &mut EventContext) -> (u32, u32, u32) {
-        let content = match &ctx.replace_buffer {
-            Some(replace_content) => replace_content,
-            None => &ctx.content,
-        };
-        let completion_tokens = count_completion_tokens(content);
+        let completion_tokens =
+            count_completion_tokens(&ctx.content) + count_tool_call_tokens(&ctx.tool_calls);
         ctx.completion_tokens = completion_tokens;
         let total_tokens = self.prompt_tokens + completion_tokens;
         (self.prompt_tokens, completion_tokens, total_tokens)
     }
 
-    // 創建角色 chunk
+    // 建立 usage 物件，estimated 標示數值為本地估算而非上游回報
+    fn usage_value(&self, completion_tokens: u32) -> serde_json::Value {
+        let mut usage = json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": self.prompt_tokens + completion_tokens,
+            "prompt_tokens_details": {"cached_tokens": 0},
+            "estimated": true
+        });
+        if let Some(truncation) = self.truncation {
+            usage["history_truncated"] = truncation.to_json();
+        }
+        usage
+    }
+
     // 創建角色 chunk
     fn create_role_chunk(&self) -> ChatCompletionChunk {
         let role_delta = Delta {
             role: Some("assistant".to_string()),
             content: None,
             refusal: None,
+            annotations: None,
             tool_calls: None,
             reasoning_content: None,
+            images: None,
+            videos: None,
+            audio: None,
+            metadata: None,
         };
         ChatCompletionChunk {
             id: format!("chatcmpl-{}", self.id),
@@ -423,14 +1571,64 @@ impl OutputGenerator {
         }
     }
 
-    // 思考 chunk
-    fn create_reasoning_chunk(&self, reasoning_content: &str) -> ChatCompletionChunk {
-        let reasoning_delta = Delta {
+    fn create_output_chunk(
+        &self,
+        output: StageOutput,
+        finish_reason: Option<String>,
+    ) -> ChatCompletionChunk {
+        let mut delta = Delta {
+            role: None,
+            content: None,
+            refusal: None,
+            annotations: None,
+            tool_calls: None,
+            reasoning_content: None,
+            images: None,
+            videos: None,
+            audio: None,
+            metadata: None,
+        };
+        delta.content = Some(match get_content_filter() {
+            Some(filter) => filter.filter_output(&output.content).into_owned(),
+            None => output.content.clone(),
+        });
+        if !output.reasoning.is_empty() {
+            delta.reasoning_content = Some(output.reasoning);
+        }
+        if !output.annotations.is_empty() {
+            delta.annotations = Some(output.annotations);
+        }
+        debug!(
+            "🔧 創建串流片段 | ID: {} | 內容長度: {}",
+            self.id,
+            format_bytes_length(output.content.len())
+        );
+        ChatCompletionChunk {
+            id: format!("chatcmpl-{}", self.id),
+            object: "chat.completion.chunk".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![Choice {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+        }
+    }
+
+    // 創建圖片 / 影片 / 音訊 chunk
+    fn create_media_chunk(&self, media: MediaOutput) -> ChatCompletionChunk {
+        let media_delta = Delta {
             role: None,
             content: None,
             refusal: None,
+            annotations: None,
             tool_calls: None,
-            reasoning_content: Some(reasoning_content.to_string()),
+            reasoning_content: None,
+            images: media.image.map(|image| vec![image]),
+            videos: media.video.map(|video| vec![video]),
+            audio: media.audio,
+            metadata: None,
         };
         ChatCompletionChunk {
             id: format!("chatcmpl-{}", self.id),
@@ -439,30 +1637,26 @@ impl OutputGenerator {
             model: self.model.clone(),
             choices: vec![Choice {
                 index: 0,
-                delta: reasoning_delta,
+                delta: media_delta,
                 finish_reason: None,
             }],
         }
     }
-    // 創建串流 chunk
-    fn create_stream_chunk(
-        &self,
-        content: &str,
-        finish_reason: Option<String>,
-    ) -> ChatCompletionChunk {
-        let mut delta = Delta {
+
+    // 創建建議回覆 chunk，於結尾片段前發送
+    fn create_metadata_chunk(&self, metadata: MessageMetadata) -> ChatCompletionChunk {
+        let metadata_delta = Delta {
             role: None,
             content: None,
             refusal: None,
+            annotations: None,
             tool_calls: None,
             reasoning_content: None,
+            images: None,
+            videos: None,
+            audio: None,
+            metadata: Some(metadata),
         };
-        delta.content = Some(content.to_string());
-        debug!(
-            "🔧 創建串流片段 | ID: {} | 內容長度: {}",
-            self.id,
-            format_bytes_length(content.len())
-        );
         ChatCompletionChunk {
             id: format!("chatcmpl-{}", self.id),
             object: "chat.completion.chunk".to_string(),
@@ -470,23 +1664,34 @@ impl OutputGenerator {
             model: self.model.clone(),
             choices: vec![Choice {
                 index: 0,
-                delta,
-                finish_reason,
+                delta: metadata_delta,
+                finish_reason: None,
             }],
         }
     }
 
-    // 創建工具調用 chunk
-    fn create_tool_calls_chunk(
-        &self,
-        tool_calls: &[poe_api_process::types::ChatToolCall],
-    ) -> ChatCompletionChunk {
+    // 訊息的擴充資訊，未啟用 SUGGESTED_REPLIES 或沒有建議回覆時為 None
+    fn message_metadata(&self, ctx: &EventContext) -> Option<MessageMetadata> {
+        (suggested_replies_enabled() && !ctx.suggested_replies.is_empty()).then(|| {
+            MessageMetadata {
+                suggested_replies: ctx.suggested_replies.clone(),
+            }
+        })
+    }
+
+    // 創建工具調用 chunk，finish_reason 由完成事件發送
+    fn create_tool_calls_chunk(&self, tool_calls: Vec<ToolCallDelta>) -> ChatCompletionChunk {
         let tool_delta = Delta {
             role: None,
             content: None,
             refusal: None,
-            tool_calls: Some(tool_calls.to_vec()),
+            annotations: None,
+            tool_calls: Some(tool_calls),
             reasoning_content: None,
+            images: None,
+            videos: None,
+            audio: None,
+            metadata: None,
         };
         ChatCompletionChunk {
             id: format!("chatcmpl-{}", self.id),
@@ -496,52 +1701,47 @@ impl OutputGenerator {
             choices: vec![Choice {
                 index: 0,
                 delta: tool_delta,
-                finish_reason: Some("tool_calls".to_string()),
+                finish_reason: None,
             }],
         }
     }
 
     // 創建最終完整回應（非串流模式）
     fn create_final_response(&self, ctx: &mut EventContext) -> ChatCompletionResponse {
-        // 處理剩餘的 pending_text
-        if !ctx.pending_text.trim().is_empty() {
-            use crate::evert::ThinkingProcessor;
-            let (reasoning_output, content_output) = ThinkingProcessor::process_text_chunk(ctx, "");
-            if let Some(final_reasoning) = reasoning_output {
-                ctx.reasoning_content.push_str(&final_reasoning);
-            }
-            if let Some(final_content) = content_output {
-                ctx.content.push_str(&final_content);
-            }
-        }
-
-        // 處理內容，包括文件引用替換
-        let content = if let Some(replace_content) = &ctx.replace_buffer {
-            self.process_file_references(replace_content, &ctx.file_refs)
-        } else {
-            self.process_file_references(&ctx.content, &ctx.file_refs)
+        // 處理內容，包括文件引用替換與處理階段
+        let content = self.process_file_references(&ctx.content, &ctx.file_refs);
+        let output = Pipeline::new(&self.stop, self.strip_footnotes).finish(&content);
+        let reasoning = output.reasoning;
+        let annotations = (!output.annotations.is_empty()).then_some(output.annotations);
+        let content = output.content;
+        let content = match get_content_filter() {
+            Some(filter) => filter.filter_output(&content).into_owned(),
+            None => content,
         };
 
+        // 要求音訊輸出時正文不含音訊連結，即為音訊的文字稿
+        let audio = ctx.audio.clone().map(|mut audio| {
+            audio.transcript =
+                (self.audio_output && !content.trim().is_empty()).then(|| content.clone());
+            audio
+        });
+
         // 計算 token
-        let (prompt_tokens, completion_tokens, total_tokens) = self.calculate_tokens(ctx);
+        let (_, completion_tokens, _) = self.calculate_tokens(ctx);
 
         // 確定 finish_reason
-        let finish_reason = if !ctx.tool_calls.is_empty() {
-            "tool_calls".to_string()
-        } else {
-            "stop".to_string()
-        };
+        let finish_reason = self.finish_reason(ctx).to_string();
 
         debug!(
             "📤 準備發送回應 | 內容長度: {} | 思考長度: {} | 工具調用數量: {} | 完成原因: {}",
             format_bytes_length(content.len()),
-            format_bytes_length(ctx.reasoning_content.len()),
+            format_bytes_length(reasoning.len()),
             ctx.tool_calls.len(),
             finish_reason
         );
 
         // 創建響應
-        let mut response = ChatCompletionResponse {
+        ChatCompletionResponse {
             id: format!("chatcmpl-{}", self.id),
             object: "chat.completion".to_string(),
             created: self.created,
@@ -552,36 +1752,337 @@ impl OutputGenerator {
                     role: "assistant".to_string(),
                     content,
                     refusal: None,
+                    annotations,
                     tool_calls: if ctx.tool_calls.is_empty() {
                         None
                     } else {
                         Some(ctx.tool_calls.clone())
                     },
-                    reasoning_content: if ctx.reasoning_content.trim().is_empty() {
+                    reasoning_content: (!reasoning.trim().is_empty()).then_some(reasoning),
+                    images: if ctx.images.is_empty() {
+                        None
+                    } else {
+                        Some(ctx.images.clone())
+                    },
+                    videos: if ctx.videos.is_empty() {
                         None
                     } else {
-                        Some(ctx.reasoning_content.clone())
+                        Some(ctx.videos.clone())
                     },
+                    audio,
+                    metadata: self.message_metadata(ctx),
                 },
                 logprobs: None,
                 finish_reason: Some(finish_reason),
             }],
-            usage: None,
+            // 非串流回應總是包含 usage，stream_options.include_usage 只影響串流
+            usage: Some(self.usage_value(completion_tokens)),
+        }
+    }
+
+    // 解碼階段：由 evert 處理 Poe 事件，返回依序發送的片段，正文尚未經過處理階段
+    fn decode_event(
+        &self,
+        handler_manager: &EventHandlerManager,
+        event: &ChatResponse,
+        mut media_output: MediaOutput,
+        ctx: &mut EventContext,
+    ) -> Vec<StreamPart<String>> {
+        // message.audio 只有一個，之後的音訊僅以連結放入正文
+        if ctx.audio.is_some() {
+            media_output.audio = None;
+        }
+
+        // 處理事件並獲取要發送的內容
+        let chunk_content_opt = handler_manager.handle(event, ctx);
+
+        // 已發送的正文被改寫時無法撤回，結束串流，之後此模型改用緩衝模式
+        if ctx.content_rewritten && ctx.error.is_none() {
+            mark_rewriting_model(&self.model);
+            ctx.error = Some(convert_poe_error_to_openai(CONTENT_REWRITTEN_MESSAGE, true));
+        }
+
+        // 檢查錯誤，中斷前先發送尚未發送的正文
+        if let Some((_, error_response)) = &ctx.error {
+            debug!("❌ 檢測到錯誤，中斷串流");
+            self.record_error();
+            let error_json = serde_json::to_string(error_response).unwrap();
+            let mut parts = Self::decode_pending(ctx);
+            parts.push(StreamPart::Error(error_json));
+            return parts;
+        }
+
+        if ctx.done {
+            debug!("✅ 檢測到完成信號");
+        }
+
+        // 正文類事件至少發送角色片段
+        let mut parts = Vec::new();
+        if matches!(
+            event.event,
+            ChatEventType::Text | ChatEventType::ReplaceResponse | ChatEventType::File
+        ) {
+            parts.push(StreamPart::Role);
+        }
+
+        // 普通 Text 正文可合併發送，其他事件需先發送累積中的正文以保持順序
+        let is_plain_text = event.event == ChatEventType::Text && chunk_content_opt.is_some();
+        let coalesced = if is_plain_text {
+            None
+        } else {
+            ctx.take_coalesced()
+        };
+        if event.event != ChatEventType::Done {
+            parts.extend(coalesced.clone().map(StreamPart::Text));
+        }
+
+        // 確保 delta.content 不會拆開表情序列等字素簇
+        match event.event {
+            ChatEventType::Text => {
+                debug!("📝 處理普通 Text 事件");
+                if let Some(content) = chunk_content_opt
+                    .and_then(|c| ctx.take_stream_safe(&c))
+                    .and_then(|c| ctx.coalesce_content(c))
+                {
+                    parts.push(StreamPart::Text(content));
+                }
+            }
+            ChatEventType::File => {
+                // 處理文件事件，如果返回了內容，表示有圖片引用需要立即處理
+                if let Some(content) = chunk_content_opt.and_then(|c| ctx.take_stream_safe(&c)) {
+                    debug!("🖼️ 處理檔案引用，產生包含URL的輸出");
+                    parts.push(StreamPart::Text(content));
+                }
+                // 以 message.images / message.videos / message.audio 返回附件
+                if !media_output.is_empty() {
+                    debug!("🖼️ 發送媒體片段");
+                    ctx.images.extend(media_output.image.clone());
+                    ctx.videos.extend(media_output.video.clone());
+                    if media_output.audio.is_some() {
+                        ctx.audio = media_output.audio.clone();
+                    }
+                    parts.push(StreamPart::Media(Box::new(media_output)));
+                }
+            }
+            ChatEventType::ReplaceResponse => {
+                // 如果 ReplaceResponse 直接返回了內容，說明其中包含了圖片引用
+                if let Some(content) = chunk_content_opt.and_then(|c| ctx.take_stream_safe(&c)) {
+                    debug!("🔄 ReplaceResponse 包含圖片引用，直接發送差異");
+                    parts.push(StreamPart::Text(content));
+                }
+            }
+            ChatEventType::Json => {
+                // 只發送本事件新增的工具調用
+                let new_tool_calls = ctx.take_new_tool_calls();
+                if !new_tool_calls.is_empty() {
+                    debug!("🔧 處理工具調用");
+                    parts.push(StreamPart::ToolCalls(new_tool_calls));
+                }
+            }
+            ChatEventType::Done => {
+                // 尚未發送的正文：累積中的正文、暫緩的字素簇及 Done 事件返回的內容
+                let mut remaining = coalesced.unwrap_or_default();
+                if let Some(tail) = ctx.flush_stream_tail() {
+                    remaining.push_str(&tail);
+                }
+                if let Some(content) = chunk_content_opt.filter(|c| c != "done") {
+                    remaining.push_str(&content);
+                }
+
+                let (_, completion_tokens, _) = self.calculate_tokens(ctx);
+                if let Some(usage) = &self.usage {
+                    usage.record(self.prompt_tokens, completion_tokens);
+                }
+                if self.needs_persist() {
+                    let mut store_ctx = ctx.clone();
+                    let response = self.create_final_response(&mut store_ctx);
+                    self.persist_completion(&response);
+                }
+
+                parts.push(StreamPart::Finish {
+                    has_text: !remaining.is_empty(),
+                    text: remaining,
+                    finish_reason: self.finish_reason(ctx),
+                    completion_tokens,
+                });
+            }
+            _ => {
+                // 其他事件類型，如果有返回內容也處理
+                parts.extend(chunk_content_opt.map(StreamPart::Text));
+            }
+        }
+        parts
+    }
+
+    // 串流中斷前尚未發送的正文（合併中、暫緩的字素簇），處理階段暫存的內容一併取出
+    fn decode_pending(ctx: &mut EventContext) -> Vec<StreamPart<String>> {
+        let mut pending = ctx.take_coalesced().unwrap_or_default();
+        if let Some(tail) = ctx.flush_stream_tail() {
+            pending.push_str(&tail);
+        }
+        vec![StreamPart::Flush(pending)]
+    }
+
+    // 處理階段：正文替換文件引用後依序通過 STREAM_STAGES
+    fn filter_parts(
+        &self,
+        parts: Vec<StreamPart<String>>,
+        ctx: &EventContext,
+    ) -> Vec<StreamPart<StageOutput>> {
+        let mut pipeline = self.pipeline.lock().unwrap();
+        let references = |text: String| self.process_file_references(&text, &ctx.file_refs);
+        parts
+            .into_iter()
+            .map(|part| match part {
+                StreamPart::Role => StreamPart::Role,
+                StreamPart::Text(text) => StreamPart::Text(pipeline.process(&references(text))),
+                StreamPart::Flush(text) => StreamPart::Flush(pipeline.finish(&references(text))),
+                StreamPart::Media(media) => StreamPart::Media(media),
+                StreamPart::ToolCalls(tool_calls) => StreamPart::ToolCalls(tool_calls),
+                StreamPart::Finish {
+                    text,
+                    has_text,
+                    finish_reason,
+                    completion_tokens,
+                } => StreamPart::Finish {
+                    text: pipeline.finish(&references(text)),
+                    has_text,
+                    finish_reason,
+                    completion_tokens,
+                },
+                StreamPart::Error(error) => StreamPart::Error(error),
+            })
+            .collect()
+    }
+
+    // 編碼階段：產生 OpenAI 串流片段，第一個非錯誤片段前先發送角色片段
+    fn encode_parts(&self, parts: Vec<StreamPart<StageOutput>>, ctx: &mut EventContext) -> String {
+        let mut output = String::new();
+        for part in parts {
+            if part.needs_role() && !ctx.role_chunk_sent {
+                output.push_str(&sse_data(&self.create_role_chunk()));
+                ctx.role_chunk_sent = true;
+            }
+            match part {
+                StreamPart::Role => {}
+                StreamPart::Text(stage_output) | StreamPart::Flush(stage_output) => {
+                    if !stage_output.is_empty() {
+                        output.push_str(&sse_data(&self.create_output_chunk(stage_output, None)));
+                    }
+                }
+                StreamPart::Media(media) => {
+                    output.push_str(&sse_data(&self.create_media_chunk(*media)));
+                }
+                StreamPart::ToolCalls(tool_calls) => {
+                    output.push_str(&sse_data(&self.create_tool_calls_chunk(tool_calls)));
+                }
+                StreamPart::Finish {
+                    text,
+                    has_text,
+                    finish_reason,
+                    completion_tokens,
+                } => {
+                    self.encode_finish(
+                        &mut output,
+                        ctx,
+                        text,
+                        has_text,
+                        finish_reason,
+                        completion_tokens,
+                    );
+                }
+                StreamPart::Error(error_json) => {
+                    output.push_str(&format!("data: {}\n\n", error_json));
+                }
+            }
+        }
+        output
+    }
+
+    // 編碼結尾片段：尚未發送的正文、finish_reason 與 usage
+    fn encode_finish(
+        &self,
+        output: &mut String,
+        ctx: &mut EventContext,
+        text: StageOutput,
+        has_text: bool,
+        finish_reason: &str,
+        completion_tokens: u32,
+    ) {
+        let compat = &self.stream_compat;
+        let finish_with_content = has_text
+            && compat.finish_reason.unwrap_or_default() == FinishReasonPlacement::WithContent;
+        let mut final_value = if finish_with_content {
+            debug!("✅ Done 事件包含尚未發送的正文，與 finish_reason 一起發送");
+            serde_json::to_value(self.create_output_chunk(text, Some(finish_reason.to_string())))
+                .unwrap()
+        } else {
+            // 尚未發送的正文與處理階段暫存的內容在結尾片段前發送
+            if !text.content.is_empty() || !text.reasoning.is_empty() {
+                debug!("✅ Done 事件包含尚未發送的正文，發送最終內容");
+                output.push_str(&sse_data(&self.create_output_chunk(text, None)));
+            }
+            let mut value = serde_json::to_value(
+                self.create_output_chunk(StageOutput::default(), Some(finish_reason.to_string())),
+            )
+            .unwrap();
+            if compat.final_delta.unwrap_or_default() == FinalDeltaShape::Empty {
+                value["choices"][0]["delta"] = json!({});
+            }
+            value
         };
 
+        let mut usage_part = None;
         if self.include_usage {
-            response.usage = Some(serde_json::json!({
-                "prompt_tokens": prompt_tokens,
-                "completion_tokens": completion_tokens,
-                "total_tokens": total_tokens,
-                "prompt_tokens_details": {"cached_tokens": 0}
-            }));
+            debug!(
+                "📊 Token 使用統計 | prompt_tokens: {} | completion_tokens: {} | total_tokens: {}",
+                self.prompt_tokens,
+                completion_tokens,
+                self.prompt_tokens + completion_tokens
+            );
+            let usage = self.usage_value(completion_tokens);
+            match compat.usage.unwrap_or_default() {
+                UsagePlacement::FinalChunk => {
+                    final_value["usage"] = usage;
+                }
+                placement => {
+                    let usage_chunk = json!({
+                        "id": format!("chatcmpl-{}", self.id),
+                        "object": "chat.completion.chunk",
+                        "created": self.created,
+                        "model": self.model,
+                        "choices": [],
+                        "usage": usage,
+                    });
+                    usage_part = Some((placement, usage_chunk));
+                }
+            }
+        }
+        if let Some(metadata) = self.message_metadata(ctx) {
+            debug!(
+                "💡 發送建議回覆 | 數量: {}",
+                metadata.suggested_replies.len()
+            );
+            output.push_str(&sse_data(&self.create_metadata_chunk(metadata)));
+        }
+        output.push_str(&sse_data(&final_value));
+        match usage_part {
+            Some((UsagePlacement::AfterDone, usage_chunk)) => {
+                ctx.after_done = Some(sse_data(&usage_chunk));
+            }
+            Some((_, usage_chunk)) => output.push_str(&sse_data(&usage_chunk)),
+            None => {}
         }
+    }
 
-        response
+    // 解碼、處理階段與編碼依序執行
+    fn render_parts(&self, parts: Vec<StreamPart<String>>, ctx: &mut EventContext) -> String {
+        let parts = self.filter_parts(parts, ctx);
+        self.encode_parts(parts, ctx)
     }
 
     // 直接處理串流事件並產生輸出，無需預讀
+    // 每個事件依序經過解碼 (decode_event)、處理階段 (filter_parts) 與編碼 (encode_parts)
     pub async fn process_stream<S>(
         self,
         event_stream: S,
@@ -589,13 +2090,15 @@ impl OutputGenerator {
     where
         S: Stream<Item = Result<ChatResponse, PoeError>> + Send + Unpin + 'static,
     {
-        let ctx = Arc::new(Mutex::new(EventContext::default()));
+        let ctx = Arc::new(Mutex::new(self.new_context()));
+        let ctx_tail = Arc::clone(&ctx);
+        let stream_compat = self.stream_compat.clone();
         let handler_manager = EventHandlerManager::new();
 
         // 直接用 unfold 邏輯處理事件流
         let stream_processor = stream::unfold(
             (event_stream, false, ctx, handler_manager, self),
-            move |(mut event_stream, mut is_done, ctx_arc, handler_manager, generator)| {
+            move |(mut event_stream, is_done, ctx_arc, handler_manager, generator)| {
                 let ctx_arc_clone = Arc::clone(&ctx_arc);
                 async move {
                     if is_done {
@@ -603,381 +2106,23 @@ impl OutputGenerator {
                         return None;
                     }
 
-                    match event_stream.next().await {
-                        Some(Ok(event)) => {
-                            // 鎖定上下文並處理事件
-                            let mut output_content: Option<String> = None;
-                            {
-                                let mut ctx_guard = ctx_arc_clone.lock().unwrap();
-
-                                // 處理事件並獲取要發送的內容
-                                let chunk_content_opt =
-                                    handler_manager.handle(&event, &mut ctx_guard);
-
-                                // 檢查錯誤
-                                if let Some((_, error_response)) = &ctx_guard.error {
-                                    debug!("❌ 檢測到錯誤，中斷串流");
-                                    let error_json = serde_json::to_string(error_response).unwrap();
+                    // 有累積中的正文時，等待下一個事件不超過預定發送時間
+                    let coalesce_deadline = ctx_arc_clone.lock().unwrap().coalesce_deadline();
+                    let next_event = match coalesce_deadline {
+                        Some(deadline) => {
+                            match tokio::time::timeout_at(deadline, event_stream.next()).await {
+                                Ok(next_event) => next_event,
+                                Err(_) => {
+                                    let output = {
+                                        let mut ctx_guard = ctx_arc_clone.lock().unwrap();
+                                        let parts = ctx_guard
+                                            .take_coalesced()
+                                            .map(StreamPart::Text)
+                                            .into_iter()
+                                            .collect();
+                                        generator.render_parts(parts, &mut ctx_guard)
+                                    };
                                     return Some((
-                                        Ok(format!("data: {}\n\n", error_json)),
-                                        (event_stream, true, ctx_arc, handler_manager, generator),
-                                    ));
-                                }
-
-                                // 檢查是否完成
-                                if ctx_guard.done {
-                                    debug!("✅ 檢測到完成信號");
-                                    is_done = true;
-                                }
-
-                                // 處理返回的內容
-                                match event.event {
-                                    ChatEventType::Text => {
-                                        if let Some(chunk_content) = chunk_content_opt {
-                                            debug!("📝 處理普通 Text 事件");
-
-                                            // 檢查是否是思考內容檢測標記
-                                            if chunk_content == "__REASONING_DETECTED__" {
-                                                debug!("🧠 檢測到思考內容，準備發送思考片段");
-
-                                                // 獲取最新的思考內容（從上次發送後的新增部分）
-                                                let current_reasoning_len =
-                                                    ctx_guard.reasoning_content.len();
-                                                let last_sent_reasoning_len = ctx_guard
-                                                    .get("last_sent_reasoning_len")
-                                                    .unwrap_or(0);
-
-                                                if current_reasoning_len > last_sent_reasoning_len {
-                                                    let new_reasoning = ctx_guard.reasoning_content
-                                                        [last_sent_reasoning_len..]
-                                                        .to_string();
-
-                                                    if !new_reasoning.trim().is_empty() {
-                                                        // 更新已發送的思考內容長度
-                                                        ctx_guard.insert(
-                                                            "last_sent_reasoning_len",
-                                                            current_reasoning_len,
-                                                        );
-
-                                                        // 發送角色塊（如果還沒發送）
-                                                        let mut output_parts = Vec::new();
-
-                                                        if !ctx_guard.role_chunk_sent {
-                                                            let role_chunk =
-                                                                generator.create_role_chunk();
-                                                            let role_json =
-                                                                serde_json::to_string(&role_chunk)
-                                                                    .unwrap();
-                                                            output_parts.push(format!(
-                                                                "data: {}",
-                                                                role_json
-                                                            ));
-                                                            ctx_guard.role_chunk_sent = true;
-                                                        }
-
-                                                        // 發送思考內容
-                                                        let reasoning_chunk = generator
-                                                            .create_reasoning_chunk(&new_reasoning);
-                                                        let reasoning_json =
-                                                            serde_json::to_string(&reasoning_chunk)
-                                                                .unwrap();
-                                                        output_parts.push(format!(
-                                                            "data: {}",
-                                                            reasoning_json
-                                                        ));
-
-                                                        let output =
-                                                            output_parts.join("\n\n") + "\n\n";
-                                                        debug!(
-                                                            "🧠 發送思考片段 | 長度: {}",
-                                                            format_bytes_length(output.len())
-                                                        );
-
-                                                        output_content = Some(output);
-                                                    }
-                                                }
-                                            } else {
-                                                // 正常內容處理
-                                                let processed = generator.process_file_references(
-                                                    &chunk_content,
-                                                    &ctx_guard.file_refs,
-                                                );
-
-                                                // 判斷是否需要發送角色塊
-                                                if !ctx_guard.role_chunk_sent {
-                                                    let role_chunk = generator.create_role_chunk();
-                                                    let role_json =
-                                                        serde_json::to_string(&role_chunk).unwrap();
-                                                    ctx_guard.role_chunk_sent = true;
-
-                                                    let content_chunk = generator
-                                                        .create_stream_chunk(&processed, None);
-                                                    let content_json =
-                                                        serde_json::to_string(&content_chunk)
-                                                            .unwrap();
-
-                                                    output_content = Some(format!(
-                                                        "data: {}\n\ndata: {}\n\n",
-                                                        role_json, content_json
-                                                    ));
-                                                } else {
-                                                    let chunk = generator
-                                                        .create_stream_chunk(&processed, None);
-                                                    let json =
-                                                        serde_json::to_string(&chunk).unwrap();
-                                                    output_content =
-                                                        Some(format!("data: {}\n\n", json));
-                                                }
-                                            }
-                                        }
-                                    }
-                                    ChatEventType::File => {
-                                        // 處理文件事件，如果返回了內容，表示有圖片引用需要立即處理
-                                        if let Some(chunk_content) = chunk_content_opt {
-                                            debug!("🖼️ 處理檔案引用，產生包含URL的輸出");
-
-                                            // 判斷是否需要發送角色塊
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-
-                                                let content_chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let content_json =
-                                                    serde_json::to_string(&content_chunk).unwrap();
-
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, content_json
-                                                ));
-                                            } else {
-                                                let chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let json = serde_json::to_string(&chunk).unwrap();
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                            }
-                                        }
-                                    }
-                                    ChatEventType::ReplaceResponse => {
-                                        // 如果 ReplaceResponse 直接返回了內容，說明其中包含了圖片引用
-                                        if let Some(chunk_content) = chunk_content_opt {
-                                            debug!("🔄 ReplaceResponse 包含圖片引用，直接發送");
-
-                                            // 判斷是否需要發送角色塊
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-
-                                                let content_chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let content_json =
-                                                    serde_json::to_string(&content_chunk).unwrap();
-
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, content_json
-                                                ));
-                                            } else {
-                                                let chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let json = serde_json::to_string(&chunk).unwrap();
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                            }
-                                        }
-                                    }
-                                    ChatEventType::Json => {
-                                        if !ctx_guard.tool_calls.is_empty() {
-                                            debug!("🔧 處理工具調用");
-                                            let tool_chunk = generator
-                                                .create_tool_calls_chunk(&ctx_guard.tool_calls);
-                                            let json = serde_json::to_string(&tool_chunk).unwrap();
-
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, json
-                                                ));
-                                            } else {
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                            }
-                                        }
-                                    }
-                                    ChatEventType::Done => {
-                                        // 如果 Done 事件返回了內容，表示有未處理的圖片引用
-                                        if let Some(chunk_content) = chunk_content_opt {
-                                            if chunk_content != "done" && !ctx_guard.image_urls_sent
-                                            {
-                                                debug!(
-                                                    "✅ Done 事件包含未處理的圖片引用，發送最終內容"
-                                                );
-                                                let chunk = generator.create_stream_chunk(
-                                                    &chunk_content,
-                                                    Some("stop".to_string()),
-                                                );
-                                                let json = serde_json::to_string(&chunk).unwrap();
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                                ctx_guard.image_urls_sent = true; // 標記已發送
-                                            } else {
-                                                // 一般完成事件
-                                                let (
-                                                    prompt_tokens,
-                                                    completion_tokens,
-                                                    total_tokens,
-                                                ) = generator.calculate_tokens(&mut ctx_guard);
-                                                let finish_reason =
-                                                    if !ctx_guard.tool_calls.is_empty() {
-                                                        "tool_calls"
-                                                    } else {
-                                                        "stop"
-                                                    };
-                                                let final_chunk = generator.create_stream_chunk(
-                                                    "",
-                                                    Some(finish_reason.to_string()),
-                                                );
-                                                let final_json = if generator.include_usage {
-                                                    debug!(
-                                                        "📊 Token 使用統計 | prompt_tokens: {} | completion_tokens: {} | total_tokens: {}",
-                                                        prompt_tokens,
-                                                        completion_tokens,
-                                                        total_tokens
-                                                    );
-                                                    let mut json_value =
-                                                        serde_json::to_value(&final_chunk).unwrap();
-                                                    json_value["usage"] = serde_json::json!({
-                                                        "prompt_tokens": prompt_tokens,
-                                                        "completion_tokens": completion_tokens,
-                                                        "total_tokens": total_tokens,
-                                                        "prompt_tokens_details": {"cached_tokens": 0}
-                                                    });
-                                                    serde_json::to_string(&json_value).unwrap()
-                                                } else {
-                                                    serde_json::to_string(&final_chunk).unwrap()
-                                                };
-
-                                                if !ctx_guard.role_chunk_sent {
-                                                    let role_chunk = generator.create_role_chunk();
-                                                    let role_json =
-                                                        serde_json::to_string(&role_chunk).unwrap();
-                                                    ctx_guard.role_chunk_sent = true;
-                                                    output_content = Some(format!(
-                                                        "data: {}\n\ndata: {}\n\n",
-                                                        role_json, final_json
-                                                    ));
-                                                } else {
-                                                    output_content =
-                                                        Some(format!("data: {}\n\n", final_json));
-                                                }
-                                            }
-                                        } else {
-                                            // 無內容的完成事件
-                                            let (prompt_tokens, completion_tokens, total_tokens) =
-                                                generator.calculate_tokens(&mut ctx_guard);
-                                            let finish_reason = if !ctx_guard.tool_calls.is_empty()
-                                            {
-                                                "tool_calls"
-                                            } else {
-                                                "stop"
-                                            };
-                                            let final_chunk = generator.create_stream_chunk(
-                                                "",
-                                                Some(finish_reason.to_string()),
-                                            );
-                                            let final_json = if generator.include_usage {
-                                                debug!(
-                                                    "📊 Token 使用統計 | prompt_tokens: {} | completion_tokens: {} | total_tokens: {}",
-                                                    prompt_tokens, completion_tokens, total_tokens
-                                                );
-                                                let mut json_value =
-                                                    serde_json::to_value(&final_chunk).unwrap();
-                                                json_value["usage"] = serde_json::json!({
-                                                    "prompt_tokens": prompt_tokens,
-                                                    "completion_tokens": completion_tokens,
-                                                    "total_tokens": total_tokens,
-                                                    "prompt_tokens_details": {"cached_tokens": 0}
-                                                });
-                                                serde_json::to_string(&json_value).unwrap()
-                                            } else {
-                                                serde_json::to_string(&final_chunk).unwrap()
-                                            };
-
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, final_json
-                                                ));
-                                            } else {
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", final_json));
-                                            }
-                                        }
-                                    }
-                                    _ => {
-                                        // 其他事件類型，如果有返回內容也處理
-                                        if let Some(chunk_content) = chunk_content_opt {
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-
-                                                let content_chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let content_json =
-                                                    serde_json::to_string(&content_chunk).unwrap();
-
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, content_json
-                                                ));
-                                            } else {
-                                                let chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let json = serde_json::to_string(&chunk).unwrap();
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                            }
-                                        }
-                                    }
-                                }
-
-                                // 如果沒有輸出內容且需要發送角色塊，則發送
-                                if output_content.is_none()
-                                    && !ctx_guard.role_chunk_sent
-                                    && (event.event == ChatEventType::Text
-                                        || event.event == ChatEventType::ReplaceResponse
-                                        || event.event == ChatEventType::File)
-                                {
-                                    let role_chunk = generator.create_role_chunk();
-                                    let role_json = serde_json::to_string(&role_chunk).unwrap();
-                                    ctx_guard.role_chunk_sent = true;
-                                    output_content = Some(format!("data: {}\n\n", role_json));
-                                }
-                            }
-
-                            // 返回輸出內容
-                            if let Some(output) = output_content {
-                                if !output.trim().is_empty() {
-                                    debug!(
-                                        "📤 發送串流片段 | 長度: {}",
-                                        format_bytes_length(output.len())
-                                    );
-                                    Some((
                                         Ok(output),
                                         (
                                             event_stream,
@@ -986,59 +2131,99 @@ impl OutputGenerator {
                                             handler_manager,
                                             generator,
                                         ),
-                                    ))
-                                } else {
-                                    // 空輸出，繼續處理
-                                    Some((
-                                        Ok(String::new()),
-                                        (
-                                            event_stream,
-                                            is_done,
-                                            ctx_arc,
-                                            handler_manager,
-                                            generator,
-                                        ),
-                                    ))
+                                    ));
                                 }
-                            } else {
-                                // 沒有輸出，但繼續處理
-                                Some((
-                                    Ok(String::new()),
-                                    (event_stream, is_done, ctx_arc, handler_manager, generator),
-                                ))
                             }
                         }
+                        None => event_stream.next().await,
+                    };
+
+                    match next_event {
+                        Some(Ok(mut event)) => {
+                            // 附件需在鎖定上下文前處理（影片與音訊轉存、b64 圖片及音訊會下載檔案）
+                            let media_output =
+                                prepare_event_attachment(&mut event, generator.audio_output).await;
+
+                            let (output, is_done) = {
+                                let mut ctx_guard = ctx_arc_clone.lock().unwrap();
+                                let parts = generator.decode_event(
+                                    &handler_manager,
+                                    &event,
+                                    media_output,
+                                    &mut ctx_guard,
+                                );
+                                let is_done = ctx_guard.done || ctx_guard.error.is_some();
+                                (generator.render_parts(parts, &mut ctx_guard), is_done)
+                            };
+                            if !output.is_empty() {
+                                debug!(
+                                    "📤 發送串流片段 | 長度: {}",
+                                    format_bytes_length(output.len())
+                                );
+                            }
+                            Some((
+                                Ok(output),
+                                (event_stream, is_done, ctx_arc, handler_manager, generator),
+                            ))
+                        }
                         Some(Err(e)) => {
-                            error!("❌ 串流處理錯誤: {}", e);
+                            error!(
+                                "{}",
+                                tr!("❌ 串流處理錯誤: {}", "❌ Stream processing error: {}", e)
+                            );
+                            generator.record_error();
                             let error_response = convert_poe_error_to_openai(&e.to_string(), false);
                             let error_json = serde_json::to_string(&error_response.1).unwrap();
+                            // 中斷前先發送尚未發送的正文
+                            let output = {
+                                let mut ctx_guard = ctx_arc_clone.lock().unwrap();
+                                let mut parts = OutputGenerator::decode_pending(&mut ctx_guard);
+                                parts.push(StreamPart::Error(error_json));
+                                generator.render_parts(parts, &mut ctx_guard)
+                            };
                             Some((
-                                Ok(format!("data: {}\n\n", error_json)),
+                                Ok(output),
                                 (event_stream, true, ctx_arc, handler_manager, generator),
                             ))
                         }
                         None => {
                             debug!("⏹️ 事件流結束");
-                            None
+                            // 事件流未經 Done 結束時，仍發送尚未發送的正文
+                            let output = {
+                                let mut ctx_guard = ctx_arc_clone.lock().unwrap();
+                                let parts = OutputGenerator::decode_pending(&mut ctx_guard);
+                                generator.render_parts(parts, &mut ctx_guard)
+                            };
+                            (!output.is_empty()).then_some((
+                                Ok(output),
+                                (event_stream, true, ctx_arc, handler_manager, generator),
+                            ))
                         }
                     }
                 }
             },
         );
 
-        // 添加結束消息
-        let done_message = "data: [DONE]\n\n".to_string();
+        // 添加結束消息，以及需要在 [DONE] 之後發送的內容
+        let done_marker = stream_compat.done_marker.unwrap_or(true);
+        let ending = stream::once(async move {
+            let mut ending = if done_marker {
+                "data: [DONE]\n\n".to_string()
+            } else {
+                String::new()
+            };
+            if let Some(after_done) = ctx_tail.lock().unwrap().after_done.take() {
+                ending.push_str(&after_done);
+            }
+            Ok(ending)
+        });
 
         // 過濾掉空的訊息，並加上結束訊息
-        Box::pin(
-            stream_processor
-                .filter(|result| {
-                    future::ready(match result {
-                        Ok(s) => !s.is_empty(),
-                        Err(_) => true,
-                    })
-                })
-                .chain(stream::once(future::ready(Ok(done_message)))),
-        )
+        Box::pin(stream_processor.chain(ending).filter(|result| {
+            future::ready(match result {
+                Ok(s) => !s.is_empty(),
+                Err(_) => true,
+            })
+        }))
     }
 }
@@ -0,0 +1,287 @@
+use crate::types::{OpenAIError, OpenAIErrorResponse};
+use salvo::prelude::*;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use tracing::{debug, info, warn};
+
+/// Depot 中存放客戶端真實 IP 的鍵名
+const CLIENT_IP_KEY: &str = "client_ip";
+
+/// IP 網段（IP 或 CIDR），用於受信任代理與 IP 允許/拒絕列表
+#[derive(Debug, Clone, Copy)]
+struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    fn parse(value: &str) -> Option<Self> {
+        let (ip_part, prefix_part) = match value.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (value, None),
+        };
+        let addr: IpAddr = ip_part.trim().parse().ok()?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix_part {
+            Some(p) => p.trim().parse::<u8>().ok().filter(|p| *p <= max_prefix)?,
+            None => max_prefix,
+        };
+        Some(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, normalize_ip(*ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+static TRUSTED_PROXIES: OnceLock<Vec<IpNet>> = OnceLock::new();
+static IP_ALLOWLIST: OnceLock<Vec<IpNet>> = OnceLock::new();
+static IP_DENYLIST: OnceLock<Vec<IpNet>> = OnceLock::new();
+
+/// 解析以逗號分隔的 IP 或 CIDR 列表，忽略無效項目
+fn parse_ip_nets(name: &str) -> Vec<IpNet> {
+    let raw = std::env::var(name).unwrap_or_default();
+    let mut nets = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match IpNet::parse(entry) {
+            Some(net) => nets.push(net),
+            None => warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無效的 {} 項目，已忽略: {}",
+                    "⚠️ Ignoring invalid {} entry: {}",
+                    name,
+                    entry
+                )
+            ),
+        }
+    }
+    nets
+}
+
+/// 取得受信任代理列表，僅初始化一次
+fn get_trusted_proxies() -> &'static [IpNet] {
+    TRUSTED_PROXIES.get_or_init(|| {
+        let nets = parse_ip_nets("TRUSTED_PROXIES");
+        if nets.is_empty() {
+            info!(
+                "{}",
+                tr!(
+                    "🛡️  受信任代理: 未設定，忽略 X-Forwarded-For / Forwarded 頭部",
+                    "🛡️  Trusted proxies: none, ignoring X-Forwarded-For / Forwarded headers"
+                )
+            );
+        } else {
+            info!(
+                "{}",
+                tr!(
+                    "🛡️  受信任代理: {} 個網段 ({})",
+                    "🛡️  Trusted proxies: {} networks ({})",
+                    nets.len(),
+                    std::env::var("TRUSTED_PROXIES").unwrap_or_default().trim()
+                )
+            );
+        }
+        nets
+    })
+}
+
+/// 取得 IP 允許列表 (IP_ALLOWLIST)，空列表表示不限制
+fn get_ip_allowlist() -> &'static [IpNet] {
+    IP_ALLOWLIST.get_or_init(|| {
+        let nets = parse_ip_nets("IP_ALLOWLIST");
+        if !nets.is_empty() {
+            info!(
+                "{}",
+                tr!(
+                    "🛡️  IP 允許列表: {} 個網段",
+                    "🛡️  IP allowlist: {} networks",
+                    nets.len()
+                )
+            );
+        }
+        nets
+    })
+}
+
+/// 取得 IP 拒絕列表 (IP_DENYLIST)
+fn get_ip_denylist() -> &'static [IpNet] {
+    IP_DENYLIST.get_or_init(|| {
+        let nets = parse_ip_nets("IP_DENYLIST");
+        if !nets.is_empty() {
+            info!(
+                "{}",
+                tr!(
+                    "🛡️  IP 拒絕列表: {} 個網段",
+                    "🛡️  IP denylist: {} networks",
+                    nets.len()
+                )
+            );
+        }
+        nets
+    })
+}
+
+/// 客戶端 IP 是否允許存取：拒絕列表優先，設定允許列表時只允許列表中的位址
+fn is_ip_allowed(ip: Option<&IpAddr>) -> bool {
+    let allowlist = get_ip_allowlist();
+    let denylist = get_ip_denylist();
+    match ip {
+        Some(ip) => {
+            !denylist.iter().any(|net| net.contains(ip))
+                && (allowlist.is_empty() || allowlist.iter().any(|net| net.contains(ip)))
+        }
+        // 無法取得 IP 時，只有未設定允許列表才放行
+        None => allowlist.is_empty(),
+    }
+}
+
+/// 將 IPv4-mapped IPv6 位址轉換回 IPv4，便於比對
+fn normalize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        v4 => v4,
+    }
+}
+
+fn is_trusted(ip: &IpAddr) -> bool {
+    get_trusted_proxies().iter().any(|net| net.contains(ip))
+}
+
+/// 初始化受信任代理與 IP 允許/拒絕列表（於啟動時記錄日誌）
+pub fn init_trusted_proxies() {
+    get_trusted_proxies();
+    get_ip_allowlist();
+    get_ip_denylist();
+}
+
+/// 解析 Forwarded 頭部中的單個 for= 值，如 `for="[2001:db8::1]:4711"`
+fn parse_forwarded_for(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    // IPv4 帶端口
+    value.rsplit_once(':')?.0.parse().ok()
+}
+
+/// 從請求頭部中收集轉發鏈（由最早的客戶端到最近的代理）
+fn forwarded_chain(req: &Request) -> Vec<IpAddr> {
+    let xff: Vec<IpAddr> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|s| s.split(','))
+        .filter_map(parse_forwarded_for)
+        .collect();
+    if !xff.is_empty() {
+        return xff;
+    }
+
+    req.headers()
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|s| s.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                if key.trim().eq_ignore_ascii_case("for") {
+                    parse_forwarded_for(value)
+                } else {
+                    None
+                }
+            })
+        })
+        .collect()
+}
+
+/// 根據受信任代理設定解析客戶端真實 IP
+fn resolve_client_ip(req: &Request) -> Option<IpAddr> {
+    let peer = req.remote_addr().clone().into_std().map(|a| a.ip())?;
+    if !is_trusted(&peer) {
+        return Some(normalize_ip(peer));
+    }
+
+    // 由右至左跳過受信任的代理，第一個不受信任的位址即為客戶端
+    let chain = forwarded_chain(req);
+    let client = chain
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted(ip))
+        .or_else(|| chain.first())
+        .copied()
+        .unwrap_or(peer);
+    Some(normalize_ip(client))
+}
+
+/// 從 Depot 取得已解析的客戶端 IP
+pub fn get_client_ip(depot: &Depot) -> String {
+    depot
+        .get::<String>(CLIENT_IP_KEY)
+        .cloned()
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[handler]
+pub async fn client_ip_middleware(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let resolved = resolve_client_ip(req);
+    let client_ip = resolved
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    debug!(
+        "🌍 客戶端 IP: {} | 連線來源: {}",
+        client_ip,
+        req.remote_addr()
+    );
+    if !is_ip_allowed(resolved.as_ref()) {
+        warn!(
+            "{}",
+            tr!(
+                "🚫 拒絕來自不允許 IP 的請求: {} | 路徑: {}",
+                "🚫 Rejected request from disallowed IP: {} | path: {}",
+                client_ip,
+                req.uri().path()
+            )
+        );
+        res.status_code(StatusCode::FORBIDDEN);
+        res.render(Json(OpenAIErrorResponse {
+            error: OpenAIError {
+                message: tr!(
+                    "此 IP 不允許存取：{}",
+                    "Requests from IP {} are not allowed.",
+                    client_ip
+                ),
+                r#type: "invalid_request_error".to_string(),
+                code: "permission_denied".to_string(),
+                param: None,
+            },
+        }));
+        ctrl.skip_rest();
+        return;
+    }
+    depot.insert(CLIENT_IP_KEY, client_ip);
+    ctrl.call_next(req, depot, res).await;
+}
@@ -0,0 +1,161 @@
+//! 合併相同的並行請求 (models.yaml 的 coalesce / key_coalesce)
+//!
+//! 同一 API Key 對同一模型送出完全相同的非串流請求時，只有最先到達的請求呼叫 Poe，
+//! 其餘請求等待其完成後取得相同的回應，避免 webhook 重試等情況重複消耗點數。
+//! 首個請求未產生可共享的回應（錯誤回應、提前返回、客戶端中斷或以保活串流返回）時，
+//! 等待中的請求改由其中一個重新呼叫 Poe
+
+use crate::store::owner_hash;
+use crate::types::ChatCompletionRequest;
+use salvo::http::{HeaderValue, ResBody, header};
+use salvo::hyper::body::Bytes;
+use salvo::prelude::*;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+/// 首個請求完成後共享的回應
+pub(super) struct SharedResponse {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+type Slot = watch::Sender<Option<Arc<SharedResponse>>>;
+
+/// 進行中的請求，以請求內容的雜湊為鍵
+static IN_FLIGHT: LazyLock<Mutex<HashMap<String, Slot>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 以共享回應完成的請求數
+static SHARED_COUNT: AtomicU64 = AtomicU64::new(0);
+
+fn lock_in_flight() -> std::sync::MutexGuard<'static, HashMap<String, Slot>> {
+    IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 合併鍵：API Key、原始模型與完整的請求內容
+pub(super) fn coalesce_key(
+    access_key: &str,
+    model: &str,
+    request: &ChatCompletionRequest,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(owner_hash(access_key).as_bytes());
+    hasher.update([0]);
+    hasher.update(model.as_bytes());
+    hasher.update([0]);
+    // 暫存附件的 ID 每次請求都不同，改以內容哈希計算
+    let mut request = serde_json::to_value(request).unwrap_or_default();
+    crate::spool::hash_refs(&mut request);
+    hasher.update(serde_json::to_vec(&request).unwrap_or_default());
+    format!("{:x}", hasher.finalize())
+}
+
+/// 首個請求持有，完成時以 finish 共享回應；未共享即被丟棄時喚醒等待中的請求重新競爭
+pub(super) struct CoalesceLeader {
+    key: String,
+    slot: Slot,
+}
+
+impl CoalesceLeader {
+    /// 共享已寫入的成功回應；錯誤回應不共享，等待中的請求重新競爭並各自重試，
+    /// 串流形式的回應無法共享，原樣保留
+    pub(super) fn finish(self, res: &mut Response) {
+        let status = res.status_code.unwrap_or(StatusCode::OK);
+        if !status.is_success() {
+            debug!("🔗 回應狀態碼為 {}，不共享給等待中的請求", status);
+            return;
+        }
+        let ResBody::Once(body) = res.take_body() else {
+            debug!("🔗 回應不是完整的內容，無法共享給等待中的請求");
+            return;
+        };
+        res.replace_body(ResBody::Once(body.clone()));
+        let waiters = self.slot.receiver_count();
+        // 即使目前沒有等待者也保存回應，剛加入的請求仍可取得
+        self.slot.send_replace(Some(Arc::new(SharedResponse {
+            status,
+            content_type: res.headers().get(header::CONTENT_TYPE).cloned(),
+            body,
+        })));
+        if waiters > 0 {
+            debug!("🔗 共享回應給 {} 個等待中的請求", waiters);
+        }
+    }
+}
+
+impl Drop for CoalesceLeader {
+    fn drop(&mut self) {
+        let mut in_flight = lock_in_flight();
+        if in_flight
+            .get(&self.key)
+            .is_some_and(|slot| slot.same_channel(&self.slot))
+        {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+pub(super) enum Coalesced {
+    /// 沒有相同的請求進行中，由此請求呼叫 Poe
+    Leader(CoalesceLeader),
+    /// 相同請求的回應
+    Shared(Arc<SharedResponse>),
+}
+
+/// 加入合併：相同請求進行中時等待其回應，否則成為首個請求
+pub(super) async fn coalesce(key: String) -> Coalesced {
+    loop {
+        let mut receiver = {
+            let mut in_flight = lock_in_flight();
+            match in_flight.get(&key) {
+                Some(slot) => slot.subscribe(),
+                None => {
+                    let (slot, _) = watch::channel(None);
+                    in_flight.insert(key.clone(), slot.clone());
+                    return Coalesced::Leader(CoalesceLeader { key, slot });
+                }
+            }
+        };
+        debug!("🔗 相同請求進行中，等待其回應");
+        let _ = receiver.changed().await;
+        if let Some(shared) = receiver.borrow().clone() {
+            SHARED_COUNT.fetch_add(1, Ordering::Relaxed);
+            return Coalesced::Shared(shared);
+        }
+        debug!("🔗 首個請求未產生可共享的回應，重新加入合併");
+    }
+}
+
+/// 以共享的回應回覆等待中的請求
+pub(super) fn render_shared(shared: &SharedResponse, res: &mut Response) {
+    info!(
+        "{}",
+        tr!(
+            "🔗 合併相同的並行請求，返回共享的回應 | 狀態碼: {}",
+            "🔗 Coalesced identical concurrent request, returning the shared response | status: {}",
+            shared.status
+        )
+    );
+    res.status_code(shared.status);
+    if let Some(content_type) = &shared.content_type {
+        res.headers_mut()
+            .insert(header::CONTENT_TYPE, content_type.clone());
+    }
+    res.headers_mut()
+        .insert("X-Coalesced", HeaderValue::from_static("true"));
+    res.replace_body(ResBody::Once(shared.body.clone()));
+}
+
+/// 進行中的合併鍵數及以共享回應完成的請求數
+pub(super) fn coalesce_stats() -> serde_json::Value {
+    json!({
+        "in_flight": lock_in_flight().len(),
+        "shared": SHARED_COUNT.load(Ordering::Relaxed),
+    })
+}
@@ -0,0 +1,304 @@
+//! 文字補全 (/v1/completions)
+//!
+//! prompt 依模型的 instruct_template 拆解為對話訊息後改寫為聊天完成請求，交由聊天完成處理，
+//! 再將回應轉換為 text_completion 格式。範本與請求的停止序列一併傳給 Poe，並在本地截斷，
+//! 確保回覆不會延續到下一個角色的標記；命中停止序列後立即結束串流
+
+use super::body::read_json_body;
+use super::chat::{body_size_limits, chat_completions, render_body_error, resolve_model};
+use crate::cache::get_cached_config;
+use crate::instruct::{prompt_to_messages, template_stops};
+use crate::pipeline::StopSequences;
+use crate::types::{OpenAIError, OpenAIErrorResponse};
+use futures_util::stream::{self, Stream, StreamExt};
+use salvo::http::body::ReqBody;
+use salvo::http::{ResBody, header};
+use salvo::prelude::*;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use tracing::{debug, info};
+
+#[derive(Deserialize)]
+struct CompletionRequest {
+    model: String,
+    prompt: PromptInput,
+    #[serde(default)]
+    stop: Option<StopInput>,
+    /// 其餘欄位（stream、max_tokens、temperature 等）原樣帶入聊天完成請求
+    #[serde(flatten)]
+    other: serde_json::Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PromptInput {
+    Text(String),
+    Batch(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StopInput {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// 聊天完成沒有對應的文字補全參數，轉換時移除
+const UNSUPPORTED_PARAMS: [&str; 4] = ["echo", "suffix", "best_of", "logprobs"];
+
+fn invalid_prompt(res: &mut Response, message: &str) {
+    res.status_code(StatusCode::BAD_REQUEST);
+    res.render(Json(OpenAIErrorResponse {
+        error: OpenAIError {
+            message: message.to_string(),
+            r#type: "invalid_request_error".to_string(),
+            code: "invalid_prompt".to_string(),
+            param: Some("prompt".to_string()),
+        },
+    }));
+}
+
+#[handler]
+pub async fn text_completions(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let (max_size, max_field_size) = body_size_limits();
+    let request = match read_json_body::<CompletionRequest>(req, max_size, max_field_size).await {
+        Ok(request) => request,
+        Err(e) => {
+            render_body_error(&e, res);
+            return;
+        }
+    };
+    let prompt = match request.prompt {
+        PromptInput::Text(prompt) => prompt,
+        PromptInput::Batch(mut prompts) if prompts.len() == 1 => prompts.remove(0),
+        PromptInput::Batch(_) => {
+            invalid_prompt(res, "Only a single prompt is supported per request.");
+            return;
+        }
+    };
+
+    let config = get_cached_config().await;
+    let (_, original_model) = resolve_model(&config, &request.model);
+    let template = config
+        .models
+        .get(&original_model)
+        .and_then(|model_config| model_config.instruct_template.as_ref());
+    let messages = prompt_to_messages(template, &prompt);
+    let mut stop = match request.stop {
+        Some(StopInput::One(stop)) => vec![stop],
+        Some(StopInput::Many(stop)) => stop,
+        None => Vec::new(),
+    };
+    for template_stop in template_stops(template) {
+        if !stop.contains(&template_stop) {
+            stop.push(template_stop);
+        }
+    }
+    stop.retain(|s| !s.is_empty());
+    info!(
+        "{}",
+        tr!(
+            "📝 文字補全請求 | 模型: {} | 範本: {} | 拆解訊息數: {} | 停止序列: {}",
+            "📝 Text completion request | model: {} | template: {} | messages: {} | stop sequences: {}",
+            request.model,
+            template.map_or("raw".to_string(), |t| format!("{:?}", t)),
+            messages.len(),
+            stop.len()
+        )
+    );
+
+    // 改寫為聊天完成請求，交由聊天完成處理
+    let mut body = request.other;
+    for param in UNSUPPORTED_PARAMS {
+        body.remove(param);
+    }
+    body.insert("model".to_string(), json!(request.model));
+    body.insert("messages".to_string(), json!(messages));
+    if !stop.is_empty() {
+        body.insert("stop".to_string(), json!(stop));
+    }
+    let body = serde_json::to_vec(&body).unwrap_or_default();
+    req.headers_mut().remove(header::CONTENT_ENCODING);
+    req.headers_mut().insert(
+        header::CONTENT_LENGTH,
+        body.len().to_string().parse().unwrap(),
+    );
+    req.replace_body(ReqBody::Once(body.into()));
+    chat_completions.handle(req, depot, res, ctrl).await;
+
+    if !res.status_code.unwrap_or(StatusCode::OK).is_success() {
+        return;
+    }
+    let stops = StopSequences::new(&stop);
+    let is_stream = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+    match res.take_body() {
+        ResBody::Once(bytes) if !is_stream => {
+            let Ok(response) = serde_json::from_slice::<Value>(&bytes) else {
+                res.replace_body(ResBody::Once(bytes));
+                return;
+            };
+            res.render(Json(convert_response(&response, stops)));
+        }
+        body if is_stream => res.stream(convert_stream(body, stops)),
+        body => {
+            res.replace_body(body);
+        }
+    }
+}
+
+/// 聊天完成的 ID 改為文字補全的前綴
+fn completion_id(id: &Value) -> Value {
+    match id.as_str() {
+        Some(id) => json!(format!(
+            "cmpl-{}",
+            id.strip_prefix("chatcmpl-").unwrap_or(id)
+        )),
+        None => id.clone(),
+    }
+}
+
+fn text_completion(chunk: &Value, choices: Value) -> Value {
+    let mut value = json!({
+        "id": completion_id(&chunk["id"]),
+        "object": "text_completion",
+        "created": chunk["created"],
+        "model": chunk["model"],
+        "choices": choices,
+    });
+    if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+        value["usage"] = usage.clone();
+    }
+    value
+}
+
+fn text_choice(text: String, finish_reason: Value) -> Value {
+    json!([{
+        "text": text,
+        "index": 0,
+        "logprobs": null,
+        "finish_reason": finish_reason,
+    }])
+}
+
+/// 非串流回應：取出助手訊息的正文並套用停止序列
+fn convert_response(response: &Value, mut stops: StopSequences) -> Value {
+    let Some(choice) = response["choices"].get(0) else {
+        return response.clone();
+    };
+    let content = choice["message"]["content"].as_str().unwrap_or_default();
+    let mut text = stops.process(content);
+    text.push_str(&stops.finish());
+    let finish_reason = if stops.stopped() {
+        debug!("🛑 文字補全命中停止序列");
+        json!("stop")
+    } else {
+        choice["finish_reason"].clone()
+    };
+    text_completion(response, text_choice(text, finish_reason))
+}
+
+/// 串流回應的轉換狀態
+struct TextStream {
+    body: ResBody,
+    // 尚未組成完整 SSE 事件的位元組
+    buffer: Vec<u8>,
+    stops: StopSequences,
+    finished: bool,
+}
+
+impl TextStream {
+    /// 轉換一個 SSE 事件，返回 None 表示略過；命中停止序列時附上 [DONE] 並結束
+    fn convert_event(&mut self, event: &str) -> Option<String> {
+        let mut lines = Vec::new();
+        for line in event.lines() {
+            let Some(chunk) = line
+                .strip_prefix("data: ")
+                .and_then(|data| serde_json::from_str::<Value>(data).ok())
+                .filter(|chunk| chunk.get("choices").is_some())
+            else {
+                lines.push(line.to_string());
+                continue;
+            };
+            let Some(choice) = chunk["choices"].get(0) else {
+                // 只帶 usage 的片段
+                lines.push(format!("data: {}", text_completion(&chunk, json!([]))));
+                continue;
+            };
+            let content = choice["delta"]["content"].as_str().unwrap_or_default();
+            let mut text = self.stops.process(content);
+            let mut finish_reason = choice["finish_reason"].clone();
+            if !finish_reason.is_null() {
+                text.push_str(&self.stops.finish());
+            }
+            if self.stops.stopped() {
+                debug!("🛑 文字補全命中停止序列，結束串流");
+                finish_reason = json!("stop");
+                self.finished = true;
+            }
+            if text.is_empty() && finish_reason.is_null() {
+                return None;
+            }
+            lines.push(format!(
+                "data: {}",
+                text_completion(&chunk, text_choice(text, finish_reason))
+            ));
+            if self.finished {
+                lines.push(String::new());
+                lines.push("data: [DONE]".to_string());
+            }
+        }
+        Some(format!("{}\n\n", lines.join("\n")))
+    }
+}
+
+fn convert_stream(
+    body: ResBody,
+    stops: StopSequences,
+) -> impl Stream<Item = Result<String, Infallible>> + Send + 'static {
+    let state = TextStream {
+        body,
+        buffer: Vec::new(),
+        stops,
+        finished: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if state.finished {
+                return None;
+            }
+            if let Some(pos) = state.buffer.windows(2).position(|w| w == b"\n\n") {
+                let event: Vec<u8> = state.buffer.drain(..pos + 2).collect();
+                let event = String::from_utf8_lossy(&event[..pos]).to_string();
+                match state.convert_event(&event) {
+                    Some(output) => return Some((Ok(output), state)),
+                    None => continue,
+                }
+            }
+            match state.body.next().await {
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data() {
+                        state.buffer.extend_from_slice(&data);
+                    }
+                }
+                _ => {
+                    state.finished = true;
+                    if state.buffer.is_empty() {
+                        return None;
+                    }
+                    let rest = String::from_utf8_lossy(&state.buffer).to_string();
+                    return Some((Ok(rest), state));
+                }
+            }
+        }
+    })
+}
@@ -1,6 +1,148 @@
 use salvo::http::{HeaderValue, Method, StatusCode, header};
 use salvo::prelude::*;
-use tracing::{debug, info};
+use std::sync::OnceLock;
+use tracing::{debug, info, warn};
+
+/// 預設允許的方法
+const DEFAULT_ALLOWED_METHODS: &str = "GET, POST, OPTIONS, PUT, DELETE, PATCH, HEAD";
+
+/// 基礎硬編碼頭部（保持向後兼容）
+const BASE_ALLOWED_HEADERS: &[&str] = &[
+    "Authorization",
+    "Content-Type",
+    "User-Agent",
+    "Accept",
+    "Origin",
+    "X-Requested-With",
+    "Access-Control-Request-Method",
+    "Access-Control-Request-Headers",
+    "Accept-Encoding",
+    "Accept-Language",
+    "Cache-Control",
+    "Connection",
+    "Referer",
+    "Sec-Fetch-Dest",
+    "Sec-Fetch-Mode",
+    "Sec-Fetch-Site",
+    "Pragma",
+    "X-Api-Key",
+];
+
+/// CORS 設定，啟動時從環境變數讀取一次
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// 允許的 Origin 列表，支援 `*` 萬用字元（如 `https://*.example.com`）
+    /// 為空表示不允許任何跨域來源
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: String,
+    /// None 表示使用基礎頭部 + 客戶端請求的安全頭部
+    pub allowed_headers: Option<Vec<String>>,
+    pub allow_credentials: bool,
+    pub max_age: u64,
+}
+
+static CORS_CONFIG: OnceLock<CorsConfig> = OnceLock::new();
+
+impl CorsConfig {
+    fn from_env() -> Self {
+        let allowed_origins =
+            parse_list(&std::env::var("CORS_ALLOWED_ORIGINS").unwrap_or_else(|_| "*".to_string()));
+        let allowed_methods = std::env::var("CORS_ALLOWED_METHODS")
+            .ok()
+            .map(|s| parse_list(&s).join(", "))
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| DEFAULT_ALLOWED_METHODS.to_string());
+        let allowed_headers = std::env::var("CORS_ALLOWED_HEADERS")
+            .ok()
+            .map(|s| parse_list(&s))
+            .filter(|list| !list.is_empty() && !list.iter().any(|h| h == "*"));
+        // 允許任意來源時會回顯請求的 Origin，需明確設定 CORS_ALLOW_CREDENTIALS=true 才攜帶憑證
+        let any_origin = allowed_origins.iter().any(|o| o == "*");
+        let allow_credentials = std::env::var("CORS_ALLOW_CREDENTIALS")
+            .map(|s| s.trim().eq_ignore_ascii_case("true"))
+            .unwrap_or(!any_origin);
+        let max_age = std::env::var("CORS_MAX_AGE")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(3600);
+
+        Self {
+            allowed_origins,
+            allowed_methods,
+            allowed_headers,
+            allow_credentials,
+            max_age,
+        }
+    }
+
+    /// 判斷 Origin 是否在允許列表中
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|pattern| origin_matches(pattern, origin))
+    }
+}
+
+/// 取得全域 CORS 設定，僅初始化一次
+pub fn get_cors_config() -> &'static CorsConfig {
+    CORS_CONFIG.get_or_init(|| {
+        let config = CorsConfig::from_env();
+        info!("{}", tr!("🌐 CORS 設定 | 允許來源: {} | 允許方法: {} | 允許頭部: {} | 憑證: {} | 預檢緩存: {}s", "🌐 CORS | allowed origins: {} | allowed methods: {} | allowed headers: {} | credentials: {} | preflight max age: {}s",
+            if config.allowed_origins.is_empty() {
+                "(無)".to_string()
+            } else {
+                config.allowed_origins.join(", ")
+            },
+            config.allowed_methods,
+            config
+                .allowed_headers
+                .as_ref()
+                .map(|h| h.join(", "))
+                .unwrap_or_else(|| tr!("(基礎頭部 + 動態頭部)", "(base headers + requested headers)")),
+            config.allow_credentials,
+            config.max_age));
+        if config.allow_credentials && config.allowed_origins.iter().any(|o| o == "*") {
+            warn!("{}", tr!("⚠️ CORS 允許任意來源並攜帶憑證，建議透過 CORS_ALLOWED_ORIGINS 限制來源", "⚠️ CORS allows any origin with credentials; consider restricting origins with CORS_ALLOWED_ORIGINS"));
+        }
+        config
+    })
+}
+
+/// 解析逗號分隔的設定值
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// 比對 Origin 與允許規則，`*` 可匹配任意字元序列
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let pattern = pattern.trim_end_matches('/').to_lowercase();
+    let origin = origin.trim_end_matches('/').to_lowercase();
+    if !pattern.contains('*') {
+        return pattern == origin;
+    }
+
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let first = parts[0];
+    let last = parts[parts.len() - 1];
+    if !origin.starts_with(first) || origin.len() < first.len() + last.len() {
+        return false;
+    }
+    let mut rest = &origin[first.len()..];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
 
 /// 检查头部是否安全
 fn is_safe_header(header: &str) -> bool {
@@ -61,43 +203,72 @@ pub async fn cors_middleware(
     res: &mut Response,
     ctrl: &mut FlowCtrl,
 ) {
+    let config = get_cors_config();
+
     // 從請求中獲取Origin頭
     let origin = req
         .headers()
         .get(header::ORIGIN)
         .and_then(|h| h.to_str().ok())
-        .unwrap_or("null");
+        .map(|s| s.to_string());
 
     // 記錄請求的Origin用於調試
-    debug!("📡 接收到來自Origin: {} 的請求", origin);
+    debug!(
+        "📡 接收到來自Origin: {} 的請求",
+        origin.as_deref().unwrap_or("null")
+    );
 
-    // 設置CORS頭部
-    match HeaderValue::from_str(origin) {
-        Ok(origin_value) => {
-            res.headers_mut()
-                .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin_value);
+    // 為所有回應添加Vary頭，表明回應基於Origin頭變化
+    res.headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("Origin"));
+
+    let origin_allowed = match &origin {
+        Some(origin) => config.is_origin_allowed(origin),
+        // 非瀏覽器請求（無 Origin）不受 CORS 限制
+        None => false,
+    };
+
+    if origin_allowed {
+        let origin = origin.as_deref().unwrap_or_default();
+        match HeaderValue::from_str(origin) {
+            Ok(origin_value) => {
+                res.headers_mut()
+                    .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin_value);
+            }
+            Err(e) => {
+                debug!("⚠️ 無效的Origin頭: {}, 錯誤: {}", origin, e);
+                res.headers_mut().insert(
+                    header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                    HeaderValue::from_static("null"),
+                );
+            }
         }
-        Err(e) => {
-            debug!("⚠️ 無效的Origin頭: {}, 錯誤: {}", origin, e);
+
+        if config.allow_credentials {
             res.headers_mut().insert(
-                header::ACCESS_CONTROL_ALLOW_ORIGIN,
-                HeaderValue::from_static("null"),
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
             );
         }
+    } else if let Some(origin) = &origin {
+        debug!("🚫 Origin 不在允許列表中: {}", origin);
     }
 
-    res.headers_mut().insert(
-        header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
-        HeaderValue::from_static("true"),
-    );
-
-    // 為所有回應添加Vary頭，表明回應基於Origin頭變化
-    res.headers_mut()
-        .insert(header::VARY, HeaderValue::from_static("Origin"));
-
     // 如果是OPTIONS請求，直接處理並停止後續流程
     if req.method() == Method::OPTIONS {
-        handle_preflight_request(req, res);
+        if origin.is_some() && !origin_allowed {
+            info!(
+                "{}",
+                tr!(
+                    "🚫 拒絕來自未允許 Origin 的預檢請求: {}",
+                    "🚫 Rejected preflight request from disallowed origin: {}",
+                    origin.as_deref().unwrap_or_default()
+                )
+            );
+            res.status_code(StatusCode::FORBIDDEN);
+        } else {
+            handle_preflight_request(req, res, config);
+        }
         ctrl.skip_rest();
     } else {
         // 非OPTIONS請求，繼續正常流程
@@ -106,59 +277,73 @@ pub async fn cors_middleware(
 }
 
 /// 專門處理CORS預檢請求
-fn handle_preflight_request(req: &Request, res: &mut Response) {
-    info!("🔍 處理OPTIONS預檢請求: {}", req.uri());
-
-    // 設置CORS預檢回應的標準頭部
-    res.headers_mut().insert(
-        header::ACCESS_CONTROL_ALLOW_METHODS,
-        HeaderValue::from_static("GET, POST, OPTIONS, PUT, DELETE, PATCH, HEAD"),
+fn handle_preflight_request(req: &Request, res: &mut Response, config: &CorsConfig) {
+    info!(
+        "{}",
+        tr!(
+            "🔍 處理OPTIONS預檢請求: {}",
+            "🔍 Handling OPTIONS preflight request: {}",
+            req.uri()
+        )
     );
 
-    // 基礎硬編碼頭部（保持向後兼容）
-    let base_headers = vec![
-        "Authorization",
-        "Content-Type",
-        "User-Agent",
-        "Accept",
-        "Origin",
-        "X-Requested-With",
-        "Access-Control-Request-Method",
-        "Access-Control-Request-Headers",
-        "Accept-Encoding",
-        "Accept-Language",
-        "Cache-Control",
-        "Connection",
-        "Referer",
-        "Sec-Fetch-Dest",
-        "Sec-Fetch-Mode",
-        "Sec-Fetch-Site",
-        "Pragma",
-        "X-Api-Key",
-    ];
-
-    // 解析客戶端請求的動態頭部
-    let dynamic_headers = parse_requested_headers(req);
-
-    // 合併基礎頭部和動態頭部
-    let mut all_headers = base_headers.clone();
-    for header in &dynamic_headers {
-        if !all_headers
-            .iter()
-            .any(|h| h.to_lowercase() == header.to_lowercase())
-        {
-            all_headers.push(header);
+    // 設置CORS預檢回應的標準頭部
+    match HeaderValue::from_str(&config.allowed_methods) {
+        Ok(methods_value) => {
+            res.headers_mut()
+                .insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods_value);
+        }
+        Err(e) => {
+            debug!("⚠️ 允許方法設置失敗: {}, 使用預設方法", e);
+            res.headers_mut().insert(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                HeaderValue::from_static(DEFAULT_ALLOWED_METHODS),
+            );
         }
     }
 
-    // 構建最終的頭部字符串
-    let headers_str = all_headers.join(", ");
+    let headers_str = match &config.allowed_headers {
+        // 已明確配置允許的頭部，直接使用
+        Some(headers) => headers.join(", "),
+        None => {
+            // 解析客戶端請求的動態頭部
+            let dynamic_headers = parse_requested_headers(req);
 
-    // 記錄調試信息
-    if !dynamic_headers.is_empty() {
-        info!("➕ 動態添加的頭部: {:?}", dynamic_headers);
-    }
-    info!("📋 最終允許的頭部: {}", headers_str);
+            // 合併基礎頭部和動態頭部
+            let mut all_headers: Vec<&str> = BASE_ALLOWED_HEADERS.to_vec();
+            for header in &dynamic_headers {
+                if !all_headers
+                    .iter()
+                    .any(|h| h.to_lowercase() == header.to_lowercase())
+                {
+                    all_headers.push(header);
+                }
+            }
+
+            // 記錄調試信息
+            if !dynamic_headers.is_empty() {
+                info!(
+                    "{}",
+                    tr!(
+                        "➕ 動態添加的頭部: {:?}",
+                        "➕ Dynamically allowed headers: {:?}",
+                        dynamic_headers
+                    )
+                );
+            }
+
+            // 構建最終的頭部字符串
+            all_headers.join(", ")
+        }
+    };
+    info!(
+        "{}",
+        tr!(
+            "📋 最終允許的頭部: {}",
+            "📋 Final allowed headers: {}",
+            headers_str
+        )
+    );
 
     // 設置 Access-Control-Allow-Headers
     match HeaderValue::from_str(&headers_str) {
@@ -169,28 +354,24 @@ fn handle_preflight_request(req: &Request, res: &mut Response) {
         Err(e) => {
             // 降級處理：如果動態頭部有問題，使用基礎頭部
             debug!("⚠️ 動態頭部設置失敗: {}, 使用基礎頭部", e);
-            res.headers_mut().insert(
-                header::ACCESS_CONTROL_ALLOW_HEADERS,
-                HeaderValue::from_static(
-                    "Authorization, Content-Type, User-Agent, Accept, Origin, \
-                    X-Requested-With, Access-Control-Request-Method, \
-                    Access-Control-Request-Headers, Accept-Encoding, Accept-Language, \
-                    Cache-Control, Connection, Referer, Sec-Fetch-Dest, Sec-Fetch-Mode, \
-                    Sec-Fetch-Site, Pragma, X-Api-Key",
-                ),
-            );
+            if let Ok(base_value) = HeaderValue::from_str(&BASE_ALLOWED_HEADERS.join(", ")) {
+                res.headers_mut()
+                    .insert(header::ACCESS_CONTROL_ALLOW_HEADERS, base_value);
+            }
         }
     }
 
     res.headers_mut().insert(
         header::ACCESS_CONTROL_MAX_AGE,
-        HeaderValue::from_static("3600"),
+        HeaderValue::from(config.max_age),
     );
 
     // 添加Vary頭，表明回應會根據這些請求頭變化
     res.headers_mut().insert(
         header::VARY,
-        HeaderValue::from_static("Access-Control-Request-Method, Access-Control-Request-Headers"),
+        HeaderValue::from_static(
+            "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+        ),
     );
 
     // 設置正確的狀態碼: 204 No Content
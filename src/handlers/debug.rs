@@ -0,0 +1,87 @@
+use super::chat::{
+    apply_param_policy, apply_size_limits, apply_stream_policy, read_chat_request, resolve_model,
+};
+use crate::cache::{get_cached_config, get_cached_url};
+use crate::poe_client::create_chat_request;
+use crate::types::{Message, OpenAiContent, OpenAiContentItem};
+use crate::utils::{count_message_tokens, is_poe_cdn_url};
+use salvo::prelude::*;
+use serde_json::json;
+use tracing::info;
+
+/// 以佔位網址取代尚未上傳的附件，返回實際請求時需要上傳的附件
+fn replace_pending_uploads(messages: &mut [Message]) -> Vec<String> {
+    let mut pending = Vec::new();
+    for message in messages.iter_mut() {
+        let Some(OpenAiContent::Multi(items)) = &mut message.content else {
+            continue;
+        };
+        for item in items.iter_mut() {
+            let OpenAiContentItem::ImageUrl { image_url } = item else {
+                continue;
+            };
+            if let Some(header) = image_url
+                .url
+                .strip_prefix("data:")
+                .and_then(|rest| rest.split(',').next())
+            {
+                // data URL 可能很大，只保留 MIME 類型與長度
+                let summary = format!("data:{},<{} bytes>", header, image_url.url.len());
+                image_url.url = format!("upload://{}", pending.len());
+                pending.push(summary);
+            } else if is_poe_cdn_url(&image_url.url) {
+                continue;
+            } else if let Some((poe_url, _)) = get_cached_url(&image_url.url) {
+                image_url.url = poe_url;
+            } else {
+                let original =
+                    std::mem::replace(&mut image_url.url, format!("upload://{}", pending.len()));
+                pending.push(original);
+            }
+        }
+    }
+    pending
+}
+
+/// 轉換預覽：返回聊天請求轉換後將發送給 Poe 的請求內容，不連線上游
+/// 尚未上傳的附件以 upload://{index} 佔位，原始來源列於 pending_uploads
+#[handler]
+pub async fn debug_convert(req: &mut Request, res: &mut Response) {
+    let Some(mut chat_request) = read_chat_request(req, res).await else {
+        return;
+    };
+    let config = get_cached_config().await;
+    let (display_model, original_model) = resolve_model(&config, &chat_request.model);
+    if !apply_param_policy(&config, &original_model, &mut chat_request, res) {
+        return;
+    }
+    apply_stream_policy(&config, &original_model, &mut chat_request);
+    if !apply_size_limits(&config, &original_model, &chat_request, res) {
+        return;
+    }
+
+    let mut messages = std::mem::take(&mut chat_request.messages);
+    let pending_uploads = replace_pending_uploads(&mut messages);
+    let prompt_tokens = count_message_tokens(&messages);
+    let poe_request = create_chat_request(&original_model, messages, &chat_request).await;
+    info!(
+        "{}",
+        tr!(
+            "🔍 轉換預覽 | 模型: {} -> {} | 訊息數量: {} | 待上傳附件: {}",
+            "🔍 Conversion preview | model: {} -> {} | messages: {} | pending uploads: {}",
+            display_model,
+            original_model,
+            poe_request.query.len(),
+            pending_uploads.len()
+        )
+    );
+
+    res.render(Json(json!({
+        "model": display_model,
+        "bot": original_model,
+        "stream": chat_request.stream.unwrap_or(false),
+        "prompt_tokens": prompt_tokens,
+        "pending_uploads": pending_uploads,
+        "request": poe_request,
+    })));
+}
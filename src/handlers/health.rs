@@ -0,0 +1,345 @@
+//! 模型健康狀態 (MODEL_HEALTH_CHECK_INTERVAL_SECS)
+//!
+//! 啟用後記錄每個模型實際請求的成功與失敗，並定期以極短的請求探測 models.yaml 中的模型
+//! （或 MODEL_HEALTH_MODELS 指定的模型）及目前不健康的模型；間隔內已有成功請求的模型不再探測。
+//! 連續失敗達 MODEL_HEALTH_FAILURE_THRESHOLD 次的模型視為不可用，/v1/models 以 availability 欄位標示，
+//! MODEL_HEALTH_HIDE_UNAVAILABLE=true 時不列出
+
+use crate::cache::get_cached_config;
+use crate::poe_client::{PoeClientWrapper, create_chat_request};
+use crate::types::ChatCompletionRequest;
+use crate::utils::is_insufficient_points;
+use chrono::Utc;
+use futures_util::stream::{Stream, StreamExt};
+use poe_api_process::{ChatEventType, ChatResponse, ChatResponseData, PoeError};
+use salvo::prelude::*;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>;
+
+/// 探測請求的內容
+const PROBE_MESSAGE: &str = "Hi";
+
+struct HealthConfig {
+    interval: Duration,
+    failure_threshold: u32,
+    hide_unavailable: bool,
+    timeout: Duration,
+    // 指定探測的模型，未設定時探測 models.yaml 中啟用的模型
+    models: Vec<String>,
+}
+
+static HEALTH_CONFIG: LazyLock<Option<HealthConfig>> = LazyLock::new(|| {
+    let env = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+    };
+    let interval = env("MODEL_HEALTH_CHECK_INTERVAL_SECS").unwrap_or(0);
+    if interval == 0 {
+        return None;
+    }
+    let models = std::env::var("MODEL_HEALTH_MODELS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|model| model.trim().to_lowercase())
+        .filter(|model| !model.is_empty())
+        .collect();
+    Some(HealthConfig {
+        interval: Duration::from_secs(interval),
+        failure_threshold: env("MODEL_HEALTH_FAILURE_THRESHOLD").unwrap_or(3).max(1) as u32,
+        hide_unavailable: std::env::var("MODEL_HEALTH_HIDE_UNAVAILABLE")
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+            .unwrap_or(false),
+        timeout: Duration::from_secs(env("MODEL_HEALTH_TIMEOUT_SECS").unwrap_or(30).max(1)),
+        models,
+    })
+});
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Availability {
+    Available,
+    Degraded,
+    Unavailable,
+    Unknown,
+}
+
+#[derive(Serialize, Clone, Default)]
+struct ModelHealth {
+    consecutive_failures: u32,
+    last_success: Option<i64>,
+    last_failure: Option<i64>,
+    last_error: Option<String>,
+    // 最近一次探測的時間
+    last_probe: Option<i64>,
+    #[serde(skip)]
+    last_success_at: Option<Instant>,
+}
+
+impl ModelHealth {
+    fn availability(&self, threshold: u32) -> Availability {
+        match self.consecutive_failures {
+            0 if self.last_success.is_some() => Availability::Available,
+            0 => Availability::Unknown,
+            n if n < threshold => Availability::Degraded,
+            _ => Availability::Unavailable,
+        }
+    }
+}
+
+/// 以小寫的原始模型名稱為鍵
+static MODEL_HEALTH: LazyLock<Mutex<HashMap<String, ModelHealth>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn lock_health() -> std::sync::MutexGuard<'static, HashMap<String, ModelHealth>> {
+    MODEL_HEALTH.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn record_success(model: &str) {
+    let mut health = lock_health();
+    let entry = health.entry(model.to_lowercase()).or_default();
+    if entry.consecutive_failures > 0 {
+        info!(
+            "{}",
+            tr!("💚 模型恢復正常: {}", "💚 Model recovered: {}", model)
+        );
+    }
+    entry.consecutive_failures = 0;
+    entry.last_success = Some(Utc::now().timestamp());
+    entry.last_success_at = Some(Instant::now());
+}
+
+fn record_failure(model: &str, error: &str) {
+    let Some(config) = HEALTH_CONFIG.as_ref() else {
+        return;
+    };
+    let mut health = lock_health();
+    let entry = health.entry(model.to_lowercase()).or_default();
+    entry.consecutive_failures += 1;
+    entry.last_failure = Some(Utc::now().timestamp());
+    entry.last_error = Some(error.to_string());
+    if entry.consecutive_failures == config.failure_threshold {
+        warn!(
+            "{}",
+            tr!(
+                "💔 模型連續失敗 {} 次，標記為不可用 | 模型: {} | 錯誤: {}",
+                "💔 Model failed {} times in a row, marked unavailable | model: {} | error: {}",
+                entry.consecutive_failures,
+                model,
+                error
+            )
+        );
+    }
+}
+
+/// 模型目前的可用狀態，未啟用健康檢查時返回 None
+pub(crate) fn model_availability(model: &str) -> Option<Availability> {
+    let config = HEALTH_CONFIG.as_ref()?;
+    Some(
+        lock_health()
+            .get(&model.to_lowercase())
+            .map_or(Availability::Unknown, |health| {
+                health.availability(config.failure_threshold)
+            }),
+    )
+}
+
+/// 模型列表是否應隱藏該模型
+pub(crate) fn hide_unavailable_model(model: &str) -> bool {
+    HEALTH_CONFIG
+        .as_ref()
+        .is_some_and(|config| config.hide_unavailable)
+        && model_availability(model) == Some(Availability::Unavailable)
+}
+
+/// 記錄實際請求的結果：完成事件為成功，錯誤為失敗；點數耗盡與模型無關，不計入
+pub(crate) fn track_model_health(model: &str, stream: EventStream) -> EventStream {
+    if HEALTH_CONFIG.is_none() {
+        return stream;
+    }
+    let model = model.to_string();
+    let mut recorded = false;
+    Box::pin(stream.inspect(move |item| {
+        if recorded {
+            return;
+        }
+        let failure = match item {
+            Ok(ChatResponse {
+                event: ChatEventType::Done,
+                ..
+            }) => None,
+            Ok(ChatResponse {
+                event: ChatEventType::Error,
+                data,
+            }) => Some(match data {
+                Some(ChatResponseData::Error { text, .. }) => text.clone(),
+                _ => "error event".to_string(),
+            }),
+            Err(e) => Some(e.to_string()),
+            _ => return,
+        };
+        recorded = true;
+        match failure {
+            None => record_success(&model),
+            Some(error) if !is_insufficient_points(&error) => record_failure(&model, &error),
+            Some(_) => {}
+        }
+    }))
+}
+
+/// 建立請求失敗時記錄
+pub(crate) fn report_model_error(model: &str, error: &str) {
+    if HEALTH_CONFIG.is_some() && !is_insufficient_points(error) {
+        record_failure(model, error);
+    }
+}
+
+/// 發送探測請求，收到第一段回覆即視為成功
+async fn probe_model(model: &str, access_key: &str) -> Result<(), String> {
+    let request: ChatCompletionRequest = serde_json::from_value(json!({
+        "model": model,
+        "messages": [{"role": "user", "content": PROBE_MESSAGE}],
+    }))
+    .map_err(|e| e.to_string())?;
+    let client = PoeClientWrapper::new(model, access_key);
+    let chat_request = create_chat_request(model, request.messages.clone(), &request).await;
+    let mut stream = client
+        .stream_request(chat_request)
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(event) = stream.next().await {
+        let event = event.map_err(|e| e.to_string())?;
+        match (event.event, event.data) {
+            (ChatEventType::Error, Some(ChatResponseData::Error { text, .. })) => return Err(text),
+            (ChatEventType::Error, _) => return Err("error event".to_string()),
+            (ChatEventType::Text | ChatEventType::ReplaceResponse | ChatEventType::Done, _) => {
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+    Err("stream ended without a response".to_string())
+}
+
+/// 本輪需要探測的模型：設定的模型及目前不健康的模型，略過間隔內已有成功請求的模型
+async fn probe_targets(config: &HealthConfig) -> Vec<String> {
+    let mut targets: Vec<String> = if config.models.is_empty() {
+        get_cached_config()
+            .await
+            .models
+            .iter()
+            .filter(|(_, model_config)| model_config.enable.unwrap_or(true))
+            .map(|(model, _)| model.to_lowercase())
+            .collect()
+    } else {
+        config.models.clone()
+    };
+    let health = lock_health();
+    targets.extend(
+        health
+            .iter()
+            .filter(|(_, health)| health.consecutive_failures > 0)
+            .map(|(model, _)| model.clone()),
+    );
+    targets.sort();
+    targets.dedup();
+    targets.retain(|model| {
+        !health.get(model).is_some_and(|health| {
+            health.consecutive_failures == 0
+                && health
+                    .last_success_at
+                    .is_some_and(|at| at.elapsed() < config.interval)
+        })
+    });
+    targets
+}
+
+async fn run_probes(config: &HealthConfig, access_key: &str) {
+    let targets = probe_targets(config).await;
+    if targets.is_empty() {
+        debug!("🩺 所有模型近期皆有成功請求，略過探測");
+        return;
+    }
+    debug!("🩺 探測模型: {}", targets.join(", "));
+    for model in targets {
+        let result = tokio::time::timeout(config.timeout, probe_model(&model, access_key))
+            .await
+            .unwrap_or_else(|_| Err("probe timed out".to_string()));
+        lock_health().entry(model.clone()).or_default().last_probe = Some(Utc::now().timestamp());
+        match result {
+            Ok(()) => {
+                debug!("🩺 模型探測成功: {}", model);
+                record_success(&model);
+            }
+            Err(e) => {
+                debug!("🩺 模型探測失敗: {} | 錯誤: {}", model, e);
+                if !is_insufficient_points(&e) {
+                    record_failure(&model, &e);
+                }
+            }
+        }
+    }
+}
+
+/// 啟動背景模型探測任務，探測使用 models.yaml 的 api_token
+pub fn spawn_model_health_monitor() {
+    let Some(config) = HEALTH_CONFIG.as_ref() else {
+        return;
+    };
+    info!(
+        "{}",
+        tr!(
+            "🩺 模型健康檢查: 每 {} 秒 | 不可用門檻: 連續失敗 {} 次 | 隱藏不可用模型: {}",
+            "🩺 Model health checks: every {}s | unavailable after {} consecutive failures | hide unavailable: {}",
+            config.interval.as_secs(),
+            config.failure_threshold,
+            config.hide_unavailable
+        )
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            let access_key = get_cached_config()
+                .await
+                .api_token
+                .clone()
+                .filter(|token| !token.trim().is_empty());
+            match access_key {
+                Some(access_key) => run_probes(config, &access_key).await,
+                None => debug!("🩺 models.yaml 未設定 api_token，只記錄實際請求的結果"),
+            }
+        }
+    });
+}
+
+/// 各模型的健康狀態
+#[handler]
+pub(super) async fn get_model_health(res: &mut Response) {
+    let Some(config) = HEALTH_CONFIG.as_ref() else {
+        res.render(Json(json!({ "enabled": false, "models": {} })));
+        return;
+    };
+    let models: BTreeMap<String, serde_json::Value> = lock_health()
+        .iter()
+        .map(|(model, health)| {
+            let mut value = serde_json::to_value(health).unwrap_or_default();
+            value["availability"] = json!(health.availability(config.failure_threshold));
+            (model.clone(), value)
+        })
+        .collect();
+    res.render(Json(json!({
+        "enabled": true,
+        "interval_secs": config.interval.as_secs(),
+        "failure_threshold": config.failure_threshold,
+        "hide_unavailable": config.hide_unavailable,
+        "models": models,
+    })));
+}
@@ -0,0 +1,26 @@
+use crate::image_cache::cached_image_asset;
+use salvo::http::header;
+use salvo::prelude::*;
+use tracing::debug;
+
+/// 提供圖片生成緩存中保存的圖片
+#[handler]
+pub async fn get_cached_image(req: &mut Request, res: &mut Response) {
+    let id = req.param::<String>("id").unwrap_or_default();
+    match cached_image_asset(&id) {
+        Some((content_type, data)) => {
+            if let Ok(value) = content_type.parse() {
+                res.headers_mut().insert(header::CONTENT_TYPE, value);
+            }
+            res.headers_mut().insert(
+                header::CACHE_CONTROL,
+                "public, max-age=86400".parse().unwrap(),
+            );
+            let _ = res.write_body(data);
+        }
+        None => {
+            debug!("🖼️ 找不到緩存的圖片: {}", id);
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! 進行中的生成 (/api/admin/requests)
+//!
+//! 每個呼叫 Poe 的聊天請求在生成期間登記於此，管理介面可列出並強制中止指定的生成：
+//! 中止時停止讀取並丟棄上游串流（結束對 Poe 的請求），再注入錯誤事件，
+//! 客戶端會收到 code 為 request_cancelled 的錯誤（串流以錯誤片段結束）
+
+use super::balance::mask_token;
+use crate::utils::{REQUEST_CANCELLED_MESSAGE, format_duration};
+use chrono::Utc;
+use futures_util::stream::{self, Stream, StreamExt};
+use poe_api_process::{ChatEventType, ChatResponse, ChatResponseData, PoeError};
+use salvo::prelude::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>;
+
+/// 進行中的生成
+struct Generation {
+    model: String,
+    stream: bool,
+    client_ip: String,
+    api_key: String,
+    created: i64,
+    started: Instant,
+    // 已收到的上游事件數
+    events: AtomicU64,
+    cancelled: AtomicBool,
+    cancel: Notify,
+}
+
+static GENERATIONS: LazyLock<Mutex<HashMap<String, Arc<Generation>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn lock_generations() -> std::sync::MutexGuard<'static, HashMap<String, Arc<Generation>>> {
+    GENERATIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 隨上游串流一起丟棄，移除登記
+struct Registration {
+    id: String,
+    generation: Arc<Generation>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut generations = lock_generations();
+        if generations
+            .get(&self.id)
+            .is_some_and(|generation| Arc::ptr_eq(generation, &self.generation))
+        {
+            generations.remove(&self.id);
+        }
+    }
+}
+
+/// 登記進行中的生成，返回可被管理員中止的上游串流
+pub(super) fn track_generation(
+    id: &str,
+    model: &str,
+    stream: bool,
+    client_ip: &str,
+    access_key: &str,
+    events: EventStream,
+) -> EventStream {
+    let generation = Arc::new(Generation {
+        model: model.to_string(),
+        stream,
+        client_ip: client_ip.to_string(),
+        api_key: mask_token(access_key),
+        created: Utc::now().timestamp(),
+        started: Instant::now(),
+        events: AtomicU64::new(0),
+        cancelled: AtomicBool::new(false),
+        cancel: Notify::new(),
+    });
+    lock_generations().insert(id.to_string(), generation.clone());
+    let registration = Registration {
+        id: id.to_string(),
+        generation,
+    };
+    // 狀態：(上游, 登記)，中止後上游為 None
+    let tracked = stream::unfold(
+        (Some(events), registration),
+        |(upstream, registration)| async move {
+            let mut upstream = upstream?;
+            let generation = registration.generation.clone();
+            tokio::select! {
+                biased;
+                _ = generation.cancel.notified() => {
+                    debug!("🛑 丟棄上游串流並注入中止錯誤 | ID: {}", registration.id);
+                    let error = Ok(ChatResponse {
+                        event: ChatEventType::Error,
+                        data: Some(ChatResponseData::Error {
+                            text: REQUEST_CANCELLED_MESSAGE.to_string(),
+                            allow_retry: false,
+                        }),
+                    });
+                    Some((error, (None, registration)))
+                }
+                item = upstream.next() => {
+                    let item = item?;
+                    generation.events.fetch_add(1, Ordering::Relaxed);
+                    Some((item, (Some(upstream), registration)))
+                }
+            }
+        },
+    );
+    Box::pin(tracked)
+}
+
+/// 列出進行中的生成，依開始時間排序
+#[handler]
+pub(super) async fn list_generations(res: &mut Response) {
+    let mut generations: Vec<(String, Arc<Generation>)> = lock_generations()
+        .iter()
+        .map(|(id, generation)| (id.clone(), generation.clone()))
+        .collect();
+    generations.sort_by_key(|(_, generation)| generation.started);
+    let data: Vec<serde_json::Value> = generations
+        .iter()
+        .map(|(id, generation)| {
+            json!({
+                "id": id,
+                "model": generation.model,
+                "stream": generation.stream,
+                "client_ip": generation.client_ip,
+                "api_key": generation.api_key,
+                "created": generation.created,
+                "elapsed_secs": generation.started.elapsed().as_secs(),
+                "events": generation.events.load(Ordering::Relaxed),
+                "cancelling": generation.cancelled.load(Ordering::Relaxed),
+            })
+        })
+        .collect();
+    res.render(Json(json!({ "data": data })));
+}
+
+/// 強制中止指定的生成
+#[handler]
+pub(super) async fn cancel_generation(req: &mut Request, res: &mut Response) {
+    let id = req.param::<String>("id").unwrap_or_default();
+    let Some(generation) = lock_generations().get(&id).cloned() else {
+        res.status_code(StatusCode::NOT_FOUND);
+        res.render(Json(json!({
+            "error": format!("找不到進行中的生成: {}", id)
+        })));
+        return;
+    };
+    if !generation.cancelled.swap(true, Ordering::Relaxed) {
+        generation.cancel.notify_one();
+        warn!(
+            "{}",
+            tr!(
+                "🛑 管理員中止生成 | ID: {} | 模型: {} | API Key: {} | 已進行: {}",
+                "🛑 Generation cancelled by the administrator | ID: {} | model: {} | API key: {} | elapsed: {}",
+                id,
+                generation.model,
+                generation.api_key,
+                format_duration(generation.started.elapsed())
+            )
+        );
+    }
+    res.render(Json(json!({ "id": id, "cancelled": true })));
+}
@@ -1,21 +1,61 @@
 use salvo::prelude::*;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
-use tracing::debug;
+use tracing::{debug, info};
 
 // 全局變量，將在 main.rs 中初始化
 pub static GLOBAL_RATE_LIMITER: tokio::sync::OnceCell<Arc<Mutex<Instant>>> =
     tokio::sync::OnceCell::const_new();
 
+// 每個客戶端 IP 下一個可用的請求時段
+static IP_RATE_LIMITER: LazyLock<std::sync::Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(|| std::sync::Mutex::new(HashMap::new()));
+
+// 超過此數量時清除已過期的 IP 記錄
+const IP_RATE_LIMITER_PRUNE_SIZE: usize = 10_000;
+
+/// 每個客戶端 IP 的速率限制間隔 (RATE_LIMIT_PER_IP_MS)，None 表示禁用
+static IP_RATE_LIMIT: LazyLock<Option<Duration>> = LazyLock::new(|| {
+    let ms = std::env::var("RATE_LIMIT_PER_IP_MS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(0);
+    if ms == 0 {
+        return None;
+    }
+    info!(
+        "{}",
+        tr!(
+            "⚙️  客戶端 IP 速率限制: 已啟用 (每個 IP 每 {}ms 一次請求)",
+            "⚙️  Per client IP rate limit: enabled (one request every {}ms per IP)",
+            ms
+        )
+    );
+    Some(Duration::from_millis(ms))
+});
+
+/// 目前的速率限制間隔 (毫秒)，執行期設定優先於 RATE_LIMIT_MS
+pub(crate) fn rate_limit_ms() -> u64 {
+    crate::runtime::rate_limit_override().unwrap_or_else(|| {
+        std::env::var("RATE_LIMIT_MS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(100)
+    })
+}
+
+/// 初始化客戶端 IP 速率限制設定（於啟動時記錄日誌）
+pub(crate) fn init_ip_rate_limit() {
+    LazyLock::force(&IP_RATE_LIMIT);
+}
+
 /// 取得速率限制間隔 (毫秒)
 /// 返回 None 表示禁用速率限制
 fn get_rate_limit_ms() -> Option<Duration> {
-    let ms = std::env::var("RATE_LIMIT_MS")
-        .ok()
-        .and_then(|s| s.parse::<u64>().ok())
-        .unwrap_or(100);
+    let ms = rate_limit_ms();
 
     // 如果值為 0，表示禁用速率限制
     if ms == 0 {
@@ -25,6 +65,27 @@ fn get_rate_limit_ms() -> Option<Duration> {
     }
 }
 
+/// 預約客戶端 IP 的下一個請求時段，返回需等待的時間
+async fn reserve_ip_slot(client_ip: &str, interval: Duration) -> Duration {
+    // 多實例部署時由 Redis 分配，每個 IP 各自一個鍵
+    let scope = format!("ip:{}", client_ip);
+    if let Some(wait) = crate::shared::reserve_rate_limit_slot(Some(&scope), interval).await {
+        return wait;
+    }
+    let mut slots = IP_RATE_LIMITER.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    if slots.len() >= IP_RATE_LIMITER_PRUNE_SIZE {
+        slots.retain(|_, slot| *slot > now);
+    }
+    let slot = slots
+        .get(client_ip)
+        .copied()
+        .filter(|slot| *slot > now)
+        .unwrap_or(now);
+    slots.insert(client_ip.to_string(), slot + interval);
+    slot - now
+}
+
 #[handler]
 pub async fn rate_limit_middleware(
     req: &mut Request,
@@ -32,9 +93,33 @@ pub async fn rate_limit_middleware(
     res: &mut Response,
     ctrl: &mut FlowCtrl,
 ) {
+    // 先依客戶端 IP 排隊，避免單一客戶端佔滿全局的請求時段
+    if let Some(interval) = *IP_RATE_LIMIT {
+        let client_ip = crate::handlers::get_client_ip(depot);
+        let wait = reserve_ip_slot(&client_ip, interval).await;
+        if !wait.is_zero() {
+            debug!(
+                "⏳ 請求觸發客戶端 IP 速率限制，延遲 {:?}，間隔設定: {:?} | 客戶端 IP: {}",
+                wait, interval, client_ip
+            );
+            sleep(wait).await;
+        }
+    }
+
     // 獲取速率限制間隔，None 表示禁用
     if let Some(interval) = get_rate_limit_ms() {
-        if let Some(cell) = GLOBAL_RATE_LIMITER.get() {
+        // 多實例部署時由 Redis 分配請求時段，所有實例共用同一個間隔
+        if let Some(wait) = crate::shared::reserve_rate_limit_slot(None, interval).await {
+            if !wait.is_zero() {
+                debug!(
+                    "⏳ 請求觸發共享速率限制，延遲 {:?}，間隔設定: {:?} | 客戶端 IP: {}",
+                    wait,
+                    interval,
+                    crate::handlers::get_client_ip(depot)
+                );
+                sleep(wait).await;
+            }
+        } else if let Some(cell) = GLOBAL_RATE_LIMITER.get() {
             let mut lock = cell.lock().await;
             let now = Instant::now();
             let elapsed = now.duration_since(*lock);
@@ -42,8 +127,10 @@ pub async fn rate_limit_middleware(
             if elapsed < interval {
                 let wait = interval - elapsed;
                 debug!(
-                    "⏳ 請求觸發全局速率限制，延遲 {:?}，間隔設定: {:?}",
-                    wait, interval
+                    "⏳ 請求觸發全局速率限制，延遲 {:?}，間隔設定: {:?} | 客戶端 IP: {}",
+                    wait,
+                    interval,
+                    crate::handlers::get_client_ip(depot)
                 );
                 sleep(wait).await;
             }
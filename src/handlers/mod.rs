@@ -1,11 +1,46 @@
 mod admin;
+mod admission;
+mod backpressure;
+mod balance;
+mod body;
 mod chat;
+mod client_ip;
+mod coalesce;
+mod completions;
 mod cors;
+mod debug;
+mod health;
+mod image_cache;
+mod inflight;
 pub(crate) mod limit;
 mod models;
+mod pool;
+mod replay;
+mod resume;
+mod runtime;
+mod scope;
+mod selftest;
+mod stats;
+mod stored;
+mod tokens;
+mod usage;
 
-pub use admin::admin_routes;
+pub use admin::{admin_routes, spawn_shared_config_sync};
+pub use balance::{report_points_exhausted, spawn_balance_monitor};
 pub use chat::chat_completions;
-pub use cors::cors_middleware;
+pub use client_ip::{client_ip_middleware, get_client_ip, init_trusted_proxies};
+pub use completions::text_completions;
+pub use cors::{cors_middleware, get_cors_config};
+pub use health::spawn_model_health_monitor;
+pub use image_cache::get_cached_image;
 pub use limit::rate_limit_middleware;
 pub use models::get_models;
+pub(crate) use models::get_models_from_api;
+pub use pool::spawn_token_pool_refresher;
+pub use selftest::{readyz, spawn_startup_self_test};
+pub use stored::{
+    delete_stored_completion, delete_stored_conversation, get_stored_completion,
+    get_stored_conversation, get_stored_messages, list_stored_completions,
+    list_stored_conversations,
+};
+pub use tokens::{detokenize, tokenize};
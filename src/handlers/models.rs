@@ -1,25 +1,130 @@
-use crate::{cache::get_cached_config, poe_client::PoeClientWrapper, types::*};
+use super::health::{hide_unavailable_model, model_availability};
+use super::scope::RequestScope;
+use crate::{
+    cache::get_cached_config,
+    poe_client::{ModelMetadata, PoeClientWrapper, get_model_list, get_model_metadata},
+    types::*,
+};
 use chrono::Utc;
-use poe_api_process::{ModelInfo, get_model_list};
+use poe_api_process::ModelInfo;
 use salvo::prelude::*;
 use serde_json::json;
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, LazyLock};
 use std::time::Instant;
 use tokio::sync::RwLock;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 // 注意：此緩存不適用於 /api/models 路徑
 static API_MODELS_CACHE: RwLock<Option<Arc<Vec<ModelInfo>>>> = RwLock::const_new(None);
 
+/// 已緩存的 API 模型數量，尚未緩存時返回 None
+pub(super) async fn cached_model_count() -> Option<usize> {
+    API_MODELS_CACHE
+        .read()
+        .await
+        .as_ref()
+        .map(|models| models.len())
+}
+
+/// 是否在模型列表中加上 Poe 機器人的名稱、描述及頭像 (MODEL_METADATA)
+static MODEL_METADATA_ENABLED: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("MODEL_METADATA")
+        .map(|v| !matches!(v.trim().to_lowercase().as_str(), "false" | "0" | "no"))
+        .unwrap_or(true)
+});
+
+// 與 API_MODELS_CACHE 一同緩存，/api/models 重新整理模型列表時一併清除
+static MODEL_METADATA_CACHE: RwLock<Option<Arc<HashMap<String, ModelMetadata>>>> =
+    RwLock::const_new(None);
+
+/// 緩存的機器人資訊，未緩存時向 Poe 取得；取得失敗時返回空表且不緩存，下次請求重試
+async fn cached_model_metadata(config: &Config) -> Arc<HashMap<String, ModelMetadata>> {
+    if !*MODEL_METADATA_ENABLED {
+        return Arc::default();
+    }
+    if let Some(metadata) = &*MODEL_METADATA_CACHE.read().await {
+        return metadata.clone();
+    }
+    let mut write_guard = MODEL_METADATA_CACHE.write().await;
+    if let Some(metadata) = &*write_guard {
+        return metadata.clone();
+    }
+    let access_key = config
+        .api_token
+        .as_deref()
+        .filter(|token| !token.trim().is_empty());
+    match get_model_metadata(access_key).await {
+        Ok(metadata) => {
+            debug!("🪪 模型資訊已緩存 | 模型數量: {}", metadata.len());
+            let metadata = Arc::new(metadata);
+            *write_guard = Some(metadata.clone());
+            metadata
+        }
+        Err(e) => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 取得模型名稱、描述及頭像失敗，本次不附加: {}",
+                    "⚠️ Failed to fetch model names, descriptions and icons, omitting them: {}",
+                    e
+                )
+            );
+            Arc::default()
+        }
+    }
+}
+
+/// 依模型健康狀態加上 availability 欄位並移除應隱藏的模型，original 返回模型對應的原始名稱；
+/// 有機器人資訊時另加上 name、description、icon 欄位
+fn with_extensions(
+    models: Vec<ModelInfo>,
+    original: impl Fn(&str) -> String,
+    metadata: &HashMap<String, ModelMetadata>,
+) -> Vec<serde_json::Value> {
+    models
+        .into_iter()
+        .filter_map(|model| {
+            let original = original(&model.id);
+            if hide_unavailable_model(&original) {
+                debug!("💔 隱藏不可用的模型: {}", model.id);
+                return None;
+            }
+            let mut value = serde_json::to_value(&model).ok()?;
+            if let Some(availability) = model_availability(&original) {
+                value["availability"] = json!(availability);
+            }
+            if let Some(metadata) = metadata.get(&original.to_lowercase()) {
+                let fields = [
+                    ("name", &metadata.name),
+                    ("description", &metadata.description),
+                    ("icon", &metadata.icon),
+                ];
+                for (field, field_value) in fields {
+                    if let Some(field_value) = field_value {
+                        value[field] = json!(field_value);
+                    }
+                }
+            }
+            Some(value)
+        })
+        .collect()
+}
+
 /// 根據配置獲取模型列表
-async fn get_models_from_api(config: &Config) -> Result<Vec<ModelInfo>, String> {
+pub(crate) async fn get_models_from_api(config: &Config) -> Result<Vec<ModelInfo>, String> {
     let use_v1_api = config.use_v1_api.unwrap_or(false);
 
     if use_v1_api {
         // 使用 v1/models API
         if let Some(api_token) = &config.api_token {
-            info!("🔄 使用 v1/models API 獲取模型列表");
+            info!(
+                "{}",
+                tr!(
+                    "🔄 使用 v1/models API 獲取模型列表",
+                    "🔄 Fetching model list via v1/models API"
+                )
+            );
             let client = PoeClientWrapper::new("dummy", api_token);
             match client.get_v1_model_list().await {
                 Ok(model_response) => {
@@ -36,17 +141,36 @@ async fn get_models_from_api(config: &Config) -> Result<Vec<ModelInfo>, String>
                     Ok(models)
                 }
                 Err(e) => {
-                    error!("❌ v1/models API 請求失敗: {}", e);
+                    error!(
+                        "{}",
+                        tr!(
+                            "❌ v1/models API 請求失敗: {}",
+                            "❌ v1/models API request failed: {}",
+                            e
+                        )
+                    );
                     Err(format!("v1/models API 請求失敗: {}", e))
                 }
             }
         } else {
-            error!("❌ 配置了使用 v1/models API 但未提供 api_token");
+            error!(
+                "{}",
+                tr!(
+                    "❌ 配置了使用 v1/models API 但未提供 api_token",
+                    "❌ use_v1_api is enabled but no api_token is configured"
+                )
+            );
             Err("配置了使用 v1/models API 但未提供 api_token".to_string())
         }
     } else {
         // 使用傳統 get_model_list API
-        info!("🔄 使用傳統 get_model_list API 獲取模型列表");
+        info!(
+            "{}",
+            tr!(
+                "🔄 使用傳統 get_model_list API 獲取模型列表",
+                "🔄 Fetching model list via legacy get_model_list API"
+            )
+        );
         match get_model_list(Some("zh-Hant")).await {
             Ok(model_list) => {
                 let models = model_list
@@ -60,7 +184,14 @@ async fn get_models_from_api(config: &Config) -> Result<Vec<ModelInfo>, String>
                 Ok(models)
             }
             Err(e) => {
-                error!("❌ get_model_list API 請求失敗: {}", e);
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ get_model_list API 請求失敗: {}",
+                        "❌ get_model_list API request failed: {}",
+                        e
+                    )
+                );
                 Err(format!("get_model_list API 請求失敗: {}", e))
             }
         }
@@ -70,12 +201,25 @@ async fn get_models_from_api(config: &Config) -> Result<Vec<ModelInfo>, String>
 #[handler]
 pub async fn get_models(req: &mut Request, res: &mut Response) {
     let path = req.uri().path();
-    info!("📋 收到獲取模型列表請求 | 路徑: {}", path);
+    info!(
+        "{}",
+        tr!(
+            "📋 收到獲取模型列表請求 | 路徑: {}",
+            "📋 Model list request | path: {}",
+            path
+        )
+    );
     let start_time = Instant::now();
 
     // 處理 /api/models 特殊路徑 (不使用緩存) ---
     if path == "/api/models" {
-        info!("⚡️ api/models 路徑：直接從 Poe 取得（無緩存）");
+        info!(
+            "{}",
+            tr!(
+                "⚡️ api/models 路徑：直接從 Poe 取得（無緩存）",
+                "⚡️ api/models: fetching directly from Poe (uncached)"
+            )
+        );
 
         let config = get_cached_config().await;
         match get_models_from_api(&config).await {
@@ -87,6 +231,10 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
                     *cache_guard = Some(models_arc.clone());
                     info!("🔄 Updated API_MODELS_CACHE after /api/models request.");
                 }
+                {
+                    // 機器人資訊隨模型列表重新取得
+                    *MODEL_METADATA_CACHE.write().await = None;
+                }
 
                 let response = json!({
                     "object": "list",
@@ -95,18 +243,26 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
 
                 let duration = start_time.elapsed();
                 info!(
-                    "✅ [/api/models] 成功獲取未過濾模型列表並更新緩存 | 模型數量: {} | 處理時間: {}",
-                    models_arc.len(),
-                    crate::utils::format_duration(duration)
+                    "{}",
+                    tr!(
+                        "✅ [/api/models] 成功獲取未過濾模型列表並更新緩存 | 模型數量: {} | 處理時間: {}",
+                        "✅ [/api/models] Fetched unfiltered model list and updated cache | models: {} | duration: {}",
+                        models_arc.len(),
+                        crate::utils::format_duration(duration)
+                    )
                 );
                 res.render(Json(response));
             }
             Err(e) => {
                 let duration = start_time.elapsed();
                 error!(
-                    "❌ [/api/models] 獲取模型列表失敗 | 錯誤: {} | 耗時: {}",
-                    e,
-                    crate::utils::format_duration(duration)
+                    "{}",
+                    tr!(
+                        "❌ [/api/models] 獲取模型列表失敗 | 錯誤: {} | 耗時: {}",
+                        "❌ [/api/models] Failed to fetch model list | error: {} | duration: {}",
+                        e,
+                        crate::utils::format_duration(duration)
+                    )
                 );
                 res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
                 res.render(Json(json!({ "error": e })));
@@ -116,6 +272,8 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
     }
 
     let config = get_cached_config().await;
+    // 組織或專案限制了可見的模型時只列出這些模型
+    let scope = RequestScope::from_request(req);
 
     let is_enabled = config.enable.unwrap_or(false);
     debug!("🔍 設定檔啟用狀態 (來自緩存): {}", is_enabled);
@@ -128,7 +286,13 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
         .collect();
 
     if is_enabled {
-        info!("⚙️ 合併緩存的 Poe API 列表與 models.yaml (啟用)");
+        info!(
+            "{}",
+            tr!(
+                "⚙️ 合併緩存的 Poe API 列表與 models.yaml (啟用)",
+                "⚙️ Merging cached Poe model list with models.yaml (enabled)"
+            )
+        );
 
         let api_models_data_arc: Arc<Vec<ModelInfo>>;
 
@@ -150,7 +314,13 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
                 api_models_data_arc = cached_data.clone();
             } else {
                 // 緩存確實是空的，從 API 獲取數據
-                info!("⏳ 從 API 取得模型以填充快取中……");
+                info!(
+                    "{}",
+                    tr!(
+                        "⏳ 從 API 取得模型以填充快取中……",
+                        "⏳ Fetching models from API to fill the cache..."
+                    )
+                );
                 match get_models_from_api(&config).await {
                     Ok(models) => {
                         let new_data = Arc::new(models);
@@ -162,9 +332,13 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
                         // 如果填充緩存失敗，返回錯誤
                         let duration = start_time.elapsed(); // 計算耗時
                         error!(
-                            "❌ 無法填充 API 模型快取：{} | 耗時：{}。",
-                            e,
-                            crate::utils::format_duration(duration) // 在日誌中使用 duration
+                            "{}",
+                            tr!(
+                                "❌ 無法填充 API 模型快取：{} | 耗時：{}。",
+                                "❌ Failed to fill API model cache: {} | duration: {}",
+                                e,
+                                crate::utils::format_duration(duration) // 在日誌中使用 duration
+                            )
                         );
                         res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
                         res.render(Json(
@@ -228,38 +402,64 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
         }
 
         // 處理自訂模型，將其添加到已處理的模型列表中
-        if let Some(custom_models) = &config.custom_models {
-            if !custom_models.is_empty() {
-                info!("📋 處理自訂模型 | 數量: {}", custom_models.len());
-                for custom_model in custom_models {
-                    let model_id = custom_model.id.to_lowercase();
-                    // 檢查該ID是否已存在於處理後的模型中
-                    if !processed_models_enabled.iter().any(|m| m.id == model_id) {
-                        // 檢查是否在 yaml_config_map 中配置了 enable: false
-                        if let Some(yaml_config) = yaml_config_map.get(&model_id) {
-                            if yaml_config.enable == Some(false) {
-                                debug!("❌ 排除自訂模型 (YAML 停用): {}", model_id);
-                                continue;
-                            }
-                        }
-
-                        debug!("➕ 添加自訂模型: {}", model_id);
-                        processed_models_enabled.push(ModelInfo {
-                            id: model_id,
-                            object: "model".to_string(),
-                            created: custom_model
-                                .created
-                                .unwrap_or_else(|| Utc::now().timestamp()),
-                            owned_by: custom_model
-                                .owned_by
-                                .clone()
-                                .unwrap_or_else(|| "poe".to_string()),
-                        });
+        if let Some(custom_models) = &config.custom_models
+            && !custom_models.is_empty()
+        {
+            info!(
+                "{}",
+                tr!(
+                    "📋 處理自訂模型 | 數量: {}",
+                    "📋 Processing custom models | count: {}",
+                    custom_models.len()
+                )
+            );
+            for custom_model in custom_models {
+                let model_id = custom_model.id.to_lowercase();
+                // 檢查該ID是否已存在於處理後的模型中
+                if !processed_models_enabled.iter().any(|m| m.id == model_id) {
+                    // 檢查是否在 yaml_config_map 中配置了 enable: false
+                    if let Some(yaml_config) = yaml_config_map.get(&model_id)
+                        && yaml_config.enable == Some(false)
+                    {
+                        debug!("❌ 排除自訂模型 (YAML 停用): {}", model_id);
+                        continue;
                     }
+
+                    debug!("➕ 添加自訂模型: {}", model_id);
+                    processed_models_enabled.push(ModelInfo {
+                        id: model_id,
+                        object: "model".to_string(),
+                        created: custom_model
+                            .created
+                            .unwrap_or_else(|| Utc::now().timestamp()),
+                        owned_by: custom_model
+                            .owned_by
+                            .clone()
+                            .unwrap_or_else(|| "poe".to_string()),
+                    });
                 }
             }
         }
 
+        // 映射後的名稱對應回原始模型以查詢健康狀態
+        let mapped_originals: std::collections::HashMap<String, String> = yaml_config_map
+            .iter()
+            .filter_map(|(original, yaml_config)| {
+                let mapping = yaml_config.mapping.as_ref()?;
+                Some((mapping.to_lowercase(), original.clone()))
+            })
+            .collect();
+        let original = |id: &str| {
+            mapped_originals
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| id.to_string())
+        };
+        processed_models_enabled
+            .retain(|model| scope.allows_model(&config, &[&model.id, &original(&model.id)]));
+        let metadata = cached_model_metadata(&config).await;
+        let processed_models_enabled =
+            with_extensions(processed_models_enabled, original, &metadata);
         let response = json!({
             "object": "list",
             "data": processed_models_enabled
@@ -267,36 +467,57 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
 
         let duration = start_time.elapsed();
         info!(
-            "✅ 成功獲取處理後模型列表 | 來源: {} | 模型數量: {} | 處理時間: {}",
-            "YAML + Cached API",
-            processed_models_enabled.len(),
-            crate::utils::format_duration(duration)
+            "{}",
+            tr!(
+                "✅ 成功獲取處理後模型列表 | 來源: {} | 模型數量: {} | 處理時間: {}",
+                "✅ Fetched processed model list | source: {} | models: {} | duration: {}",
+                "YAML + Cached API",
+                processed_models_enabled.len(),
+                crate::utils::format_duration(duration)
+            )
         );
 
         res.render(Json(response));
     } else {
-        info!("🔌 YAML 停用，直接從 Poe API 獲取模型列表 (無緩存，無 YAML 規則)...");
+        info!(
+            "{}",
+            tr!(
+                "🔌 YAML 停用，直接從 Poe API 獲取模型列表 (無緩存，無 YAML 規則)...",
+                "🔌 YAML disabled, fetching model list directly from Poe API (uncached, no YAML rules)..."
+            )
+        );
 
         match get_models_from_api(&config).await {
-            Ok(models) => {
+            Ok(mut models) => {
+                models.retain(|model| scope.allows_model(&config, &[&model.id]));
+                let metadata = cached_model_metadata(&config).await;
+                let models = with_extensions(models, str::to_string, &metadata);
                 let response = json!({
                     "object": "list",
                     "data": models
                 });
                 let duration = start_time.elapsed();
                 info!(
-                    "✅ [直連 Poe] 成功直接獲取模型列表 | 模型數量: {} | 處理時間: {}",
-                    models.len(),
-                    crate::utils::format_duration(duration)
+                    "{}",
+                    tr!(
+                        "✅ [直連 Poe] 成功直接獲取模型列表 | 模型數量: {} | 處理時間: {}",
+                        "✅ [direct Poe] Fetched model list | models: {} | duration: {}",
+                        models.len(),
+                        crate::utils::format_duration(duration)
+                    )
                 );
                 res.render(Json(response));
             }
             Err(e) => {
                 let duration = start_time.elapsed();
                 error!(
-                    "❌ [直連 Poe] 直接獲取模型列表失敗 | 錯誤: {} | 耗時: {}",
-                    e,
-                    crate::utils::format_duration(duration)
+                    "{}",
+                    tr!(
+                        "❌ [直連 Poe] 直接獲取模型列表失敗 | 錯誤: {} | 耗時: {}",
+                        "❌ [direct Poe] Failed to fetch model list | error: {} | duration: {}",
+                        e,
+                        crate::utils::format_duration(duration)
+                    )
                 );
                 res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
                 res.render(Json(
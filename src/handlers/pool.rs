@@ -0,0 +1,332 @@
+//! Poe 帳戶 Token 池 (POE_TOKEN_POOL)
+//!
+//! 以 POE_POOL_ACCESS_KEYS 中的金鑰發出的請求，改由池中選出的 Token 連線 Poe。
+//! 背景定期查詢各帳戶的剩餘點數作為權重，以平滑加權輪詢分配請求，讓各帳戶按剩餘點數比例消耗；
+//! 上游回報點數耗盡的帳戶在下次查詢到點數前不再分配。尚未取得點數的帳戶以已知點數的平均值計算。
+//! 設定 REDIS_URL 時，點數與耗盡標記寫入共享狀態（以 Token 的雜湊為欄位），各實例定期同步
+
+use super::balance::mask_token;
+use crate::poe_client::get_current_point_balance;
+use crate::shared::{SYNC_INTERVAL, get_shared_state};
+use crate::utils::get_env_secret;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+struct PoolEntry {
+    token: String,
+    balance: Option<i64>,
+    exhausted: bool,
+    // 平滑加權輪詢的目前權重
+    current: i64,
+    requests: u64,
+}
+
+struct TokenPool {
+    access_keys: HashSet<String>,
+    entries: Mutex<Vec<PoolEntry>>,
+    refresh: Duration,
+}
+
+/// 逗號或換行分隔的設定值，方便以 *_FILE 逐行列出
+fn split_list(value: Option<String>) -> Vec<String> {
+    let mut items: Vec<String> = Vec::new();
+    for item in value
+        .unwrap_or_default()
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+    {
+        if !items.iter().any(|i| i == item) {
+            items.push(item.to_string());
+        }
+    }
+    items
+}
+
+static TOKEN_POOL: LazyLock<Option<TokenPool>> = LazyLock::new(|| {
+    let tokens = split_list(get_env_secret("POE_TOKEN_POOL"));
+    if tokens.is_empty() {
+        return None;
+    }
+    let access_keys: HashSet<String> = split_list(get_env_secret("POE_POOL_ACCESS_KEYS"))
+        .into_iter()
+        .collect();
+    if access_keys.is_empty() {
+        warn!(
+            "{}",
+            tr!(
+                "⚠️ 已設定 POE_TOKEN_POOL 但未設定 POE_POOL_ACCESS_KEYS，Token 池不會被使用",
+                "⚠️ POE_TOKEN_POOL is set but POE_POOL_ACCESS_KEYS is empty, the token pool is unused"
+            )
+        );
+        return None;
+    }
+    let refresh = std::env::var("POE_TOKEN_POOL_REFRESH_SECS")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(300);
+    info!(
+        "{}",
+        tr!(
+            "🎯 Token 池: {} 個 Token | 存取金鑰: {} 個 | 點數查詢間隔: {} 秒",
+            "🎯 Token pool: {} tokens | access keys: {} | balance refresh: every {}s",
+            tokens.len(),
+            access_keys.len(),
+            refresh
+        )
+    );
+    Some(TokenPool {
+        access_keys,
+        entries: Mutex::new(
+            tokens
+                .into_iter()
+                .map(|token| PoolEntry {
+                    token,
+                    balance: None,
+                    exhausted: false,
+                    current: 0,
+                    requests: 0,
+                })
+                .collect(),
+        ),
+        refresh: Duration::from_secs(refresh),
+    })
+});
+
+/// 共享狀態中各 Token 的點數狀態
+#[derive(Serialize, Deserialize)]
+struct SharedPoolEntry {
+    balance: Option<i64>,
+    exhausted: bool,
+}
+
+/// 共享狀態中代表 Token 的欄位，不寫入 Token 本身
+fn shared_field(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))[..16].to_string()
+}
+
+/// 將 Token 的點數狀態寫入共享狀態，未設定 REDIS_URL 時不處理
+fn publish_shared_entry(entry: &PoolEntry) {
+    let Some(shared) = get_shared_state() else {
+        return;
+    };
+    let Ok(value) = serde_json::to_string(&SharedPoolEntry {
+        balance: entry.balance,
+        exhausted: entry.exhausted,
+    }) else {
+        return;
+    };
+    let field = shared_field(&entry.token);
+    tokio::spawn(async move {
+        let _ = shared
+            .query::<()>(
+                redis::cmd("HSET")
+                    .arg(shared.key("token_pool"))
+                    .arg(field)
+                    .arg(value),
+            )
+            .await;
+    });
+}
+
+/// 套用其他實例寫入的點數狀態
+async fn sync_shared_entries(pool: &TokenPool) {
+    let Some(shared) = get_shared_state() else {
+        return;
+    };
+    let Ok(fields) = shared
+        .query::<HashMap<String, String>>(redis::cmd("HGETALL").arg(shared.key("token_pool")))
+        .await
+    else {
+        return;
+    };
+    for entry in pool.lock().iter_mut() {
+        let Some(state) = fields
+            .get(&shared_field(&entry.token))
+            .and_then(|v| serde_json::from_str::<SharedPoolEntry>(v).ok())
+        else {
+            continue;
+        };
+        if state.exhausted && !entry.exhausted {
+            debug!("🎯 Token 池同步耗盡標記: {}", mask_token(&entry.token));
+        }
+        entry.balance = state.balance.or(entry.balance);
+        entry.exhausted = state.exhausted;
+    }
+}
+
+impl TokenPool {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<PoolEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// 各 Token 的權重：剩餘點數，未知時以已知點數的平均值計算；全部為 0 時平均分配
+fn weights(entries: &[PoolEntry]) -> Vec<i64> {
+    let known: Vec<i64> = entries
+        .iter()
+        .filter_map(|e| e.balance)
+        .map(|b| b.max(0))
+        .collect();
+    let fallback = if known.is_empty() {
+        1
+    } else {
+        (known.iter().sum::<i64>() / known.len() as i64).max(1)
+    };
+    let weights: Vec<i64> = entries
+        .iter()
+        .map(|e| match (e.exhausted, e.balance) {
+            (true, _) => 0,
+            (false, Some(balance)) => balance.max(0),
+            (false, None) => fallback,
+        })
+        .collect();
+    if weights.iter().all(|w| *w == 0) {
+        vec![1; entries.len()]
+    } else {
+        weights
+    }
+}
+
+/// 以池中金鑰請求時，返回實際連線 Poe 使用的 Token
+pub(super) fn select_upstream_token(access_key: &str) -> Option<String> {
+    let pool = TOKEN_POOL.as_ref()?;
+    if !pool.access_keys.contains(access_key) {
+        return None;
+    }
+    let mut entries = pool.lock();
+    let weights = weights(&entries);
+    let total: i64 = weights.iter().sum();
+    // 平滑加權輪詢：每次加上權重並選出目前權重最大者，再扣除總權重
+    for (entry, weight) in entries.iter_mut().zip(&weights) {
+        entry.current = entry.current.saturating_add(*weight);
+    }
+    let (index, _) = entries
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| weights[*i] > 0)
+        .max_by_key(|(_, e)| e.current)?;
+    let entry = &mut entries[index];
+    entry.current = entry.current.saturating_sub(total);
+    entry.requests += 1;
+    debug!(
+        "🎯 Token 池選擇: {} | 權重: {}/{}",
+        mask_token(&entry.token),
+        weights[index],
+        total
+    );
+    Some(entry.token.clone())
+}
+
+/// 池中的所有 Token，供點數查詢使用
+pub(super) fn pool_tokens() -> Vec<String> {
+    TOKEN_POOL
+        .as_ref()
+        .map(|pool| pool.lock().iter().map(|e| e.token.clone()).collect())
+        .unwrap_or_default()
+}
+
+/// 記錄查詢到的點數，點數大於 0 時解除耗盡標記
+pub(super) fn record_pool_balance(token: &str, balance: i64) {
+    let Some(pool) = TOKEN_POOL.as_ref() else {
+        return;
+    };
+    if let Some(entry) = pool.lock().iter_mut().find(|e| e.token == token) {
+        entry.balance = Some(balance);
+        entry.exhausted = balance <= 0;
+        publish_shared_entry(entry);
+    }
+}
+
+/// 上游回報點數耗盡，在下次查詢到點數前不再分配
+pub(super) fn mark_pool_token_exhausted(token: &str) {
+    let Some(pool) = TOKEN_POOL.as_ref() else {
+        return;
+    };
+    if let Some(entry) = pool.lock().iter_mut().find(|e| e.token == token)
+        && !entry.exhausted
+    {
+        entry.exhausted = true;
+        publish_shared_entry(entry);
+        info!(
+            "{}",
+            tr!(
+                "🎯 Token 池暫停分配點數耗盡的 Token: {}",
+                "🎯 Token pool stops assigning exhausted token: {}",
+                mask_token(token)
+            )
+        );
+    }
+}
+
+/// Token 池狀態，供 /api/admin/balance 使用；未設定時返回 None
+pub(super) fn pool_status() -> Option<serde_json::Value> {
+    let pool = TOKEN_POOL.as_ref()?;
+    let entries = pool.lock();
+    let weights = weights(&entries);
+    let total: i64 = weights.iter().sum();
+    Some(json!(
+        entries
+            .iter()
+            .zip(&weights)
+            .map(|(entry, weight)| json!({
+                "token": mask_token(&entry.token),
+                "balance": entry.balance,
+                "exhausted": entry.exhausted,
+                "share": if total > 0 { *weight as f64 / total as f64 } else { 0.0 },
+                "requests": entry.requests,
+            }))
+            .collect::<Vec<_>>()
+    ))
+}
+
+/// 啟動 Token 池的背景點數查詢及共享狀態同步，未設定 Token 池時不處理
+pub fn spawn_token_pool_refresher() {
+    let Some(pool) = TOKEN_POOL.as_ref() else {
+        return;
+    };
+    if get_shared_state().is_some() {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SYNC_INTERVAL);
+            loop {
+                interval.tick().await;
+                sync_shared_entries(pool).await;
+            }
+        });
+    }
+    if pool.refresh.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(pool.refresh);
+        loop {
+            interval.tick().await;
+            for token in pool_tokens() {
+                match get_current_point_balance(&token).await {
+                    Ok(balance) => {
+                        debug!(
+                            "🎯 Token 池點數 | Token: {} | 剩餘: {}",
+                            mask_token(&token),
+                            balance
+                        );
+                        record_pool_balance(&token, balance);
+                    }
+                    Err(e) => warn!(
+                        "{}",
+                        tr!(
+                            "⚠️ Token 池查詢點數失敗 | Token: {} | 錯誤: {}",
+                            "⚠️ Token pool balance query failed | token: {} | error: {}",
+                            mask_token(&token),
+                            e
+                        )
+                    ),
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,221 @@
+use super::chat::run_chat_request;
+use crate::cache::get_cached_config;
+use crate::store::{CompletionFilter, list_completions, read_completion};
+use crate::types::ChatCompletionRequest;
+use crate::utils::format_duration;
+use salvo::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// 逐行比對的行數上限，超過時不產生差異
+const MAX_DIFF_LINES: usize = 2000;
+
+#[derive(Deserialize)]
+struct ReplayRequest {
+    completion_id: String,
+    /// 重播使用的模型，未指定時使用原記錄的模型
+    model: Option<String>,
+    /// Poe API Token，未指定時使用 models.yaml 的 api_token
+    api_key: Option<String>,
+}
+
+/// 取出 chat.completion 回應中第一個選項的文字內容
+fn response_content(response: &serde_json::Value) -> String {
+    response["choices"][0]["message"]["content"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// 最後一則使用者訊息的文字摘要
+fn last_user_preview(messages: &serde_json::Value) -> String {
+    let Some(message) = messages
+        .as_array()
+        .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"))
+    else {
+        return String::new();
+    };
+    let text = match &message["content"] {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter_map(|item| item["text"].as_str())
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => String::new(),
+    };
+    text.chars().take(120).collect()
+}
+
+/// 以最長共同子序列逐行比對，返回 {op, text} 列表，op 為 equal、delete 或 insert
+fn line_diff(original: &str, replay: &str) -> Option<Vec<serde_json::Value>> {
+    let a: Vec<&str> = original.lines().collect();
+    let b: Vec<&str> = replay.lines().collect();
+    if a.len() > MAX_DIFF_LINES || b.len() > MAX_DIFF_LINES {
+        return None;
+    }
+    // lcs[i][j]：a[i..] 與 b[j..] 的最長共同子序列長度
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            diff.push(json!({ "op": "equal", "text": a[i] }));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(json!({ "op": "delete", "text": a[i] }));
+            i += 1;
+        } else {
+            diff.push(json!({ "op": "insert", "text": b[j] }));
+            j += 1;
+        }
+    }
+    Some(diff)
+}
+
+/// 列出最近的聊天完成記錄（所有 API Key），供管理介面選擇重播
+#[handler]
+pub async fn list_replay_candidates(req: &mut Request, res: &mut Response) {
+    let filter = CompletionFilter {
+        model: req.query::<String>("model").filter(|m| !m.is_empty()),
+        limit: req.query::<usize>("limit").unwrap_or(20).clamp(1, 100),
+        descending: true,
+        ..Default::default()
+    };
+    // 未使用游標，不會失敗
+    let (records, has_more) = list_completions(None, &filter).unwrap_or_default();
+    let data: Vec<serde_json::Value> = records
+        .iter()
+        .map(|record| {
+            json!({
+                "id": record.id,
+                "created": record.created,
+                "model": record.model,
+                "owner": record.owner.chars().take(12).collect::<String>(),
+                "metadata": record.metadata,
+                "prompt": last_user_preview(&record.messages),
+            })
+        })
+        .collect();
+    res.render(Json(json!({ "data": data, "has_more": has_more })));
+}
+
+/// 重播已儲存的聊天完成記錄，可改用其他模型，並與原始輸出逐行比對
+/// 僅重播訊息與模型，原始請求的取樣參數未被儲存
+#[handler]
+pub async fn replay_completion(req: &mut Request, res: &mut Response) {
+    let body = match req.parse_json::<ReplayRequest>().await {
+        Ok(body) => body,
+        Err(e) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(json!({ "error": e.to_string() })));
+            return;
+        }
+    };
+    let Some(record) = read_completion(&body.completion_id) else {
+        res.status_code(StatusCode::NOT_FOUND);
+        res.render(Json(json!({
+            "error": format!("找不到聊天完成記錄: {}", body.completion_id)
+        })));
+        return;
+    };
+    let config = get_cached_config().await;
+    let Some(access_key) = body
+        .api_key
+        .filter(|k| !k.trim().is_empty())
+        .or_else(|| config.api_token.clone().filter(|t| !t.trim().is_empty()))
+    else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(json!({
+            "error": "未指定 api_key，且 models.yaml 未設定 api_token"
+        })));
+        return;
+    };
+    let model = body
+        .model
+        .filter(|m| !m.trim().is_empty())
+        .unwrap_or_else(|| record.model.clone());
+    let chat_request = match serde_json::from_value::<ChatCompletionRequest>(json!({
+        "model": model,
+        "messages": record.messages,
+    })) {
+        Ok(chat_request) => chat_request,
+        Err(e) => {
+            res.status_code(StatusCode::UNPROCESSABLE_ENTITY);
+            res.render(Json(json!({
+                "error": format!("無法還原請求訊息: {}", e)
+            })));
+            return;
+        }
+    };
+
+    let start_time = Instant::now();
+    info!(
+        "{}",
+        tr!(
+            "🔁 重播聊天完成記錄 | ID: {} | 原始模型: {} | 重播模型: {}",
+            "🔁 Replaying stored completion | ID: {} | original model: {} | replay model: {}",
+            record.id,
+            record.model,
+            model
+        )
+    );
+    let original = json!({
+        "model": record.model,
+        "content": response_content(&record.response),
+        "usage": record.response["usage"],
+    });
+    match run_chat_request(chat_request, &access_key).await {
+        Ok(response) => {
+            let response = serde_json::to_value(&response).unwrap_or_default();
+            let content = response_content(&response);
+            let diff = line_diff(original["content"].as_str().unwrap_or_default(), &content);
+            info!(
+                "{}",
+                tr!(
+                    "✅ 重播完成 | ID: {} | 耗時: {}",
+                    "✅ Replay completed | ID: {} | duration: {}",
+                    record.id,
+                    format_duration(start_time.elapsed())
+                )
+            );
+            res.render(Json(json!({
+                "completion_id": record.id,
+                "original": original,
+                "replay": {
+                    "model": model,
+                    "content": content,
+                    "usage": response["usage"],
+                    "response": response,
+                },
+                "identical": original["content"] == content,
+                "diff": diff,
+            })));
+        }
+        Err((status, error_response)) => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 重播失敗 | ID: {} | 錯誤: {}",
+                    "⚠️ Replay failed | ID: {} | error: {}",
+                    record.id,
+                    error_response.error.message
+                )
+            );
+            res.status_code(status);
+            res.render(Json(error_response));
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! 可續傳的串流響應 (STREAM_RESUME_SECS)
+//!
+//! 啟用後每個 SSE 事件帶有 `id: {completion_id}:{序號}`，生成在背景持續進行並保存已輸出的事件，
+//! 客戶端斷線不會中止生成。客戶端重新發送請求並帶上 Last-Event-ID 標頭時，
+//! 從該事件之後繼續輸出；完成後的事件保留 STREAM_RESUME_SECS 秒
+
+use futures_util::stream::{self, Stream, StreamExt};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+static RESUME_TTL: LazyLock<Option<Duration>> = LazyLock::new(|| {
+    let secs = std::env::var("STREAM_RESUME_SECS")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)?;
+    info!(
+        "{}",
+        tr!(
+            "🔁 可續傳串流已啟用 | 完成後保留: {} 秒",
+            "🔁 Resumable streams enabled | kept for {}s after completion",
+            secs
+        )
+    );
+    Some(Duration::from_secs(secs))
+});
+
+#[derive(Default)]
+struct Frames {
+    // 已加上 id 的 SSE 事件，索引即序號
+    frames: Vec<String>,
+    done: bool,
+}
+
+struct ResumableStream {
+    owner: String,
+    frames: Mutex<Frames>,
+    // 有新事件或完成時通知讀取端
+    changed: watch::Sender<()>,
+}
+
+impl ResumableStream {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Frames> {
+        self.frames.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+static STREAMS: LazyLock<Mutex<HashMap<String, Arc<ResumableStream>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn lock_streams() -> std::sync::MutexGuard<'static, HashMap<String, Arc<ResumableStream>>> {
+    STREAMS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(super) fn resume_enabled() -> bool {
+    RESUME_TTL.is_some()
+}
+
+/// 在背景執行串流並保存事件，返回從頭開始讀取的串流；未啟用時原樣返回
+pub(super) fn make_resumable<S>(
+    id: &str,
+    owner: &str,
+    stream: S,
+) -> stream::BoxStream<'static, Result<String, Infallible>>
+where
+    S: Stream<Item = Result<String, Infallible>> + Send + 'static,
+{
+    let Some(ttl) = *RESUME_TTL else {
+        return stream.boxed();
+    };
+    let (changed, _) = watch::channel(());
+    let entry = Arc::new(ResumableStream {
+        owner: owner.to_string(),
+        frames: Mutex::new(Frames::default()),
+        changed,
+    });
+    lock_streams().insert(id.to_string(), entry.clone());
+
+    let id = id.to_string();
+    let producer = entry.clone();
+    tokio::spawn(async move {
+        let mut stream = Box::pin(stream);
+        while let Some(Ok(text)) = stream.next().await {
+            let mut state = producer.lock();
+            for frame in text.split("\n\n").filter(|frame| !frame.is_empty()) {
+                let seq = state.frames.len();
+                state
+                    .frames
+                    .push(format!("id: {}:{}\n{}\n\n", id, seq, frame));
+            }
+            drop(state);
+            producer.changed.send_replace(());
+        }
+        producer.lock().done = true;
+        producer.changed.send_replace(());
+        debug!(
+            "🔁 串流已完成，保留 {} 個事件 {} 秒 | ID: {}",
+            producer.lock().frames.len(),
+            ttl.as_secs(),
+            id
+        );
+        tokio::time::sleep(ttl).await;
+        let mut streams = lock_streams();
+        if streams
+            .get(&id)
+            .is_some_and(|current| Arc::ptr_eq(current, &producer))
+        {
+            streams.remove(&id);
+        }
+    });
+    follow(entry, 0)
+}
+
+/// 由 start 序號開始讀取保存的事件，直到串流完成
+fn follow(
+    entry: Arc<ResumableStream>,
+    start: usize,
+) -> stream::BoxStream<'static, Result<String, Infallible>> {
+    let receiver = entry.changed.subscribe();
+    stream::unfold(
+        (entry, receiver, start),
+        |(entry, mut receiver, next)| async move {
+            loop {
+                {
+                    let state = entry.lock();
+                    if next < state.frames.len() {
+                        let text = state.frames[next..].concat();
+                        let next = state.frames.len();
+                        drop(state);
+                        return Some((Ok(text), (entry, receiver, next)));
+                    }
+                    if state.done {
+                        return None;
+                    }
+                }
+                if receiver.changed().await.is_err() {
+                    return None;
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+/// 解析 Last-Event-ID，返回同一 API Key 的串流中該事件之後的內容；找不到時返回 None
+pub(super) fn resume_stream(
+    last_event_id: &str,
+    owner: &str,
+) -> Option<stream::BoxStream<'static, Result<String, Infallible>>> {
+    let (id, seq) = last_event_id.trim().rsplit_once(':')?;
+    let seq: usize = seq.parse().ok()?;
+    let entry = lock_streams().get(id).cloned()?;
+    if entry.owner != owner {
+        return None;
+    }
+    debug!("🔁 續傳串流 | ID: {} | 自事件: {}", id, seq + 1);
+    Some(follow(entry, seq + 1))
+}
+
+/// 保存中的可續傳串流數，供 /api/admin/stats 使用；未啟用時返回 None
+pub(super) fn resume_stats() -> Option<serde_json::Value> {
+    RESUME_TTL.as_ref()?;
+    let streams = lock_streams();
+    let active = streams.values().filter(|s| !s.lock().done).count();
+    Some(serde_json::json!({
+        "buffered": streams.len(),
+        "active": active,
+    }))
+}
@@ -0,0 +1,50 @@
+use super::limit::rate_limit_ms;
+use crate::runtime::{
+    RuntimeOverrides, clear_runtime_overrides, current_log_level, runtime_overrides,
+    update_runtime_overrides,
+};
+use salvo::prelude::*;
+use serde_json::json;
+
+fn runtime_status(overrides: &RuntimeOverrides) -> serde_json::Value {
+    json!({
+        "log_level": current_log_level(),
+        "rate_limit_ms": rate_limit_ms(),
+        "overrides": overrides,
+    })
+}
+
+/// 目前生效的執行期設定與覆蓋值
+#[handler]
+pub(super) async fn get_runtime(res: &mut Response) {
+    res.render(Json(runtime_status(&runtime_overrides())));
+}
+
+/// 修改執行期設定，未提供的項目保持不變
+#[handler]
+pub(super) async fn update_runtime(req: &mut Request, res: &mut Response) {
+    let update = match req.parse_json::<RuntimeOverrides>().await {
+        Ok(update) => update,
+        Err(e) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(json!({ "error": e.to_string() })));
+            return;
+        }
+    };
+    match update_runtime_overrides(update) {
+        Ok(overrides) => res.render(Json(runtime_status(&overrides))),
+        Err(e) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(json!({
+                "error": format!("無效的日誌過濾規則: {}", e)
+            })));
+        }
+    }
+}
+
+/// 清除所有覆蓋，恢復環境變數的設定
+#[handler]
+pub(super) async fn reset_runtime(res: &mut Response) {
+    clear_runtime_overrides();
+    res.render(Json(runtime_status(&RuntimeOverrides::default())));
+}
@@ -0,0 +1,76 @@
+//! 依 OpenAI-Organization / OpenAI-Project 標頭區分的請求範圍
+//!
+//! 兩個標頭的值記錄在日誌與用量統計中。models.yaml 的 scopes 以專案名稱（優先）或組織名稱對應設定：
+//! models 限制該範圍在 /v1/models 中可見及可請求的模型；api_token 讓 access_keys 中的 API Key
+//! 改以該 Token 連線 Poe。標頭由客戶端自行設定，因此 Token 綁定必須同時符合 API Key
+
+use crate::types::{Config, ScopeConfig};
+use salvo::prelude::*;
+use tracing::debug;
+
+/// 請求的組織與專案
+#[derive(Default)]
+pub(super) struct RequestScope {
+    pub(super) organization: Option<String>,
+    pub(super) project: Option<String>,
+}
+
+impl RequestScope {
+    pub(super) fn from_request(req: &Request) -> Self {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+        Self {
+            organization: header("OpenAI-Organization"),
+            project: header("OpenAI-Project"),
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.organization.is_none() && self.project.is_none()
+    }
+
+    /// 套用的範圍設定，專案的設定優先於組織
+    fn config<'a>(&self, config: &'a Config) -> Option<(&str, &'a ScopeConfig)> {
+        let scopes = config.scopes.as_ref()?;
+        [&self.project, &self.organization]
+            .into_iter()
+            .flatten()
+            .find_map(|name| scopes.get(name).map(|scope| (name.as_str(), scope)))
+    }
+
+    /// 範圍是否允許使用模型，names 為模型對外與原始名稱，任一符合即可
+    pub(super) fn allows_model(&self, config: &Config, names: &[&str]) -> bool {
+        let Some((_, scope)) = self.config(config) else {
+            return true;
+        };
+        scope.models.as_ref().is_none_or(|models| {
+            models
+                .iter()
+                .any(|model| names.iter().any(|name| model.eq_ignore_ascii_case(name)))
+        })
+    }
+
+    /// 範圍綁定的 Poe Token，API Key 不在該範圍的 access_keys 中時返回 None
+    pub(super) fn upstream_token(&self, config: &Config, access_key: &str) -> Option<String> {
+        let (name, scope) = self.config(config)?;
+        let token = scope
+            .api_token
+            .as_ref()
+            .filter(|token| !token.trim().is_empty())?;
+        if !scope.access_keys.iter().any(|key| key == access_key) {
+            debug!(
+                "🏢 API Key 不在範圍 {} 的 access_keys 中，不使用綁定的 Token",
+                name
+            );
+            return None;
+        }
+        debug!("🏢 使用範圍 {} 綁定的 Token", name);
+        Some(token.clone())
+    }
+}
@@ -0,0 +1,226 @@
+use super::balance::{collect_tokens, mask_token};
+use super::models::get_models_from_api;
+use crate::cache::get_cached_config;
+use crate::poe_client::get_current_point_balance;
+use chrono::Utc;
+use salvo::prelude::*;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::{LazyLock, RwLock};
+use tracing::{error, info, warn};
+
+/// 最近一次啟動自檢的結果，未啟用自檢時為 None
+static SELF_TEST_REPORT: LazyLock<RwLock<Option<SelfTestReport>>> =
+    LazyLock::new(|| RwLock::new(None));
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SelfTestStatus {
+    Running,
+    Passed,
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+struct TokenCheck {
+    token: String,
+    source: String,
+    ok: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct SelfTestReport {
+    status: SelfTestStatus,
+    started_at: i64,
+    finished_at: Option<i64>,
+    tokens: Vec<TokenCheck>,
+    model_count: Option<usize>,
+    model_list_error: Option<String>,
+    /// models.yaml 中找不到對應 Poe 機器人的模型
+    unresolved_models: Vec<String>,
+}
+
+impl SelfTestReport {
+    fn running() -> Self {
+        SelfTestReport {
+            status: SelfTestStatus::Running,
+            started_at: Utc::now().timestamp(),
+            finished_at: None,
+            tokens: Vec::new(),
+            model_count: None,
+            model_list_error: None,
+            unresolved_models: Vec::new(),
+        }
+    }
+}
+
+fn self_test_enabled() -> bool {
+    std::env::var("STARTUP_SELF_TEST")
+        .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+        .unwrap_or(false)
+}
+
+async fn run_self_test() -> SelfTestReport {
+    let mut report = SelfTestReport::running();
+
+    // 以查詢點數驗證每個 Token 是否有效
+    let tokens = collect_tokens().await;
+    if tokens.is_empty() {
+        warn!(
+            "{}",
+            tr!(
+                "⚠️ 啟動自檢: 未設定任何 Poe Token（models.yaml api_token 或 POE_BALANCE_TOKENS），跳過驗證",
+                "⚠️ Startup self-test: no Poe token configured (models.yaml api_token or POE_BALANCE_TOKENS), skipping token check"
+            )
+        );
+    }
+    for (token, source) in tokens {
+        let masked = mask_token(&token);
+        let check = match get_current_point_balance(&token).await {
+            Ok(_) => {
+                info!(
+                    "{}",
+                    tr!(
+                        "✅ 啟動自檢: Token 驗證成功 | Token: {} | 來源: {}",
+                        "✅ Startup self-test: token valid | token: {} | source: {}",
+                        masked,
+                        source
+                    )
+                );
+                TokenCheck {
+                    token: masked,
+                    source: source.to_string(),
+                    ok: true,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ 啟動自檢: Token 驗證失敗 | Token: {} | 來源: {} | 錯誤: {}",
+                        "❌ Startup self-test: token check failed | token: {} | source: {} | error: {}",
+                        masked,
+                        source,
+                        e
+                    )
+                );
+                TokenCheck {
+                    token: masked,
+                    source: source.to_string(),
+                    ok: false,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        report.tokens.push(check);
+    }
+
+    // 取得模型列表並檢查 models.yaml 中的模型
+    let config = get_cached_config().await;
+    match get_models_from_api(&config).await {
+        Ok(models) => {
+            let available: HashSet<String> = models.iter().map(|m| m.id.to_lowercase()).collect();
+            report.model_count = Some(available.len());
+            let mut unresolved: Vec<String> = config
+                .models
+                .keys()
+                .filter(|id| !available.contains(&id.to_lowercase()))
+                .cloned()
+                .collect();
+            unresolved.sort();
+            for id in &unresolved {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 啟動自檢: models.yaml 中的模型找不到對應的 Poe 機器人: {}",
+                        "⚠️ Startup self-test: models.yaml entry has no matching Poe bot: {}",
+                        id
+                    )
+                );
+            }
+            info!(
+                "{}",
+                tr!(
+                    "📋 啟動自檢: 取得 {} 個模型 | 無法對應的設定: {}",
+                    "📋 Startup self-test: fetched {} models | unresolved entries: {}",
+                    available.len(),
+                    unresolved.len()
+                )
+            );
+            report.unresolved_models = unresolved;
+        }
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 啟動自檢: 取得模型列表失敗: {}",
+                    "❌ Startup self-test: failed to fetch model list: {}",
+                    e
+                )
+            );
+            report.model_list_error = Some(e);
+        }
+    }
+
+    let failed = report.model_list_error.is_some() || report.tokens.iter().any(|t| !t.ok);
+    report.status = if failed {
+        SelfTestStatus::Failed
+    } else {
+        SelfTestStatus::Passed
+    };
+    report.finished_at = Some(Utc::now().timestamp());
+    report
+}
+
+/// 啟動自檢 (STARTUP_SELF_TEST=true)：驗證 Poe Token 並檢查 models.yaml 中的模型，結果透過 /readyz 提供
+pub fn spawn_startup_self_test() {
+    if !self_test_enabled() {
+        return;
+    }
+    info!(
+        "{}",
+        tr!(
+            "🩺 啟動自檢: 開始驗證 Poe Token 與模型設定",
+            "🩺 Startup self-test: checking Poe tokens and model config"
+        )
+    );
+    *SELF_TEST_REPORT.write().unwrap_or_else(|e| e.into_inner()) = Some(SelfTestReport::running());
+    tokio::spawn(async move {
+        let report = run_self_test().await;
+        match report.status {
+            SelfTestStatus::Failed => error!(
+                "{}",
+                tr!(
+                    "❌ 啟動自檢未通過，詳情見 /readyz",
+                    "❌ Startup self-test failed, see /readyz for details"
+                )
+            ),
+            _ => info!("{}", tr!("✅ 啟動自檢通過", "✅ Startup self-test passed")),
+        }
+        *SELF_TEST_REPORT.write().unwrap_or_else(|e| e.into_inner()) = Some(report);
+    });
+}
+
+/// 就緒檢查：自檢進行中或未通過時返回 503
+#[handler]
+pub async fn readyz(res: &mut Response) {
+    let report = SELF_TEST_REPORT
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let status = match report.as_ref().map(|r| r.status) {
+        None | Some(SelfTestStatus::Passed) => "ready",
+        Some(SelfTestStatus::Running) => "starting",
+        Some(SelfTestStatus::Failed) => "not_ready",
+    };
+    if status != "ready" {
+        res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    res.render(Json(json!({
+        "status": status,
+        "self_test": report,
+    })));
+}
@@ -0,0 +1,103 @@
+use super::admission::admission_stats;
+use super::backpressure::backpressure_stats;
+use super::balance::points_exhausted_count;
+use super::coalesce::coalesce_stats;
+use super::models::cached_model_count;
+use super::resume::resume_stats;
+use crate::cache::cache_stats;
+use crate::dns::dns_stats;
+use crate::store::store_stats;
+use salvo::prelude::*;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 處理中的聊天完成請求數
+static ACTIVE_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+/// 仍在輸出的串流響應數
+static ACTIVE_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// 進行中計數，離開作用域（包括串流被客戶端中斷而丟棄）時自動減少
+pub(super) struct InFlight(&'static AtomicUsize);
+
+impl InFlight {
+    pub(super) fn request() -> Self {
+        ACTIVE_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        InFlight(&ACTIVE_REQUESTS)
+    }
+
+    pub(super) fn stream() -> Self {
+        ACTIVE_STREAMS.fetch_add(1, Ordering::Relaxed);
+        InFlight(&ACTIVE_STREAMS)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// mimalloc 回報的行程資訊；Linux 上 RSS 由已提交記憶體估算
+fn process_stats() -> serde_json::Value {
+    let mut elapsed_msecs = 0;
+    let mut user_msecs = 0;
+    let mut system_msecs = 0;
+    let mut current_rss = 0;
+    let mut peak_rss = 0;
+    let mut current_commit = 0;
+    let mut peak_commit = 0;
+    let mut page_faults = 0;
+    // SAFETY: 所有參數皆為有效的輸出指標
+    unsafe {
+        libmimalloc_sys::mi_process_info(
+            &mut elapsed_msecs,
+            &mut user_msecs,
+            &mut system_msecs,
+            &mut current_rss,
+            &mut peak_rss,
+            &mut current_commit,
+            &mut peak_commit,
+            &mut page_faults,
+        );
+    }
+    json!({
+        "uptime_ms": elapsed_msecs,
+        "user_ms": user_msecs,
+        "system_ms": system_msecs,
+        "current_rss": current_rss,
+        "peak_rss": peak_rss,
+        "current_commit": current_commit,
+        "peak_commit": peak_commit,
+        "page_faults": page_faults,
+    })
+}
+
+/// 執行期統計：記憶體、緩存、儲存資料庫、DNS 解析、進行中、排隊中與合併的請求及上游點數耗盡次數
+#[handler]
+pub async fn get_stats(res: &mut Response) {
+    let runtime = tokio::runtime::Handle::current().metrics();
+    res.render(Json(json!({
+        "process": process_stats(),
+        "runtime": {
+            "workers": runtime.num_workers(),
+            "alive_tasks": runtime.num_alive_tasks(),
+        },
+        "in_flight": {
+            "requests": ACTIVE_REQUESTS.load(Ordering::Relaxed),
+            "streams": ACTIVE_STREAMS.load(Ordering::Relaxed),
+        },
+        "admission": admission_stats(),
+        "resumable_streams": resume_stats(),
+        "coalesced_requests": coalesce_stats(),
+        "stream_backpressure": backpressure_stats(),
+        "upstream": {
+            "points_exhausted": points_exhausted_count(),
+        },
+        "cache": cache_stats(),
+        "store": store_stats(),
+        "dns": dns_stats(),
+        "memory_caches": {
+            "api_models": cached_model_count().await,
+        },
+    })));
+}
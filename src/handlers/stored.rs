@@ -0,0 +1,295 @@
+use crate::store::{
+    CompletionFilter, UnknownCursor, delete_completion, delete_conversation, get_completion,
+    get_conversation, list_completions, list_conversations, owner_hash,
+};
+use crate::types::{OpenAIError, OpenAIErrorResponse};
+use salvo::prelude::*;
+use serde_json::json;
+use std::collections::HashMap;
+use tracing::{debug, info};
+
+/// 從 Authorization 標頭取得 API Key，缺少時返回 401
+pub(crate) fn require_bearer(req: &Request, res: &mut Response) -> Option<String> {
+    let key = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.to_string());
+    if key.is_none() {
+        res.status_code(StatusCode::UNAUTHORIZED);
+        res.render(Json(json!({ "error": "缺少或無效的 Authorization" })));
+    }
+    key
+}
+
+fn render_not_found(res: &mut Response, id: &str) {
+    res.status_code(StatusCode::NOT_FOUND);
+    res.render(Json(OpenAIErrorResponse {
+        error: OpenAIError {
+            message: format!("No chat completion found with id '{}'.", id),
+            r#type: "invalid_request_error".to_string(),
+            code: "not_found".to_string(),
+            param: None,
+        },
+    }));
+}
+
+#[handler]
+pub async fn list_stored_completions(req: &mut Request, res: &mut Response) {
+    let Some(access_key) = require_bearer(req, res) else {
+        return;
+    };
+
+    // metadata 以 metadata[key]=value 形式傳入
+    let metadata: HashMap<String, String> = req
+        .queries()
+        .iter()
+        .filter_map(|(key, value)| {
+            key.strip_prefix("metadata[")
+                .and_then(|k| k.strip_suffix(']'))
+                .map(|k| (k.to_string(), value.clone()))
+        })
+        .collect();
+    let filter = CompletionFilter {
+        model: req.query::<String>("model"),
+        metadata,
+        created_after: req.query::<i64>("created_after"),
+        created_before: req.query::<i64>("created_before"),
+        after: req.query::<String>("after"),
+        limit: req.query::<usize>("limit").unwrap_or(20).clamp(1, 100),
+        descending: req.query::<String>("order").as_deref() == Some("desc"),
+    };
+    info!(
+        "{}",
+        tr!(
+            "💾 列出已儲存的聊天完成記錄 | 模型: {:?} | metadata: {:?}",
+            "💾 Listing stored chat completions | model: {:?} | metadata: {:?}",
+            filter.model,
+            filter.metadata
+        )
+    );
+
+    let (records, has_more) = match list_completions(Some(&owner_hash(&access_key)), &filter) {
+        Ok(page) => page,
+        Err(UnknownCursor(after)) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(OpenAIErrorResponse {
+                error: OpenAIError {
+                    message: format!(
+                        "No chat completion found with id '{}' to list after.",
+                        after
+                    ),
+                    r#type: "invalid_request_error".to_string(),
+                    code: "invalid_cursor".to_string(),
+                    param: Some("after".to_string()),
+                },
+            }));
+            return;
+        }
+    };
+    let data: Vec<serde_json::Value> = records
+        .iter()
+        .map(|record| record.to_completion_object())
+        .collect();
+    res.render(Json(json!({
+        "object": "list",
+        "first_id": records.first().map(|r| r.id.clone()),
+        "last_id": records.last().map(|r| r.id.clone()),
+        "has_more": has_more,
+        "data": data,
+    })));
+}
+
+#[handler]
+pub async fn get_stored_completion(req: &mut Request, res: &mut Response) {
+    let Some(access_key) = require_bearer(req, res) else {
+        return;
+    };
+    let id = req.param::<String>("id").unwrap_or_default();
+    info!(
+        "{}",
+        tr!(
+            "💾 查詢已儲存的聊天完成記錄 | ID: {}",
+            "💾 Fetching stored chat completion | ID: {}",
+            id
+        )
+    );
+    match get_completion(&id, &owner_hash(&access_key)) {
+        Some(record) => res.render(Json(record.to_completion_object())),
+        None => render_not_found(res, &id),
+    }
+}
+
+#[handler]
+pub async fn delete_stored_completion(req: &mut Request, res: &mut Response) {
+    let Some(access_key) = require_bearer(req, res) else {
+        return;
+    };
+    let id = req.param::<String>("id").unwrap_or_default();
+    info!(
+        "{}",
+        tr!(
+            "🗑️ 刪除已儲存的聊天完成記錄 | ID: {}",
+            "🗑️ Deleting stored chat completion | ID: {}",
+            id
+        )
+    );
+    if delete_completion(&id, &owner_hash(&access_key)) {
+        res.render(Json(json!({
+            "object": "chat.completion.deleted",
+            "id": id,
+            "deleted": true,
+        })));
+    } else {
+        render_not_found(res, &id);
+    }
+}
+
+#[handler]
+pub async fn get_stored_messages(req: &mut Request, res: &mut Response) {
+    let Some(access_key) = require_bearer(req, res) else {
+        return;
+    };
+    let id = req.param::<String>("id").unwrap_or_default();
+    let Some(record) = get_completion(&id, &owner_hash(&access_key)) else {
+        render_not_found(res, &id);
+        return;
+    };
+
+    let limit = req.query::<usize>("limit").unwrap_or(20).clamp(1, 100);
+    let after = req.query::<String>("after");
+    let descending = req.query::<String>("order").as_deref() == Some("desc");
+
+    // 以 {id}-{index} 作為訊息 ID
+    let mut messages: Vec<serde_json::Value> = record
+        .messages
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut message)| {
+            message["id"] = json!(format!("{}-{}", id, index));
+            message
+        })
+        .collect();
+    if descending {
+        messages.reverse();
+    }
+    if let Some(after) = after
+        && let Some(pos) = messages.iter().position(|m| m["id"] == json!(after))
+    {
+        messages.drain(..=pos);
+    }
+    let has_more = messages.len() > limit;
+    messages.truncate(limit);
+    debug!(
+        "💾 返回已儲存的訊息 | ID: {} | 數量: {} | 還有更多: {}",
+        id,
+        messages.len(),
+        has_more
+    );
+
+    res.render(Json(json!({
+        "object": "list",
+        "first_id": messages.first().map(|m| m["id"].clone()),
+        "last_id": messages.last().map(|m| m["id"].clone()),
+        "has_more": has_more,
+        "data": messages,
+    })));
+}
+
+#[handler]
+pub async fn list_stored_conversations(req: &mut Request, res: &mut Response) {
+    let Some(access_key) = require_bearer(req, res) else {
+        return;
+    };
+    let limit = req.query::<usize>("limit").unwrap_or(20).clamp(1, 100);
+    let after = req.query::<String>("after");
+    info!("{}", tr!("💬 列出對話記錄", "💬 Listing conversations"));
+    let (conversations, has_more) =
+        match list_conversations(&owner_hash(&access_key), after.as_deref(), limit) {
+            Ok(page) => page,
+            Err(UnknownCursor(after)) => {
+                res.status_code(StatusCode::BAD_REQUEST);
+                res.render(Json(OpenAIErrorResponse {
+                    error: OpenAIError {
+                        message: format!(
+                            "No conversation found with id '{}' to list after.",
+                            after
+                        ),
+                        r#type: "invalid_request_error".to_string(),
+                        code: "invalid_cursor".to_string(),
+                        param: Some("after".to_string()),
+                    },
+                }));
+                return;
+            }
+        };
+    let data: Vec<serde_json::Value> = conversations.iter().map(|c| c.to_object(false)).collect();
+    res.render(Json(json!({
+        "object": "list",
+        "first_id": conversations.first().map(|c| c.id.clone()),
+        "last_id": conversations.last().map(|c| c.id.clone()),
+        "has_more": has_more,
+        "data": data,
+    })));
+}
+
+#[handler]
+pub async fn get_stored_conversation(req: &mut Request, res: &mut Response) {
+    let Some(access_key) = require_bearer(req, res) else {
+        return;
+    };
+    let id = req.param::<String>("id").unwrap_or_default();
+    info!(
+        "{}",
+        tr!(
+            "💬 查詢對話記錄 | ID: {}",
+            "💬 Fetching conversation | ID: {}",
+            id
+        )
+    );
+    match get_conversation(&id, &owner_hash(&access_key)) {
+        Some(conversation) => res.render(Json(conversation.to_object(true))),
+        None => render_conversation_not_found(res, &id),
+    }
+}
+
+#[handler]
+pub async fn delete_stored_conversation(req: &mut Request, res: &mut Response) {
+    let Some(access_key) = require_bearer(req, res) else {
+        return;
+    };
+    let id = req.param::<String>("id").unwrap_or_default();
+    info!(
+        "{}",
+        tr!(
+            "🗑️ 刪除對話記錄 | ID: {}",
+            "🗑️ Deleting conversation | ID: {}",
+            id
+        )
+    );
+    if delete_conversation(&id, &owner_hash(&access_key)) {
+        res.render(Json(json!({
+            "object": "conversation.deleted",
+            "id": id,
+            "deleted": true,
+        })));
+    } else {
+        render_conversation_not_found(res, &id);
+    }
+}
+
+fn render_conversation_not_found(res: &mut Response, id: &str) {
+    res.status_code(StatusCode::NOT_FOUND);
+    res.render(Json(OpenAIErrorResponse {
+        error: OpenAIError {
+            message: format!("No conversation found with id '{}'.", id),
+            r#type: "invalid_request_error".to_string(),
+            code: "not_found".to_string(),
+            param: None,
+        },
+    }));
+}
@@ -0,0 +1,119 @@
+use super::body::read_json_body;
+use crate::types::{OpenAIError, OpenAIErrorResponse};
+use crate::utils::get_tokenizer_for_model;
+use salvo::prelude::*;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use tracing::{debug, info};
+
+#[derive(Deserialize)]
+struct TokenizeRequest {
+    model: Option<String>,
+    #[serde(alias = "prompt")]
+    content: String,
+    /// 是否將文本中的特殊標記解析為特殊 token，與用量計算一致默認為 true
+    parse_special: Option<bool>,
+    /// 是否同時返回每個 token 對應的文本片段
+    with_pieces: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct DetokenizeRequest {
+    model: Option<String>,
+    tokens: Vec<u32>,
+}
+
+fn render_bad_request(res: &mut Response, message: String) {
+    res.status_code(StatusCode::BAD_REQUEST);
+    res.render(Json(OpenAIErrorResponse {
+        error: OpenAIError {
+            message,
+            r#type: "invalid_request_error".to_string(),
+            code: "bad_request".to_string(),
+            param: None,
+        },
+    }));
+}
+
+// 不要求 Content-Type，方便 llama.cpp 相容工具直接呼叫
+async fn read_request<T: DeserializeOwned>(req: &mut Request, res: &mut Response) -> Option<T> {
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1024 * 1024 * 1024);
+    match read_json_body::<T>(req, max_size, 0).await {
+        Ok(body) => Some(body),
+        Err(e) => {
+            render_bad_request(res, format!("無效的請求: {}", e));
+            None
+        }
+    }
+}
+
+#[handler]
+pub async fn tokenize(req: &mut Request, res: &mut Response) {
+    let Some(body) = read_request::<TokenizeRequest>(req, res).await else {
+        return;
+    };
+    let (tokenizer, bpe) = get_tokenizer_for_model(body.model.as_deref());
+    let tokens = if body.parse_special.unwrap_or(true) {
+        bpe.encode_with_special_tokens(&body.content)
+    } else {
+        bpe.encode_ordinary(&body.content)
+    };
+    info!(
+        "{}",
+        tr!(
+            "🔢 分詞 | 模型: {:?} | 編碼器: {} | token 數: {}",
+            "🔢 Tokenize | model: {:?} | tokenizer: {} | tokens: {}",
+            body.model,
+            tokenizer,
+            tokens.len()
+        )
+    );
+
+    let count = tokens.len();
+    let tokens = if body.with_pieces.unwrap_or(false) {
+        let pieces: Vec<serde_json::Value> = tokens
+            .iter()
+            .copied()
+            .zip(bpe._decode_native_and_split(tokens.clone()))
+            .map(|(id, bytes)| match String::from_utf8(bytes) {
+                Ok(piece) => json!({ "id": id, "piece": piece }),
+                // 不完整的 UTF-8 片段以位元組陣列返回
+                Err(e) => json!({ "id": id, "piece": e.into_bytes() }),
+            })
+            .collect();
+        json!(pieces)
+    } else {
+        json!(tokens)
+    };
+    res.render(Json(json!({
+        "tokens": tokens,
+        "count": count,
+        "model": body.model,
+        "tokenizer": tokenizer,
+    })));
+}
+
+#[handler]
+pub async fn detokenize(req: &mut Request, res: &mut Response) {
+    let Some(body) = read_request::<DetokenizeRequest>(req, res).await else {
+        return;
+    };
+    let (tokenizer, bpe) = get_tokenizer_for_model(body.model.as_deref());
+    debug!(
+        "🔢 還原分詞 | 編碼器: {} | token 數: {}",
+        tokenizer,
+        body.tokens.len()
+    );
+    match bpe.decode(body.tokens) {
+        Ok(content) => res.render(Json(json!({
+            "content": content,
+            "model": body.model,
+            "tokenizer": tokenizer,
+        }))),
+        Err(e) => render_bad_request(res, format!("無法還原 token: {}", e)),
+    }
+}
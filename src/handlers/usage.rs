@@ -0,0 +1,72 @@
+use crate::i18n::get_lang;
+use crate::usage::{UsageQuery, usage_enabled, usage_report};
+use askama::Template;
+use chrono::Utc;
+use salvo::prelude::*;
+
+const HOUR_SECS: i64 = 3600;
+const DAY_SECS: i64 = 24 * HOUR_SECS;
+/// 以小時為單位查詢時的最大天數
+const MAX_HOURLY_DAYS: i64 = 31;
+
+#[derive(Template)]
+#[template(path = "usage.html")]
+struct UsageTemplate {
+    lang: &'static str,
+}
+
+/// 用量統計頁面
+#[handler]
+pub async fn usage_page(res: &mut Response) {
+    let template = UsageTemplate {
+        lang: get_lang().code(),
+    };
+    res.render(Text::Html(template.render().unwrap()));
+}
+
+/// 查詢用量統計
+/// 參數：days 天數 (1-365)、bucket 為 hour 或 day、tz 為時區偏移分鐘數（東正西負）、
+/// key 為 API Key 的雜湊、model 為模型名稱、user 為請求的 user、organization 與 project 為請求的
+/// OpenAI-Organization 與 OpenAI-Project 標頭、metadata[鍵]=值 為請求的 metadata，
+/// group_tag 為分組用的 metadata 鍵
+#[handler]
+pub async fn get_usage(req: &mut Request, res: &mut Response) {
+    let days = req.query::<i64>("days").unwrap_or(7).clamp(1, 365);
+    let bucket_secs = match req.query::<String>("bucket").as_deref() {
+        Some("hour") if days <= MAX_HOURLY_DAYS => HOUR_SECS,
+        Some("hour") | Some("day") => DAY_SECS,
+        _ if days <= 2 => HOUR_SECS,
+        _ => DAY_SECS,
+    };
+    let offset_secs = req.query::<i64>("tz").unwrap_or(0).clamp(-14 * 60, 14 * 60) * 60;
+
+    // 以本地時間對齊區間，結束於目前區間的結尾
+    let now = Utc::now().timestamp();
+    let to = ((now + offset_secs).div_euclid(bucket_secs) + 1) * bucket_secs - offset_secs;
+    let query = UsageQuery {
+        from: to - days * DAY_SECS,
+        to,
+        bucket_secs,
+        owner: req.query::<String>("key").filter(|k| !k.is_empty()),
+        model: req.query::<String>("model").filter(|m| !m.is_empty()),
+        user: req.query::<String>("user").filter(|u| !u.is_empty()),
+        organization: req
+            .query::<String>("organization")
+            .filter(|o| !o.is_empty()),
+        project: req.query::<String>("project").filter(|p| !p.is_empty()),
+        // metadata 以 metadata[key]=value 形式傳入
+        metadata: req
+            .queries()
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix("metadata[")
+                    .and_then(|k| k.strip_suffix(']'))
+                    .map(|k| (k.to_string(), value.clone()))
+            })
+            .collect(),
+        group_tag: req.query::<String>("group_tag").filter(|t| !t.is_empty()),
+    };
+    let mut report = usage_report(&query).await;
+    report["enabled"] = usage_enabled().into();
+    res.render(Json(report));
+}
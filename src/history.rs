@@ -0,0 +1,279 @@
+//! 對話歷史超出模型上下文長度時的處理 (history_policy)
+//!
+//! truncate：由最舊的非系統訊息開始移除，直到估算的 token 數符合 context_length 扣除
+//! max_tokens 的預算；只剩最後一則訊息仍超出時，保留該訊息文字的結尾部分
+//!
+//! summarize：保留約半數預算的最近訊息，較舊的訊息交由 history_summary_model 濃縮為一則系統訊息；
+//! 摘要失敗或摘要後仍超出時改以 truncate 處理
+
+use crate::poe_client::{PoeClientWrapper, create_chat_request};
+use crate::types::{ChatCompletionRequest, Message, OpenAiContent};
+use crate::utils::{count_message_tokens, get_text_from_openai_content};
+use futures_util::StreamExt;
+use poe_api_process::{ChatEventType, ChatResponseData};
+use serde_json::json;
+use tiktoken_rs::o200k_base_singleton;
+use tracing::{debug, warn};
+
+/// 未設定 history_summary_model 時使用的機器人
+const DEFAULT_SUMMARY_MODEL: &str = "GPT-4o-Mini";
+
+const SUMMARY_INSTRUCTION: &str = "Summarize the earlier part of a conversation between a user and an assistant given below. \
+Keep facts, decisions, names, numbers, code identifiers and open questions that later turns may rely on. \
+Write in the language of the conversation and reply with the summary only.";
+
+const SUMMARY_HEADER: &str = "Summary of the earlier conversation:";
+
+/// 截斷結果，以 usage.history_truncated 與 X-History-Truncated 標頭告知客戶端
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Truncation {
+    /// 移除的訊息數
+    pub(crate) dropped: usize,
+    /// 是否裁剪了保留訊息的文字
+    pub(crate) trimmed: bool,
+    /// 被濃縮為摘要的訊息數
+    pub(crate) summarized: usize,
+    /// 截斷前的估算 token 數
+    pub(crate) tokens_before: u32,
+}
+
+impl Truncation {
+    pub(crate) fn to_json(self) -> serde_json::Value {
+        json!({
+            "dropped_messages": self.dropped,
+            "summarized_messages": self.summarized,
+            "trimmed": self.trimmed,
+            "prompt_tokens_before": self.tokens_before,
+        })
+    }
+}
+
+fn is_system(message: &Message) -> bool {
+    matches!(message.role.as_str(), "system" | "developer")
+}
+
+/// 單則訊息的估算 token 數（不含訊息列表的固定開銷）
+fn message_tokens(message: &Message) -> u32 {
+    count_message_tokens(std::slice::from_ref(message)).saturating_sub(2)
+}
+
+/// 將訊息裁剪至符合預算，未超出時返回 None
+pub(crate) fn truncate_history(messages: &mut Vec<Message>, budget: u32) -> Option<Truncation> {
+    let tokens_before = count_message_tokens(messages);
+    if tokens_before <= budget {
+        return None;
+    }
+    let mut costs: Vec<u32> = messages.iter().map(message_tokens).collect();
+    let mut total = tokens_before;
+    let mut dropped = 0;
+    while total > budget {
+        let Some(index) = messages.iter().position(|m| !is_system(m)) else {
+            break;
+        };
+        // 保留最後一則訊息
+        if index + 1 >= messages.len() {
+            break;
+        }
+        messages.remove(index);
+        total = total.saturating_sub(costs.remove(index));
+        dropped += 1;
+        // 一併移除失去對應工具調用的工具結果
+        while index + 1 < messages.len() && messages[index].role == "tool" {
+            messages.remove(index);
+            total = total.saturating_sub(costs.remove(index));
+            dropped += 1;
+        }
+    }
+
+    let mut trimmed = false;
+    if total > budget
+        && let Some(message) = messages.iter_mut().find(|m| !is_system(m))
+        && let Some(OpenAiContent::Text(text)) = &mut message.content
+    {
+        let over = total - budget;
+        if let Some(tail) = keep_tail(text, over) {
+            *text = tail;
+            trimmed = true;
+        }
+    }
+
+    let tokens_after = count_message_tokens(messages);
+    debug!(
+        "✂️ 對話歷史超出上下文長度 | 預算: {} | 截斷前: {} | 截斷後: {} | 移除訊息: {} | 裁剪文字: {}",
+        budget, tokens_before, tokens_after, dropped, trimmed
+    );
+    (dropped > 0 || trimmed).then_some(Truncation {
+        dropped,
+        trimmed,
+        summarized: 0,
+        tokens_before,
+    })
+}
+
+/// 移除文字開頭約 over 個 token，返回保留的結尾部分；無法裁剪時返回 None
+fn keep_tail(text: &str, over: u32) -> Option<String> {
+    let bpe = o200k_base_singleton();
+    let tokens = bpe.encode_with_special_tokens(text);
+    // 多移除一個 token 作為省略號的空間
+    let start = over as usize + 1;
+    if start >= tokens.len() {
+        return None;
+    }
+    // 切點落在多位元組字元中間時無法解碼，往後移動幾個 token 再試
+    (start..tokens.len().min(start + 4))
+        .find_map(|start| bpe.decode(tokens[start..].to_vec()).ok())
+        .map(|tail| format!("…{}", tail))
+}
+
+/// 將較舊的訊息濃縮為摘要使訊息符合預算，未超出時返回 None
+pub(crate) async fn summarize_history(
+    messages: &mut Vec<Message>,
+    budget: u32,
+    summary_model: Option<&str>,
+    access_key: &str,
+) -> Option<Truncation> {
+    let tokens_before = count_message_tokens(messages);
+    if tokens_before <= budget {
+        return None;
+    }
+
+    // 由最後往前保留約半數預算的非系統訊息，至少保留最後一則
+    let mut kept_tokens = 0;
+    let mut tail_start = messages.len().saturating_sub(1);
+    for (index, message) in messages.iter().enumerate().rev().skip(1) {
+        if is_system(message) {
+            continue;
+        }
+        kept_tokens += message_tokens(message);
+        if kept_tokens > budget / 2 {
+            break;
+        }
+        tail_start = index;
+    }
+    // 保留的部分不以工具結果開頭
+    while tail_start + 1 < messages.len() && messages[tail_start].role == "tool" {
+        tail_start += 1;
+    }
+    let older: Vec<usize> = (0..tail_start)
+        .filter(|index| !is_system(&messages[*index]))
+        .collect();
+    let Some(&insert_at) = older.first() else {
+        return truncate_history(messages, budget);
+    };
+
+    let transcript = older
+        .iter()
+        .map(|index| transcript_line(&messages[*index]))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let summary_model = summary_model.unwrap_or(DEFAULT_SUMMARY_MODEL);
+    let summary = match request_summary(summary_model, access_key, transcript).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 對話摘要失敗，改為截斷歷史 | 模型: {} | 錯誤: {}",
+                    "⚠️ History summarization failed, truncating instead | model: {} | error: {}",
+                    summary_model,
+                    e
+                )
+            );
+            return truncate_history(messages, budget);
+        }
+    };
+
+    let summarized = older.len();
+    let mut index = 0;
+    messages.retain(|_| {
+        let keep = older.binary_search(&index).is_err();
+        index += 1;
+        keep
+    });
+    messages.insert(
+        insert_at,
+        Message {
+            role: "system".to_string(),
+            content: Some(OpenAiContent::Text(format!(
+                "{}\n{}",
+                SUMMARY_HEADER, summary
+            ))),
+            tool_calls: None,
+            tool_call_id: None,
+        },
+    );
+    debug!(
+        "📝 已將 {} 則訊息濃縮為摘要 | 模型: {} | 摘要長度: {}",
+        summarized,
+        summary_model,
+        summary.len()
+    );
+
+    // 摘要後仍超出時再截斷
+    let (dropped, trimmed) = match truncate_history(messages, budget) {
+        Some(truncation) => (truncation.dropped, truncation.trimmed),
+        None => (0, false),
+    };
+    Some(Truncation {
+        dropped,
+        trimmed,
+        summarized,
+        tokens_before,
+    })
+}
+
+/// 摘要對話記錄中的一則訊息
+fn transcript_line(message: &Message) -> String {
+    let mut line = format!(
+        "{}: {}",
+        message.role,
+        get_text_from_openai_content(&message.content)
+    );
+    for call in message.tool_calls.iter().flatten() {
+        line.push_str(&format!(
+            "\n[tool call] {}({})",
+            call.function.name, call.function.arguments
+        ));
+    }
+    line
+}
+
+/// 以摘要機器人產生摘要
+async fn request_summary(
+    model: &str,
+    access_key: &str,
+    transcript: String,
+) -> Result<String, String> {
+    let request: ChatCompletionRequest = serde_json::from_value(json!({
+        "model": model,
+        "messages": [
+            {"role": "system", "content": SUMMARY_INSTRUCTION},
+            {"role": "user", "content": transcript},
+        ],
+    }))
+    .map_err(|e| e.to_string())?;
+    let client = PoeClientWrapper::new(model, access_key);
+    let chat_request = create_chat_request(model, request.messages.clone(), &request).await;
+    let mut stream = client
+        .stream_request_with_retry(chat_request, true)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut summary = String::new();
+    while let Some(event) = stream.next().await {
+        let event = event.map_err(|e| e.to_string())?;
+        match (event.event, event.data) {
+            (ChatEventType::Text, Some(ChatResponseData::Text { text })) => summary.push_str(&text),
+            (ChatEventType::ReplaceResponse, Some(ChatResponseData::Text { text })) => {
+                summary = text
+            }
+            (ChatEventType::Error, Some(ChatResponseData::Error { text, .. })) => return Err(text),
+            (ChatEventType::Done, _) => break,
+            _ => {}
+        }
+    }
+    let summary = summary.trim().to_string();
+    if summary.is_empty() {
+        return Err("摘要為空".to_string());
+    }
+    Ok(summary)
+}
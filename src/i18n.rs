@@ -0,0 +1,49 @@
+//! 日誌與管理介面的顯示語言，由 LANG 決定：`en` 開頭時使用英文，其餘使用繁體中文
+
+use std::sync::LazyLock;
+
+static LANG: LazyLock<Lang> = LazyLock::new(|| {
+    std::env::var("LANG")
+        .map(|value| Lang::parse(&value))
+        .unwrap_or_default()
+});
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Lang {
+    #[default]
+    ZhHant,
+    En,
+}
+
+impl Lang {
+    /// 解析 `en`、`en_US.UTF-8`、`zh-Hant` 等語系字串
+    fn parse(value: &str) -> Self {
+        if value.trim().to_lowercase().starts_with("en") {
+            Lang::En
+        } else {
+            Lang::ZhHant
+        }
+    }
+
+    /// BCP 47 語言代碼
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::ZhHant => "zh-Hant",
+            Lang::En => "en",
+        }
+    }
+}
+
+pub fn get_lang() -> Lang {
+    *LANG
+}
+
+/// 依 LANG 選擇文字並格式化：`tr!("中文 {}", "English {}", value)`
+macro_rules! tr {
+    ($zh:literal, $en:literal $(, $arg:expr)* $(,)?) => {
+        match $crate::i18n::get_lang() {
+            $crate::i18n::Lang::En => format!($en $(, $arg)*),
+            $crate::i18n::Lang::ZhHant => format!($zh $(, $arg)*),
+        }
+    };
+}
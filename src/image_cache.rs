@@ -0,0 +1,258 @@
+//! 圖片生成結果緩存 (models.yaml 的 image_cache_ttl)
+//!
+//! 啟用的模型以「API Key + 模型 + 轉換後的 Poe 請求」的雜湊為鍵，保存成功且包含圖片的回應事件；
+//! 圖片下載後存放在儲存資料庫，以 MEDIA_PUBLIC_URL/image-cache/{id} 提供，避免 Poe CDN 連結過期。
+//! 有效期內的相同請求直接重播保存的事件，不再連線 Poe
+
+use crate::media::{get_media_rehost_config, is_image};
+use crate::poe_client::download_attachment;
+use crate::store::{IMAGE_CACHE_TREE, open_store_tree};
+use futures_util::stream::{self, Stream, StreamExt};
+use poe_api_process::types::FileData;
+use poe_api_process::{ChatEventType, ChatRequest, ChatResponse, ChatResponseData, PoeError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+const ASSETS_TREE: &str = "image_cache_assets";
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum CachedEvent {
+    Text { text: String },
+    Replace { text: String },
+    File { file: FileData },
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    expires_at: u64,
+    events: Vec<CachedEvent>,
+    // 存放在 ASSETS_TREE 的圖片，過期時一併刪除
+    assets: Vec<String>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 緩存鍵：請求者、模型與 Poe 請求內容（不含每次隨機產生的識別）的雜湊
+pub(crate) fn image_cache_key(owner: &str, model: &str, request: &ChatRequest) -> String {
+    let mut request = request.clone();
+    request.user_id.clear();
+    request.conversation_id.clear();
+    request.message_id.clear();
+    let mut hasher = Sha256::new();
+    hasher.update(owner.as_bytes());
+    hasher.update([0]);
+    hasher.update(model.as_bytes());
+    hasher.update([0]);
+    hasher.update(serde_json::to_vec(&request).unwrap_or_default());
+    format!("{:x}", hasher.finalize())
+}
+
+fn remove_entry(tree: &sled::Tree, key: &str, entry: &CacheEntry) {
+    let _ = tree.remove(key.as_bytes());
+    if let Some(assets) = open_store_tree(ASSETS_TREE) {
+        for id in &entry.assets {
+            let _ = assets.remove(id.as_bytes());
+        }
+    }
+}
+
+/// 查詢緩存，命中時返回重播的事件流
+pub(crate) fn cached_image_response(key: &str) -> Option<EventStream> {
+    let tree = open_store_tree(IMAGE_CACHE_TREE)?;
+    let bytes = tree.get(key.as_bytes()).ok()??;
+    let entry: CacheEntry = serde_json::from_slice(&bytes).ok()?;
+    if entry.expires_at <= now_secs() {
+        debug!("🗑️ 刪除過期的圖片緩存: {}", key);
+        remove_entry(&tree, key, &entry);
+        return None;
+    }
+    info!(
+        "{}",
+        tr!(
+            "🖼️ 圖片緩存命中，直接返回保存的結果",
+            "🖼️ Image cache hit, returning the stored result"
+        )
+    );
+    let mut events: Vec<Result<ChatResponse, PoeError>> = entry
+        .events
+        .into_iter()
+        .map(|event| {
+            let (event, data) = match event {
+                CachedEvent::Text { text } => {
+                    (ChatEventType::Text, ChatResponseData::Text { text })
+                }
+                CachedEvent::Replace { text } => (
+                    ChatEventType::ReplaceResponse,
+                    ChatResponseData::Text { text },
+                ),
+                CachedEvent::File { file } => (ChatEventType::File, ChatResponseData::File(file)),
+            };
+            Ok(ChatResponse {
+                event,
+                data: Some(data),
+            })
+        })
+        .collect();
+    events.push(Ok(ChatResponse {
+        event: ChatEventType::Done,
+        data: Some(ChatResponseData::Empty),
+    }));
+    Some(Box::pin(stream::iter(events)))
+}
+
+/// 記錄上游事件，成功完成且包含圖片時寫入緩存
+pub(crate) fn record_image_response(
+    key: String,
+    ttl: Duration,
+    stream: EventStream,
+) -> EventStream {
+    let mut events: Vec<CachedEvent> = Vec::new();
+    let mut failed = false;
+    Box::pin(stream.inspect(move |item| {
+        if failed {
+            return;
+        }
+        let Ok(response) = item else {
+            failed = true;
+            return;
+        };
+        match (&response.event, &response.data) {
+            (ChatEventType::Text, Some(ChatResponseData::Text { text })) => {
+                events.push(CachedEvent::Text { text: text.clone() })
+            }
+            (ChatEventType::ReplaceResponse, Some(ChatResponseData::Text { text })) => {
+                events.push(CachedEvent::Replace { text: text.clone() })
+            }
+            (ChatEventType::File, Some(ChatResponseData::File(file))) => {
+                events.push(CachedEvent::File { file: file.clone() })
+            }
+            (ChatEventType::Error, _) => failed = true,
+            (ChatEventType::Done, _) => {
+                let has_image = events
+                    .iter()
+                    .any(|e| matches!(e, CachedEvent::File { file } if is_image(file)));
+                if has_image {
+                    let events = std::mem::take(&mut events);
+                    let key = key.clone();
+                    tokio::spawn(async move { store_entry(key, ttl, events).await });
+                }
+            }
+            _ => {}
+        }
+    }))
+}
+
+/// 下載圖片並寫入緩存
+async fn store_entry(key: String, ttl: Duration, mut events: Vec<CachedEvent>) {
+    let (Some(tree), Some(assets_tree)) = (
+        open_store_tree(IMAGE_CACHE_TREE),
+        open_store_tree(ASSETS_TREE),
+    ) else {
+        return;
+    };
+    let public_url = &get_media_rehost_config().public_url;
+    let mut assets = Vec::new();
+    for event in events.iter_mut() {
+        let CachedEvent::File { file } = event else {
+            continue;
+        };
+        if !is_image(file) {
+            continue;
+        }
+        match download_attachment(&file.url).await {
+            Ok((data, content_type)) => {
+                let content_type = content_type
+                    .filter(|c| c.starts_with("image/"))
+                    .unwrap_or_else(|| file.content_type.clone());
+                // 以 "Content-Type\0內容" 的格式保存
+                let mut value = content_type.into_bytes();
+                value.push(0);
+                value.extend_from_slice(&data);
+                let id = nanoid::nanoid!(21);
+                if assets_tree.insert(id.as_bytes(), value).is_ok() {
+                    file.url = format!("{}/image-cache/{}", public_url, id);
+                    assets.push(id);
+                }
+            }
+            // 下載失敗時保留原始連結，緩存期間內仍可使用
+            Err(e) => warn!(
+                "{}",
+                tr!(
+                    "⚠️ 下載圖片以供緩存失敗，保留原始連結 | URL: {} | 錯誤: {}",
+                    "⚠️ Failed to download image for caching, keeping the original URL | URL: {} | error: {}",
+                    file.url,
+                    e
+                )
+            ),
+        }
+    }
+    let entry = CacheEntry {
+        expires_at: now_secs() + ttl.as_secs(),
+        events,
+        assets,
+    };
+    match serde_json::to_vec(&entry) {
+        Ok(value) => {
+            if let Err(e) = tree.insert(key.as_bytes(), value) {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 寫入圖片緩存失敗: {}",
+                        "⚠️ Failed to write image cache: {}",
+                        e
+                    )
+                );
+                return;
+            }
+            debug!(
+                "🖼️ 圖片生成結果已緩存 | 鍵: {} | 圖片: {} | 有效期: {} 秒",
+                key,
+                entry.assets.len(),
+                ttl.as_secs()
+            );
+        }
+        Err(e) => warn!(
+            "{}",
+            tr!(
+                "⚠️ 序列化圖片緩存失敗: {}",
+                "⚠️ Failed to serialize image cache: {}",
+                e
+            )
+        ),
+    }
+    prune_expired(&tree);
+}
+
+/// 刪除所有過期的緩存項目
+fn prune_expired(tree: &sled::Tree) {
+    let now = now_secs();
+    for (key, value) in tree.iter().flatten() {
+        let Ok(entry) = serde_json::from_slice::<CacheEntry>(&value) else {
+            let _ = tree.remove(&key);
+            continue;
+        };
+        if entry.expires_at <= now {
+            remove_entry(tree, &String::from_utf8_lossy(&key), &entry);
+        }
+    }
+}
+
+/// 讀取緩存的圖片，返回 (Content-Type, 內容)
+pub(crate) fn cached_image_asset(id: &str) -> Option<(String, Vec<u8>)> {
+    let tree = open_store_tree(ASSETS_TREE)?;
+    let bytes = tree.get(id.as_bytes()).ok()??;
+    let split = bytes.iter().position(|b| *b == 0)?;
+    let content_type = String::from_utf8_lossy(&bytes[..split]).to_string();
+    Some((content_type, bytes[split + 1..].to_vec()))
+}
@@ -0,0 +1,172 @@
+//! 文字補全的 instruct 範本 (models.yaml 的 instruct_template)
+//!
+//! SillyTavern 等前端的文字補全已在 prompt 中以 ChatML、Alpaca 等格式排好對話，
+//! 依相同的角色標記拆回多則訊息，Poe 才能收到正確的多輪對話而非一整段文字。
+//! 第一個標記之前的文字視為系統提示；最後一則空白的助手訊息是生成提示，直接捨棄
+
+use crate::types::{
+    CustomInstructTemplate, InstructPreset, InstructTemplate, Message, OpenAiContent,
+};
+
+/// 範本展開後的標記
+struct Markers {
+    // (標記, 角色)
+    turns: Vec<(String, &'static str)>,
+    // 訊息結尾的標記，拆解時移除
+    end: Option<String>,
+    // 與角色無關、拆解時直接移除的標記
+    strip: Vec<String>,
+    stop: Vec<String>,
+}
+
+fn markers(template: &InstructTemplate) -> Option<Markers> {
+    let markers = match template {
+        InstructTemplate::Preset(InstructPreset::Raw) => return None,
+        InstructTemplate::Preset(InstructPreset::ChatMl) => Markers {
+            turns: vec![
+                ("<|im_start|>system".to_string(), "system"),
+                ("<|im_start|>user".to_string(), "user"),
+                ("<|im_start|>assistant".to_string(), "assistant"),
+            ],
+            end: Some("<|im_end|>".to_string()),
+            strip: Vec::new(),
+            stop: vec!["<|im_end|>".to_string(), "<|im_start|>".to_string()],
+        },
+        InstructTemplate::Preset(InstructPreset::Alpaca) => Markers {
+            turns: vec![
+                ("### Instruction:".to_string(), "user"),
+                ("### Input:".to_string(), "user"),
+                ("### Response:".to_string(), "assistant"),
+            ],
+            end: None,
+            strip: Vec::new(),
+            stop: vec!["### Instruction:".to_string(), "### Input:".to_string()],
+        },
+        InstructTemplate::Preset(InstructPreset::Llama3) => Markers {
+            turns: ["system", "user", "assistant"]
+                .into_iter()
+                .map(|role| {
+                    (
+                        format!("<|start_header_id|>{}<|end_header_id|>", role),
+                        role,
+                    )
+                })
+                .collect(),
+            end: Some("<|eot_id|>".to_string()),
+            strip: vec!["<|begin_of_text|>".to_string()],
+            stop: vec!["<|eot_id|>".to_string(), "<|start_header_id|>".to_string()],
+        },
+        InstructTemplate::Custom(custom) => custom_markers(custom),
+    };
+    Some(markers)
+}
+
+/// 自訂範本另以訊息結尾與使用者標記作為停止序列，避免模型代替使用者發言
+fn custom_markers(custom: &CustomInstructTemplate) -> Markers {
+    let turns: Vec<(String, &'static str)> = [
+        (custom.system.as_deref(), "system"),
+        (Some(custom.user.as_str()), "user"),
+        (Some(custom.assistant.as_str()), "assistant"),
+    ]
+    .into_iter()
+    .filter_map(|(marker, role)| {
+        let marker = marker?.trim();
+        (!marker.is_empty()).then(|| (marker.to_string(), role))
+    })
+    .collect();
+    let end = custom
+        .end
+        .as_deref()
+        .map(str::trim)
+        .filter(|end| !end.is_empty())
+        .map(str::to_string);
+    let mut stop = custom.stop.clone();
+    stop.extend(end.clone());
+    stop.extend(
+        turns
+            .iter()
+            .filter(|(_, role)| *role == "user")
+            .map(|(marker, _)| marker.clone()),
+    );
+    Markers {
+        turns,
+        end,
+        strip: Vec::new(),
+        stop,
+    }
+}
+
+fn text_message(role: &str, text: String) -> Message {
+    Message {
+        role: role.to_string(),
+        content: Some(OpenAiContent::Text(text)),
+        tool_calls: None,
+        tool_call_id: None,
+    }
+}
+
+/// 依範本將 prompt 拆解為對話訊息；未設定範本、為 raw 或找不到任何標記時整段作為一則使用者訊息
+pub(crate) fn prompt_to_messages(
+    template: Option<&InstructTemplate>,
+    prompt: &str,
+) -> Vec<Message> {
+    let Some(markers) = template.and_then(markers) else {
+        return vec![text_message("user", prompt.to_string())];
+    };
+    let mut prompt = prompt.to_string();
+    for strip in &markers.strip {
+        prompt = prompt.replace(strip.as_str(), "");
+    }
+    let clean = |segment: &str| {
+        let segment = segment.trim();
+        let segment = markers
+            .end
+            .as_deref()
+            .and_then(|end| segment.strip_suffix(end))
+            .unwrap_or(segment);
+        segment.trim().to_string()
+    };
+    // 下一個標記的位置、標記長度及角色
+    let next_marker = |from: usize| {
+        markers
+            .turns
+            .iter()
+            .filter_map(|(marker, role)| {
+                prompt[from..]
+                    .find(marker.as_str())
+                    .map(|pos| (from + pos, marker.len(), *role))
+            })
+            .min_by_key(|(pos, _, _)| *pos)
+    };
+
+    let Some((first, _, _)) = next_marker(0) else {
+        return vec![text_message("user", prompt.trim().to_string())];
+    };
+    let mut messages = Vec::new();
+    let preamble = clean(&prompt[..first]);
+    if !preamble.is_empty() {
+        messages.push(text_message("system", preamble));
+    }
+    let mut cursor = next_marker(0);
+    while let Some((pos, len, role)) = cursor {
+        let start = pos + len;
+        cursor = next_marker(start);
+        let end = cursor.map_or(prompt.len(), |(next, _, _)| next);
+        let content = clean(&prompt[start..end]);
+        if !content.is_empty() {
+            messages.push(text_message(role, content));
+        }
+    }
+    if messages.is_empty() {
+        messages.push(text_message("user", String::new()));
+    }
+    messages
+}
+
+/// 範本的停止序列
+pub(crate) fn template_stops(template: Option<&InstructTemplate>) -> Vec<String> {
+    template
+        .and_then(markers)
+        .map(|markers| markers.stop)
+        .unwrap_or_default()
+}
@@ -1,22 +1,52 @@
+use clap::Parser;
+use salvo::conn::tcp::{DynTcpAcceptors, TcpAcceptor};
 use salvo::prelude::*;
-use std::env;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+// tr! 巨集需在其他模組之前宣告
+#[macro_use]
+mod i18n;
 
 mod cache;
+mod cli;
+mod dns;
 mod evert;
+mod filter;
 mod handlers;
+mod history;
+mod image_cache;
+mod instruct;
+mod mcp;
+mod media;
+mod mock;
+mod output_limit;
+mod pipeline;
 mod poe_client;
+mod redact;
+mod runtime;
+mod script;
+mod server_tools;
+mod shared;
+mod spool;
+mod store;
+mod systemd;
+mod tool_schema;
 mod types;
+mod usage;
 mod utils;
+mod web_search;
 
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 fn get_env_or_default(key: &str, default: &str) -> String {
-    let value = env::var(key).unwrap_or_else(|_| default.to_string());
+    let value = utils::get_env_secret(key).unwrap_or_else(|| default.to_string());
     if key == "ADMIN_PASSWORD" {
         debug!("🔧 環境變數 {} = {}", key, "*".repeat(value.len()));
     } else {
@@ -26,15 +56,33 @@ fn get_env_or_default(key: &str, default: &str) -> String {
 }
 
 fn setup_logging(log_level: &str) {
-    tracing_subscriber::fmt()
+    let builder = tracing_subscriber::fmt()
         .with_target(false)
         .with_thread_ids(true)
         .with_level(true)
         .with_file(false)
         .with_line_number(false)
         .with_env_filter(log_level)
-        .init();
-    info!("🚀 日誌系統初始化完成，日誌級別: {}", log_level);
+        .with_writer(redact::RedactingStdout)
+        .with_filter_reloading();
+    // 保留過濾規則的重新載入句柄，供 /api/admin/runtime 在執行期切換日誌級別
+    let handle = builder.reload_handle();
+    runtime::init_log_filter(
+        log_level,
+        Box::new(move |filter| {
+            let filter = EnvFilter::try_new(filter).map_err(|e| e.to_string())?;
+            handle.reload(filter).map_err(|e| e.to_string())
+        }),
+    );
+    builder.init();
+    info!(
+        "{}",
+        tr!(
+            "🚀 日誌系統初始化完成，日誌級別: {}",
+            "🚀 Logging initialized, level: {}",
+            log_level
+        )
+    );
 }
 
 fn log_cache_settings() {
@@ -54,47 +102,171 @@ fn log_cache_settings() {
     let ttl_secs = cache_ttl_seconds % 60;
 
     let ttl_str = if ttl_days > 0 {
-        format!(
+        tr!(
             "{}天 {}小時 {}分 {}秒",
-            ttl_days, ttl_hours, ttl_mins, ttl_secs
+            "{}d {}h {}m {}s",
+            ttl_days,
+            ttl_hours,
+            ttl_mins,
+            ttl_secs
         )
     } else if ttl_hours > 0 {
-        format!("{}小時 {}分 {}秒", ttl_hours, ttl_mins, ttl_secs)
+        tr!(
+            "{}小時 {}分 {}秒",
+            "{}h {}m {}s",
+            ttl_hours,
+            ttl_mins,
+            ttl_secs
+        )
     } else if ttl_mins > 0 {
-        format!("{}分 {}秒", ttl_mins, ttl_secs)
+        tr!("{}分 {}秒", "{}m {}s", ttl_mins, ttl_secs)
     } else {
-        format!("{}秒", ttl_secs)
+        tr!("{}秒", "{}s", ttl_secs)
     };
 
     info!(
-        "📦 Poe CDN URL 緩存設定 | TTL: {} | 最大空間: {}MB",
-        ttl_str, cache_size_mb
+        "{}",
+        tr!(
+            "📦 Poe CDN URL 緩存設定 | TTL: {} | 最大空間: {}MB",
+            "📦 Poe CDN URL cache | TTL: {} | max size: {}MB",
+            ttl_str,
+            cache_size_mb
+        )
     );
 }
 
+/// 解析逗號分隔的綁定地址列表，如 `0.0.0.0:8080,[::]:8080`
+fn parse_bind_addresses(value: &str) -> Vec<SocketAddr> {
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match entry.to_socket_addrs() {
+            Ok(resolved) => {
+                for addr in resolved {
+                    if !addrs.contains(&addr) {
+                        addrs.push(addr);
+                    }
+                }
+            }
+            Err(e) => error!(
+                "{}",
+                tr!(
+                    "❌ 無效的綁定地址 {}: {}",
+                    "❌ Invalid bind address {}: {}",
+                    entry,
+                    e
+                )
+            ),
+        }
+    }
+    addrs
+}
+
+/// 綁定單個 TCP 監聽地址
+/// 當同一端口也綁定了 IPv4 地址時，IPv6 socket 需設為 IPV6_V6ONLY 以避免衝突
+fn bind_tcp(addr: SocketAddr, only_v6: bool) -> std::io::Result<TcpAcceptor> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    let listener = tokio::net::TcpListener::from_std(socket.into())?;
+    TcpAcceptor::try_from(listener)
+}
+
+/// 為每個綁定地址建立監聽器
+fn bind_listeners(addrs: &[SocketAddr]) -> DynTcpAcceptors {
+    let mut acceptors = Vec::new();
+    for addr in addrs {
+        let only_v6 = addr.is_ipv6()
+            && addrs
+                .iter()
+                .any(|other| other.is_ipv4() && other.port() == addr.port());
+        match bind_tcp(*addr, only_v6) {
+            Ok(acceptor) => {
+                info!(
+                    "{}",
+                    tr!(
+                        "🎯 服務已啟動並監聽於 {}{}",
+                        "🎯 Server listening on {}{}",
+                        addr,
+                        if addr.is_ipv6() && !only_v6 {
+                            " (IPv4/IPv6 雙棧)"
+                        } else {
+                            ""
+                        }
+                    )
+                );
+                acceptors.push(acceptor.into_boxed());
+            }
+            Err(e) => {
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ 無法綁定地址 {}: {}",
+                        "❌ Failed to bind address {}: {}",
+                        addr,
+                        e
+                    )
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    DynTcpAcceptors::new(acceptors)
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = cli::Cli::parse();
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => serve().await,
+        command => {
+            // 維運指令只輸出警告以上的日誌，避免干擾結果
+            let log_level = get_env_or_default("LOG_LEVEL", "warn");
+            setup_logging(&log_level);
+            std::process::exit(cli::run(command).await);
+        }
+    }
+}
+
+async fn serve() {
     let log_level = get_env_or_default("LOG_LEVEL", "debug");
     setup_logging(&log_level);
 
     // 初始化緩存設定
     log_cache_settings();
 
+    // 套用保存的執行期設定（日誌級別、速率限制）
+    runtime::load_runtime_overrides();
+
     // 初始化全域速率限制
     let _ = handlers::limit::GLOBAL_RATE_LIMITER.set(Arc::new(tokio::sync::Mutex::new(
         std::time::Instant::now() - Duration::from_secs(60),
     )));
 
     // 顯示速率限制設定
-    let rate_limit_ms = std::env::var("RATE_LIMIT_MS")
-        .ok()
-        .and_then(|s| s.parse::<u64>().ok())
-        .unwrap_or(100);
+    let rate_limit_ms = handlers::limit::rate_limit_ms();
 
     if rate_limit_ms == 0 {
-        info!("⚙️  全域速率限制: 已禁用 (RATE_LIMIT_MS=0)");
+        info!(
+            "{}",
+            tr!(
+                "⚙️  全域速率限制: 已禁用 (RATE_LIMIT_MS=0)",
+                "⚙️  Global rate limit: disabled (RATE_LIMIT_MS=0)"
+            )
+        );
     } else {
-        info!("⚙️  全域速率限制: 已啟用 (每 {}ms 一次請求)", rate_limit_ms);
+        info!(
+            "{}",
+            tr!(
+                "⚙️  全域速率限制: 已啟用 (每 {}ms 一次請求)",
+                "⚙️  Global rate limit: enabled (one request every {}ms)",
+                rate_limit_ms
+            )
+        );
     }
 
     let host = get_env_or_default("HOST", "0.0.0.0");
@@ -103,24 +275,104 @@ async fn main() {
     get_env_or_default("ADMIN_PASSWORD", "123456");
     let config_dir = get_env_or_default("CONFIG_DIR", "./");
     let config_path = Path::new(&config_dir).join("models.yaml");
-    info!("📁 配置文件路徑: {}", config_path.display());
+    info!(
+        "{}",
+        tr!(
+            "📁 配置文件路徑: {}",
+            "📁 Config file path: {}",
+            config_path.display()
+        )
+    );
     get_env_or_default("POE_BASE_URL", "https://api.poe.com");
     get_env_or_default(
         "POE_FILE_UPLOAD_URL",
         "https://www.quora.com/poe_api/file_upload_3RD_PARTY_POST",
     );
+    get_env_or_default("POE_GQL_URL", "https://poe.com/api/gql_POST");
+    get_env_or_default("POE_CDN_URL_PREFIXES", "https://pfst.cf2.poecdn.net");
+
+    // 初始化 CORS 設定
+    handlers::get_cors_config();
+
+    // 初始化受信任代理設定
+    handlers::init_trusted_proxies();
+    handlers::limit::init_ip_rate_limit();
 
     let salvo_max_size = get_env_or_default("MAX_REQUEST_SIZE", "1073741824")
         .parse()
         .unwrap_or(1024 * 1024 * 1024); // 預設 1GB
 
-    let bind_address = format!("{}:{}", host, port);
-    info!("🌟 正在啟動 Poe API To OpenAI API 服務...");
-    debug!("📍 服務綁定地址: {}", bind_address);
+    let bind_addresses = get_env_or_default("BIND_ADDRESSES", &format!("{}:{}", host, port));
+    info!(
+        "{}",
+        tr!(
+            "🌟 正在啟動 Poe API To OpenAI API 服務...",
+            "🌟 Starting Poe API To OpenAI API service..."
+        )
+    );
+    debug!("📍 服務綁定地址: {}", bind_addresses);
+    let bind_addrs = parse_bind_addresses(&bind_addresses);
+    if bind_addrs.is_empty() {
+        error!(
+            "{}",
+            tr!(
+                "❌ 沒有可用的綁定地址: {}",
+                "❌ No usable bind address: {}",
+                bind_addresses
+            )
+        );
+        std::process::exit(1);
+    }
+
+    if mock::get_mock_config().is_some() {
+        warn!(
+            "{}",
+            tr!(
+                "🧪 MOCK_MODE 已啟用：不會連線 Poe，所有回應皆為模擬內容",
+                "🧪 MOCK_MODE enabled: Poe is never contacted and all responses are simulated"
+            )
+        );
+    }
+
+    // 載入請求/回應轉換腳本
+    if let Err(e) = script::init_script_hooks() {
+        error!("❌ {}", e);
+        std::process::exit(1);
+    }
+
+    // 載入內容過濾規則
+    if let Err(e) = filter::init_content_filter() {
+        error!("❌ {}", e);
+        std::process::exit(1);
+    }
 
     // 初始化Sled DB
     let _ = cache::get_sled_db();
-    info!("💾 初始化內存數據庫完成");
+    info!(
+        "{}",
+        tr!(
+            "💾 初始化內存數據庫完成",
+            "💾 In-memory database initialized"
+        )
+    );
+
+    // 連接共享狀態（REDIS_URL）
+    shared::spawn_shared_state_check();
+
+    // 同步共享的 models.yaml 設定（REDIS_URL）
+    handlers::spawn_shared_config_sync();
+
+    // 啟動 Poe 帳戶點數背景檢查
+    handlers::spawn_balance_monitor();
+
+    // 啟動模型健康檢查（MODEL_HEALTH_CHECK_INTERVAL_SECS）
+    handlers::spawn_model_health_monitor();
+
+    // 啟動 Token 池的背景點數查詢（POE_TOKEN_POOL）
+    handlers::spawn_token_pool_refresher();
+
+    // 啟動自檢（STARTUP_SELF_TEST=true）
+    handlers::spawn_startup_self_test();
 
     let api_router = Router::new()
         .hoop(handlers::cors_middleware)
@@ -132,9 +384,27 @@ async fn main() {
         .push(
             Router::with_path("chat/completions")
                 .hoop(handlers::rate_limit_middleware)
+                .get(handlers::list_stored_completions)
                 .post(handlers::chat_completions)
                 .options(handlers::cors_middleware),
         )
+        .push(
+            Router::with_path("chat/completions/{id}")
+                .get(handlers::get_stored_completion)
+                .delete(handlers::delete_stored_completion)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("chat/completions/{id}/messages")
+                .get(handlers::get_stored_messages)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("completions")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::text_completions)
+                .options(handlers::cors_middleware),
+        )
         .push(
             Router::with_path("api/models")
                 .get(handlers::get_models)
@@ -148,20 +418,93 @@ async fn main() {
         .push(
             Router::with_path("v1/chat/completions")
                 .hoop(handlers::rate_limit_middleware)
+                .get(handlers::list_stored_completions)
                 .post(handlers::chat_completions)
                 .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/completions")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::text_completions)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/chat/completions/{id}")
+                .get(handlers::get_stored_completion)
+                .delete(handlers::delete_stored_completion)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/chat/completions/{id}/messages")
+                .get(handlers::get_stored_messages)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("tokenize")
+                .post(handlers::tokenize)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("detokenize")
+                .post(handlers::detokenize)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/tokenize")
+                .post(handlers::tokenize)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/detokenize")
+                .post(handlers::detokenize)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/conversations")
+                .get(handlers::list_stored_conversations)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/conversations/{id}")
+                .get(handlers::get_stored_conversation)
+                .delete(handlers::delete_stored_conversation)
+                .options(handlers::cors_middleware),
         );
 
     let router: Router = Router::new()
+        .hoop(handlers::client_ip_middleware)
         .hoop(max_size(salvo_max_size.try_into().unwrap()))
         .push(Router::with_path("static/{**path}").get(StaticDir::new(["static"])))
+        .push(Router::with_path("readyz").get(handlers::readyz))
+        .push(Router::with_path("image-cache/{id}").get(handlers::get_cached_image))
         .push(handlers::admin_routes())
         .push(api_router);
 
-    info!("🛣️  API 路由配置完成");
+    // 轉存的媒體檔案
+    let media_config = media::get_media_rehost_config();
+    let router = if media_config.enabled {
+        router.push(
+            Router::with_path("media/{**path}").get(StaticDir::new([media_config.dir.clone()])),
+        )
+    } else {
+        router
+    };
+
+    info!(
+        "{}",
+        tr!("🛣️  API 路由配置完成", "🛣️  API routes configured")
+    );
+
+    // 優先使用 systemd socket activation 傳入的監聽器
+    let activated = systemd::take_activated_listeners();
+    let acceptor = if activated.is_empty() {
+        bind_listeners(&bind_addrs)
+    } else {
+        DynTcpAcceptors::new(activated.into_iter().map(|a| a.into_boxed()).collect())
+    };
 
-    let acceptor = TcpListener::new(bind_address.clone()).bind().await;
-    info!("🎯 服務已啟動並監聽於 {}", bind_address);
+    // 監聽器與 sled 均已就緒，通知 systemd
+    systemd::notify_ready("服務已就緒");
 
     Server::new(acceptor).serve(router).await;
 }
@@ -0,0 +1,407 @@
+//! MCP (Model Context Protocol) 客戶端，供伺服器端工具調用使用 (models.yaml 的 mcp_servers)
+//!
+//! 支援兩種傳輸方式：command 以子行程啟動並透過 stdin/stdout 交換逐行 JSON-RPC 訊息；
+//! url 以 Streamable HTTP 傳送，回應可為 JSON 或 SSE。連線在首次使用時建立並重複使用，
+//! 設定變更或子行程結束後的下一次使用會重新連線
+
+use crate::poe_client::shared_http_client;
+use crate::types::McpServerConfig;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{OnceCell, oneshot};
+use tracing::{debug, info, warn};
+
+const PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// 單次 MCP 請求（含工具執行）的逾時時間 (MCP_TIMEOUT_SECS)
+static MCP_TIMEOUT: LazyLock<Duration> = LazyLock::new(|| {
+    Duration::from_secs(
+        std::env::var("MCP_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(60),
+    )
+});
+
+/// MCP 伺服器提供的工具
+#[derive(Clone, Debug)]
+pub(crate) struct McpTool {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) input_schema: Value,
+}
+
+type PendingRequests = Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+struct StdioTransport {
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: PendingRequests,
+    closed: Arc<AtomicBool>,
+    // 連線釋放時結束子行程
+    _child: Child,
+}
+
+struct HttpTransport {
+    url: String,
+    headers: HashMap<String, String>,
+    session_id: Mutex<Option<String>>,
+}
+
+enum Transport {
+    Stdio(StdioTransport),
+    Http(HttpTransport),
+}
+
+pub(crate) struct McpClient {
+    name: String,
+    config: McpServerConfig,
+    transport: Transport,
+    next_id: AtomicU64,
+    tools: OnceCell<Vec<McpTool>>,
+}
+
+static MCP_CLIENTS: LazyLock<tokio::sync::Mutex<HashMap<String, Arc<McpClient>>>> =
+    LazyLock::new(|| tokio::sync::Mutex::new(HashMap::new()));
+
+/// 取得 MCP 伺服器的連線，尚未連線、設定變更或子行程已結束時重新連線
+pub(crate) async fn mcp_client(
+    name: &str,
+    config: &McpServerConfig,
+) -> Result<Arc<McpClient>, String> {
+    let mut clients = MCP_CLIENTS.lock().await;
+    if let Some(client) = clients.get(name)
+        && client.config == *config
+        && !client.is_closed()
+    {
+        return Ok(client.clone());
+    }
+    let client = Arc::new(McpClient::connect(name, config).await?);
+    clients.insert(name.to_string(), client.clone());
+    Ok(client)
+}
+
+/// JSON-RPC 回應轉換為結果或錯誤訊息
+fn into_result(response: Value) -> Result<Value, String> {
+    if let Some(error) = response.get("error") {
+        return Err(error
+            .get("message")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| error.to_string()));
+    }
+    Ok(response.get("result").cloned().unwrap_or(Value::Null))
+}
+
+impl McpClient {
+    async fn connect(name: &str, config: &McpServerConfig) -> Result<Self, String> {
+        let transport = match (&config.command, &config.url) {
+            (Some(command), _) => Transport::Stdio(spawn_stdio(name, command, config)?),
+            (None, Some(url)) => Transport::Http(HttpTransport {
+                url: url.clone(),
+                headers: config.headers.clone(),
+                session_id: Mutex::new(None),
+            }),
+            (None, None) => return Err("未設定 command 或 url".to_string()),
+        };
+        let client = Self {
+            name: name.to_string(),
+            config: config.clone(),
+            transport,
+            next_id: AtomicU64::new(1),
+            tools: OnceCell::new(),
+        };
+        let result = client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {"name": "poe2openai", "version": env!("CARGO_PKG_VERSION")},
+                }),
+            )
+            .await?;
+        client.notify("notifications/initialized").await?;
+        info!(
+            "{}",
+            tr!(
+                "🔌 已連線 MCP 伺服器: {} | 伺服器: {} | 協議版本: {}",
+                "🔌 Connected to MCP server: {} | server: {} | protocol: {}",
+                name,
+                result["serverInfo"]["name"].as_str().unwrap_or("unknown"),
+                result["protocolVersion"].as_str().unwrap_or("unknown")
+            )
+        );
+        Ok(client)
+    }
+
+    fn is_closed(&self) -> bool {
+        match &self.transport {
+            Transport::Stdio(stdio) => stdio.closed.load(Ordering::Relaxed),
+            Transport::Http(_) => false,
+        }
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        debug!("🔌 MCP 請求 | 伺服器: {} | 方法: {}", self.name, method);
+        let response = match &self.transport {
+            Transport::Stdio(stdio) => {
+                let (sender, receiver) = oneshot::channel();
+                stdio
+                    .pending
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(id, sender);
+                let result = async {
+                    write_line(&stdio.stdin, &message).await?;
+                    tokio::time::timeout(*MCP_TIMEOUT, receiver)
+                        .await
+                        .map_err(|_| "請求逾時".to_string())?
+                        .map_err(|_| "MCP 伺服器已結束".to_string())
+                }
+                .await;
+                stdio
+                    .pending
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(&id);
+                result?
+            }
+            Transport::Http(http) => http.post(&message, Some(id)).await?,
+        };
+        into_result(response)
+    }
+
+    async fn notify(&self, method: &str) -> Result<(), String> {
+        let message = json!({"jsonrpc": "2.0", "method": method});
+        match &self.transport {
+            Transport::Stdio(stdio) => write_line(&stdio.stdin, &message).await,
+            Transport::Http(http) => http.post(&message, None).await.map(|_| ()),
+        }
+    }
+
+    /// 伺服器提供的工具列表，首次取得後保存在連線中
+    pub(crate) async fn tools(&self) -> Result<&[McpTool], String> {
+        let tools = self
+            .tools
+            .get_or_try_init(|| async {
+                let mut tools = Vec::new();
+                let mut cursor: Option<String> = None;
+                loop {
+                    let params = match &cursor {
+                        Some(cursor) => json!({"cursor": cursor}),
+                        None => json!({}),
+                    };
+                    let result = self.request("tools/list", params).await?;
+                    for tool in result["tools"].as_array().into_iter().flatten() {
+                        let Some(name) = tool["name"].as_str() else {
+                            continue;
+                        };
+                        tools.push(McpTool {
+                            name: name.to_string(),
+                            description: tool["description"].as_str().map(str::to_string),
+                            input_schema: tool
+                                .get("inputSchema")
+                                .cloned()
+                                .unwrap_or_else(|| json!({"type": "object"})),
+                        });
+                    }
+                    cursor = result["nextCursor"].as_str().map(str::to_string);
+                    if cursor.is_none() {
+                        break;
+                    }
+                }
+                debug!("🔌 MCP 伺服器 {} 提供 {} 個工具", self.name, tools.len());
+                Ok::<_, String>(tools)
+            })
+            .await?;
+        Ok(tools)
+    }
+
+    /// 調用工具並返回文字結果；工具回報錯誤時以 "Error: " 開頭交由機器人處理
+    pub(crate) async fn call_tool(&self, name: &str, arguments: Value) -> Result<String, String> {
+        let result = self
+            .request("tools/call", json!({"name": name, "arguments": arguments}))
+            .await?;
+        let text = result["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|item| match item["type"].as_str() {
+                Some("text") => item["text"].as_str().unwrap_or_default().to_string(),
+                Some("resource") => item["resource"]["text"]
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| {
+                        format!(
+                            "[resource: {}]",
+                            item["resource"]["uri"].as_str().unwrap_or("")
+                        )
+                    }),
+                Some(kind) => format!(
+                    "[{}: {}]",
+                    kind,
+                    item["mimeType"].as_str().unwrap_or("unknown")
+                ),
+                None => item.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if result["isError"].as_bool().unwrap_or(false) {
+            return Ok(format!("Error: {}", text));
+        }
+        Ok(text)
+    }
+}
+
+/// 以子行程啟動 stdio MCP 伺服器，背景讀取其輸出並分派回應
+fn spawn_stdio(
+    name: &str,
+    command: &str,
+    config: &McpServerConfig,
+) -> Result<StdioTransport, String> {
+    let mut child = Command::new(command)
+        .args(&config.args)
+        .envs(&config.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("無法啟動 {}: {}", command, e))?;
+    let stdin = Arc::new(tokio::sync::Mutex::new(
+        child.stdin.take().ok_or("無法取得 stdin")?,
+    ));
+    let stdout = child.stdout.take().ok_or("無法取得 stdout")?;
+    let pending: PendingRequests = Arc::default();
+    let closed = Arc::new(AtomicBool::new(false));
+
+    let name = name.to_string();
+    let reader_pending = pending.clone();
+    let reader_stdin = stdin.clone();
+    let reader_closed = closed.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                debug!("🔌 忽略 MCP 伺服器 {} 的非 JSON 輸出: {}", name, line);
+                continue;
+            };
+            match (message.get("id"), message.get("method")) {
+                // 伺服器發出的請求：只回應 ping，其餘不支援
+                (Some(id), Some(method)) => {
+                    let reply = if method == "ping" {
+                        json!({"jsonrpc": "2.0", "id": id, "result": {}})
+                    } else {
+                        json!({"jsonrpc": "2.0", "id": id, "error": {"code": -32601, "message": "Method not found"}})
+                    };
+                    let _ = write_line(&reader_stdin, &reply).await;
+                }
+                (Some(id), None) => {
+                    let sender = id.as_u64().and_then(|id| {
+                        reader_pending
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .remove(&id)
+                    });
+                    if let Some(sender) = sender {
+                        let _ = sender.send(message);
+                    }
+                }
+                _ => {}
+            }
+        }
+        reader_closed.store(true, Ordering::Relaxed);
+        reader_pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        warn!(
+            "{}",
+            tr!("🔌 MCP 伺服器已結束: {}", "🔌 MCP server exited: {}", name)
+        );
+    });
+
+    Ok(StdioTransport {
+        stdin,
+        pending,
+        closed,
+        _child: child,
+    })
+}
+
+async fn write_line(stdin: &tokio::sync::Mutex<ChildStdin>, message: &Value) -> Result<(), String> {
+    let mut line = message.to_string();
+    line.push('\n');
+    let mut stdin = stdin.lock().await;
+    stdin
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| format!("寫入 MCP 伺服器失敗: {}", e))?;
+    stdin
+        .flush()
+        .await
+        .map_err(|e| format!("寫入 MCP 伺服器失敗: {}", e))
+}
+
+impl HttpTransport {
+    /// 傳送 JSON-RPC 訊息；id 為 None（通知）時不解析回應
+    async fn post(&self, message: &Value, id: Option<u64>) -> Result<Value, String> {
+        let mut request = shared_http_client()
+            .post(&self.url)
+            .timeout(*MCP_TIMEOUT)
+            .header("Accept", "application/json, text/event-stream")
+            .json(message);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let session_id = self
+            .session_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Some(session_id) = session_id {
+            request = request.header("Mcp-Session-Id", session_id);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("HTTP {}: {}", status, body));
+        }
+        if let Some(session_id) = response
+            .headers()
+            .get("Mcp-Session-Id")
+            .and_then(|v| v.to_str().ok())
+        {
+            *self.session_id.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some(session_id.to_string());
+        }
+        let Some(id) = id else {
+            return Ok(Value::Null);
+        };
+        let is_sse = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        if !is_sse {
+            return serde_json::from_str(&body).map_err(|e| format!("無效的回應: {}", e));
+        }
+        // SSE 回應：找出 id 相符的 JSON-RPC 回應
+        body.lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .filter_map(|data| serde_json::from_str::<Value>(data.trim()).ok())
+            .find(|message| message["id"].as_u64() == Some(id) && message.get("method").is_none())
+            .ok_or_else(|| "SSE 回應中沒有對應的結果".to_string())
+    }
+}
@@ -0,0 +1,474 @@
+//! Poe 機器人輸出附件（圖片、影片、音訊等）的轉換與本地轉存
+
+use crate::poe_client::{download_attachment, download_attachment_to_file};
+use crate::types::{AudioOutput, ImageOutput, ImageOutputUrl, VideoOutput, VideoOutputUrl};
+use base64::prelude::*;
+use poe_api_process::types::FileData;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
+
+/// 圖片機器人輸出的返回方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageOutputMode {
+    /// 以 Markdown 圖片連結嵌入正文（預設）
+    Markdown,
+    /// 額外以 message.images 陣列返回 Poe CDN 連結
+    Images,
+    /// 下載圖片並以 base64 data URL 放入 message.images，避免 CDN 連結過期
+    B64,
+}
+
+static IMAGE_OUTPUT_MODE: OnceLock<ImageOutputMode> = OnceLock::new();
+
+/// 取得圖片輸出方式 (IMAGE_OUTPUT_MODE=markdown|images|b64)
+pub fn get_image_output_mode() -> ImageOutputMode {
+    *IMAGE_OUTPUT_MODE.get_or_init(|| {
+        let value = std::env::var("IMAGE_OUTPUT_MODE").unwrap_or_default();
+        let mode = match value.trim().to_lowercase().as_str() {
+            "" | "markdown" => ImageOutputMode::Markdown,
+            "images" => ImageOutputMode::Images,
+            "b64" | "base64" => ImageOutputMode::B64,
+            other => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 無效的 IMAGE_OUTPUT_MODE: {}，使用 markdown",
+                        "⚠️ Invalid IMAGE_OUTPUT_MODE: {}, using markdown",
+                        other
+                    )
+                );
+                ImageOutputMode::Markdown
+            }
+        };
+        info!(
+            "{}",
+            tr!(
+                "🖼️  圖片輸出方式: {:?}",
+                "🖼️  Image output mode: {:?}",
+                mode
+            )
+        );
+        mode
+    })
+}
+
+/// 媒體轉存設定
+#[derive(Debug)]
+pub struct MediaRehostConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+    pub public_url: String,
+    pub max_age: Duration,
+}
+
+static MEDIA_REHOST_CONFIG: OnceLock<MediaRehostConfig> = OnceLock::new();
+
+/// 取得媒體轉存設定
+/// MEDIA_REHOST=true 時將影片與音訊下載到 MEDIA_DIR，並以 MEDIA_PUBLIC_URL/media/ 提供
+pub fn get_media_rehost_config() -> &'static MediaRehostConfig {
+    MEDIA_REHOST_CONFIG.get_or_init(|| {
+        let enabled = std::env::var("MEDIA_REHOST")
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+            .unwrap_or(false);
+        let dir = std::env::var("MEDIA_DIR")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| crate::utils::get_config_path("media"));
+        let public_url = std::env::var("MEDIA_PUBLIC_URL")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(|| {
+                let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+                format!("http://localhost:{}", port)
+            })
+            .trim_end_matches('/')
+            .to_string();
+        let max_age_secs = std::env::var("MEDIA_MAX_AGE_SECS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(86400);
+
+        if enabled {
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                error!("{}", tr!("❌ 無法建立媒體目錄 {}: {}", "❌ Failed to create media directory {}: {}", dir.display(), e));
+            }
+            info!("{}", tr!("🎬 媒體轉存: 已啟用 | 目錄: {} | 公開網址: {}/media/ | 保留: {} 秒", "🎬 Media rehosting: enabled | directory: {} | public URL: {}/media/ | retention: {} seconds",
+                dir.display(),
+                public_url,
+                max_age_secs));
+        } else {
+            info!("{}", tr!("🎬 媒體轉存: 已禁用", "🎬 Media rehosting: disabled"));
+        }
+        MediaRehostConfig {
+            enabled,
+            dir,
+            public_url,
+            max_age: Duration::from_secs(max_age_secs),
+        }
+    })
+}
+
+/// 判斷附件是否為圖片
+pub fn is_image(file: &FileData) -> bool {
+    file.content_type.starts_with("image/")
+}
+
+/// 判斷附件是否為影片
+pub fn is_video(file: &FileData) -> bool {
+    file.content_type.starts_with("video/")
+}
+
+/// 判斷附件是否為音訊
+pub fn is_audio(file: &FileData) -> bool {
+    file.content_type.starts_with("audio/")
+}
+
+/// 根據檔名或 Content-Type 決定本地檔案副檔名
+fn media_extension(file: &FileData) -> String {
+    if let Some(ext) = Path::new(&file.name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| !e.is_empty() && e.len() <= 8 && e.chars().all(|c| c.is_ascii_alphanumeric()))
+    {
+        return ext.to_lowercase();
+    }
+    match file.content_type.as_str() {
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "video/quicktime" => "mov",
+        "video/x-matroska" => "mkv",
+        "video/mpeg" => "mpeg",
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        "audio/ogg" => "ogg",
+        "audio/opus" => "opus",
+        "audio/flac" | "audio/x-flac" => "flac",
+        "audio/aac" => "aac",
+        "audio/mp4" | "audio/x-m4a" => "m4a",
+        "audio/webm" => "webm",
+        _ => "bin",
+    }
+    .to_string()
+}
+
+/// 刪除超過保留時間的轉存檔案
+fn cleanup_expired_media(dir: &Path, max_age: Duration) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age > max_age);
+        if expired {
+            match std::fs::remove_file(entry.path()) {
+                Ok(_) => debug!("🗑️ 已刪除過期媒體檔案: {}", entry.path().display()),
+                Err(e) => warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 刪除過期媒體檔案失敗 {}: {}",
+                        "⚠️ Failed to delete expired media file {}: {}",
+                        entry.path().display(),
+                        e
+                    )
+                ),
+            }
+        }
+    }
+}
+
+/// 將影片或音訊附件轉存到本地，成功時改寫附件 URL 並返回本地路徑
+async fn rehost_attachment(file: &mut FileData) -> Option<PathBuf> {
+    let config = get_media_rehost_config();
+    if !config.enabled {
+        return None;
+    }
+    if let Err(e) = tokio::fs::create_dir_all(&config.dir).await {
+        error!(
+            "{}",
+            tr!(
+                "❌ 無法建立媒體目錄 {}: {}",
+                "❌ Failed to create media directory {}: {}",
+                config.dir.display(),
+                e
+            )
+        );
+        return None;
+    }
+    let dir = config.dir.clone();
+    let max_age = config.max_age;
+    let _ = tokio::task::spawn_blocking(move || cleanup_expired_media(&dir, max_age)).await;
+
+    let filename = format!("{}.{}", nanoid::nanoid!(16), media_extension(file));
+    let path = config.dir.join(&filename);
+    match download_attachment_to_file(&file.url, &path).await {
+        Ok((_, content_type)) => {
+            // 上游 Content-Type 較 Poe 事件中的值更可靠，但須與原本的媒體類別相同
+            let media_type = file.content_type.split('/').next().unwrap_or_default();
+            if let Some(content_type) = content_type
+                .map(|c| c.split(';').next().unwrap_or_default().trim().to_string())
+                .filter(|c| c.split('/').next() == Some(media_type))
+            {
+                file.content_type = content_type;
+            }
+            let url = format!("{}/media/{}", config.public_url, filename);
+            info!(
+                "{}",
+                tr!(
+                    "🎬 媒體已轉存 | 原始: {} | 本地: {}",
+                    "🎬 Media rehosted | original: {} | local: {}",
+                    file.url,
+                    url
+                )
+            );
+            file.url = url;
+            Some(path)
+        }
+        Err(e) => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 轉存媒體失敗，改用原始連結 | URL: {} | 錯誤: {}",
+                    "⚠️ Failed to rehost media, using original URL | URL: {} | error: {}",
+                    file.url,
+                    e
+                )
+            );
+            None
+        }
+    }
+}
+
+/// 讀取 MP4 / MOV 檔案 moov/mvhd 中的時長（秒）
+fn read_mp4_duration(path: &Path) -> Option<f64> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let file_len = file.metadata().ok()?.len();
+
+    // 讀取 box 標頭，返回 (類型, 內容起點, 內容長度)
+    fn read_box_header(
+        file: &mut std::fs::File,
+        offset: u64,
+        end: u64,
+    ) -> Option<([u8; 4], u64, u64)> {
+        if offset + 8 > end {
+            return None;
+        }
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header).ok()?;
+        let size = u32::from_be_bytes(header[0..4].try_into().ok()?) as u64;
+        let box_type: [u8; 4] = header[4..8].try_into().ok()?;
+        let (body_start, box_size) = match size {
+            0 => (offset + 8, end - offset),
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large).ok()?;
+                (offset + 16, u64::from_be_bytes(large))
+            }
+            _ => (offset + 8, size),
+        };
+        if box_size < body_start - offset || offset + box_size > end {
+            return None;
+        }
+        Some((box_type, body_start, offset + box_size - body_start))
+    }
+
+    let find_box = |file: &mut std::fs::File, start: u64, end: u64, target: &[u8; 4]| {
+        let mut offset = start;
+        while let Some((box_type, body_start, body_len)) = read_box_header(file, offset, end) {
+            if &box_type == target {
+                return Some((body_start, body_len));
+            }
+            offset = body_start + body_len;
+        }
+        None
+    };
+
+    let (moov_start, moov_len) = find_box(&mut file, 0, file_len, b"moov")?;
+    let (mvhd_start, _) = find_box(&mut file, moov_start, moov_start + moov_len, b"mvhd")?;
+    file.seek(SeekFrom::Start(mvhd_start)).ok()?;
+    let mut version = [0u8; 4];
+    file.read_exact(&mut version).ok()?;
+    let (timescale, duration) = if version[0] == 1 {
+        let mut buf = [0u8; 28];
+        file.read_exact(&mut buf).ok()?;
+        (
+            u32::from_be_bytes(buf[16..20].try_into().ok()?) as u64,
+            u64::from_be_bytes(buf[20..28].try_into().ok()?),
+        )
+    } else {
+        let mut buf = [0u8; 16];
+        file.read_exact(&mut buf).ok()?;
+        (
+            u32::from_be_bytes(buf[8..12].try_into().ok()?) as u64,
+            u32::from_be_bytes(buf[12..16].try_into().ok()?) as u64,
+        )
+    };
+    if timescale == 0 {
+        return None;
+    }
+    Some(duration as f64 / timescale as f64)
+}
+
+/// File 事件附件轉換後的結構化輸出
+#[derive(Debug, Default)]
+pub struct MediaOutput {
+    pub image: Option<ImageOutput>,
+    pub video: Option<VideoOutput>,
+    pub audio: Option<AudioOutput>,
+}
+
+impl MediaOutput {
+    pub fn is_empty(&self) -> bool {
+        self.image.is_none() && self.video.is_none() && self.audio.is_none()
+    }
+}
+
+/// 處理 File 事件中的附件：影片與音訊視設定轉存到本地（會改寫附件 URL），
+/// 並產生 message.images / message.videos / message.audio 項目；
+/// audio_data 為 true（請求 modalities 包含 audio）時音訊以 base64 放入 data
+pub async fn prepare_attachment(file: &mut FileData, audio_data: bool) -> MediaOutput {
+    if is_audio(file) {
+        let local_path = rehost_attachment(file).await;
+        return MediaOutput {
+            audio: Some(build_audio_output(file, local_path, audio_data).await),
+            ..Default::default()
+        };
+    }
+    if is_video(file) {
+        let local_path = rehost_attachment(file).await;
+        let duration = match local_path {
+            Some(path) => tokio::task::spawn_blocking(move || read_mp4_duration(&path))
+                .await
+                .ok()
+                .flatten(),
+            None => None,
+        };
+        debug!(
+            "🎬 影片附件 | 名稱: {} | 類型: {} | 時長: {:?}",
+            file.name, file.content_type, duration
+        );
+        return MediaOutput {
+            video: Some(VideoOutput {
+                r#type: "video_url".to_string(),
+                video_url: VideoOutputUrl {
+                    url: file.url.clone(),
+                    mime_type: file.content_type.clone(),
+                    duration,
+                },
+            }),
+            ..Default::default()
+        };
+    }
+    MediaOutput {
+        image: build_image_output(file).await,
+        ..Default::default()
+    }
+}
+
+/// 將音訊附件轉換為 message.audio，base64 失敗時改用連結
+/// 有效期限與轉存檔案的保留時間相同
+async fn build_audio_output(
+    file: &FileData,
+    local_path: Option<PathBuf>,
+    audio_data: bool,
+) -> AudioOutput {
+    let data = if audio_data {
+        let bytes = match local_path {
+            Some(path) => tokio::fs::read(&path).await.map_err(|e| e.to_string()),
+            None => download_attachment(&file.url)
+                .await
+                .map(|(bytes, _)| bytes)
+                .map_err(|e| e.to_string()),
+        };
+        match bytes {
+            Ok(bytes) => {
+                debug!(
+                    "🔊 音訊已轉換為 base64 | 名稱: {} | 大小: {}",
+                    file.name,
+                    crate::utils::format_bytes_length(bytes.len())
+                );
+                Some(BASE64_STANDARD.encode(bytes))
+            }
+            Err(e) => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 下載音訊失敗，改用原始連結 | URL: {} | 錯誤: {}",
+                        "⚠️ Failed to download audio, using original URL | URL: {} | error: {}",
+                        file.url,
+                        e
+                    )
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    AudioOutput {
+        id: format!("audio_{}", nanoid::nanoid!(24)),
+        url: data.is_none().then(|| file.url.clone()),
+        data,
+        format: media_extension(file),
+        expires_at: (now + get_media_rehost_config().max_age).as_secs() as i64,
+        transcript: None,
+    }
+}
+
+/// 以 Markdown 表示附件：圖片使用 `![name](url)`，其他檔案使用連結
+pub fn attachment_markdown(file: &FileData) -> String {
+    if is_image(file) {
+        format!("![{}]({})", file.name, file.url)
+    } else {
+        format!("[{}]({})", file.name, file.url)
+    }
+}
+
+/// 根據輸出方式將圖片附件轉換為 message.images 項目
+/// Markdown 模式或非圖片附件返回 None
+async fn build_image_output(file: &FileData) -> Option<ImageOutput> {
+    if !is_image(file) {
+        return None;
+    }
+    let url = match get_image_output_mode() {
+        ImageOutputMode::Markdown => return None,
+        ImageOutputMode::Images => file.url.clone(),
+        ImageOutputMode::B64 => match download_attachment(&file.url).await {
+            Ok((bytes, content_type)) => {
+                let mime = content_type
+                    .filter(|c| c.starts_with("image/"))
+                    .unwrap_or_else(|| file.content_type.clone());
+                debug!("🖼️ 圖片已轉換為 base64 | 名稱: {}", file.name);
+                format!("data:{};base64,{}", mime, BASE64_STANDARD.encode(bytes))
+            }
+            Err(e) => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 下載圖片失敗，改用原始連結 | URL: {} | 錯誤: {}",
+                        "⚠️ Failed to download image, using original URL | URL: {} | error: {}",
+                        file.url,
+                        e
+                    )
+                );
+                file.url.clone()
+            }
+        },
+    };
+    Some(ImageOutput {
+        r#type: "image_url".to_string(),
+        image_url: ImageOutputUrl { url },
+    })
+}
@@ -0,0 +1,326 @@
+//! 模擬上游 (MOCK_MODE)：不連線 Poe，以固定內容回應，方便離線開發與整合測試
+//!
+//! - 回應內容：MOCK_RESPONSE，未設定時回顯最後一則使用者訊息
+//! - MOCK_LATENCY_MS：每個片段之間的延遲
+//! - MOCK_ERROR_EVERY：每 N 個請求注入一次錯誤事件；訊息包含 `[mock:error]` 時也會注入
+//! - 訊息包含 `[mock:points]` 時直接返回點數不足錯誤
+//! - 訊息包含 `[mock:image]` 時在正文後附上一張圖片（MOCK_IMAGE_URL）
+//! - 訊息包含 `[mock:audio]` 時在正文後附上一段音訊（MOCK_AUDIO_URL）
+//! - 訊息包含 `[mock:rewrite]` 時在正文後以 ReplaceResponse 改寫已發送的內容
+//! - 訊息包含 `[mock:suggest]` 時在完成前發送兩則建議回覆
+//! - 訊息包含 `[mock:tool:名稱]` 且請求提供該工具時調用它（必填參數填入 "mock"）；
+//!   請求帶有工具結果時改為回顯工具結果
+
+use crate::poe_client::ModelMetadata;
+use futures_util::Stream;
+use futures_util::stream;
+use poe_api_process::types::{
+    ChatToolCall, FileData, FileUploadRequest, FileUploadResponse, FunctionCall,
+};
+use poe_api_process::{
+    ChatEventType, ChatRequest, ChatResponse, ChatResponseData, ModelInfo, ModelResponse, PoeError,
+};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::debug;
+
+static MOCK_CONFIG: LazyLock<Option<MockConfig>> = LazyLock::new(load_mock_config);
+
+/// 已處理的模擬請求數，用於 MOCK_ERROR_EVERY
+static MOCK_REQUEST_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// 訊息中包含此標記時注入錯誤
+const ERROR_MARKER: &str = "[mock:error]";
+/// 訊息中包含此標記時返回點數不足錯誤
+const POINTS_MARKER: &str = "[mock:points]";
+/// 訊息中包含此標記時附上一張圖片
+const IMAGE_MARKER: &str = "[mock:image]";
+/// 訊息中包含此標記時附上一段音訊
+const AUDIO_MARKER: &str = "[mock:audio]";
+/// 訊息中包含此標記時以 ReplaceResponse 改寫已發送的內容
+const REWRITE_MARKER: &str = "[mock:rewrite]";
+/// 訊息中包含此標記時發送建議回覆
+const SUGGEST_MARKER: &str = "[mock:suggest]";
+/// 訊息中包含此標記（後接工具名稱與 `]`）時調用工具
+const TOOL_MARKER: &str = "[mock:tool:";
+
+pub struct MockConfig {
+    pub latency: Duration,
+    pub error_every: u64,
+    pub response: Option<String>,
+    pub image_url: String,
+    pub audio_url: String,
+    pub models: Vec<String>,
+}
+
+fn load_mock_config() -> Option<MockConfig> {
+    let enabled = std::env::var("MOCK_MODE")
+        .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+        .unwrap_or(false);
+    if !enabled {
+        return None;
+    }
+    let models: Vec<String> = std::env::var("MOCK_MODELS")
+        .unwrap_or_else(|_| "mock-model".to_string())
+        .split(',')
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .collect();
+    Some(MockConfig {
+        latency: Duration::from_millis(
+            std::env::var("MOCK_LATENCY_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(50),
+        ),
+        error_every: std::env::var("MOCK_ERROR_EVERY")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        response: std::env::var("MOCK_RESPONSE")
+            .ok()
+            .filter(|s| !s.is_empty()),
+        image_url: std::env::var("MOCK_IMAGE_URL")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "https://example.com/mock-image.png".to_string()),
+        audio_url: std::env::var("MOCK_AUDIO_URL")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "https://example.com/mock-audio.mp3".to_string()),
+        models,
+    })
+}
+
+/// 取得模擬模式設定，未啟用時返回 None
+pub fn get_mock_config() -> Option<&'static MockConfig> {
+    MOCK_CONFIG.as_ref()
+}
+
+/// 將回應切成以空白結尾的片段，模擬逐字串流
+fn split_into_chunks(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        current.push(c);
+        if c.is_whitespace() {
+            chunks.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// 依 `[mock:tool:名稱]` 標記產生工具調用，請求未提供該工具時返回 None
+fn mock_tool_call(chat_request: &ChatRequest, last_user: &str) -> Option<ChatToolCall> {
+    let start = last_user.find(TOOL_MARKER)? + TOOL_MARKER.len();
+    let name = &last_user[start..start + last_user[start..].find(']')?];
+    let tool = chat_request
+        .tools
+        .iter()
+        .flatten()
+        .find(|tool| tool.function.name == name)?;
+    let arguments: serde_json::Map<String, serde_json::Value> = tool
+        .function
+        .parameters
+        .iter()
+        .flat_map(|parameters| parameters.required.iter())
+        .map(|name| (name.clone(), serde_json::Value::from("mock")))
+        .collect();
+    Some(ChatToolCall {
+        id: format!("call_mock_{}", nanoid::nanoid!(8)),
+        r#type: "function".to_string(),
+        function: FunctionCall {
+            name: name.to_string(),
+            arguments: serde_json::Value::Object(arguments).to_string(),
+        },
+    })
+}
+
+impl MockConfig {
+    /// 產生模擬的串流事件
+    pub fn stream_request(
+        &self,
+        model: &str,
+        chat_request: &ChatRequest,
+    ) -> Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>> {
+        let count = MOCK_REQUEST_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+        let last_user = chat_request
+            .query
+            .iter()
+            .rev()
+            .find(|m| m.role == "user")
+            .map(|m| m.content.clone())
+            .unwrap_or_default();
+        let inject_error = last_user.contains(ERROR_MARKER)
+            || (self.error_every > 0 && count.is_multiple_of(self.error_every));
+        let tool_results = chat_request
+            .tool_results
+            .iter()
+            .flatten()
+            .map(|result| format!("{} = {}", result.name, result.content))
+            .collect::<Vec<_>>();
+        let text = match &self.response {
+            Some(response) => response.clone(),
+            None if !tool_results.is_empty() => format!(
+                "Mock response from {}: tool results: {}",
+                model,
+                tool_results.join("; ")
+            ),
+            None => format!("Mock response from {}: {}", model, last_user),
+        };
+        let tool_call = if tool_results.is_empty() {
+            mock_tool_call(chat_request, &last_user)
+        } else {
+            None
+        };
+        debug!(
+            "🧪 模擬串流請求 #{} | 片段延遲: {:?} | 注入錯誤: {}",
+            count, self.latency, inject_error
+        );
+
+        let mut events: Vec<ChatResponse> = split_into_chunks(&text)
+            .into_iter()
+            .map(|text| ChatResponse {
+                event: ChatEventType::Text,
+                data: Some(ChatResponseData::Text { text }),
+            })
+            .collect();
+        if last_user.contains(POINTS_MARKER) {
+            events.clear();
+            events.push(ChatResponse {
+                event: ChatEventType::Error,
+                data: Some(ChatResponseData::Error {
+                    text: "This bot needs more points to answer your request.".to_string(),
+                    allow_retry: true,
+                }),
+            });
+        } else if inject_error {
+            events.truncate(events.len() / 2);
+            events.push(ChatResponse {
+                event: ChatEventType::Error,
+                data: Some(ChatResponseData::Error {
+                    text: "Mock error injected".to_string(),
+                    allow_retry: true,
+                }),
+            });
+        } else if let Some(call) = tool_call {
+            events = vec![
+                ChatResponse {
+                    event: ChatEventType::Json,
+                    data: Some(ChatResponseData::ToolCalls(vec![call])),
+                },
+                ChatResponse {
+                    event: ChatEventType::Done,
+                    data: Some(ChatResponseData::Empty),
+                },
+            ];
+        } else {
+            if last_user.contains(REWRITE_MARKER) {
+                events.push(ChatResponse {
+                    event: ChatEventType::ReplaceResponse,
+                    data: Some(ChatResponseData::Text {
+                        text: format!("Rewritten: {}", text),
+                    }),
+                });
+            }
+            if last_user.contains(IMAGE_MARKER) {
+                events.push(ChatResponse {
+                    event: ChatEventType::File,
+                    data: Some(ChatResponseData::File(FileData {
+                        url: self.image_url.clone(),
+                        name: "mock-image.png".to_string(),
+                        content_type: "image/png".to_string(),
+                        inline_ref: "mock".to_string(),
+                    })),
+                });
+            }
+            if last_user.contains(AUDIO_MARKER) {
+                events.push(ChatResponse {
+                    event: ChatEventType::File,
+                    data: Some(ChatResponseData::File(FileData {
+                        url: self.audio_url.clone(),
+                        name: "mock-audio.mp3".to_string(),
+                        content_type: "audio/mpeg".to_string(),
+                        inline_ref: "mock-audio".to_string(),
+                    })),
+                });
+            }
+            if last_user.contains(SUGGEST_MARKER) {
+                events.extend(
+                    ["Tell me more", "Give an example"].map(|text| ChatResponse {
+                        event: ChatEventType::SuggestedReply,
+                        data: Some(ChatResponseData::Text {
+                            text: text.to_string(),
+                        }),
+                    }),
+                );
+            }
+            events.push(ChatResponse {
+                event: ChatEventType::Done,
+                data: Some(ChatResponseData::Empty),
+            });
+        }
+
+        let latency = self.latency;
+        Box::pin(stream::unfold(
+            events.into_iter(),
+            move |mut events| async move {
+                let event = events.next()?;
+                if !latency.is_zero() {
+                    tokio::time::sleep(latency).await;
+                }
+                Some((Ok(event), events))
+            },
+        ))
+    }
+
+    /// 模擬的模型列表
+    pub fn model_list(&self) -> ModelResponse {
+        ModelResponse {
+            data: self
+                .models
+                .iter()
+                .map(|id| ModelInfo {
+                    id: id.clone(),
+                    object: "model".to_string(),
+                    created: 0,
+                    owned_by: "mock".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// 模擬的模型資訊，頭像使用 MOCK_IMAGE_URL
+    pub fn model_metadata(&self) -> HashMap<String, ModelMetadata> {
+        self.models
+            .iter()
+            .map(|id| {
+                let metadata = ModelMetadata {
+                    name: Some(id.clone()),
+                    description: Some(format!("Mock bot {}", id)),
+                    icon: Some(self.image_url.clone()),
+                };
+                (id.to_lowercase(), metadata)
+            })
+            .collect()
+    }
+
+    /// 模擬檔案上傳，返回不可下載的假附件網址
+    pub fn upload_files(&self, files: &[FileUploadRequest]) -> Vec<FileUploadResponse> {
+        files
+            .iter()
+            .enumerate()
+            .map(|(index, _)| FileUploadResponse {
+                attachment_url: format!("https://mock.invalid/attachments/{}", index),
+                mime_type: None,
+                size: None,
+            })
+            .collect()
+    }
+}
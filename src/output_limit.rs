@@ -0,0 +1,91 @@
+//! 回覆長度上限 (models.yaml 的 max_output_tokens)
+//!
+//! 逐一計算 Poe 正文事件的 token 數，達到上限時截斷該事件的文字、補上完成事件並停止讀取上游，
+//! 不再等待機器人生成其餘內容。思考內容在轉換前與正文一同計算，與 max_completion_tokens 的含義相同
+
+use futures_util::stream::{self, Stream, StreamExt};
+use poe_api_process::{ChatEventType, ChatResponse, ChatResponseData, PoeError};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tiktoken_rs::o200k_base_singleton;
+use tracing::debug;
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>;
+
+/// 一個請求的回覆長度上限，記錄是否已截斷以決定 finish_reason
+pub(crate) struct OutputLimit {
+    max_tokens: u32,
+    reached: AtomicBool,
+}
+
+impl OutputLimit {
+    pub(crate) fn new(max_tokens: u32) -> Arc<Self> {
+        Arc::new(Self {
+            max_tokens,
+            reached: AtomicBool::new(false),
+        })
+    }
+
+    /// 回覆是否因達到上限而截斷
+    pub(crate) fn reached(&self) -> bool {
+        self.reached.load(Ordering::Relaxed)
+    }
+}
+
+/// 文字未超過 max_tokens 時返回其 token 數，超過時返回保留開頭 max_tokens 個 token 的文字
+fn fit_tokens(text: &str, max_tokens: u32) -> Result<u32, String> {
+    let bpe = o200k_base_singleton();
+    let tokens = bpe.encode_with_special_tokens(text);
+    let end = max_tokens as usize;
+    if end >= tokens.len() {
+        return Ok(tokens.len() as u32);
+    }
+    // 切點落在多位元組字元中間時無法解碼，往前移動幾個 token 再試
+    Err((end.saturating_sub(4)..=end)
+        .rev()
+        .find_map(|end| bpe.decode(tokens[..end].to_vec()).ok())
+        .unwrap_or_default())
+}
+
+/// 套用回覆長度上限，達到上限後以完成事件結束串流
+pub(crate) fn limit_output(stream: EventStream, limit: Arc<OutputLimit>) -> EventStream {
+    // 狀態：(上游, 已輸出的 token 數)，達到上限後上游為 None
+    let limited = stream::unfold((Some(stream), 0u32), move |(upstream, used)| {
+        let limit = limit.clone();
+        async move {
+            let mut upstream = upstream?;
+            let mut item = upstream.next().await?;
+            let (base, text) = match &mut item {
+                Ok(ChatResponse {
+                    event: ChatEventType::Text,
+                    data: Some(ChatResponseData::Text { text }),
+                }) => (used, text),
+                // 取代事件以新的全文重新計算
+                Ok(ChatResponse {
+                    event: ChatEventType::ReplaceResponse,
+                    data: Some(ChatResponseData::Text { text }),
+                }) => (0, text),
+                _ => return Some((vec![item], (Some(upstream), used))),
+            };
+            let remaining = limit.max_tokens.saturating_sub(base);
+            match fit_tokens(text, remaining) {
+                Ok(tokens) => Some((vec![item], (Some(upstream), base + tokens))),
+                Err(head) => {
+                    debug!(
+                        "✂️ 回覆達到 max_output_tokens ({})，截斷並停止讀取上游",
+                        limit.max_tokens
+                    );
+                    *text = head;
+                    limit.reached.store(true, Ordering::Relaxed);
+                    let done = Ok(ChatResponse {
+                        event: ChatEventType::Done,
+                        data: Some(ChatResponseData::Empty),
+                    });
+                    Some((vec![item, done], (None, limit.max_tokens)))
+                }
+            }
+        }
+    });
+    Box::pin(limited.flat_map(stream::iter))
+}
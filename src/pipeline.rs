@@ -0,0 +1,960 @@
+//! 串流正文處理管線：Poe 事件由 evert 解碼為正文後，依 STREAM_STAGES 設定的順序通過各階段，
+//! 最後由聊天處理器編碼為 OpenAI 片段。串流與非串流回應使用相同的階段
+//!
+//! - think_tags：將 `<think>...</think>` 區塊移至 reasoning_content
+//! - stop_sequences：在本地套用請求的 stop，命中後捨棄其後的正文
+//! - citations：將 `[[1]](url)` 形式的引用改寫為 `[1](url)`
+//! - annotations：將 `[[1]](url)` 形式的引用移出正文，改為 url_citation 註解，
+//!   註解範圍為引用所在的句子；應放在最後，以免後續階段改變正文導致位置偏移
+//!
+//! Poe 思考格式（`*Thinking...*` 之後以 `> ` 引用的段落）總是最先移至 reasoning_content；
+//! 模型設定 strip_footnotes 時另外在其後加入腳註階段，移除 `[1]` 形式的腳註標記與結尾的來源列表
+//!
+//! 每個階段可暫存可能被下一個片段延續的結尾（例如尚未完整的標籤），串流結束時再取出
+
+use crate::types::Annotation;
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+use tracing::{debug, info, warn};
+
+static STREAM_STAGES: LazyLock<Vec<StageKind>> = LazyLock::new(|| {
+    let value = std::env::var("STREAM_STAGES").unwrap_or_default();
+    match parse_stage_list(&value) {
+        Ok(stages) => {
+            if !stages.is_empty() {
+                info!(
+                    "{}",
+                    tr!(
+                        "🧩 串流處理階段: {}",
+                        "🧩 Stream stages: {}",
+                        stage_names(&stages)
+                    )
+                );
+            }
+            stages
+        }
+        Err(e) => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無效的 STREAM_STAGES: {}，不啟用串流處理階段",
+                    "⚠️ Invalid STREAM_STAGES: {}, stream stages disabled",
+                    e
+                )
+            );
+            Vec::new()
+        }
+    }
+});
+
+/// 可設定的處理階段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageKind {
+    ThinkTags,
+    StopSequences,
+    Citations,
+    Annotations,
+}
+
+impl StageKind {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "think_tags" => Some(StageKind::ThinkTags),
+            "stop_sequences" => Some(StageKind::StopSequences),
+            "citations" => Some(StageKind::Citations),
+            "annotations" => Some(StageKind::Annotations),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StageKind::ThinkTags => "think_tags",
+            StageKind::StopSequences => "stop_sequences",
+            StageKind::Citations => "citations",
+            StageKind::Annotations => "annotations",
+        }
+    }
+}
+
+/// 解析以逗號分隔的階段列表，遇到未知的階段名稱時返回錯誤
+pub fn parse_stage_list(value: &str) -> Result<Vec<StageKind>, String> {
+    value
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            StageKind::parse(&name).ok_or_else(|| {
+                tr!(
+                    "未知的階段: {}（可用: think_tags, stop_sequences, citations, annotations）",
+                    "unknown stage: {} (available: think_tags, stop_sequences, citations, annotations)",
+                    name
+                )
+            })
+        })
+        .collect()
+}
+
+pub fn stage_names(stages: &[StageKind]) -> String {
+    stages
+        .iter()
+        .map(|stage| stage.name())
+        .collect::<Vec<_>>()
+        .join(" → ")
+}
+
+/// 取得 STREAM_STAGES 設定的階段列表
+pub fn get_stream_stages() -> &'static [StageKind] {
+    &STREAM_STAGES
+}
+
+/// 經過階段處理的一段輸出
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StageOutput {
+    pub content: String,
+    pub reasoning: String,
+    /// 引用註解，位置以整段正文的字元數計算
+    pub annotations: Vec<Annotation>,
+}
+
+impl StageOutput {
+    pub fn content(text: &str) -> Self {
+        Self {
+            content: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty() && self.reasoning.is_empty() && self.annotations.is_empty()
+    }
+
+    fn append(&mut self, other: StageOutput) {
+        self.content.push_str(&other.content);
+        self.reasoning.push_str(&other.reasoning);
+        self.annotations.extend(other.annotations);
+    }
+}
+
+/// 處理階段：接收上一階段的輸出，返回可交給下一階段的部分
+trait Stage: Send {
+    fn process(&mut self, input: StageOutput) -> StageOutput;
+
+    /// 串流結束時取出暫存的內容
+    fn finish(&mut self) -> StageOutput {
+        StageOutput::default()
+    }
+}
+
+/// 找出文本結尾可能是 pattern 開頭的部分，返回需要暫存的起點
+fn partial_match_start(text: &str, pattern: &str) -> usize {
+    (1..pattern.len().min(text.len() + 1))
+        .rev()
+        .filter(|&len| pattern.is_char_boundary(len))
+        .find(|&len| text.ends_with(&pattern[..len]))
+        .map_or(text.len(), |len| text.len() - len)
+}
+
+/// Poe 思考區塊的開始標記，依序比對
+const POE_THINKING_MARKERS: &[&str] = &["*Thinking...*", "Thinking..."];
+
+#[derive(Default, PartialEq)]
+enum PoeThinkingState {
+    #[default]
+    Detecting,
+    Thinking,
+    Ended,
+}
+
+/// 將 Poe 的思考區塊（`*Thinking...*` 之後以 `>` 開頭的引用行）移至思考內容
+/// 思考區塊只出現一次，遇到第一個非引用的非空行即結束
+#[derive(Default)]
+struct PoeThinkingStage {
+    buffer: String,
+    state: PoeThinkingState,
+}
+
+impl PoeThinkingStage {
+    // 尋找開始標記，找到時輸出標記前的正文並移除標記
+    fn detect(&mut self, output: &mut StageOutput) {
+        let found = POE_THINKING_MARKERS
+            .iter()
+            .find_map(|marker| self.buffer.find(marker).map(|pos| (pos, marker.len())));
+        match found {
+            Some((pos, len)) => {
+                debug!("🧠 思考模式開始");
+                output.content.push_str(&self.buffer[..pos]);
+                self.buffer.drain(..pos + len);
+                self.state = PoeThinkingState::Thinking;
+            }
+            None => {
+                let hold = POE_THINKING_MARKERS
+                    .iter()
+                    .map(|marker| partial_match_start(&self.buffer, marker))
+                    .min()
+                    .unwrap_or(self.buffer.len());
+                let rest = self.buffer.split_off(hold);
+                output
+                    .content
+                    .push_str(&std::mem::replace(&mut self.buffer, rest));
+            }
+        }
+    }
+
+    // 逐行取出引用行作為思考內容，遇到一般正文時結束思考
+    fn think(&mut self, output: &mut StageOutput) {
+        loop {
+            let line_end = self.buffer.find('\n');
+            let line = &self.buffer[..line_end.unwrap_or(self.buffer.len())];
+            let trimmed = line.trim();
+            if let Some(quoted) = trimmed.strip_prefix('>') {
+                // 未完整的引用行留待下一個片段
+                let Some(end) = line_end else {
+                    return;
+                };
+                output
+                    .reasoning
+                    .push_str(quoted.strip_prefix(' ').unwrap_or(quoted));
+                output.reasoning.push('\n');
+                self.buffer.drain(..=end);
+            } else if trimmed.is_empty() {
+                let Some(end) = line_end else {
+                    return;
+                };
+                self.buffer.drain(..=end);
+            } else {
+                debug!("🧠 思考模式結束");
+                self.state = PoeThinkingState::Ended;
+                let line_start = self.buffer.len() - self.buffer.trim_start().len();
+                output.content.push_str(&self.buffer[line_start..]);
+                self.buffer.clear();
+                return;
+            }
+        }
+    }
+}
+
+impl Stage for PoeThinkingStage {
+    fn process(&mut self, input: StageOutput) -> StageOutput {
+        if self.state == PoeThinkingState::Ended {
+            return input;
+        }
+        let mut output = StageOutput {
+            content: String::new(),
+            ..input
+        };
+        self.buffer.push_str(&input.content);
+        if self.state == PoeThinkingState::Detecting {
+            self.detect(&mut output);
+        }
+        if self.state == PoeThinkingState::Thinking {
+            self.think(&mut output);
+        }
+        output
+    }
+
+    fn finish(&mut self) -> StageOutput {
+        let rest = std::mem::take(&mut self.buffer);
+        let mut output = StageOutput::default();
+        match rest.trim().strip_prefix('>') {
+            Some(quoted) if self.state == PoeThinkingState::Thinking => {
+                output.reasoning = quoted.strip_prefix(' ').unwrap_or(quoted).to_string();
+            }
+            _ => output.content = rest,
+        }
+        output
+    }
+}
+
+const THINK_OPEN: &str = "<think>";
+const THINK_CLOSE: &str = "</think>";
+
+/// 將 <think> 標籤內的內容移至思考內容
+#[derive(Default)]
+struct ThinkTagStage {
+    buffer: String,
+    in_think: bool,
+    // 思考結束後略過正文開頭的換行
+    skip_newlines: bool,
+}
+
+impl ThinkTagStage {
+    fn push_content(&mut self, output: &mut StageOutput, text: &str) {
+        let text = if self.skip_newlines {
+            let trimmed = text.trim_start_matches(['\r', '\n']);
+            self.skip_newlines = trimmed.is_empty();
+            trimmed
+        } else {
+            text
+        };
+        output.content.push_str(text);
+    }
+}
+
+impl Stage for ThinkTagStage {
+    fn process(&mut self, input: StageOutput) -> StageOutput {
+        let mut output = StageOutput {
+            content: String::new(),
+            ..input
+        };
+        self.buffer.push_str(&input.content);
+        loop {
+            let tag = if self.in_think {
+                THINK_CLOSE
+            } else {
+                THINK_OPEN
+            };
+            let (text, found) = match self.buffer.find(tag) {
+                Some(pos) => {
+                    let text = self.buffer[..pos].to_string();
+                    self.buffer.drain(..pos + tag.len());
+                    (text, true)
+                }
+                None => {
+                    let hold = partial_match_start(&self.buffer, tag);
+                    let rest = self.buffer.split_off(hold);
+                    (std::mem::replace(&mut self.buffer, rest), false)
+                }
+            };
+            if self.in_think {
+                output.reasoning.push_str(&text);
+            } else {
+                self.push_content(&mut output, &text);
+            }
+            if !found {
+                return output;
+            }
+            debug!(
+                "🧠 {}",
+                if self.in_think {
+                    "思考標籤結束"
+                } else {
+                    "思考標籤開始"
+                }
+            );
+            self.in_think = !self.in_think;
+            self.skip_newlines = !self.in_think;
+        }
+    }
+
+    fn finish(&mut self) -> StageOutput {
+        let text = std::mem::take(&mut self.buffer);
+        let mut output = StageOutput::default();
+        if self.in_think {
+            output.reasoning = text;
+        } else {
+            self.push_content(&mut output, &text);
+        }
+        output
+    }
+}
+
+/// 在本地套用停止序列，命中後捨棄其後的正文
+struct StopSequenceStage {
+    stops: Vec<String>,
+    buffer: String,
+    stopped: bool,
+}
+
+impl Stage for StopSequenceStage {
+    fn process(&mut self, input: StageOutput) -> StageOutput {
+        let mut output = StageOutput {
+            content: String::new(),
+            ..input
+        };
+        if self.stopped {
+            return output;
+        }
+        self.buffer.push_str(&input.content);
+        let hit = self
+            .stops
+            .iter()
+            .filter_map(|stop| self.buffer.find(stop.as_str()))
+            .min();
+        if let Some(pos) = hit {
+            debug!("🛑 命中停止序列，捨棄其後的正文");
+            self.buffer.truncate(pos);
+            self.stopped = true;
+            output.content = std::mem::take(&mut self.buffer);
+            return output;
+        }
+        let hold = self
+            .stops
+            .iter()
+            .map(|stop| partial_match_start(&self.buffer, stop))
+            .min()
+            .unwrap_or(self.buffer.len());
+        let rest = self.buffer.split_off(hold);
+        output.content = std::mem::replace(&mut self.buffer, rest);
+        output
+    }
+
+    fn finish(&mut self) -> StageOutput {
+        StageOutput::content(&std::mem::take(&mut self.buffer))
+    }
+}
+
+/// 只套用停止序列，供不經過 STREAM_STAGES 的文字補全在本地截斷
+pub struct StopSequences(StopSequenceStage);
+
+impl StopSequences {
+    pub fn new(stop: &[String]) -> Self {
+        Self(StopSequenceStage {
+            stops: stop.iter().filter(|s| !s.is_empty()).cloned().collect(),
+            buffer: String::new(),
+            stopped: false,
+        })
+    }
+
+    /// 處理一段正文，返回可輸出的部分
+    pub fn process(&mut self, text: &str) -> String {
+        self.0.process(StageOutput::content(text)).content
+    }
+
+    /// 取出暫存的結尾
+    pub fn finish(&mut self) -> String {
+        self.0.finish().content
+    }
+
+    /// 是否已命中停止序列
+    pub fn stopped(&self) -> bool {
+        self.0.stopped
+    }
+}
+
+static CITATION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[\[(\d+)\]\]\(").expect("invalid citation regex"));
+// 結尾可能是尚未完整的引用：[、[[、[[1、[[1]、[[1]]
+static PARTIAL_CITATION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[(\[\d*(\]\]?)?)?$").expect("invalid citation regex"));
+
+/// 將 [[1]](url) 形式的引用改寫為一般的 Markdown 連結 [1](url)
+#[derive(Default)]
+struct CitationStage {
+    buffer: String,
+}
+
+impl Stage for CitationStage {
+    fn process(&mut self, input: StageOutput) -> StageOutput {
+        self.buffer.push_str(&input.content);
+        let mut text = CITATION_RE.replace_all(&self.buffer, "[$1](").into_owned();
+        self.buffer = match PARTIAL_CITATION_RE.find(&text) {
+            Some(partial) => text.split_off(partial.start()),
+            None => String::new(),
+        };
+        StageOutput {
+            content: text,
+            ..input
+        }
+    }
+
+    fn finish(&mut self) -> StageOutput {
+        StageOutput::content(&std::mem::take(&mut self.buffer))
+    }
+}
+
+static ANNOTATION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r" ?\[\[(\d+)\]\]\(([^)\s]+)\)").expect("invalid annotation regex")
+});
+// 結尾可能是尚未完整的引用（含其前的空白）：[、[[1、[[1]]、[[1]](https://...
+static PARTIAL_ANNOTATION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r" ?(\[(\[\d*(\](\](\([^)\s]*)?)?)?)?)?$").expect("invalid annotation regex")
+});
+
+/// 將 [[1]](url) 形式的引用移出正文，改為 url_citation 註解
+#[derive(Default)]
+struct AnnotationStage {
+    buffer: String,
+    // 已輸出的正文字元數
+    emitted: usize,
+    // 目前句子的起點
+    sentence_start: usize,
+    // 上一個完整句子的範圍，引用緊接在句尾標點之後時使用
+    last_sentence: Option<(usize, usize)>,
+}
+
+impl AnnotationStage {
+    fn emit(&mut self, output: &mut StageOutput, text: &str) {
+        for c in text.chars() {
+            self.emitted += 1;
+            if matches!(c, '.' | '!' | '?' | '。' | '！' | '？' | '\n') {
+                let end = if c == '\n' {
+                    self.emitted - 1
+                } else {
+                    self.emitted
+                };
+                if end > self.sentence_start {
+                    self.last_sentence = Some((self.sentence_start, end));
+                }
+                self.sentence_start = self.emitted;
+            } else if c.is_whitespace() && self.sentence_start == self.emitted - 1 {
+                // 句子起點略過開頭的空白
+                self.sentence_start = self.emitted;
+            }
+        }
+        output.content.push_str(text);
+    }
+
+    // 引用所標註的正文範圍
+    fn cited_span(&self) -> (usize, usize) {
+        if self.emitted > self.sentence_start {
+            (self.sentence_start, self.emitted)
+        } else {
+            self.last_sentence.unwrap_or((self.emitted, self.emitted))
+        }
+    }
+}
+
+impl Stage for AnnotationStage {
+    fn process(&mut self, input: StageOutput) -> StageOutput {
+        let mut output = StageOutput {
+            content: String::new(),
+            ..input
+        };
+        self.buffer.push_str(&input.content);
+        let buffer = std::mem::take(&mut self.buffer);
+        let hold = PARTIAL_ANNOTATION_RE
+            .find(&buffer)
+            .map_or(buffer.len(), |partial| partial.start());
+        let (ready, rest) = buffer.split_at(hold);
+        let mut last = 0;
+        for caps in ANNOTATION_RE.captures_iter(ready) {
+            let marker = caps.get(0).expect("capture group 0 always exists");
+            self.emit(&mut output, &ready[last..marker.start()]);
+            let (start, end) = self.cited_span();
+            debug!("📎 引用 [{}] 轉換為註解: {}", &caps[1], &caps[2]);
+            output
+                .annotations
+                .push(Annotation::url_citation(start, end, &caps[2]));
+            last = marker.end();
+        }
+        self.emit(&mut output, &ready[last..]);
+        self.buffer = rest.to_string();
+        output
+    }
+
+    fn finish(&mut self) -> StageOutput {
+        let mut output = StageOutput::default();
+        let rest = std::mem::take(&mut self.buffer);
+        self.emit(&mut output, &rest);
+        output
+    }
+}
+
+// 腳註標記：[[1]](url)（第 1 組）、[1]、[^1] 及 [1](url)，含其前的空白
+static FOOTNOTE_MARKER_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r" ?(\[\[\d+\]\]\([^)\s]*\))| ?\[\^?\d+\](?:\([^)\s]*\))?")
+        .expect("invalid footnote regex")
+});
+// 結尾可能是尚未完整的腳註標記
+static PARTIAL_FOOTNOTE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r" ?(\[(\[?\^?\d*(\]\]?(\([^)\s]*)?)?)?)?$").expect("invalid footnote regex")
+});
+// 腳註定義：[1]: url
+static FOOTNOTE_DEFINITION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*\[\^?\d+\]:").expect("invalid footnote regex"));
+// 來源列表中的項目
+static SOURCE_ITEM_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:[-*+]\s|\d+[.)]\s|\[\^?\d+\])").expect("invalid footnote regex")
+});
+// 來源列表的標題，如 Sources:、**參考資料**
+static SOURCE_HEADING_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?i)^\s*(?:#+\s*)?(?:\*\*)?(?:{})(?:\*\*)?\s*[:：]?\s*(?:\*\*)?\s*$",
+        SOURCE_HEADINGS.join("|")
+    ))
+    .expect("invalid footnote regex")
+});
+const SOURCE_HEADINGS: &[&str] = &[
+    "sources",
+    "source",
+    "references",
+    "citations",
+    "來源",
+    "来源",
+    "資料來源",
+    "资料来源",
+    "參考資料",
+    "参考资料",
+    "參考來源",
+    "参考来源",
+];
+
+/// 尚未完整的一行是否可能是來源列表的開頭
+fn could_start_sources(line: &str) -> bool {
+    let line = line
+        .trim_start()
+        .trim_start_matches(['#', '*', ' '])
+        .to_lowercase();
+    line.is_empty()
+        || line.starts_with('[')
+        || SOURCE_HEADINGS
+            .iter()
+            .any(|heading| heading.starts_with(&line) || line.starts_with(heading))
+}
+
+/// 移除腳註標記與結尾的來源列表 (strip_footnotes)
+#[derive(Default)]
+struct FootnoteStage {
+    // annotations 階段啟用時保留 [[1]](url)，交由該階段轉為註解
+    keep_linked: bool,
+    buffer: String,
+    // 目前這一行已確定是一般正文
+    line_is_text: bool,
+    // 可能是結尾來源列表的內容，之後出現一般正文時才輸出
+    sources: Option<String>,
+}
+
+impl FootnoteStage {
+    fn strip_markers(&self, text: &str) -> String {
+        FOOTNOTE_MARKER_RE
+            .replace_all(text, |caps: &Captures| {
+                if self.keep_linked && caps.get(1).is_some() {
+                    caps[0].to_string()
+                } else {
+                    String::new()
+                }
+            })
+            .into_owned()
+    }
+
+    // 處理完整的一行
+    fn push_line(&mut self, output: &mut String, line: &str) {
+        if let Some(sources) = &mut self.sources {
+            if line.trim().is_empty() || SOURCE_ITEM_RE.is_match(line) {
+                sources.push_str(line);
+                return;
+            }
+            // 來源列表之後還有一般正文，照常輸出
+            let sources = std::mem::take(sources);
+            self.sources = None;
+            output.push_str(&self.strip_markers(&sources));
+        }
+        if !self.line_is_text
+            && (SOURCE_HEADING_RE.is_match(line) || FOOTNOTE_DEFINITION_RE.is_match(line))
+        {
+            debug!("📝 可能是來源列表，暫存至確定是否位於結尾");
+            self.sources = Some(line.to_string());
+            return;
+        }
+        output.push_str(&self.strip_markers(line));
+    }
+}
+
+impl Stage for FootnoteStage {
+    fn process(&mut self, input: StageOutput) -> StageOutput {
+        let mut output = StageOutput {
+            content: String::new(),
+            ..input
+        };
+        self.buffer.push_str(&input.content);
+        while let Some(pos) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=pos).collect();
+            self.push_line(&mut output.content, &line);
+            self.line_is_text = false;
+        }
+        // 未完整的一行確定是一般正文時先輸出，只保留可能未完整的標記
+        if self.sources.is_none() && (self.line_is_text || !could_start_sources(&self.buffer)) {
+            self.line_is_text = true;
+            let hold = PARTIAL_FOOTNOTE_RE
+                .find(&self.buffer)
+                .map_or(self.buffer.len(), |partial| partial.start());
+            let rest = self.buffer.split_off(hold);
+            let text = std::mem::replace(&mut self.buffer, rest);
+            output.content.push_str(&self.strip_markers(&text));
+        }
+        output
+    }
+
+    fn finish(&mut self) -> StageOutput {
+        let mut output = String::new();
+        let rest = std::mem::take(&mut self.buffer);
+        if !rest.is_empty() {
+            self.push_line(&mut output, &rest);
+        }
+        if self.sources.take().is_some() {
+            debug!("📝 移除結尾的來源列表");
+        }
+        StageOutput::content(&output)
+    }
+}
+
+/// 依序執行的處理階段
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    /// 依 STREAM_STAGES 建立管線，stop 為請求的停止序列，strip_footnotes 為模型的腳註設定
+    pub fn new(stop: &[String], strip_footnotes: bool) -> Self {
+        Self::from_stages(get_stream_stages(), stop, strip_footnotes)
+    }
+
+    fn from_stages(kinds: &[StageKind], stop: &[String], strip_footnotes: bool) -> Self {
+        let stops: Vec<String> = stop.iter().filter(|s| !s.is_empty()).cloned().collect();
+        let footnotes = strip_footnotes.then(|| {
+            Box::new(FootnoteStage {
+                keep_linked: kinds.contains(&StageKind::Annotations),
+                ..Default::default()
+            }) as Box<dyn Stage>
+        });
+        let stages = std::iter::once(Box::new(PoeThinkingStage::default()) as Box<dyn Stage>)
+            .chain(footnotes)
+            .chain(kinds.iter().filter_map(|kind| -> Option<Box<dyn Stage>> {
+                match kind {
+                    StageKind::ThinkTags => Some(Box::new(ThinkTagStage::default())),
+                    // 請求未指定 stop 時略過
+                    StageKind::StopSequences => (!stops.is_empty()).then(|| {
+                        Box::new(StopSequenceStage {
+                            stops: stops.clone(),
+                            buffer: String::new(),
+                            stopped: false,
+                        }) as Box<dyn Stage>
+                    }),
+                    StageKind::Citations => Some(Box::new(CitationStage::default())),
+                    StageKind::Annotations => Some(Box::new(AnnotationStage::default())),
+                }
+            }))
+            .collect();
+        Self { stages }
+    }
+
+    /// 處理一段正文
+    pub fn process(&mut self, content: &str) -> StageOutput {
+        self.stages
+            .iter_mut()
+            .fold(StageOutput::content(content), |output, stage| {
+                stage.process(output)
+            })
+    }
+
+    /// 處理最後一段正文並取出各階段暫存的內容
+    /// 前面階段取出的內容仍需經過後面的階段
+    pub fn finish(&mut self, content: &str) -> StageOutput {
+        self.stages
+            .iter_mut()
+            .fold(StageOutput::content(content), |output, stage| {
+                let mut output = stage.process(output);
+                output.append(stage.finish());
+                output
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(kinds: &[StageKind], stop: &[&str], strip_footnotes: bool) -> Pipeline {
+        let stop: Vec<String> = stop.iter().map(|s| s.to_string()).collect();
+        Pipeline::from_stages(kinds, &stop, strip_footnotes)
+    }
+
+    // 逐片段處理後再呼叫 finish，返回每個片段的輸出與合併後的結果
+    fn run(pipeline: &mut Pipeline, chunks: &[&str]) -> (Vec<StageOutput>, StageOutput) {
+        let mut outputs: Vec<StageOutput> = chunks.iter().map(|c| pipeline.process(c)).collect();
+        outputs.push(pipeline.finish(""));
+        let mut total = StageOutput::default();
+        for output in &outputs {
+            total.append(output.clone());
+        }
+        (outputs, total)
+    }
+
+    #[test]
+    fn partial_match_start_holds_pattern_prefix() {
+        assert_eq!(partial_match_start("abc<thi", THINK_OPEN), 3);
+        assert_eq!(partial_match_start("abc<", THINK_OPEN), 3);
+        assert_eq!(partial_match_start("abc", THINK_OPEN), 3);
+        assert_eq!(partial_match_start("", THINK_OPEN), 0);
+        // 完整的 pattern 不在此處理
+        assert_eq!(partial_match_start("abc<think>", THINK_OPEN), 10);
+        // 多位元組字元只在字元邊界切開
+        assert_eq!(partial_match_start("你好停", "停止"), "你好".len());
+        assert_eq!(partial_match_start("你好", "停止"), "你好".len());
+    }
+
+    #[test]
+    fn poe_thinking_split_across_chunks() {
+        let mut pipeline = pipeline(&[], &[], false);
+        let (outputs, total) = run(
+            &mut pipeline,
+            &[
+                "Intro *Think",
+                "ing...*\n\n> first ",
+                "line\n>\n> second line\n",
+                "\nAnswer > not reasoning\n> quoted",
+            ],
+        );
+        assert_eq!(outputs[0].content, "Intro ");
+        assert_eq!(outputs[1].reasoning, "");
+        assert_eq!(outputs[2].reasoning, "first line\n\nsecond line\n");
+        assert_eq!(outputs[3].content, "Answer > not reasoning\n> quoted");
+        assert_eq!(total.content, "Intro Answer > not reasoning\n> quoted");
+        assert_eq!(total.reasoning, "first line\n\nsecond line\n");
+    }
+
+    #[test]
+    fn poe_thinking_flushes_unfinished_block() {
+        let mut unfinished = pipeline(&[StageKind::ThinkTags], &[], false);
+        let (_, total) = run(&mut unfinished, &["Thinking...\n> only reasoning"]);
+        assert_eq!(total.content, "");
+        assert_eq!(total.reasoning, "only reasoning");
+
+        // 沒有思考標記時，暫存的部分標記在結束時輸出
+        let mut pipeline = pipeline(&[], &[], false);
+        let (outputs, total) = run(&mut pipeline, &["Plain *Th", "at*"]);
+        assert_eq!(outputs[0].content, "Plain ");
+        assert_eq!(total.content, "Plain *That*");
+        assert_eq!(total.reasoning, "");
+    }
+
+    #[test]
+    fn think_tags_split_across_chunks() {
+        let mut pipeline = pipeline(&[StageKind::ThinkTags], &[], false);
+        let (outputs, total) = run(
+            &mut pipeline,
+            &["Hi <thi", "nk>reason", "ing</th", "ink>\n\nAnswer"],
+        );
+        assert_eq!(outputs[0].content, "Hi ");
+        assert_eq!(outputs[1].reasoning, "reason");
+        assert_eq!(outputs[2].reasoning, "ing");
+        assert_eq!(outputs[3].content, "Answer");
+        assert_eq!(total.content, "Hi Answer");
+        assert_eq!(total.reasoning, "reasoning");
+    }
+
+    #[test]
+    fn stop_sequence_completed_in_next_chunk() {
+        let mut pipeline = pipeline(&[StageKind::StopSequences], &["STOP"], false);
+        let (outputs, total) = run(&mut pipeline, &["abc ST", "OP tail", "more"]);
+        assert_eq!(outputs[0].content, "abc ");
+        assert_eq!(outputs[1].content, "");
+        assert_eq!(outputs[2].content, "");
+        assert_eq!(total.content, "abc ");
+    }
+
+    #[test]
+    fn stop_sequence_partial_match_fails_in_next_chunk() {
+        let mut pipeline = pipeline(&[StageKind::StopSequences], &["STOP"], false);
+        let (outputs, total) = run(&mut pipeline, &["abc ST", "ART ok"]);
+        assert_eq!(outputs[0].content, "abc ");
+        assert_eq!(outputs[1].content, "START ok");
+        assert_eq!(total.content, "abc START ok");
+    }
+
+    #[test]
+    fn stop_sequences_wrapper_reports_stopped() {
+        let mut stops = StopSequences::new(&["\n\n".to_string()]);
+        assert_eq!(stops.process("line\n"), "line");
+        assert!(!stops.stopped());
+        assert_eq!(stops.process("\nnext"), "");
+        assert!(stops.stopped());
+        assert_eq!(stops.finish(), "");
+    }
+
+    #[test]
+    fn citations_split_across_chunks() {
+        let mut pipeline = pipeline(&[StageKind::Citations], &[], false);
+        let (outputs, total) = run(&mut pipeline, &["see [[1", "]](https://a.com) ok"]);
+        assert_eq!(outputs[0].content, "see ");
+        assert_eq!(total.content, "see [1](https://a.com) ok");
+    }
+
+    #[test]
+    fn annotations_use_char_offsets_for_multibyte_text() {
+        let mut pipeline = pipeline(&[StageKind::Annotations], &[], false);
+        let (_, total) = run(
+            &mut pipeline,
+            &[
+                "你好世界。 [[1",
+                "]](https://a.com/x) 第二句",
+                " [[2]](https://b.org)",
+            ],
+        );
+        assert_eq!(total.content, "你好世界。 第二句");
+        assert_eq!(total.annotations.len(), 2);
+
+        let first = &total.annotations[0].url_citation;
+        assert_eq!((first.start_index, first.end_index), (0, 5));
+        assert_eq!(first.url, "https://a.com/x");
+        let cited: String = total
+            .content
+            .chars()
+            .skip(first.start_index)
+            .take(first.end_index - first.start_index)
+            .collect();
+        assert_eq!(cited, "你好世界。");
+
+        let second = &total.annotations[1].url_citation;
+        assert_eq!((second.start_index, second.end_index), (6, 9));
+        assert_eq!(second.title, "b.org");
+        let cited: String = total
+            .content
+            .chars()
+            .skip(second.start_index)
+            .take(second.end_index - second.start_index)
+            .collect();
+        assert_eq!(cited, "第二句");
+    }
+
+    #[test]
+    fn footnote_sources_at_eof_with_trailing_newline() {
+        let mut pipeline = pipeline(&[], &[], true);
+        let (_, total) = run(
+            &mut pipeline,
+            &["答案[1]在此。\n\nSour", "ces:\n[1]: https://a.com\n"],
+        );
+        assert_eq!(total.content, "答案在此。\n\n");
+    }
+
+    #[test]
+    fn footnote_sources_at_eof_without_trailing_newline() {
+        let mut pipeline = pipeline(&[], &[], true);
+        let (_, total) = run(
+            &mut pipeline,
+            &["答案[1]在此。\n\n**來源**\n- [1", "] https://a.com"],
+        );
+        assert_eq!(total.content, "答案在此。\n\n");
+    }
+
+    #[test]
+    fn footnote_sources_followed_by_text_are_kept() {
+        let mut pipeline = pipeline(&[], &[], true);
+        let (_, total) = run(&mut pipeline, &["Sources:\n", "- a\n", "more text"]);
+        assert_eq!(total.content, "Sources:\n- a\nmore text");
+    }
+
+    #[test]
+    fn finish_flushes_held_back_text() {
+        let mut think = pipeline(&[StageKind::ThinkTags], &[], false);
+        assert_eq!(think.process("abc <thi").content, "abc ");
+        assert_eq!(think.finish("").content, "<thi");
+
+        let mut stop = pipeline(&[StageKind::StopSequences], &["STOP"], false);
+        assert_eq!(stop.process("abc ST").content, "abc ");
+        assert_eq!(stop.finish("").content, "ST");
+
+        let mut citations = pipeline(&[StageKind::Citations], &[], false);
+        assert_eq!(citations.process("see [[1").content, "see ");
+        assert_eq!(citations.finish("]").content, "[[1]");
+
+        // 前面階段取出的內容仍經過後面的階段
+        let mut chained = pipeline(
+            &[StageKind::ThinkTags, StageKind::StopSequences],
+            &["<thi"],
+            false,
+        );
+        assert_eq!(chained.process("abc <th").content, "abc ");
+        assert_eq!(chained.finish("").content, "<th");
+    }
+
+    #[test]
+    fn finish_flushes_open_think_block_as_reasoning() {
+        let mut pipeline = pipeline(&[StageKind::ThinkTags], &[], false);
+        assert_eq!(pipeline.process("<think>still").reasoning, "still");
+        let output = pipeline.finish(" going</th");
+        assert_eq!(output.reasoning, " going</th");
+        assert_eq!(output.content, "");
+    }
+}
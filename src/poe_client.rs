@@ -1,28 +1,39 @@
+use crate::handlers::report_points_exhausted;
 use crate::{
     cache::get_cached_config,
+    mock::get_mock_config,
     types::*,
     utils::{
         extract_tool_call_id, filename_from_url, filter_tools_for_poe,
-        get_text_from_openai_content, infer_mime_from_url,
+        get_text_from_openai_content, infer_mime_from_url, is_insufficient_points,
     },
 };
-use futures_util::Stream;
-use poe_api_process::types::Attachment;
-use poe_api_process::{ChatMessage, ChatRequest, ChatResponse, PoeClient, PoeError};
+use futures_util::{Stream, StreamExt, stream};
+use poe_api_process::types::{
+    Attachment, ChatToolCall, ChatToolResult, FileUploadRequest, FileUploadResponse,
+};
+use poe_api_process::{
+    ChatEventType, ChatMessage, ChatRequest, ChatResponse, ChatResponseData, ModelResponse,
+    PoeClient, PoeError,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::time::Instant;
-use tracing::{debug, error, info};
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
 
+#[derive(Clone)]
 pub struct PoeClientWrapper {
     pub client: PoeClient, // 修改為公開，以便外部訪問
-    _model: String,
+    model: String,
+    access_key: String,
 }
 
 impl PoeClientWrapper {
+    /// 建立 POE 客戶端，聊天與上傳請求經由共享的 HTTP 客戶端發送，共用連接池與連接設定
     pub fn new(model: &str, access_key: &str) -> Self {
-        info!("🔑 初始化 POE 客戶端 | 模型: {}", model);
-
         // 從環境變數獲取 POE API 配置，使用預設值
         let poe_base_url =
             std::env::var("POE_BASE_URL").unwrap_or_else(|_| "https://api.poe.com".to_string());
@@ -31,13 +42,22 @@ impl PoeClientWrapper {
         });
 
         debug!(
-            "🔧 POE 配置 | Base URL: {} | Upload URL: {}",
-            poe_base_url, poe_file_upload_url
+            "🔑 初始化 POE 客戶端 | 模型: {} | Base URL: {} | Upload URL: {}",
+            model, poe_base_url, poe_file_upload_url
+        );
+
+        let client = PoeClient::with_client(
+            SHARED_HTTP_CLIENT.clone(),
+            model,
+            access_key,
+            &poe_base_url,
+            &poe_file_upload_url,
         );
 
         Self {
-            client: PoeClient::new(model, access_key, &poe_base_url, &poe_file_upload_url),
-            _model: model.to_string(),
+            client,
+            model: model.to_string(),
+            access_key: access_key.to_string(),
         }
     }
 
@@ -45,6 +65,9 @@ impl PoeClientWrapper {
     pub async fn get_v1_model_list(
         &self,
     ) -> Result<poe_api_process::ModelResponse, poe_api_process::PoeError> {
+        if let Some(mock) = get_mock_config() {
+            return Ok(mock.model_list());
+        }
         let start_time = std::time::Instant::now();
         debug!("📋 發送 v1/models API 請求");
 
@@ -54,17 +77,25 @@ impl PoeClientWrapper {
             Ok(model_response) => {
                 let duration = start_time.elapsed();
                 info!(
-                    "✅ v1/models API 請求成功 | 模型數量: {} | 耗時: {}",
-                    model_response.data.len(),
-                    crate::utils::format_duration(duration)
+                    "{}",
+                    tr!(
+                        "✅ v1/models API 請求成功 | 模型數量: {} | 耗時: {}",
+                        "✅ v1/models API request succeeded | models: {} | duration: {}",
+                        model_response.data.len(),
+                        crate::utils::format_duration(duration)
+                    )
                 );
             }
             Err(e) => {
                 let duration = start_time.elapsed();
                 error!(
-                    "❌ v1/models API 請求失敗 | 錯誤: {} | 耗時: {}",
-                    e,
-                    crate::utils::format_duration(duration)
+                    "{}",
+                    tr!(
+                        "❌ v1/models API 請求失敗 | 錯誤: {} | 耗時: {}",
+                        "❌ v1/models API request failed | error: {} | duration: {}",
+                        e,
+                        crate::utils::format_duration(duration)
+                    )
                 );
             }
         }
@@ -82,25 +113,567 @@ impl PoeClientWrapper {
             chat_request.query.len(),
             chat_request.temperature
         );
+        if let Some(mock) = get_mock_config() {
+            let stream = mock.stream_request(&self.model, &chat_request);
+            return Ok(self.watch_points_exhausted(stream));
+        }
         let result = self.client.stream_request(chat_request).await;
         match &result {
             Ok(_) => {
                 let duration = start_time.elapsed();
                 info!(
-                    "✅ 串流請求建立成功 | 耗時: {}",
-                    crate::utils::format_duration(duration)
+                    "{}",
+                    tr!(
+                        "✅ 串流請求建立成功 | 耗時: {}",
+                        "✅ Stream request established | duration: {}",
+                        crate::utils::format_duration(duration)
+                    )
                 );
             }
             Err(e) => {
                 let duration = start_time.elapsed();
                 error!(
-                    "❌ 串流請求失敗 | 錯誤: {} | 耗時: {}",
-                    e,
-                    crate::utils::format_duration(duration)
+                    "{}",
+                    tr!(
+                        "❌ 串流請求失敗 | 錯誤: {} | 耗時: {}",
+                        "❌ Stream request failed | error: {} | duration: {}",
+                        e,
+                        crate::utils::format_duration(duration)
+                    )
                 );
+                if is_insufficient_points(&e.to_string()) {
+                    report_points_exhausted(&self.access_key, &self.model);
+                }
             }
         }
-        result
+        result.map(|stream| self.watch_points_exhausted(stream))
+    }
+
+    /// 上游以錯誤事件回報點數不足時觸發告警
+    fn watch_points_exhausted(&self, stream: EventStream) -> EventStream {
+        let access_key = self.access_key.clone();
+        let model = self.model.clone();
+        Box::pin(stream.inspect(move |item| {
+            if let Ok(ChatResponse {
+                data: Some(ChatResponseData::Error { text, .. }),
+                ..
+            }) = item
+                && is_insufficient_points(text)
+            {
+                report_points_exhausted(&access_key, &model);
+            }
+        }))
+    }
+
+    /// 發送串流請求，上游錯誤事件標示 allow_retry 時自動重新發送 (POE_RETRY_ATTEMPTS)
+    /// hold_until_done 為 true（非串流）時暫存事件直到完成，正文開始後出錯仍可重試；
+    /// 否則只在尚未輸出任何內容前重試，避免客戶端收到重複的正文
+    pub async fn stream_request_with_retry(
+        &self,
+        chat_request: ChatRequest,
+        hold_until_done: bool,
+    ) -> Result<EventStream, PoeError> {
+        if RETRY_CONFIG.attempts == 0 {
+            return self.stream_request(chat_request).await;
+        }
+        let stream = self.stream_request(chat_request.clone()).await?;
+        let retrying = RetryingStream {
+            client: self.clone(),
+            request: chat_request,
+            stream,
+            attempt: 0,
+            committed: false,
+            finished: false,
+            hold_until_done,
+            held: Vec::new(),
+            pending: VecDeque::new(),
+        };
+        Ok(Box::pin(stream::unfold(
+            retrying,
+            |mut retrying| async move { retrying.next().await.map(|item| (item, retrying)) },
+        )))
+    }
+
+    /// 批次上傳檔案到 Poe
+    pub async fn upload_files_batch(
+        &self,
+        files: Vec<FileUploadRequest>,
+    ) -> Result<Vec<FileUploadResponse>, PoeError> {
+        if let Some(mock) = get_mock_config() {
+            return Ok(mock.upload_files(&files));
+        }
+        self.client.upload_files_batch(files).await
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>;
+
+/// 可重試錯誤的重試設定
+struct RetryConfig {
+    attempts: u32,
+    delay: Duration,
+}
+
+static RETRY_CONFIG: LazyLock<RetryConfig> = LazyLock::new(|| {
+    let attempts = std::env::var("POE_RETRY_ATTEMPTS")
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(2);
+    let delay = std::env::var("POE_RETRY_DELAY_MS")
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(500);
+    if attempts > 0 {
+        info!(
+            "{}",
+            tr!(
+                "🔁 可重試的上游錯誤最多重試 {} 次，初始間隔 {}ms",
+                "🔁 Retryable upstream errors are retried up to {} times, starting at {}ms",
+                attempts,
+                delay
+            )
+        );
+    }
+    RetryConfig {
+        attempts,
+        delay: Duration::from_millis(delay),
+    }
+});
+
+/// 遇到標示 allow_retry 的錯誤事件時重新發送請求的事件流
+struct RetryingStream {
+    client: PoeClientWrapper,
+    request: ChatRequest,
+    stream: EventStream,
+    attempt: u32,
+    // 已有事件交給下游，之後不再重試
+    committed: bool,
+    finished: bool,
+    hold_until_done: bool,
+    // 暫存中的事件 (hold_until_done)
+    held: Vec<ChatResponse>,
+    // 待交給下游的事件
+    pending: VecDeque<Result<ChatResponse, PoeError>>,
+}
+
+impl RetryingStream {
+    async fn next(&mut self) -> Option<Result<ChatResponse, PoeError>> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+            if self.finished {
+                return None;
+            }
+            let event = match self.stream.next().await {
+                Some(Ok(event)) => event,
+                Some(Err(e)) => {
+                    self.release_held();
+                    self.pending.push_back(Err(e));
+                    continue;
+                }
+                None => {
+                    self.finished = true;
+                    self.release_held();
+                    continue;
+                }
+            };
+            if let Some(text) = self.retryable_error(&event) {
+                self.attempt += 1;
+                let delay = RETRY_CONFIG.delay * 2u32.saturating_pow(self.attempt - 1);
+                warn!(
+                    "{}",
+                    tr!(
+                        "🔁 上游返回可重試的錯誤，{}ms 後重試 ({}/{}) | 錯誤: {}",
+                        "🔁 Upstream returned a retryable error, retrying in {}ms ({}/{}) | error: {}",
+                        delay.as_millis(),
+                        self.attempt,
+                        RETRY_CONFIG.attempts,
+                        text
+                    )
+                );
+                self.held.clear();
+                tokio::time::sleep(delay).await;
+                match self.client.stream_request(self.request.clone()).await {
+                    Ok(stream) => self.stream = stream,
+                    Err(e) => {
+                        self.finished = true;
+                        self.pending.push_back(Err(e));
+                    }
+                }
+                continue;
+            }
+            let terminal = matches!(event.event, ChatEventType::Done | ChatEventType::Error);
+            if self.hold_until_done {
+                self.held.push(event);
+                if terminal {
+                    self.release_held();
+                }
+                continue;
+            }
+            if !terminal {
+                self.committed = true;
+            }
+            return Some(Ok(event));
+        }
+    }
+
+    // 尚可重試時返回錯誤訊息
+    fn retryable_error<'a>(&self, event: &'a ChatResponse) -> Option<&'a str> {
+        match &event.data {
+            // 點數不足時重試只會繼續失敗
+            Some(ChatResponseData::Error {
+                text,
+                allow_retry: true,
+            }) if !self.committed
+                && self.attempt < RETRY_CONFIG.attempts
+                && !is_insufficient_points(text) =>
+            {
+                Some(text)
+            }
+            _ => None,
+        }
+    }
+
+    fn release_held(&mut self) {
+        self.committed = true;
+        self.pending.extend(self.held.drain(..).map(Ok));
+    }
+}
+
+/// 共享的 HTTP 客戶端（聊天、檔案上傳、GraphQL、點數查詢、附件下載及告警 Webhook 使用）
+static SHARED_HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(build_shared_http_client);
+
+pub(crate) fn shared_http_client() -> &'static reqwest::Client {
+    &SHARED_HTTP_CLIENT
+}
+
+/// 讀取以秒為單位的環境變數
+fn env_secs(name: &str) -> Option<u64> {
+    std::env::var(name).ok().and_then(|s| s.trim().parse().ok())
+}
+
+/// 依上游連接設定建立 HTTP 客戶端
+/// - UPSTREAM_POOL_MAX_IDLE：每個主機保留的閒置連接數上限
+/// - UPSTREAM_POOL_IDLE_TIMEOUT：閒置連接保留秒數，0 表示不因閒置而關閉
+/// - UPSTREAM_TCP_KEEPALIVE：TCP keepalive 間隔秒數，0 表示停用
+/// - UPSTREAM_HTTP_VERSION：auto（默認，依 ALPN 協商）、http1 或 http2
+fn build_shared_http_client() -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36");
+    let mut settings = Vec::new();
+
+    if let Some(max_idle) = std::env::var("UPSTREAM_POOL_MAX_IDLE")
+        .ok()
+        .and_then(|s| s.trim().parse::<usize>().ok())
+    {
+        builder = builder.pool_max_idle_per_host(max_idle);
+        settings.push(format!("pool_max_idle={}", max_idle));
+    }
+    if let Some(secs) = env_secs("UPSTREAM_POOL_IDLE_TIMEOUT") {
+        builder = builder.pool_idle_timeout((secs > 0).then(|| Duration::from_secs(secs)));
+        settings.push(format!("pool_idle_timeout={}s", secs));
+    }
+    if let Some(secs) = env_secs("UPSTREAM_TCP_KEEPALIVE") {
+        builder = builder.tcp_keepalive((secs > 0).then(|| Duration::from_secs(secs)));
+        settings.push(format!("tcp_keepalive={}s", secs));
+    }
+    match std::env::var("UPSTREAM_HTTP_VERSION")
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+        .as_str()
+    {
+        "" | "auto" => {}
+        "http1" | "1.1" => {
+            builder = builder.http1_only();
+            settings.push("http1".to_string());
+        }
+        "http2" | "2" => {
+            builder = builder.http2_prior_knowledge();
+            settings.push("http2".to_string());
+        }
+        other => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無效的 UPSTREAM_HTTP_VERSION: {}，使用 auto",
+                    "⚠️ Invalid UPSTREAM_HTTP_VERSION: {}, using auto",
+                    other
+                )
+            );
+        }
+    }
+
+    if crate::dns::dns_cache_enabled() {
+        builder = builder.dns_resolver(Arc::new(crate::dns::CachingResolver));
+        settings.push("dns_cache".to_string());
+    }
+
+    if !settings.is_empty() {
+        info!(
+            "{}",
+            tr!(
+                "🌐 上游連接設定: {}",
+                "🌐 Upstream connection settings: {}",
+                settings.join(" | ")
+            )
+        );
+    }
+    builder.build().unwrap_or_else(|e| {
+        error!(
+            "{}",
+            tr!(
+                "❌ 建立 HTTP 客戶端失敗，使用預設設定: {}",
+                "❌ Failed to build HTTP client, using defaults: {}",
+                e
+            )
+        );
+        reqwest::Client::default()
+    })
+}
+
+/// 獲取傳統 GraphQL 模型列表
+/// 透過共享 HTTP 客戶端查詢，POE_GQL_URL 可覆寫預設端點
+pub async fn get_model_list(language_code: Option<&str>) -> Result<ModelResponse, PoeError> {
+    if let Some(mock) = get_mock_config() {
+        return Ok(mock.model_list());
+    }
+    let gql_url = std::env::var("POE_GQL_URL")
+        .ok()
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty());
+    if let Some(url) = &gql_url {
+        debug!("🔧 使用自訂 GraphQL 端點獲取模型列表: {}", url);
+    }
+    poe_api_process::get_model_list_with_client(
+        &SHARED_HTTP_CLIENT,
+        gql_url.as_deref(),
+        language_code,
+    )
+    .await
+}
+
+/// 查詢 Poe 帳戶剩餘點數 (GET {POE_BASE_URL}/usage/current_balance)
+pub async fn get_current_point_balance(access_key: &str) -> Result<i64, PoeError> {
+    if get_mock_config().is_some() {
+        return Ok(1_000_000);
+    }
+    let poe_base_url =
+        std::env::var("POE_BASE_URL").unwrap_or_else(|_| "https://api.poe.com".to_string());
+    let url = format!(
+        "{}/usage/current_balance",
+        poe_base_url.trim_end_matches('/')
+    );
+    let start_time = Instant::now();
+    debug!("💰 查詢 Poe 帳戶點數: {}", url);
+
+    let response = SHARED_HTTP_CLIENT
+        .get(&url)
+        .bearer_auth(access_key)
+        .send()
+        .await
+        .map_err(PoeError::RequestFailed)?;
+    let status = response.status();
+    if !status.is_success() {
+        let text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "無法讀取回應內容".to_string());
+        error!(
+            "{}",
+            tr!(
+                "❌ 查詢 Poe 帳戶點數失敗 | 狀態碼: {} | 耗時: {}",
+                "❌ Failed to query Poe point balance | status: {} | duration: {}",
+                status,
+                crate::utils::format_duration(start_time.elapsed())
+            )
+        );
+        return Err(PoeError::BotError(format!(
+            "API 回應錯誤 - 狀態碼: {}, 內容: {}",
+            status, text
+        )));
+    }
+
+    let data: serde_json::Value = response.json().await.map_err(PoeError::RequestFailed)?;
+    let balance = data["current_point_balance"]
+        .as_i64()
+        .ok_or_else(|| PoeError::BotError("回應中缺少 current_point_balance".to_string()))?;
+    debug!(
+        "✅ Poe 帳戶點數: {} | 耗時: {}",
+        balance,
+        crate::utils::format_duration(start_time.elapsed())
+    );
+    Ok(balance)
+}
+
+/// Poe 機器人的顯示名稱、描述及頭像網址
+#[derive(Clone, Default, Debug)]
+pub(crate) struct ModelMetadata {
+    pub(crate) name: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) icon: Option<String>,
+}
+
+/// 從 v1/models 的模型項目取出機器人資訊，欄位不存在時為 None
+fn parse_model_metadata(model: &serde_json::Value) -> ModelMetadata {
+    let text = |value: &serde_json::Value| {
+        value
+            .as_str()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+    };
+    let metadata = &model["metadata"];
+    ModelMetadata {
+        name: text(&metadata["display_name"]).or_else(|| text(&model["display_name"])),
+        description: text(&model["description"]).or_else(|| text(&metadata["description"])),
+        icon: text(&metadata["image"]["url"])
+            .or_else(|| text(&metadata["image"]))
+            .or_else(|| text(&model["image"]["url"])),
+    }
+}
+
+/// 取得 Poe 機器人的顯示名稱、描述及頭像 (GET {POE_BASE_URL}/v1/models)，以小寫模型名稱為鍵
+pub(crate) async fn get_model_metadata(
+    access_key: Option<&str>,
+) -> Result<HashMap<String, ModelMetadata>, PoeError> {
+    if let Some(mock) = get_mock_config() {
+        return Ok(mock.model_metadata());
+    }
+    let poe_base_url =
+        std::env::var("POE_BASE_URL").unwrap_or_else(|_| "https://api.poe.com".to_string());
+    let url = format!("{}/v1/models", poe_base_url.trim_end_matches('/'));
+    let start_time = Instant::now();
+    debug!("🪪 取得模型資訊: {}", url);
+
+    let mut request = SHARED_HTTP_CLIENT.get(&url);
+    if let Some(access_key) = access_key {
+        request = request.bearer_auth(access_key);
+    }
+    let response = request.send().await.map_err(PoeError::RequestFailed)?;
+    let status = response.status();
+    if !status.is_success() {
+        let text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "無法讀取回應內容".to_string());
+        return Err(PoeError::BotError(format!(
+            "API 回應錯誤 - 狀態碼: {}, 內容: {}",
+            status, text
+        )));
+    }
+
+    let data: serde_json::Value = response.json().await.map_err(PoeError::RequestFailed)?;
+    let models = data["data"]
+        .as_array()
+        .ok_or_else(|| PoeError::BotError("無法從回應中取得模型列表".to_string()))?;
+    let metadata: HashMap<String, ModelMetadata> = models
+        .iter()
+        .filter_map(|model| {
+            let id = model["id"].as_str()?;
+            Some((id.to_lowercase(), parse_model_metadata(model)))
+        })
+        .collect();
+    debug!(
+        "✅ 模型資訊取得完成 | 模型數量: {} | 耗時: {}",
+        metadata.len(),
+        crate::utils::format_duration(start_time.elapsed())
+    );
+    Ok(metadata)
+}
+
+/// 下載 Poe 返回的附件，返回內容及 Content-Type
+pub async fn download_attachment(url: &str) -> Result<(Vec<u8>, Option<String>), PoeError> {
+    let start_time = Instant::now();
+    let response = SHARED_HTTP_CLIENT
+        .get(url)
+        .send()
+        .await
+        .map_err(PoeError::RequestFailed)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(PoeError::BotError(format!(
+            "下載附件失敗 - 狀態碼: {}",
+            status
+        )));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    let bytes = response.bytes().await.map_err(PoeError::RequestFailed)?;
+    debug!(
+        "📥 下載附件完成 | 大小: {} | 耗時: {}",
+        crate::utils::format_bytes_length(bytes.len()),
+        crate::utils::format_duration(start_time.elapsed())
+    );
+    Ok((bytes.to_vec(), content_type))
+}
+
+/// 逐塊下載附件並寫入本地檔案，避免大型影片佔用記憶體
+/// 返回寫入的位元組數與上游 Content-Type
+pub async fn download_attachment_to_file(
+    url: &str,
+    path: &std::path::Path,
+) -> Result<(u64, Option<String>), PoeError> {
+    use tokio::io::AsyncWriteExt;
+
+    let start_time = Instant::now();
+    let mut response = SHARED_HTTP_CLIENT
+        .get(url)
+        .send()
+        .await
+        .map_err(PoeError::RequestFailed)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(PoeError::BotError(format!(
+            "下載附件失敗 - 狀態碼: {}",
+            status
+        )));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+
+    let result: Result<u64, PoeError> = async {
+        let file = tokio::fs::File::create(path)
+            .await
+            .map_err(|e| PoeError::BotError(format!("建立檔案失敗: {}", e)))?;
+        let mut writer = tokio::io::BufWriter::new(file);
+        let mut written: u64 = 0;
+        while let Some(chunk) = response.chunk().await.map_err(PoeError::RequestFailed)? {
+            writer
+                .write_all(&chunk)
+                .await
+                .map_err(|e| PoeError::BotError(format!("寫入檔案失敗: {}", e)))?;
+            written += chunk.len() as u64;
+        }
+        writer
+            .flush()
+            .await
+            .map_err(|e| PoeError::BotError(format!("寫入檔案失敗: {}", e)))?;
+        Ok(written)
+    }
+    .await;
+
+    match result {
+        Ok(written) => {
+            debug!(
+                "📥 附件已下載至本地 | 路徑: {} | 大小: {} | 耗時: {}",
+                path.display(),
+                crate::utils::format_bytes_length(written as usize),
+                crate::utils::format_duration(start_time.elapsed())
+            );
+            Ok((written, content_type))
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(path).await;
+            Err(e)
+        }
     }
 }
 
@@ -109,6 +682,8 @@ fn openai_message_to_poe(
     msg: &Message,
     role_override: Option<String>,
     chat_completion_request: Option<&ChatCompletionRequest>,
+    strip_sampling: bool,
+    tool_name: Option<&str>,
 ) -> ChatMessage {
     let mut attachments: Vec<Attachment> = vec![];
     let mut texts: Vec<String> = vec![];
@@ -152,29 +727,28 @@ fn openai_message_to_poe(
         );
         // 將 tool_calls 轉換為文本格式添加到內容中
         for tool_call in tool_calls {
-            let tool_call_text = format!(
-                "Tool Call: {} ({})\nArguments: {}",
-                tool_call.function.name, tool_call.id, tool_call.function.arguments
-            );
-            texts.push(tool_call_text);
+            texts.push(tool_call_text(tool_call));
         }
     }
 
-    // 處理 tool_call_id
+    // 處理 tool_call_id：標明結果對應的工具調用，讓機器人能與先前的 Tool Call 配對
     if let Some(tool_call_id) = &msg.tool_call_id {
         debug!("🔧 處理 tool 消息中的 tool_call_id: {}", tool_call_id);
-        // 將 tool_call_id 添加到內容開頭
-        let tool_id_text = format!("Tool Call ID: {}", tool_call_id);
+        let tool_id_text = match tool_name {
+            Some(name) => format!("Tool Result: {} ({})", name, tool_call_id),
+            None => format!("Tool Call ID: {}", tool_call_id),
+        };
         texts.insert(0, tool_id_text);
     }
 
     let mut content = texts.join("\n");
 
     // 如果是用戶消息且是最後一條消息，應用後綴處理
-    if msg.role == "user" {
-        if let Some(request) = chat_completion_request {
-            content = crate::utils::process_message_content_with_suffixes(&content, request);
-        }
+    if msg.role == "user"
+        && let Some(request) = chat_completion_request
+    {
+        content =
+            crate::utils::process_message_content_with_suffixes(&content, request, strip_sampling);
     }
 
     let role = role_override.unwrap_or_else(|| msg.role.clone());
@@ -191,15 +765,197 @@ fn openai_message_to_poe(
     }
 }
 
+/// 是否將請求對應到固定的 Poe user_id / conversation_id (POE_CONVERSATION_IDS)
+pub fn conversation_ids_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        let enabled = std::env::var("POE_CONVERSATION_IDS")
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+            .unwrap_or(false);
+        info!(
+            "{}",
+            tr!(
+                "💬 Poe 對話識別對應: {}",
+                "💬 Poe conversation id mapping: {}",
+                if enabled { "已啟用" } else { "已禁用" }
+            )
+        );
+        enabled
+    })
+}
+
+/// 以 `user` 或對話 ID 為請求設置固定的 Poe user_id / conversation_id
+/// Poe 協議為無狀態，每次仍需發送完整歷史；固定的識別讓機器人能將多輪請求關聯為同一對話
+pub fn apply_conversation_ids(
+    request: &mut ChatRequest,
+    access_key: &str,
+    user: Option<&str>,
+    conversation: Option<&str>,
+) {
+    let hash = |parts: &[&str]| {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part.as_bytes());
+            hasher.update([0u8]);
+        }
+        format!("{:x}", hasher.finalize())[..32].to_string()
+    };
+    let Some(key) = conversation.or(user).filter(|k| !k.trim().is_empty()) else {
+        return;
+    };
+    // 以 API Key 區分，不同 Key 的相同識別不會對應到同一對話
+    if let Some(user) = user.filter(|u| !u.trim().is_empty()) {
+        request.user_id = format!("u-{}", hash(&[access_key, user]));
+    }
+    request.conversation_id = format!("c-{}", hash(&[access_key, key]));
+    request.message_id = format!("m-{}", nanoid::nanoid!(24));
+    debug!(
+        "💬 已設置 Poe 對話識別 | conversation_id: {} | 歷史訊息數: {}",
+        request.conversation_id,
+        request.query.len()
+    );
+}
+
+/// 取出 prediction（`{"type": "content", "content": ...}`）中的文字，content 可為字串或文字片段陣列
+fn prediction_text(prediction: &serde_json::Value) -> Option<String> {
+    if prediction
+        .get("type")
+        .and_then(|t| t.as_str())
+        .is_some_and(|t| t != "content")
+    {
+        return None;
+    }
+    let text = match prediction.get("content")? {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join(""),
+        _ => return None,
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+/// 待回覆的工具回合：(工具調用, 工具結果消息)
+type PendingToolRound<'a> = (&'a [ChatToolCall], &'a [Message]);
+
+/// 分出結尾待回覆的工具回合：帶 tool_calls 的 assistant 消息及其後的 tool 消息
+/// 返回 (其餘的對話, 待回覆的工具回合)
+fn split_pending_tool_round(messages: &[Message]) -> (&[Message], Option<PendingToolRound<'_>>) {
+    let results_start = messages
+        .iter()
+        .rposition(|msg| msg.role != "tool")
+        .map_or(0, |index| index + 1);
+    if results_start == messages.len() || results_start == 0 {
+        return (messages, None);
+    }
+    let call_message = &messages[results_start - 1];
+    match &call_message.tool_calls {
+        Some(calls) if call_message.role == "assistant" && !calls.is_empty() => (
+            &messages[..results_start - 1],
+            Some((calls.as_slice(), &messages[results_start..])),
+        ),
+        _ => (messages, None),
+    }
+}
+
+/// 以文字表示的工具調用，用於已回覆的工具回合
+fn tool_call_text(call: &ChatToolCall) -> String {
+    format!(
+        "Tool Call: {} ({})\nArguments: {}",
+        call.function.name, call.id, call.function.arguments
+    )
+}
+
+/// 為請求設置新的待回覆工具回合，原有的待回覆回合以文字併入對話（供伺服器端工具循環使用）
+pub(crate) fn push_tool_round(
+    request: &mut ChatRequest,
+    calls: Vec<ChatToolCall>,
+    results: Vec<ChatToolResult>,
+) {
+    let message = |role: &str, content: String| ChatMessage {
+        role: role.to_string(),
+        content,
+        attachments: None,
+        content_type: "text/markdown".to_string(),
+    };
+    if let Some(previous) = request.tool_calls.take() {
+        let text = previous
+            .iter()
+            .map(tool_call_text)
+            .collect::<Vec<_>>()
+            .join("\n");
+        request.query.push(message("bot", text));
+        for result in request.tool_results.take().into_iter().flatten() {
+            request.query.push(message(
+                "user",
+                format!(
+                    "Tool Result: {} ({})\n{}",
+                    result.name, result.tool_call_id, result.content
+                ),
+            ));
+        }
+    }
+    request.tool_calls = Some(calls);
+    request.tool_results = Some(results);
+}
+
+/// 將 tool 消息轉換為 Poe 的工具結果
+fn tool_results_for_poe(
+    messages: &[Message],
+    tool_call_names: &HashMap<String, String>,
+) -> Vec<ChatToolResult> {
+    messages
+        .iter()
+        .filter_map(|msg| {
+            let content = get_text_from_openai_content(&msg.content);
+            // 優先使用 tool_call_id 欄位，沒有時嘗試從內容中提取
+            let Some(tool_call_id) = msg
+                .tool_call_id
+                .clone()
+                .or_else(|| extract_tool_call_id(&content))
+            else {
+                debug!("⚠️ 無法從工具消息中提取 tool_call_id");
+                return None;
+            };
+            let name = tool_call_names
+                .get(&tool_call_id)
+                .cloned()
+                .unwrap_or_else(|| {
+                    debug!(
+                        "⚠️ 無法找到 tool_call_id {} 對應的工具名稱，使用 unknown",
+                        tool_call_id
+                    );
+                    "unknown".to_string()
+                });
+            debug!(
+                "🔧 處理工具結果 | tool_call_id: {} | 工具名稱: {}",
+                tool_call_id, name
+            );
+            Some(ChatToolResult {
+                role: "tool".to_string(),
+                tool_call_id,
+                name,
+                content,
+            })
+        })
+        .collect()
+}
+
+/// parallel_tool_calls 為 false 時給機器人的指示
+const SINGLE_TOOL_CALL_INSTRUCTION: &str =
+    "Call at most one tool per response. Wait for its result before calling another tool.";
+
 pub async fn create_chat_request(
     model: &str,
     messages: Vec<Message>,
     chat_completion_request: &ChatCompletionRequest,
 ) -> ChatRequest {
-    let temperature = chat_completion_request.temperature;
+    let mut temperature = chat_completion_request.temperature;
     let original_tools = chat_completion_request.tools.clone();
     let tools = filter_tools_for_poe(&original_tools);
-    let logit_bias = chat_completion_request.logit_bias.clone();
+    let mut logit_bias = chat_completion_request.logit_bias.clone();
     let stop = chat_completion_request.stop.clone();
 
     debug!(
@@ -223,7 +979,48 @@ pub async fn create_chat_request(
         "🔍 模型 {} 的 replace_response 設置: {}",
         model, should_replace_response
     );
-    let query = messages
+    // 推理模型會拒絕取樣參數，移除而非轉發
+    let is_reasoning_model = config
+        .models
+        .get(model)
+        .and_then(|model_config| model_config.reasoning)
+        .unwrap_or(false);
+    if is_reasoning_model && (temperature.is_some() || logit_bias.is_some()) {
+        debug!(
+            "🧠 推理模型 {} 不支援取樣參數，移除 temperature/logit_bias",
+            model
+        );
+        temperature = None;
+        logit_bias = None;
+    }
+    // tool_call_id 對應的工具名稱，取自先前 assistant 消息的 tool_calls
+    let tool_call_names: HashMap<String, String> = messages
+        .iter()
+        .filter(|msg| msg.role == "assistant")
+        .flat_map(|msg| msg.tool_calls.iter().flatten())
+        .map(|call| (call.id.clone(), call.function.name.clone()))
+        .collect();
+
+    // 結尾為帶 tool_calls 的 assistant 消息及其工具結果時，該回合尚待機器人回覆，
+    // 以 Poe 的 tool_calls / tool_results 欄位傳送；更早的回合以文字保留在對話中
+    let (history, pending_round) = split_pending_tool_round(&messages);
+    let (tool_calls, tool_results) = match pending_round {
+        Some((calls, results)) => {
+            let results = tool_results_for_poe(results, &tool_call_names);
+            debug!(
+                "🔧 待回覆的工具回合 | 工具調用: {} | 工具結果: {}",
+                calls.len(),
+                results.len()
+            );
+            (
+                Some(calls.to_vec()),
+                (!results.is_empty()).then_some(results),
+            )
+        }
+        None => (None, None),
+    };
+
+    let mut query: Vec<ChatMessage> = history
         .iter()
         .enumerate()
         .map(|(index, msg)| {
@@ -242,13 +1039,24 @@ pub async fn create_chat_request(
             };
             // 將 OpenAI 消息轉換為 Poe 消息
             // 只對最後一條用戶消息應用後綴處理
-            let is_last_user_message = msg.role == "user" && index == messages.len() - 1;
+            let is_last_user_message = msg.role == "user" && index == history.len() - 1;
             let request_param = if is_last_user_message {
                 Some(chat_completion_request)
             } else {
                 None
             };
-            let poe_message = openai_message_to_poe(msg, role_override, request_param);
+            let tool_name = msg
+                .tool_call_id
+                .as_ref()
+                .and_then(|id| tool_call_names.get(id))
+                .map(String::as_str);
+            let poe_message = openai_message_to_poe(
+                msg,
+                role_override,
+                request_param,
+                is_reasoning_model,
+                tool_name,
+            );
             // 紀錄轉換結果
             debug!(
                 "🔄 處理訊息 | 原始角色: {} | 轉換後角色: {} | 內容長度: {} | 附件數量: {}",
@@ -261,68 +1069,49 @@ pub async fn create_chat_request(
         })
         .collect();
 
-    // 處理工具結果消息
-    let mut tool_results = None;
-    // 檢查是否有 tool 角色的消息，並將其轉換為 ToolResult
-    if messages.iter().any(|msg| msg.role == "tool") {
-        // 首先建立 tool_call_id 到工具名稱的映射
-        let mut tool_call_id_to_name: std::collections::HashMap<String, String> = std::collections::HashMap::new();
-        
-        // 從之前的 assistant 消息中提取工具調用信息
-        for msg in &messages {
-            if msg.role == "assistant" {
-                if let Some(tool_calls) = &msg.tool_calls {
-                    for tool_call in tool_calls {
-                        tool_call_id_to_name.insert(tool_call.id.clone(), tool_call.function.name.clone());
-                        debug!("🔧 映射工具調用 | ID: {} | 名稱: {}", tool_call.id, tool_call.function.name);
-                    }
-                }
-            }
-        }
-        
-        let mut results = Vec::new();
-        for msg in messages {
-            if msg.role == "tool" {
-                // 優先使用新的 tool_call_id 欄位
-                let tool_call_id = if let Some(id) = &msg.tool_call_id {
-                    id.clone()
-                } else {
-                    // 如果沒有 tool_call_id 欄位，嘗試從內容中提取
-                    let content_text = get_text_from_openai_content(&msg.content);
-                    if let Some(id) = extract_tool_call_id(&content_text) {
-                        id
-                    } else {
-                        debug!("⚠️ 無法從工具消息中提取 tool_call_id");
-                        continue;
-                    }
-                };
-
-                // 從映射中查找工具名稱，如果找不到則使用 "unknown"
-                let tool_name = tool_call_id_to_name.get(&tool_call_id)
-                    .cloned()
-                    .unwrap_or_else(|| {
-                        debug!("⚠️ 無法找到 tool_call_id {} 對應的工具名稱，使用 unknown", tool_call_id);
-                        "unknown".to_string()
-                    });
-
-                let content_text = get_text_from_openai_content(&msg.content);
-                debug!("🔧 處理工具結果 | tool_call_id: {} | 工具名稱: {}", tool_call_id, tool_name);
-                results.push(poe_api_process::types::ChatToolResult {
-                    role: "tool".to_string(),
-                    tool_call_id,
-                    name: tool_name,
-                    content: content_text,
+    // prediction 預設忽略；模型設定 prediction_prefill 時附加為最後一則機器人訊息，由機器人接續回覆
+    if let Some(prediction) = &chat_completion_request.prediction {
+        let prefill = config
+            .models
+            .get(model)
+            .and_then(|model_config| model_config.prediction_prefill)
+            .unwrap_or(false);
+        match prediction_text(prediction) {
+            Some(text) if prefill && query.last().is_some_and(|m| m.role == "user") => {
+                debug!(
+                    "🔮 以 prediction 作為預填內容 | 長度: {}",
+                    crate::utils::format_bytes_length(text.len())
+                );
+                query.push(ChatMessage {
+                    role: "bot".to_string(),
+                    content: text,
+                    attachments: None,
+                    content_type: "text/markdown".to_string(),
                 });
             }
-        }
-        if !results.is_empty() {
-            tool_results = Some(results);
-            debug!(
-                "🔧 創建了 {} 個工具結果",
-                tool_results.as_ref().unwrap().len()
-            );
+            _ => debug!("🔮 忽略請求中的 prediction"),
         }
     }
+
+    // parallel_tool_calls 為 false 時要求機器人每回合只調用一個工具，多餘的調用在輸出時捨棄
+    if chat_completion_request.parallel_tool_calls == Some(false) && tools.is_some() {
+        debug!("🔧 parallel_tool_calls 為 false，要求每回合只調用一個工具");
+        query.insert(
+            0,
+            ChatMessage {
+                role: if should_replace_response {
+                    "user"
+                } else {
+                    "system"
+                }
+                .to_string(),
+                content: SINGLE_TOOL_CALL_INSTRUCTION.to_string(),
+                attachments: None,
+                content_type: "text/markdown".to_string(),
+            },
+        );
+    }
+
     ChatRequest {
         version: "1.2".to_string(),
         r#type: "query".to_string(),
@@ -332,7 +1121,7 @@ pub async fn create_chat_request(
         conversation_id: "".to_string(),
         message_id: "".to_string(),
         tools,
-        tool_calls: None,
+        tool_calls,
         tool_results,
         logit_bias,
         stop_sequences: stop,
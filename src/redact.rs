@@ -0,0 +1,193 @@
+//! 個人資料遮蔽：在寫入日誌及儲存聊天完成記錄前移除電子郵件、電話號碼與 API Key
+
+use regex::Regex;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::LazyLock;
+use tracing_subscriber::fmt::MakeWriter;
+
+static REDACTOR: LazyLock<Option<Redactor>> = LazyLock::new(load_redactor);
+
+pub struct Redactor {
+    rules: Vec<(Regex, &'static str)>,
+}
+
+// 內建類別：(名稱, 正則表達式, 替換文字)
+const BUILTIN_RULES: &[(&str, &str, &str)] = &[
+    (
+        "email",
+        r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+        "[REDACTED_EMAIL]",
+    ),
+    (
+        "api_key",
+        r"\b(?:sk|pk|rk)-[A-Za-z0-9_-]{16,}|(?i:bearer)\s+[A-Za-z0-9._~+/=-]{8,}",
+        "[REDACTED_KEY]",
+    ),
+    // 國際格式、帶分隔符的號碼及中國大陸手機號；不含分隔符的純數字不處理，避免誤判時間戳等。
+    // 不以 + 開頭的號碼只接受空白或 - 分隔，避免把 IPv4 位址（如 192.168.100.200）當成電話
+    (
+        "phone",
+        r"\+\d{1,3}[\s.-]?\(?\d{1,4}\)?(?:[\s.-]?\d{2,4}){2,4}|\(?\b\d{2,4}\)?[\s-]\d{3,4}[\s-]\d{3,4}\b|\b1[3-9]\d{9}\b",
+        "[REDACTED_PHONE]",
+    ),
+];
+
+fn load_redactor() -> Option<Redactor> {
+    let categories = std::env::var("PII_REDACTION").unwrap_or_default();
+    let categories: Vec<String> = categories
+        .split(',')
+        .map(|c| c.trim().to_lowercase())
+        .filter(|c| !c.is_empty() && c != "false")
+        .collect();
+    let enable_all = categories.iter().any(|c| c == "true" || c == "all");
+
+    let mut rules = Vec::new();
+    for (name, pattern, replacement) in BUILTIN_RULES {
+        if enable_all || categories.iter().any(|c| c == name) {
+            rules.push((Regex::new(pattern).expect("內建遮蔽規則無效"), *replacement));
+        }
+    }
+    // 自訂規則，每行一個正則表達式
+    if let Ok(patterns) = std::env::var("PII_REDACTION_PATTERNS") {
+        for pattern in patterns.lines().map(str::trim).filter(|p| !p.is_empty()) {
+            match Regex::new(pattern) {
+                Ok(regex) => rules.push((regex, "[REDACTED]")),
+                // 日誌系統可能尚未初始化，直接輸出到 stderr
+                Err(e) => eprintln!(
+                    "⚠️ 忽略無效的 PII_REDACTION_PATTERNS 規則 {}: {}",
+                    pattern, e
+                ),
+            }
+        }
+    }
+
+    if rules.is_empty() {
+        None
+    } else {
+        Some(Redactor { rules })
+    }
+}
+
+/// 取得遮蔽器，未啟用時返回 None
+pub fn get_redactor() -> Option<&'static Redactor> {
+    REDACTOR.as_ref()
+}
+
+impl Redactor {
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for (regex, replacement) in &self.rules {
+            if let Cow::Owned(replaced) = regex.replace_all(&text, *replacement) {
+                text = Cow::Owned(replaced);
+            }
+        }
+        text
+    }
+
+    /// 遞迴遮蔽 JSON 中的所有字串
+    pub fn redact_value(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => {
+                if let Cow::Owned(redacted) = self.redact(text) {
+                    *text = redacted;
+                }
+            }
+            serde_json::Value::Array(items) => {
+                items.iter_mut().for_each(|item| self.redact_value(item));
+            }
+            serde_json::Value::Object(map) => {
+                map.values_mut().for_each(|item| self.redact_value(item));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 遮蔽 JSON 中的個人資料（未啟用時不做任何處理）
+pub fn redact_value(value: &mut serde_json::Value) {
+    if let Some(redactor) = get_redactor() {
+        redactor.redact_value(value);
+    }
+}
+
+/// 日誌輸出：寫入 stdout 前套用遮蔽規則
+pub struct RedactingStdout;
+
+impl<'a> MakeWriter<'a> for RedactingStdout {
+    type Writer = RedactingStdout;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingStdout
+    }
+}
+
+impl Write for RedactingStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match get_redactor() {
+            Some(redactor) => {
+                let text = String::from_utf8_lossy(buf);
+                io::stdout().write_all(redactor.redact(&text).as_bytes())?;
+            }
+            None => io::stdout().write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin_redactor() -> Redactor {
+        Redactor {
+            rules: BUILTIN_RULES
+                .iter()
+                .map(|(_, pattern, replacement)| (Regex::new(pattern).unwrap(), *replacement))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn ip_addresses_are_not_phones() {
+        let redactor = builtin_redactor();
+        for text in [
+            "client 192.168.100.200 connected",
+            "upstream 10.123.234.210:8080",
+            "dns 8.8.8.8 and 172.16.254.1",
+            "from 203.0.113.195, 198.51.100.7",
+        ] {
+            assert_eq!(redactor.redact(text), text);
+        }
+    }
+
+    #[test]
+    fn phones_are_redacted() {
+        let redactor = builtin_redactor();
+        for (text, expected) in [
+            ("call +886 912 345 678 now", "call [REDACTED_PHONE] now"),
+            ("call +1 (555) 123-4567", "call [REDACTED_PHONE]"),
+            ("office 02-2345-6789", "office [REDACTED_PHONE]"),
+            ("office (02) 2345 6789", "office [REDACTED_PHONE]"),
+            ("mobile 13812345678.", "mobile [REDACTED_PHONE]."),
+        ] {
+            assert_eq!(redactor.redact(text), expected);
+        }
+        // 不含分隔符的純數字（如時間戳）不處理
+        assert_eq!(redactor.redact("ts 1712345678901"), "ts 1712345678901");
+    }
+
+    #[test]
+    fn emails_are_redacted() {
+        let redactor = builtin_redactor();
+        assert_eq!(
+            redactor.redact("mail user.name+tag@example.co.uk or a@b.io"),
+            "mail [REDACTED_EMAIL] or [REDACTED_EMAIL]"
+        );
+        assert_eq!(redactor.redact("host example.com"), "host example.com");
+    }
+}
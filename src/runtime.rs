@@ -0,0 +1,168 @@
+//! 執行期調整的設定 (/api/admin/runtime)
+//!
+//! 不需重啟即可修改日誌過濾規則 (LOG_LEVEL) 與 RATE_LIMIT_MS，便於在問題重現時切換到 debug 日誌。
+//! 覆蓋值保存在儲存資料庫，重啟後仍然生效；清除覆蓋後恢復環境變數的設定
+
+use crate::store::{RUNTIME_TREE, open_store_tree, store_exists};
+use serde::{Deserialize, Serialize};
+use std::sync::{LazyLock, OnceLock, RwLock};
+use tracing::{info, warn};
+
+const OVERRIDES_KEY: &str = "overrides";
+
+/// 執行期覆蓋的設定，未設置的項目使用環境變數
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub(crate) struct RuntimeOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) log_level: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rate_limit_ms: Option<u64>,
+}
+
+type FilterReloader = Box<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// 啟動時的日誌過濾規則及重新載入過濾規則的函數
+static LOG_FILTER: OnceLock<(String, FilterReloader)> = OnceLock::new();
+
+static OVERRIDES: LazyLock<RwLock<RuntimeOverrides>> = LazyLock::new(RwLock::default);
+
+/// 記錄啟動時的日誌過濾規則，供執行期切換與恢復
+pub(crate) fn init_log_filter(default: &str, reload: FilterReloader) {
+    let _ = LOG_FILTER.set((default.to_string(), reload));
+}
+
+/// 目前的覆蓋設定
+pub(crate) fn runtime_overrides() -> RuntimeOverrides {
+    OVERRIDES.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// 執行期覆蓋的 RATE_LIMIT_MS
+pub(crate) fn rate_limit_override() -> Option<u64> {
+    OVERRIDES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .rate_limit_ms
+}
+
+/// 目前生效的日誌過濾規則
+pub(crate) fn current_log_level() -> Option<String> {
+    let (default, _) = LOG_FILTER.get()?;
+    Some(
+        runtime_overrides()
+            .log_level
+            .unwrap_or_else(|| default.clone()),
+    )
+}
+
+fn reload_log_filter(filter: Option<&str>) -> Result<(), String> {
+    let Some((default, reload)) = LOG_FILTER.get() else {
+        return Ok(());
+    };
+    reload(filter.unwrap_or(default))
+}
+
+fn persist(overrides: &RuntimeOverrides) {
+    let Some(tree) = open_store_tree(RUNTIME_TREE) else {
+        return;
+    };
+    let result = serde_json::to_vec(overrides)
+        .map_err(|e| e.to_string())
+        .and_then(|value| {
+            tree.insert(OVERRIDES_KEY, value)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        warn!(
+            "{}",
+            tr!(
+                "⚠️ 保存執行期設定失敗: {}",
+                "⚠️ Failed to persist runtime settings: {}",
+                e
+            )
+        );
+    }
+}
+
+/// 載入保存的覆蓋設定並套用，於啟動時呼叫
+pub(crate) fn load_runtime_overrides() {
+    // 儲存資料庫不存在時不可能有保存的設定，避免為此建立資料庫
+    if !store_exists() {
+        return;
+    }
+    let Some(mut overrides) = open_store_tree(RUNTIME_TREE)
+        .and_then(|tree| tree.get(OVERRIDES_KEY).ok().flatten())
+        .and_then(|value| serde_json::from_slice::<RuntimeOverrides>(&value).ok())
+    else {
+        return;
+    };
+    if let Err(e) = reload_log_filter(overrides.log_level.as_deref()) {
+        warn!(
+            "{}",
+            tr!(
+                "⚠️ 保存的日誌過濾規則無效，略過: {}",
+                "⚠️ Stored log filter is invalid, ignoring: {}",
+                e
+            )
+        );
+        overrides.log_level = None;
+    }
+    info!(
+        "{}",
+        tr!(
+            "🎛️ 已套用執行期設定 | 日誌: {} | RATE_LIMIT_MS: {}",
+            "🎛️ Runtime settings applied | log: {} | RATE_LIMIT_MS: {}",
+            overrides.log_level.as_deref().unwrap_or("-"),
+            overrides
+                .rate_limit_ms
+                .map_or("-".to_string(), |ms| ms.to_string())
+        )
+    );
+    *OVERRIDES.write().unwrap_or_else(|e| e.into_inner()) = overrides;
+}
+
+/// 合併新的覆蓋設定，套用並保存；日誌過濾規則無效時不做任何修改
+pub(crate) fn update_runtime_overrides(
+    update: RuntimeOverrides,
+) -> Result<RuntimeOverrides, String> {
+    let mut overrides = OVERRIDES.write().unwrap_or_else(|e| e.into_inner());
+    if let Some(log_level) = &update.log_level {
+        reload_log_filter(Some(log_level))?;
+        overrides.log_level = Some(log_level.clone());
+    }
+    if let Some(rate_limit_ms) = update.rate_limit_ms {
+        overrides.rate_limit_ms = Some(rate_limit_ms);
+    }
+    persist(&overrides);
+    info!(
+        "{}",
+        tr!(
+            "🎛️ 執行期設定已更新 | 日誌: {} | RATE_LIMIT_MS: {}",
+            "🎛️ Runtime settings updated | log: {} | RATE_LIMIT_MS: {}",
+            overrides.log_level.as_deref().unwrap_or("-"),
+            overrides
+                .rate_limit_ms
+                .map_or("-".to_string(), |ms| ms.to_string())
+        )
+    );
+    Ok(overrides.clone())
+}
+
+/// 清除所有覆蓋設定，恢復環境變數的設定
+pub(crate) fn clear_runtime_overrides() {
+    let mut overrides = OVERRIDES.write().unwrap_or_else(|e| e.into_inner());
+    *overrides = RuntimeOverrides::default();
+    let _ = reload_log_filter(None);
+    if store_exists()
+        && let Some(tree) = open_store_tree(RUNTIME_TREE)
+    {
+        let _ = tree.remove(OVERRIDES_KEY);
+    }
+    info!(
+        "{}",
+        tr!(
+            "🎛️ 已清除執行期設定，恢復環境變數的設定",
+            "🎛️ Runtime settings cleared, environment settings restored"
+        )
+    );
+}
@@ -0,0 +1,241 @@
+//! 使用者提供的 Rhai 腳本 (TRANSFORM_SCRIPT)，可在轉發前修改請求，並在返回前修改回應與串流片段
+//!
+//! 腳本中可定義以下函數（皆為可選）：
+//! - `on_request(request, headers)`：返回修改後的請求；返回字串則以該訊息拒絕請求
+//! - `on_response(response)`：返回修改後的非串流回應
+//! - `on_chunk(chunk)`：返回修改後的串流片段；返回 `()` 則丟棄該片段
+
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{AST, CallFnOptions, Dynamic, Engine, Scope};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tracing::{debug, error, info, warn};
+
+/// 單次腳本呼叫可執行的操作數上限，避免無限迴圈阻塞請求
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+static SCRIPT_HOOKS: OnceLock<Option<ScriptHooks>> = OnceLock::new();
+
+pub struct ScriptHooks {
+    engine: Engine,
+    ast: AST,
+    on_request: bool,
+    on_response: bool,
+    on_chunk: bool,
+}
+
+/// 啟動時載入並編譯腳本，編譯失敗時返回錯誤
+pub fn init_script_hooks() -> Result<(), String> {
+    let hooks = match std::env::var("TRANSFORM_SCRIPT")
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+    {
+        Some(path) => Some(ScriptHooks::load(PathBuf::from(path))?),
+        None => None,
+    };
+    let _ = SCRIPT_HOOKS.set(hooks);
+    Ok(())
+}
+
+/// 取得已載入的腳本
+pub fn get_script_hooks() -> Option<&'static ScriptHooks> {
+    SCRIPT_HOOKS.get_or_init(|| None).as_ref()
+}
+
+impl ScriptHooks {
+    fn load(path: PathBuf) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("{}", tr!("📜 腳本輸出: {}", "📜 Script output: {}", text)));
+        engine.on_debug(|text, _, pos| debug!("📜 腳本除錯 ({}): {}", pos, text));
+
+        let ast = engine
+            .compile_file(path.clone())
+            .map_err(|e| format!("無法編譯腳本 {}: {}", path.display(), e))?;
+        let has_fn = |name: &str| ast.iter_functions().any(|f| f.name == name);
+        let hooks = Self {
+            on_request: has_fn("on_request"),
+            on_response: has_fn("on_response"),
+            on_chunk: has_fn("on_chunk"),
+            engine,
+            ast,
+        };
+        info!(
+            "{}",
+            tr!(
+                "📜 已載入轉換腳本 {} | on_request: {} | on_response: {} | on_chunk: {}",
+                "📜 Loaded transform script {} | on_request: {} | on_response: {} | on_chunk: {}",
+                path.display(),
+                hooks.on_request,
+                hooks.on_response,
+                hooks.on_chunk
+            )
+        );
+        Ok(hooks)
+    }
+
+    pub fn has_request_hook(&self) -> bool {
+        self.on_request
+    }
+
+    pub fn has_response_hook(&self) -> bool {
+        self.on_response
+    }
+
+    pub fn has_chunk_hook(&self) -> bool {
+        self.on_chunk
+    }
+
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> Result<Dynamic, String> {
+        // 僅呼叫函數，不重複執行腳本頂層語句
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, name, args)
+            .map_err(|e| e.to_string())
+    }
+
+    /// 執行 on_request；返回 Err 表示腳本拒絕了請求
+    /// 腳本執行失敗時記錄錯誤並沿用原始請求
+    pub fn on_request(
+        &self,
+        request: serde_json::Value,
+        headers: HashMap<String, String>,
+    ) -> Result<serde_json::Value, String> {
+        let (Ok(request_dyn), Ok(headers_dyn)) = (to_dynamic(&request), to_dynamic(&headers))
+        else {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無法將請求轉換為腳本數據，跳過 on_request",
+                    "⚠️ Failed to convert request for the script, skipping on_request"
+                )
+            );
+            return Ok(request);
+        };
+        match self.call("on_request", (request_dyn, headers_dyn)) {
+            Ok(result) if result.is_string() => {
+                let message = result.into_string().unwrap_or_default();
+                info!(
+                    "{}",
+                    tr!(
+                        "📜 腳本拒絕請求: {}",
+                        "📜 Script rejected request: {}",
+                        message
+                    )
+                );
+                Err(message)
+            }
+            Ok(result) => match from_dynamic::<serde_json::Value>(&result) {
+                Ok(value) if value.is_object() => Ok(value),
+                _ => {
+                    warn!(
+                        "{}",
+                        tr!(
+                            "⚠️ on_request 未返回物件，沿用原始請求",
+                            "⚠️ on_request did not return an object, keeping the original request"
+                        )
+                    );
+                    Ok(request)
+                }
+            },
+            Err(e) => {
+                error!(
+                    "{}",
+                    tr!("❌ 執行 on_request 失敗: {}", "❌ on_request failed: {}", e)
+                );
+                Ok(request)
+            }
+        }
+    }
+
+    /// 執行 on_response，失敗時返回原始回應
+    pub fn on_response(&self, response: serde_json::Value) -> serde_json::Value {
+        let Ok(response_dyn) = to_dynamic(&response) else {
+            return response;
+        };
+        match self
+            .call("on_response", (response_dyn,))
+            .and_then(|result| {
+                from_dynamic::<serde_json::Value>(&result).map_err(|e| e.to_string())
+            }) {
+            Ok(value) if value.is_object() => value,
+            Ok(_) => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ on_response 未返回物件，沿用原始回應",
+                        "⚠️ on_response did not return an object, keeping the original response"
+                    )
+                );
+                response
+            }
+            Err(e) => {
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ 執行 on_response 失敗: {}",
+                        "❌ on_response failed: {}",
+                        e
+                    )
+                );
+                response
+            }
+        }
+    }
+
+    /// 對一段 SSE 輸出中的每個 data 片段執行 on_chunk
+    pub fn transform_sse(&self, text: String) -> String {
+        let mut events = Vec::new();
+        for event in text.split("\n\n").filter(|event| !event.is_empty()) {
+            let Some(data) = event.strip_prefix("data: ") else {
+                events.push(event.to_string());
+                continue;
+            };
+            let Ok(chunk) = serde_json::from_str::<serde_json::Value>(data) else {
+                // [DONE] 等非 JSON 內容原樣保留
+                events.push(event.to_string());
+                continue;
+            };
+            if let Some(chunk) = self.on_chunk(chunk) {
+                events.push(format!("data: {}", chunk));
+            }
+        }
+        if events.is_empty() {
+            String::new()
+        } else {
+            events.join("\n\n") + "\n\n"
+        }
+    }
+
+    // 返回 None 表示丟棄該片段
+    fn on_chunk(&self, chunk: serde_json::Value) -> Option<serde_json::Value> {
+        let Ok(chunk_dyn) = to_dynamic(&chunk) else {
+            return Some(chunk);
+        };
+        match self.call("on_chunk", (chunk_dyn,)) {
+            Ok(result) if result.is_unit() => None,
+            Ok(result) => match from_dynamic::<serde_json::Value>(&result) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    error!(
+                        "{}",
+                        tr!(
+                            "❌ 無法轉換 on_chunk 結果: {}",
+                            "❌ Failed to convert on_chunk result: {}",
+                            e
+                        )
+                    );
+                    Some(chunk)
+                }
+            },
+            Err(e) => {
+                error!(
+                    "{}",
+                    tr!("❌ 執行 on_chunk 失敗: {}", "❌ on_chunk failed: {}", e)
+                );
+                Some(chunk)
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! 伺服器端工具循環 (models.yaml 的 mcp_servers 與 builtin_tools)
+//!
+//! 模型啟用伺服器端工具時，代理將工具定義附加到 Poe 請求，機器人調用這些工具時由代理執行，
+//! 結果以 tool_calls / tool_results 送回機器人，重複直到機器人給出最終回覆或達到 MAX_TOOL_ROUNDS。
+//! 每個回合都完整接收後才決定是否繼續，因此串流請求會在最終回合完成後才開始輸出。
+//! 客戶端自帶的工具照常返回給客戶端；同一回合同時調用客戶端工具時，伺服器端工具的調用被捨棄
+
+use crate::mcp::{McpClient, McpTool, mcp_client};
+use crate::poe_client::{PoeClientWrapper, push_tool_round};
+use crate::types::{BuiltinTool, Config};
+use crate::web_search::{web_search, web_search_available, web_search_tool};
+use futures_util::future::join_all;
+use futures_util::stream::{self, Stream, StreamExt};
+use poe_api_process::types::{
+    ChatTool, ChatToolCall, ChatToolResult, FunctionDefinition, FunctionParameters,
+};
+use poe_api_process::{ChatEventType, ChatRequest, ChatResponse, ChatResponseData, PoeError};
+use serde_json::{Value, json};
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+use tracing::{debug, info, warn};
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>;
+
+/// 單一請求最多執行的工具回合數，超過時要求機器人不使用工具直接回覆
+static MAX_TOOL_ROUNDS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MAX_TOOL_ROUNDS")
+        .ok()
+        .and_then(|s| s.trim().parse::<usize>().ok())
+        .filter(|rounds| *rounds > 0)
+        .unwrap_or(8)
+});
+
+enum ToolBackend {
+    Mcp {
+        client: Arc<McpClient>,
+        tool: String,
+    },
+    WebSearch,
+}
+
+struct ServerTool {
+    definition: ChatTool,
+    backend: ToolBackend,
+}
+
+impl ServerTool {
+    fn name(&self) -> &str {
+        &self.definition.function.name
+    }
+
+    /// MCP 工具以「伺服器名稱__工具名稱」提供給機器人，避免不同伺服器的工具同名
+    fn mcp(server: &str, client: Arc<McpClient>, tool: &McpTool) -> Self {
+        let name: String = format!("{}__{}", server, tool.name)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .take(64)
+            .collect();
+        let schema = &tool.input_schema;
+        let parameters = FunctionParameters {
+            r#type: schema["type"].as_str().unwrap_or("object").to_string(),
+            properties: schema
+                .get("properties")
+                .cloned()
+                .unwrap_or_else(|| json!({})),
+            required: schema["required"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|name| name.as_str().map(str::to_string))
+                .collect(),
+        };
+        Self {
+            definition: ChatTool {
+                r#type: "function".to_string(),
+                function: FunctionDefinition {
+                    // 沒有描述時以工具名稱代替
+                    description: Some(
+                        tool.description
+                            .clone()
+                            .filter(|d| !d.is_empty())
+                            .unwrap_or_else(|| tool.name.clone()),
+                    ),
+                    name,
+                    parameters: Some(parameters),
+                },
+            },
+            backend: ToolBackend::Mcp {
+                client,
+                tool: tool.name.clone(),
+            },
+        }
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String, String> {
+        match &self.backend {
+            ToolBackend::Mcp { client, tool } => client.call_tool(tool, arguments).await,
+            ToolBackend::WebSearch => web_search(&arguments).await,
+        }
+    }
+}
+
+/// 模型可使用的伺服器端工具
+pub(crate) struct ServerTools {
+    tools: Vec<ServerTool>,
+}
+
+impl ServerTools {
+    /// 載入模型設定的伺服器端工具，無可用工具時返回 None
+    pub(crate) async fn for_model(config: &Config, model: &str) -> Option<Self> {
+        let model_config = config.models.get(model)?;
+        let mut tools = Vec::new();
+        for builtin in model_config.builtin_tools.iter().flatten() {
+            match builtin {
+                BuiltinTool::WebSearch if web_search_available() => tools.push(ServerTool {
+                    definition: web_search_tool(),
+                    backend: ToolBackend::WebSearch,
+                }),
+                BuiltinTool::WebSearch => warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 模型 {} 啟用了 web_search，但未設定 WEB_SEARCH_BACKEND，略過",
+                        "⚠️ Model {} enables web_search but WEB_SEARCH_BACKEND is not configured, skipping",
+                        model
+                    )
+                ),
+            }
+        }
+        for name in model_config.mcp_servers.iter().flatten() {
+            let Some(server) = config
+                .mcp_servers
+                .as_ref()
+                .and_then(|servers| servers.get(name))
+            else {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 模型 {} 使用未定義的 MCP 伺服器: {}",
+                        "⚠️ Model {} refers to an undefined MCP server: {}",
+                        model,
+                        name
+                    )
+                );
+                continue;
+            };
+            let listed = match mcp_client(name, server).await {
+                Ok(client) => client.tools().await.map(|list| {
+                    list.iter()
+                        .map(|tool| ServerTool::mcp(name, client.clone(), tool))
+                        .collect::<Vec<_>>()
+                }),
+                Err(e) => Err(e),
+            };
+            match listed {
+                Ok(list) => tools.extend(list),
+                // 無法使用的伺服器不影響請求，機器人只是看不到其工具
+                Err(e) => warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 無法取得 MCP 伺服器的工具，略過 | 伺服器: {} | 錯誤: {}",
+                        "⚠️ Failed to list MCP server tools, skipping | server: {} | error: {}",
+                        name,
+                        e
+                    )
+                ),
+            }
+        }
+        (!tools.is_empty()).then_some(Self { tools })
+    }
+
+    fn find(&self, name: &str) -> Option<&ServerTool> {
+        self.tools.iter().find(|tool| tool.name() == name)
+    }
+
+    /// 執行一個工具調用，失敗時以錯誤訊息作為結果交由機器人處理
+    async fn execute(&self, call: &ChatToolCall) -> ChatToolResult {
+        let start = Instant::now();
+        let content = match self.find(&call.function.name) {
+            Some(tool) => {
+                let arguments = if call.function.arguments.trim().is_empty() {
+                    Ok(json!({}))
+                } else {
+                    serde_json::from_str::<Value>(&call.function.arguments)
+                };
+                match arguments {
+                    Ok(arguments) => tool
+                        .call(arguments)
+                        .await
+                        .unwrap_or_else(|e| format!("Error: {}", e)),
+                    Err(e) => format!("Error: invalid JSON arguments: {}", e),
+                }
+            }
+            None => format!("Error: unknown tool {}", call.function.name),
+        };
+        debug!(
+            "🛠️ 伺服器端工具執行完成 | 工具: {} | 結果長度: {} | 耗時: {}",
+            call.function.name,
+            crate::utils::format_bytes_length(content.len()),
+            crate::utils::format_duration(start.elapsed())
+        );
+        ChatToolResult {
+            role: "tool".to_string(),
+            tool_call_id: call.id.clone(),
+            name: call.function.name.clone(),
+            content,
+        }
+    }
+
+    /// 附加工具定義並執行工具循環，返回最終回合的事件
+    pub(crate) async fn run(
+        &self,
+        client: &PoeClientWrapper,
+        mut request: ChatRequest,
+    ) -> Result<EventStream, PoeError> {
+        let client_tools = request.tools.take().unwrap_or_default();
+        // 與客戶端工具同名時以客戶端的工具為準
+        let is_server_tool = |name: &str| {
+            self.find(name).is_some() && !client_tools.iter().any(|t| t.function.name == name)
+        };
+        let mut tools: Vec<ChatTool> = self
+            .tools
+            .iter()
+            .filter(|tool| is_server_tool(tool.name()))
+            .map(|tool| tool.definition.clone())
+            .collect();
+        tools.extend(client_tools.iter().cloned());
+        request.tools = Some(tools);
+
+        let max_rounds = *MAX_TOOL_ROUNDS;
+        let mut round = 0;
+        loop {
+            let last_round = round >= max_rounds;
+            if last_round {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 伺服器端工具已執行 {} 回合，要求機器人直接回覆",
+                        "⚠️ Server-side tools ran for {} rounds, asking the bot to answer directly",
+                        round
+                    )
+                );
+                request.tools = (!client_tools.is_empty()).then(|| client_tools.clone());
+            }
+            let mut upstream = client
+                .stream_request_with_retry(request.clone(), true)
+                .await?;
+            let mut events = Vec::new();
+            let mut server_calls: Vec<ChatToolCall> = Vec::new();
+            let mut finished = true;
+            let mut client_calls = false;
+            while let Some(item) = upstream.next().await {
+                match item {
+                    Ok(ChatResponse {
+                        event,
+                        data: Some(ChatResponseData::ToolCalls(calls)),
+                    }) => {
+                        let (server, others): (Vec<_>, Vec<_>) = calls
+                            .into_iter()
+                            .partition(|call| is_server_tool(&call.function.name));
+                        server_calls.extend(server);
+                        if !others.is_empty() {
+                            client_calls = true;
+                            events.push(Ok(ChatResponse {
+                                event,
+                                data: Some(ChatResponseData::ToolCalls(others)),
+                            }));
+                        }
+                    }
+                    item => {
+                        if matches!(
+                            &item,
+                            Err(_)
+                                | Ok(ChatResponse {
+                                    event: ChatEventType::Error,
+                                    ..
+                                })
+                        ) {
+                            finished = false;
+                        }
+                        events.push(item);
+                    }
+                }
+            }
+            if server_calls.is_empty() || client_calls || !finished || last_round {
+                if !server_calls.is_empty() {
+                    debug!(
+                        "🛠️ 捨棄 {} 個伺服器端工具調用（回合未完成、包含客戶端工具調用或已達回合上限）",
+                        server_calls.len()
+                    );
+                }
+                return Ok(Box::pin(stream::iter(events)));
+            }
+
+            for call in server_calls.iter_mut() {
+                if call.id.is_empty() {
+                    call.id = format!("call_{}", nanoid::nanoid!(24));
+                }
+            }
+            round += 1;
+            info!(
+                "{}",
+                tr!(
+                    "🛠️ 執行伺服器端工具 | 回合: {} | 工具: {}",
+                    "🛠️ Running server-side tools | round: {} | tools: {}",
+                    round,
+                    server_calls
+                        .iter()
+                        .map(|call| call.function.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            );
+            let results = join_all(server_calls.iter().map(|call| self.execute(call))).await;
+            push_tool_round(&mut request, server_calls, results);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! 多實例部署的共享狀態 (REDIS_URL)
+//!
+//! 設定 Redis 後，全局速率限制、附件上傳緩存、用量統計、models.yaml 設定（含 API Key 的範圍與優先級）
+//! 及 Token 池的點數狀態改存放於 Redis，多個實例共用同一份狀態。
+//! 使用 redis crate 的 ConnectionManager（單一多工連接，中斷時自動重連），命令可並行送出。
+//! Redis 無法使用時各功能退回本機狀態，不影響請求處理
+
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{Client, Cmd, FromRedisValue, Pipeline, Script};
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{debug, error, info, warn};
+
+/// 單一命令（含連接建立）的逾時
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+/// 命令失敗後暫停使用 Redis 的時間，避免每個請求都等待逾時
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+/// 各功能從共享狀態同步到本機的間隔
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct SharedState {
+    client: Client,
+    prefix: String,
+    conn: OnceCell<ConnectionManager>,
+    // 暫停使用 Redis 直到此時間
+    paused_until: std::sync::Mutex<Option<Instant>>,
+}
+
+static SHARED_STATE: LazyLock<Option<SharedState>> = LazyLock::new(|| {
+    let url = crate::utils::get_env_secret("REDIS_URL")
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())?;
+    let client = match Client::open(url) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ REDIS_URL 無效，不啟用共享狀態: {}",
+                    "❌ Invalid REDIS_URL, shared state disabled: {}",
+                    e
+                )
+            );
+            return None;
+        }
+    };
+    let prefix = std::env::var("REDIS_KEY_PREFIX").unwrap_or_else(|_| "poe2openai:".to_string());
+    let info = client.get_connection_info();
+    info!(
+        "{}",
+        tr!(
+            "🔗 共享狀態: Redis {} (資料庫 {}) | 鍵前綴: {}",
+            "🔗 Shared state: Redis {} (db {}) | key prefix: {}",
+            info.addr,
+            info.redis.db,
+            prefix
+        )
+    );
+    Some(SharedState {
+        client,
+        prefix,
+        conn: OnceCell::new(),
+        paused_until: std::sync::Mutex::new(None),
+    })
+});
+
+/// 取得共享狀態，未設定 REDIS_URL 時返回 None
+pub fn get_shared_state() -> Option<&'static SharedState> {
+    SHARED_STATE.as_ref()
+}
+
+/// 啟動時於背景確認 Redis 可連接，未設定 REDIS_URL 時不處理
+pub fn spawn_shared_state_check() {
+    let Some(shared) = get_shared_state() else {
+        return;
+    };
+    tokio::spawn(async move {
+        if shared.query::<String>(&redis::cmd("PING")).await.is_ok() {
+            info!(
+                "{}",
+                tr!(
+                    "✅ 已連接共享狀態 Redis: {}",
+                    "✅ Connected to shared state Redis: {}",
+                    shared.client.get_connection_info().addr
+                )
+            );
+        }
+    });
+}
+
+impl SharedState {
+    /// 加上前綴的鍵名
+    pub fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// 取得共用的連接，首次使用時建立；建立失敗不會被記住，下次重新嘗試
+    async fn connection(&self) -> redis::RedisResult<ConnectionManager> {
+        self.conn
+            .get_or_try_init(|| async {
+                debug!("🔗 連接 Redis: {}", self.client.get_connection_info().addr);
+                let config = ConnectionManagerConfig::new()
+                    .set_connection_timeout(COMMAND_TIMEOUT)
+                    .set_response_timeout(COMMAND_TIMEOUT)
+                    .set_number_of_retries(1);
+                ConnectionManager::new_with_config(self.client.clone(), config).await
+            })
+            .await
+            .cloned()
+    }
+
+    /// 以共用連接執行操作；失敗時暫停使用 Redis 一段時間，期間各功能使用本機狀態
+    async fn run<T, F, Fut>(&self, operation: F) -> Result<T, String>
+    where
+        F: FnOnce(ConnectionManager) -> Fut,
+        Fut: Future<Output = redis::RedisResult<T>>,
+    {
+        if self
+            .paused_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some_and(|until| Instant::now() < until)
+        {
+            return Err("Redis 暫時不可用".to_string());
+        }
+        let result = tokio::time::timeout(COMMAND_TIMEOUT, async {
+            operation(self.connection().await?).await
+        })
+        .await
+        .map_err(|_| "命令逾時".to_string())
+        .and_then(|result| result.map_err(|e| e.to_string()));
+        if let Err(e) = &result {
+            *self.paused_until.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some(Instant::now() + RETRY_AFTER);
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ Redis 命令失敗，{} 秒內使用本機狀態: {}",
+                    "⚠️ Redis command failed, using local state for {}s: {}",
+                    RETRY_AFTER.as_secs(),
+                    e
+                )
+            );
+        }
+        result
+    }
+
+    /// 執行單一命令
+    pub async fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> Result<T, String> {
+        self.run(|mut conn| async move { cmd.query_async(&mut conn).await })
+            .await
+    }
+
+    /// 以管線方式執行多個命令
+    pub async fn query_pipeline<T: FromRedisValue>(&self, pipe: &Pipeline) -> Result<T, String> {
+        self.run(|mut conn| async move { pipe.query_async(&mut conn).await })
+            .await
+    }
+
+    /// 讀取字串值
+    pub async fn get(&self, name: &str) -> Result<Option<String>, String> {
+        self.query(redis::cmd("GET").arg(self.key(name))).await
+    }
+
+    /// 寫入帶有效期的字串值
+    pub async fn set_ex(&self, name: &str, value: &str, ttl: Duration) -> Result<(), String> {
+        self.query(
+            redis::cmd("SET")
+                .arg(self.key(name))
+                .arg(value)
+                .arg("EX")
+                .arg(ttl.as_secs().max(1)),
+        )
+        .await
+    }
+}
+
+/// 以 Redis 的時間預約下一個請求時段，返回需等待的時間
+const RESERVE_SLOT_SCRIPT: &str = r#"
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+local interval = tonumber(ARGV[1])
+local slot = math.max(now, tonumber(redis.call('GET', KEYS[1]) or '0'))
+redis.call('SET', KEYS[1], slot + interval, 'PX', slot - now + interval + 1000)
+return slot - now
+"#;
+
+static RESERVE_SLOT: LazyLock<Script> = LazyLock::new(|| Script::new(RESERVE_SLOT_SCRIPT));
+
+/// 共享的速率限制：返回本請求需等待的時間，Redis 無法使用時返回 None
+/// scope 為 None 時為全局限制，否則為該範圍（如客戶端 IP）各自的限制
+pub async fn reserve_rate_limit_slot(scope: Option<&str>, interval: Duration) -> Option<Duration> {
+    let shared = get_shared_state()?;
+    let key = match scope {
+        Some(scope) => shared.key(&format!("rate_limit:{}", scope)),
+        None => shared.key("rate_limit"),
+    };
+    let interval_ms = interval.as_millis() as u64;
+    let wait_ms: i64 = shared
+        .run(|mut conn| async move {
+            RESERVE_SLOT
+                .key(&key)
+                .arg(interval_ms)
+                .invoke_async(&mut conn)
+                .await
+        })
+        .await
+        .ok()?;
+    Some(Duration::from_millis(wait_ms.max(0) as u64))
+}
@@ -0,0 +1,363 @@
+//! 請求體中大型 base64 data URL 附件的磁碟暫存
+//! 讀取請求體時即逐塊解碼寫入臨時檔案，請求中只保留 `data:<MIME>;spool,<ID>` 引用，
+//! 上傳至 Poe 時直接串流該檔案，附件大小不再影響記憶體用量
+
+use base64::prelude::*;
+use nanoid::nanoid;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use tracing::{debug, info, warn};
+
+/// data URL 中取代 `;base64,` 的暫存引用標記
+pub const SPOOL_MARKER: &str = ";spool,";
+
+/// 累積多少個 base64 字元後解碼寫入一次
+const DECODE_BLOCK: usize = 64 * 1024;
+
+/// 與 hash_base64_content 相同：取 base64 內容頭尾各 1024 個字元計算哈希
+const HASH_EDGE: usize = 1024;
+
+/// 暫存門檻的下限，確保頭尾哈希取樣不重疊
+const MIN_SPOOL_SIZE: usize = 4 * HASH_EDGE;
+
+/// 已暫存的附件
+pub struct SpooledAttachment {
+    pub path: PathBuf,
+    /// 與 hash_base64_content 相容的哈希，寫入完成前為空
+    pub hash: String,
+    /// 解碼後的位元組數
+    pub size: usize,
+    /// 原始請求中 base64 內容的位元組數
+    pub encoded_size: usize,
+}
+
+// 暫存 ID 對應的附件，僅伺服器產生的 ID 可被解析，客戶端無法藉此引用任意路徑
+static SPOOLED: LazyLock<Mutex<HashMap<String, SpooledAttachment>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// data URL 超過多少位元組時暫存至磁碟 (ATTACHMENT_SPOOL_SIZE)，0 表示停用
+pub fn spool_threshold() -> usize {
+    static THRESHOLD: LazyLock<usize> = LazyLock::new(|| {
+        let size = std::env::var("ATTACHMENT_SPOOL_SIZE")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(1024 * 1024);
+        if size == 0 {
+            return 0;
+        }
+        let size = size.max(MIN_SPOOL_SIZE);
+        info!(
+            "{}",
+            tr!(
+                "⚙️  附件暫存: 超過 {} bytes 的 data URL 於讀取請求時寫入臨時檔案",
+                "⚙️  Attachment spooling: data URLs over {} bytes are written to temp files while reading the request",
+                size
+            )
+        );
+        size
+    });
+    *THRESHOLD
+}
+
+/// 取出暫存的附件，之後由呼叫端負責刪除檔案
+pub fn take(id: &str) -> Option<SpooledAttachment> {
+    SPOOLED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(id)
+        .filter(|attachment| !attachment.hash.is_empty())
+}
+
+fn remove(id: &str) {
+    let attachment = SPOOLED.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+    if let Some(attachment) = attachment {
+        match fs::remove_file(&attachment.path) {
+            Ok(()) => debug!("🗑️ 已刪除未使用的暫存附件: {}", attachment.path.display()),
+            Err(e) => warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無法刪除臨時文件 {}: {}",
+                    "⚠️ Failed to delete temporary file {}: {}",
+                    attachment.path.display(),
+                    e
+                )
+            ),
+        }
+    }
+}
+
+/// 一個請求所暫存的附件；釋放時刪除尚未被上傳流程取走的檔案
+#[derive(Default)]
+pub struct SpooledFiles(Vec<String>);
+
+impl SpooledFiles {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// 暫存附件在原始請求中所佔的 base64 位元組數，計入 max_input_bytes
+    pub fn encoded_bytes(&self) -> u64 {
+        let spooled = SPOOLED.lock().unwrap_or_else(|e| e.into_inner());
+        self.0
+            .iter()
+            .filter_map(|id| spooled.get(id))
+            .map(|attachment| attachment.encoded_size as u64)
+            .sum()
+    }
+}
+
+impl Drop for SpooledFiles {
+    fn drop(&mut self) {
+        for id in &self.0 {
+            remove(id);
+        }
+    }
+}
+
+/// 逐字元接收 JSON 字串中的 base64 內容並解碼寫入臨時檔案
+pub struct DataUrlSpool {
+    id: String,
+    writer: BufWriter<fs::File>,
+    pending: Vec<u8>,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    size: usize,
+    encoded_size: usize,
+    escape: bool,
+}
+
+impl DataUrlSpool {
+    /// 建立臨時檔案並登記到 files，之後無論成功與否都會隨 files 一併清理
+    pub fn create(files: &mut SpooledFiles, mime_type: &str) -> std::io::Result<Self> {
+        let id = nanoid!(21);
+        let file_ext = crate::utils::mime_type_to_extension(mime_type).unwrap_or("bin");
+        let path = std::env::temp_dir().join(format!("poe2openai_spool_{}.{}", id, file_ext));
+        let file = fs::File::create(&path)?;
+        debug!("📄 建立附件暫存檔案: {}", path.display());
+        SPOOLED.lock().unwrap_or_else(|e| e.into_inner()).insert(
+            id.clone(),
+            SpooledAttachment {
+                path,
+                hash: String::new(),
+                size: 0,
+                encoded_size: 0,
+            },
+        );
+        files.0.push(id.clone());
+        Ok(Self {
+            id,
+            writer: BufWriter::new(file),
+            pending: Vec::with_capacity(DECODE_BLOCK + 4),
+            head: Vec::with_capacity(HASH_EDGE),
+            tail: Vec::with_capacity(HASH_EDGE * 2),
+            size: 0,
+            encoded_size: 0,
+            escape: false,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// 接收 JSON 字串中的原始位元組（可能含跳脫字元）
+    pub fn push(&mut self, b: u8) -> std::io::Result<()> {
+        if self.escape {
+            self.escape = false;
+            return match b {
+                b'/' => self.accept(b'/'),
+                // 部分編碼器會在 base64 中插入換行
+                b'n' | b'r' | b't' => Ok(()),
+                _ => Err(invalid("data URL 中包含無法處理的跳脫字元")),
+            };
+        }
+        match b {
+            b'\\' => {
+                self.escape = true;
+                Ok(())
+            }
+            b' ' => Ok(()),
+            _ => self.accept(b),
+        }
+    }
+
+    fn accept(&mut self, b: u8) -> std::io::Result<()> {
+        self.encoded_size += 1;
+        if self.head.len() < HASH_EDGE {
+            self.head.push(b);
+        }
+        self.tail.push(b);
+        if self.tail.len() >= HASH_EDGE * 2 {
+            self.tail.drain(..HASH_EDGE);
+        }
+        self.pending.push(b);
+        if self.pending.len() >= DECODE_BLOCK {
+            self.decode(false)?;
+        }
+        Ok(())
+    }
+
+    fn decode(&mut self, last: bool) -> std::io::Result<()> {
+        let end = if last {
+            self.pending.len()
+        } else {
+            self.pending.len() / 4 * 4
+        };
+        let decoded = BASE64_STANDARD
+            .decode(&self.pending[..end])
+            .map_err(|e| invalid(&format!("base64 解碼失敗: {}", e)))?;
+        self.writer.write_all(&decoded)?;
+        self.size += decoded.len();
+        self.pending.drain(..end);
+        Ok(())
+    }
+
+    /// 寫入剩餘內容並記錄哈希，之後可透過 take 取出
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.decode(true)?;
+        self.writer.flush()?;
+        let start = self.tail.len().saturating_sub(HASH_EDGE);
+        let mut hasher = Sha256::new();
+        hasher.update(&self.head);
+        hasher.update(&self.tail[start..]);
+        let hash = format!("{:x}", hasher.finalize());
+        debug!(
+            "✅ 附件暫存完成 | ID: {} | 大小: {} bytes | 哈希頭部: {}...",
+            self.id,
+            self.size,
+            &hash[..8]
+        );
+        if let Some(attachment) = SPOOLED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&self.id)
+        {
+            attachment.hash = hash;
+            attachment.size = self.size;
+            attachment.encoded_size = self.encoded_size;
+        }
+        Ok(())
+    }
+}
+
+/// 走訪 JSON 中所有的暫存引用，以 f 的返回值取代；f 返回 None 時保留原字串
+fn replace_refs(value: &mut Value, f: &impl Fn(&str, &SpooledAttachment) -> Option<String>) {
+    match value {
+        Value::String(s) => {
+            let Some((mime_type, id)) = s
+                .strip_prefix("data:")
+                .and_then(|rest| rest.split_once(SPOOL_MARKER))
+            else {
+                return;
+            };
+            let replaced = SPOOLED
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(id)
+                .filter(|attachment| !attachment.hash.is_empty())
+                .and_then(|attachment| f(mime_type, attachment));
+            if let Some(replaced) = replaced {
+                *s = replaced;
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| replace_refs(item, f)),
+        Value::Object(map) => map.values_mut().for_each(|item| replace_refs(item, f)),
+        _ => {}
+    }
+}
+
+/// 將暫存引用還原為原本的 base64 data URL，用於儲存聊天完成及對話記錄
+pub fn resolve_refs(value: &mut Value) {
+    replace_refs(value, &|mime_type, attachment| {
+        match fs::read(&attachment.path) {
+            Ok(data) => Some(format!(
+                "data:{};base64,{}",
+                mime_type,
+                BASE64_STANDARD.encode(data)
+            )),
+            Err(e) => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 無法讀取暫存附件 {}: {}",
+                        "⚠️ Failed to read spooled attachment {}: {}",
+                        attachment.path.display(),
+                        e
+                    )
+                );
+                // 無法還原時移除內容，不在記錄中保留暫存 ID
+                Some(format!("data:{};base64,", mime_type))
+            }
+        }
+    });
+}
+
+/// 將暫存引用中隨機的 ID 換成內容哈希，讓相同附件的請求得到相同的內容
+pub fn hash_refs(value: &mut Value) {
+    replace_refs(value, &|mime_type, attachment| {
+        Some(format!(
+            "data:{};spool-sha256,{}:{}",
+            mime_type, attachment.hash, attachment.size
+        ))
+    });
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn spool(files: &mut SpooledFiles, encoded: &str) -> String {
+        let mut spool = DataUrlSpool::create(files, "image/png").unwrap();
+        for b in encoded.bytes() {
+            spool.push(b).unwrap();
+        }
+        let url = format!("data:image/png{}{}", SPOOL_MARKER, spool.id());
+        spool.finish().unwrap();
+        url
+    }
+
+    #[test]
+    fn refs_are_resolved_and_hashed_by_content() {
+        let encoded = BASE64_STANDARD.encode(vec![7u8; 6000]);
+        let mut files = SpooledFiles::default();
+        let first = spool(&mut files, &encoded);
+        let second = spool(&mut files, &encoded);
+        assert_ne!(first, second);
+        assert_eq!(files.encoded_bytes(), 2 * encoded.len() as u64);
+
+        let mut hashed = [json!({ "url": first }), json!({ "url": second })];
+        hashed.iter_mut().for_each(hash_refs);
+        assert_eq!(hashed[0], hashed[1]);
+
+        let mut resolved = json!([{ "url": first }, "data:image/png;spool,unknown"]);
+        resolve_refs(&mut resolved);
+        assert_eq!(
+            resolved,
+            json!([
+                { "url": format!("data:image/png;base64,{}", encoded) },
+                "data:image/png;spool,unknown"
+            ])
+        );
+
+        // 釋放時刪除暫存檔案
+        let paths: Vec<PathBuf> = [&first, &second]
+            .iter()
+            .map(|url| {
+                let id = url.split_once(SPOOL_MARKER).unwrap().1;
+                SPOOLED.lock().unwrap().get(id).unwrap().path.clone()
+            })
+            .collect();
+        drop(files);
+        assert!(paths.iter().all(|path| !path.exists()));
+    }
+}
@@ -0,0 +1,706 @@
+//! 已儲存的聊天完成記錄 (store=true) 與對話記錄，保存在持久化的 sled 資料庫中
+
+use crate::redact::redact_value;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::Transactional;
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::OnceLock;
+use tracing::{debug, error, info};
+
+const COMPLETIONS_TREE: &str = "chat_completions";
+/// 聊天完成記錄依 (擁有者, 建立時間, ID) 排序的索引，值為記錄 ID
+const COMPLETIONS_INDEX_TREE: &str = "chat_completions_by_created";
+const CONVERSATIONS_TREE: &str = "conversations";
+/// 對話記錄依 (擁有者, 最近更新時間, ID) 排序的索引，值為對話 ID
+const CONVERSATIONS_INDEX_TREE: &str = "conversations_by_updated";
+pub(crate) const USAGE_TREE: &str = "usage";
+pub(crate) const IMAGE_CACHE_TREE: &str = "image_cache";
+pub(crate) const RUNTIME_TREE: &str = "runtime_settings";
+
+/// 儲存用的 sled 資料庫（與記憶體緩存分開，重啟後仍保留）
+static STORE_DB: OnceLock<Option<sled::Db>> = OnceLock::new();
+
+fn store_path() -> std::path::PathBuf {
+    std::env::var("COMPLETIONS_STORE_PATH")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| crate::utils::get_config_path("completions_store"))
+}
+
+/// 儲存資料庫是否已存在；尚未開啟時只檢查路徑，不會建立資料庫
+pub(crate) fn store_exists() -> bool {
+    STORE_DB.get().is_some_and(Option::is_some) || store_path().exists()
+}
+
+fn get_store_db() -> Option<&'static sled::Db> {
+    STORE_DB
+        .get_or_init(|| {
+            let path = store_path();
+            match sled::open(&path) {
+                Ok(db) => {
+                    info!(
+                        "{}",
+                        tr!(
+                            "💾 聊天完成記錄儲存路徑: {}",
+                            "💾 Chat completion store path: {}",
+                            path.display()
+                        )
+                    );
+                    Some(db)
+                }
+                Err(e) => {
+                    error!(
+                        "{}",
+                        tr!(
+                            "❌ 無法開啟聊天完成記錄儲存 {}: {}",
+                            "❌ Failed to open chat completion store {}: {}",
+                            path.display(),
+                            e
+                        )
+                    );
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// 儲存資料庫統計，尚未開啟時返回 None（不會為統計而建立資料庫）
+pub fn store_stats() -> Option<serde_json::Value> {
+    let db = STORE_DB.get()?.as_ref()?;
+    let tree_len = |name: &str| db.open_tree(name).map(|tree| tree.len()).unwrap_or(0);
+    Some(serde_json::json!({
+        "size_on_disk": db.size_on_disk().unwrap_or(0),
+        "completions": tree_len(COMPLETIONS_TREE),
+        "conversations": tree_len(CONVERSATIONS_TREE),
+        "usage_buckets": tree_len(USAGE_TREE),
+        "image_cache": tree_len(IMAGE_CACHE_TREE),
+    }))
+}
+
+pub(crate) fn open_store_tree(name: &str) -> Option<sled::Tree> {
+    match get_store_db()?.open_tree(name) {
+        Ok(tree) => Some(tree),
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 無法開啟儲存樹 {}: {}",
+                    "❌ Failed to open store tree {}: {}",
+                    name,
+                    e
+                )
+            );
+            None
+        }
+    }
+}
+
+fn get_completions_tree() -> Option<sled::Tree> {
+    open_store_tree(COMPLETIONS_TREE)
+}
+
+static COMPLETIONS_INDEX: OnceLock<Option<sled::Tree>> = OnceLock::new();
+
+/// 索引中列出所有擁有者記錄的範圍（擁有者為十六進位雜湊，不會與此衝突）
+const ALL_OWNERS: &str = "*";
+
+/// 索引鍵：{範圍}\0{建立時間}{ID}，建立時間翻轉符號位後以大端序寫入，位元組順序即為時間順序
+fn index_key(scope: &str, created: i64, id: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(scope.len() + 9 + id.len());
+    key.extend_from_slice(scope.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&((created as u64) ^ (1 << 63)).to_be_bytes());
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+/// 一筆記錄在擁有者範圍及所有擁有者範圍中的索引鍵
+fn index_keys(record: &StoredCompletion) -> [Vec<u8>; 2] {
+    [
+        index_key(&record.owner, record.created, &record.id),
+        index_key(ALL_OWNERS, record.created, &record.id),
+    ]
+}
+
+/// 開啟聊天完成記錄的索引，索引為空而已有記錄時（舊版資料）先重建
+fn get_completions_index() -> Option<sled::Tree> {
+    COMPLETIONS_INDEX
+        .get_or_init(|| {
+            let tree = get_completions_tree()?;
+            let index = open_store_tree(COMPLETIONS_INDEX_TREE)?;
+            if index.is_empty() && !tree.is_empty() {
+                let mut count = 0;
+                for bytes in tree.iter().values().filter_map(|value| value.ok()) {
+                    let Ok(record) = serde_json::from_slice::<StoredCompletion>(&bytes) else {
+                        continue;
+                    };
+                    for key in index_keys(&record) {
+                        index.insert(key, record.id.as_bytes()).ok();
+                    }
+                    count += 1;
+                }
+                index.flush().ok();
+                info!(
+                    "{}",
+                    tr!(
+                        "💾 已重建聊天完成記錄索引 | 記錄數: {}",
+                        "💾 Rebuilt the chat completion index | records: {}",
+                        count
+                    )
+                );
+            }
+            Some(index)
+        })
+        .clone()
+}
+
+fn get_conversations_tree() -> Option<sled::Tree> {
+    open_store_tree(CONVERSATIONS_TREE)
+}
+
+static CONVERSATIONS_INDEX: OnceLock<Option<sled::Tree>> = OnceLock::new();
+
+fn conversation_index_key(conversation: &Conversation) -> Vec<u8> {
+    index_key(&conversation.owner, conversation.updated, &conversation.id)
+}
+
+/// 開啟對話記錄的索引，索引為空而已有記錄時（舊版資料）先重建
+fn get_conversations_index() -> Option<sled::Tree> {
+    CONVERSATIONS_INDEX
+        .get_or_init(|| {
+            let tree = get_conversations_tree()?;
+            let index = open_store_tree(CONVERSATIONS_INDEX_TREE)?;
+            if index.is_empty() && !tree.is_empty() {
+                let mut count = 0;
+                for bytes in tree.iter().values().filter_map(|value| value.ok()) {
+                    let Ok(conversation) = serde_json::from_slice::<Conversation>(&bytes) else {
+                        continue;
+                    };
+                    index
+                        .insert(
+                            conversation_index_key(&conversation),
+                            conversation.id.as_bytes(),
+                        )
+                        .ok();
+                    count += 1;
+                }
+                index.flush().ok();
+                info!(
+                    "{}",
+                    tr!(
+                        "💾 已重建對話記錄索引 | 對話數: {}",
+                        "💾 Rebuilt the conversation index | conversations: {}",
+                        count
+                    )
+                );
+            }
+            Some(index)
+        })
+        .clone()
+}
+
+/// 以 API Key 的雜湊標記記錄擁有者，只有相同的 Key 才能讀取
+pub fn owner_hash(access_key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(access_key.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// 一筆已儲存的聊天完成記錄
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StoredCompletion {
+    pub id: String,
+    pub created: i64,
+    pub model: String,
+    pub owner: String,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// 請求中的訊息（OpenAI 格式）
+    pub messages: serde_json::Value,
+    /// 完整的 chat.completion 回應
+    pub response: serde_json::Value,
+}
+
+impl StoredCompletion {
+    /// 轉為 OpenAI stored completion 格式
+    pub fn to_completion_object(&self) -> serde_json::Value {
+        let mut object = self.response.clone();
+        object["metadata"] = serde_json::json!(self.metadata);
+        object
+    }
+}
+
+/// 請求時即可確定的儲存資訊，待回應完成後寫入
+pub struct PendingStore {
+    pub owner: String,
+    pub metadata: HashMap<String, String>,
+    pub messages: serde_json::Value,
+}
+
+impl PendingStore {
+    /// 以完成的回應建立記錄並寫入
+    pub fn complete(&self, mut response: serde_json::Value) {
+        let id = response["id"].as_str().unwrap_or_default().to_string();
+        let created = response["created"].as_i64().unwrap_or_default();
+        let model = response["model"].as_str().unwrap_or_default().to_string();
+        let mut messages = self.messages.clone();
+        redact_value(&mut messages);
+        redact_value(&mut response);
+        let record = StoredCompletion {
+            id,
+            created,
+            model,
+            owner: self.owner.clone(),
+            metadata: self.metadata.clone(),
+            messages,
+            response,
+        };
+        save_completion(&record);
+    }
+}
+
+/// 寫入一筆聊天完成記錄
+pub fn save_completion(record: &StoredCompletion) {
+    let Some(tree) = get_completions_tree() else {
+        return;
+    };
+    let bytes = match serde_json::to_vec(record) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 序列化聊天完成記錄失敗: {}",
+                    "❌ Failed to serialize chat completion: {}",
+                    e
+                )
+            );
+            return;
+        }
+    };
+    let Some(index) = get_completions_index() else {
+        return;
+    };
+    let result: sled::transaction::TransactionResult<(), sled::Error> = (&tree, &index)
+        .transaction(|(tree, index)| {
+            if let Some(old) = tree.insert(record.id.as_bytes(), bytes.as_slice())?
+                && let Ok(old) = serde_json::from_slice::<StoredCompletion>(&old)
+            {
+                for key in index_keys(&old) {
+                    index.remove(key)?;
+                }
+            }
+            for key in index_keys(record) {
+                index.insert(key, record.id.as_bytes())?;
+            }
+            Ok(())
+        });
+    match result {
+        Ok(()) => {
+            tree.flush().ok();
+            index.flush().ok();
+            debug!("💾 已儲存聊天完成記錄 | ID: {}", record.id);
+        }
+        Err(e) => error!(
+            "{}",
+            tr!(
+                "❌ 儲存聊天完成記錄失敗: {}",
+                "❌ Failed to store chat completion: {}",
+                e
+            )
+        ),
+    }
+}
+
+/// 讀取一筆聊天完成記錄，擁有者不符時視為不存在
+pub fn get_completion(id: &str, owner: &str) -> Option<StoredCompletion> {
+    read_completion(id).filter(|record| record.owner == owner)
+}
+
+/// 讀取一筆聊天完成記錄，不檢查擁有者（僅供管理介面使用）
+pub fn read_completion(id: &str) -> Option<StoredCompletion> {
+    let tree = get_completions_tree()?;
+    let bytes = tree.get(id.as_bytes()).ok()??;
+    match serde_json::from_slice::<StoredCompletion>(&bytes) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 解析聊天完成記錄失敗 | ID: {} | 錯誤: {}",
+                    "❌ Failed to parse stored chat completion | ID: {} | error: {}",
+                    id,
+                    e
+                )
+            );
+            None
+        }
+    }
+}
+
+/// 刪除一筆聊天完成記錄，返回是否已刪除
+pub fn delete_completion(id: &str, owner: &str) -> bool {
+    let Some(record) = get_completion(id, owner) else {
+        return false;
+    };
+    let (Some(tree), Some(index)) = (get_completions_tree(), get_completions_index()) else {
+        return false;
+    };
+    let result: sled::transaction::TransactionResult<bool, sled::Error> = (&tree, &index)
+        .transaction(|(tree, index)| {
+            for key in index_keys(&record) {
+                index.remove(key)?;
+            }
+            Ok(tree.remove(id.as_bytes())?.is_some())
+        });
+    match result {
+        Ok(removed) => {
+            tree.flush().ok();
+            index.flush().ok();
+            removed
+        }
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 刪除聊天完成記錄失敗: {}",
+                    "❌ Failed to delete stored chat completion: {}",
+                    e
+                )
+            );
+            false
+        }
+    }
+}
+
+/// 列出聊天完成記錄時的篩選條件
+#[derive(Default)]
+pub struct CompletionFilter {
+    pub model: Option<String>,
+    pub metadata: HashMap<String, String>,
+    pub created_after: Option<i64>,
+    pub created_before: Option<i64>,
+    /// 游標：從此 ID 之後開始
+    pub after: Option<String>,
+    pub limit: usize,
+    pub descending: bool,
+}
+
+impl CompletionFilter {
+    fn matches(&self, record: &StoredCompletion) -> bool {
+        self.model.as_ref().is_none_or(|m| &record.model == m)
+            && self
+                .metadata
+                .iter()
+                .all(|(k, v)| record.metadata.get(k) == Some(v))
+    }
+}
+
+/// 游標指向的記錄不存在（或不屬於此擁有者）
+pub struct UnknownCursor(pub String);
+
+/// 依建立時間排序列出擁有者的聊天完成記錄，返回 (記錄, 是否還有更多)
+/// 擁有者為 None 時列出所有記錄（僅供管理介面使用）
+/// 以索引從游標或時間範圍的起點依序讀取，只載入需要的記錄
+pub fn list_completions(
+    owner: Option<&str>,
+    filter: &CompletionFilter,
+) -> Result<(Vec<StoredCompletion>, bool), UnknownCursor> {
+    let (Some(tree), Some(index)) = (get_completions_tree(), get_completions_index()) else {
+        return Ok((Vec::new(), false));
+    };
+    let scope = owner.unwrap_or(ALL_OWNERS);
+    let mut start = match filter.created_after {
+        Some(t) => index_key(scope, t, ""),
+        None => index_key(scope, i64::MIN, ""),
+    };
+    let mut end = match filter.created_before {
+        Some(t) => index_key(scope, t, ""),
+        // 範圍前綴之後的第一個鍵
+        None => [scope.as_bytes(), &[1]].concat(),
+    };
+    let mut start_bound = Bound::Included(start.clone());
+    if let Some(after) = &filter.after {
+        let cursor = read_completion(after)
+            .filter(|record| owner.is_none_or(|o| record.owner == o))
+            .ok_or_else(|| UnknownCursor(after.clone()))?;
+        let cursor = index_key(scope, cursor.created, &cursor.id);
+        if filter.descending {
+            end = end.min(cursor);
+        } else if cursor >= start {
+            start = cursor;
+            start_bound = Bound::Excluded(start.clone());
+        }
+    }
+    if start >= end {
+        return Ok((Vec::new(), false));
+    }
+    let range = index.range::<Vec<u8>, _>((start_bound, Bound::Excluded(end)));
+    let ids: Box<dyn Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>> = if filter.descending
+    {
+        Box::new(range.rev())
+    } else {
+        Box::new(range)
+    };
+    let mut records: Vec<StoredCompletion> = ids
+        .filter_map(|entry| entry.ok())
+        .filter_map(|(_, id)| tree.get(id).ok().flatten())
+        .filter_map(|bytes| serde_json::from_slice::<StoredCompletion>(&bytes).ok())
+        .filter(|record| filter.matches(record))
+        .take(filter.limit + 1)
+        .collect();
+    let has_more = records.len() > filter.limit;
+    records.truncate(filter.limit);
+    debug!(
+        "💾 列出聊天完成記錄 | 數量: {} | 還有更多: {}",
+        records.len(),
+        has_more
+    );
+    Ok((records, has_more))
+}
+
+/// 對話中的一輪：本輪新增的輸入訊息與助手回覆
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConversationTurn {
+    pub completion_id: String,
+    pub created: i64,
+    pub model: String,
+    /// 上一則助手訊息之後的輸入訊息（OpenAI 格式）
+    pub input: serde_json::Value,
+    /// 助手回覆（choices[0].message）
+    pub output: serde_json::Value,
+    pub usage: serde_json::Value,
+}
+
+/// 一段對話記錄，以 {擁有者}:{對話 ID} 為鍵，不同 API Key 的相同 ID 互不影響
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Conversation {
+    pub id: String,
+    pub owner: String,
+    pub created: i64,
+    pub updated: i64,
+    /// 最近一輪使用的模型
+    pub model: String,
+    pub turns: Vec<ConversationTurn>,
+}
+
+impl Conversation {
+    /// 轉為 API 回應格式
+    pub fn to_object(&self, include_turns: bool) -> serde_json::Value {
+        let mut object = serde_json::json!({
+            "id": self.id,
+            "object": "conversation",
+            "created": self.created,
+            "updated": self.updated,
+            "model": self.model,
+            "turn_count": self.turns.len(),
+        });
+        if include_turns {
+            object["turns"] = serde_json::json!(self.turns);
+        }
+        object
+    }
+}
+
+fn conversation_key(owner: &str, id: &str) -> String {
+    format!("{}:{}", owner, id)
+}
+
+/// 請求時即可確定的對話資訊，待回應完成後追加為新的一輪
+pub struct PendingTurn {
+    pub owner: String,
+    pub conversation_id: String,
+    pub input: serde_json::Value,
+}
+
+impl PendingTurn {
+    /// 從請求訊息中取出上一則助手訊息之後的輸入
+    pub fn new(owner: String, conversation_id: String, messages: &serde_json::Value) -> Self {
+        let input = messages
+            .as_array()
+            .map(|messages| {
+                let start = messages
+                    .iter()
+                    .rposition(|m| m["role"] == "assistant")
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                serde_json::Value::Array(messages[start..].to_vec())
+            })
+            .unwrap_or_default();
+        Self {
+            owner,
+            conversation_id,
+            input,
+        }
+    }
+
+    /// 以完成的回應追加一輪對話
+    pub fn complete(&self, response: &serde_json::Value) {
+        let (Some(tree), Some(index)) = (get_conversations_tree(), get_conversations_index())
+        else {
+            return;
+        };
+        let key = conversation_key(&self.owner, &self.conversation_id);
+        let created = response["created"].as_i64().unwrap_or_default();
+        let model = response["model"].as_str().unwrap_or_default().to_string();
+        let mut turn = ConversationTurn {
+            completion_id: response["id"].as_str().unwrap_or_default().to_string(),
+            created,
+            model: model.clone(),
+            input: self.input.clone(),
+            output: response["choices"][0]["message"].clone(),
+            usage: response["usage"].clone(),
+        };
+        redact_value(&mut turn.input);
+        redact_value(&mut turn.output);
+
+        // 在交易中讀取、追加並寫回，同時更新索引，同一對話的並行請求不會互相覆蓋
+        // （寫入衝突時閉包會以最新的值重新執行）
+        let result: sled::transaction::TransactionResult<(), sled::Error> = (&tree, &index)
+            .transaction(|(tree, index)| {
+                let old = tree
+                    .get(key.as_bytes())?
+                    .and_then(|bytes| serde_json::from_slice::<Conversation>(&bytes).ok());
+                if let Some(old) = &old {
+                    index.remove(conversation_index_key(old))?;
+                }
+                let mut conversation = old.unwrap_or_else(|| Conversation {
+                    id: self.conversation_id.clone(),
+                    owner: self.owner.clone(),
+                    created,
+                    updated: created,
+                    model: model.clone(),
+                    turns: Vec::new(),
+                });
+                conversation.updated = created;
+                conversation.model = model.clone();
+                conversation.turns.push(turn.clone());
+                let Ok(bytes) = serde_json::to_vec(&conversation) else {
+                    // 序列化失敗時放棄本輪，保留原本的對話
+                    return sled::transaction::abort(sled::Error::Unsupported(
+                        "無法序列化對話記錄".to_string(),
+                    ));
+                };
+                tree.insert(key.as_bytes(), bytes)?;
+                index.insert(
+                    conversation_index_key(&conversation),
+                    conversation.id.as_bytes(),
+                )?;
+                Ok(())
+            });
+        match result {
+            Ok(()) => {
+                tree.flush().ok();
+                index.flush().ok();
+                debug!("💬 已記錄對話 | ID: {}", self.conversation_id);
+            }
+            Err(e) => error!(
+                "{}",
+                tr!(
+                    "❌ 儲存對話記錄失敗: {}",
+                    "❌ Failed to store conversation: {}",
+                    e
+                )
+            ),
+        }
+    }
+}
+
+/// 讀取一段對話記錄
+pub fn get_conversation(id: &str, owner: &str) -> Option<Conversation> {
+    let tree = get_conversations_tree()?;
+    let bytes = tree.get(conversation_key(owner, id).as_bytes()).ok()??;
+    match serde_json::from_slice::<Conversation>(&bytes) {
+        Ok(conversation) => Some(conversation),
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 解析對話記錄失敗 | ID: {} | 錯誤: {}",
+                    "❌ Failed to parse conversation | ID: {} | error: {}",
+                    id,
+                    e
+                )
+            );
+            None
+        }
+    }
+}
+
+/// 刪除一段對話記錄，返回是否已刪除
+pub fn delete_conversation(id: &str, owner: &str) -> bool {
+    let (Some(tree), Some(index)) = (get_conversations_tree(), get_conversations_index()) else {
+        return false;
+    };
+    let key = conversation_key(owner, id);
+    let result: sled::transaction::TransactionResult<bool, sled::Error> = (&tree, &index)
+        .transaction(|(tree, index)| {
+            let Some(bytes) = tree.remove(key.as_bytes())? else {
+                return Ok(false);
+            };
+            if let Ok(conversation) = serde_json::from_slice::<Conversation>(&bytes) {
+                index.remove(conversation_index_key(&conversation))?;
+            }
+            Ok(true)
+        });
+    match result {
+        Ok(removed) => {
+            tree.flush().ok();
+            index.flush().ok();
+            removed
+        }
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 刪除對話記錄失敗: {}",
+                    "❌ Failed to delete conversation: {}",
+                    e
+                )
+            );
+            false
+        }
+    }
+}
+
+/// 依最近更新時間（新到舊）列出擁有者的對話記錄，返回 (記錄, 是否還有更多)
+/// 以索引從游標處依序讀取，只載入需要的對話
+pub fn list_conversations(
+    owner: &str,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<(Vec<Conversation>, bool), UnknownCursor> {
+    let (Some(tree), Some(index)) = (get_conversations_tree(), get_conversations_index()) else {
+        return Ok((Vec::new(), false));
+    };
+    let start = index_key(owner, i64::MIN, "");
+    let end = match after {
+        Some(after) => {
+            let cursor =
+                get_conversation(after, owner).ok_or_else(|| UnknownCursor(after.to_string()))?;
+            conversation_index_key(&cursor)
+        }
+        // 範圍前綴之後的第一個鍵
+        None => [owner.as_bytes(), &[1]].concat(),
+    };
+    let mut conversations: Vec<Conversation> = index
+        .range(start..end)
+        .rev()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|(_, id)| {
+            let id = String::from_utf8_lossy(&id).into_owned();
+            tree.get(conversation_key(owner, &id).as_bytes())
+                .ok()
+                .flatten()
+        })
+        .filter_map(|bytes| serde_json::from_slice::<Conversation>(&bytes).ok())
+        .take(limit + 1)
+        .collect();
+    let has_more = conversations.len() > limit;
+    conversations.truncate(limit);
+    Ok((conversations, has_more))
+}
@@ -0,0 +1,144 @@
+//! systemd 整合：Type=notify 就緒通知與 socket activation
+
+use salvo::conn::Acceptor;
+use salvo::conn::tcp::TcpAcceptor;
+use tracing::{debug, info, warn};
+
+/// systemd 傳入的第一個檔案描述符編號 (SD_LISTEN_FDS_START)
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// 取得 systemd socket activation 傳入的監聽器
+/// 未由 systemd 啟動或 LISTEN_PID 不符時返回空列表
+#[cfg(unix)]
+pub fn take_activated_listeners() -> Vec<TcpAcceptor> {
+    use std::os::fd::FromRawFd;
+
+    let listen_pid = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok());
+    if listen_pid != Some(std::process::id()) {
+        return Vec::new();
+    }
+    let listen_fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|s| s.parse::<i32>().ok())
+        .unwrap_or(0);
+    if listen_fds <= 0 {
+        return Vec::new();
+    }
+    info!(
+        "{}",
+        tr!(
+            "🔌 偵測到 systemd socket activation | 檔案描述符數量: {}",
+            "🔌 systemd socket activation detected | file descriptors: {}",
+            listen_fds
+        )
+    );
+
+    let mut acceptors = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + listen_fds {
+        // SAFETY: systemd 保證 LISTEN_FDS 範圍內的描述符已開啟並交由本進程擁有
+        let std_listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        let result = std_listener
+            .set_nonblocking(true)
+            .and_then(|_| tokio::net::TcpListener::from_std(std_listener))
+            .and_then(TcpAcceptor::try_from);
+        match result {
+            Ok(acceptor) => {
+                if let Some(holding) = acceptor.holdings().first() {
+                    info!(
+                        "{}",
+                        tr!(
+                            "🎯 服務已啟動並監聽於 {} (systemd fd {})",
+                            "🎯 Server listening on {} (systemd fd {})",
+                            holding.local_addr,
+                            fd
+                        )
+                    );
+                }
+                acceptors.push(acceptor);
+            }
+            Err(e) => warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無法使用 systemd 傳入的描述符 {}: {}",
+                    "⚠️ Cannot use systemd file descriptor {}: {}",
+                    fd,
+                    e
+                )
+            ),
+        }
+    }
+    acceptors
+}
+
+#[cfg(not(unix))]
+pub fn take_activated_listeners() -> Vec<TcpAcceptor> {
+    Vec::new()
+}
+
+/// 向 systemd 發送狀態通知 (sd_notify)
+/// 未設置 NOTIFY_SOCKET 時不做任何事
+#[cfg(unix)]
+fn sd_notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Ok(socket_path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無法建立 systemd 通知 socket: {}",
+                    "⚠️ Failed to create systemd notify socket: {}",
+                    e
+                )
+            );
+            return;
+        }
+    };
+
+    let result = if let Some(abstract_name) = socket_path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            std::os::unix::net::SocketAddr::from_abstract_name(abstract_name)
+                .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = abstract_name;
+            Err(std::io::Error::other("不支援 abstract socket"))
+        }
+    } else {
+        socket.send_to(state.as_bytes(), &socket_path)
+    };
+
+    match result {
+        Ok(_) => debug!("📣 已發送 systemd 通知: {}", state.replace('\n', " ")),
+        Err(e) => warn!(
+            "{}",
+            tr!(
+                "⚠️ 發送 systemd 通知失敗: {}",
+                "⚠️ Failed to send systemd notification: {}",
+                e
+            )
+        ),
+    }
+}
+
+#[cfg(not(unix))]
+fn sd_notify(_state: &str) {}
+
+/// 通知 systemd 服務已就緒（監聽器已建立且 sled 已開啟）
+pub fn notify_ready(status: &str) {
+    sd_notify(&format!(
+        "READY=1\nSTATUS={}\nMAINPID={}",
+        status,
+        std::process::id()
+    ));
+}
@@ -0,0 +1,289 @@
+//! strict 工具的參數檢查：工具定義設定 `strict: true` 時，以 parameters 的 JSON Schema 檢查
+//! 機器人返回的 tool_calls 參數
+//!
+//! 可自動修正的小問題會直接修正：參數被程式碼區塊包裹或帶有多餘逗號、字串形式的數字與布林值、
+//! 數字形式的字串、schema 未定義的多餘欄位，以及可為 null 的必填欄位缺漏。其餘問題返回錯誤
+
+use poe_api_process::types::ChatToolCall;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use tracing::debug;
+
+/// 工具名稱對應的參數 JSON Schema
+pub type StrictToolSchemas = HashMap<String, Value>;
+
+/// $ref 與巢狀結構的最大展開深度
+const MAX_DEPTH: usize = 64;
+
+static TRAILING_COMMA: LazyLock<Regex> = LazyLock::new(|| Regex::new(r",\s*([}\]])").unwrap());
+
+/// 檢查並修正 strict 工具的調用參數，非 strict 工具不處理；失敗時返回錯誤說明
+pub fn check_tool_call(call: &mut ChatToolCall, schemas: &StrictToolSchemas) -> Result<(), String> {
+    let Some(schema) = schemas.get(&call.function.name) else {
+        return Ok(());
+    };
+    let arguments = parse_arguments(&call.function.arguments)
+        .ok_or_else(|| "參數不是有效的 JSON".to_string())?;
+    let checker = Checker { root: schema };
+    let fixed = checker.conform(arguments, schema, "$", 0)?;
+    let fixed = serde_json::to_string(&fixed).unwrap_or_default();
+    if fixed != call.function.arguments {
+        debug!(
+            "🔧 修正工具調用參數 | 工具: {} | 原始: {} | 修正後: {}",
+            call.function.name, call.function.arguments, fixed
+        );
+        call.function.arguments = fixed;
+    }
+    Ok(())
+}
+
+/// 解析參數 JSON，容忍程式碼區塊包裹、前後多餘文字與多餘逗號
+fn parse_arguments(arguments: &str) -> Option<Value> {
+    let text = arguments.trim();
+    if text.is_empty() {
+        return Some(Value::Object(Map::new()));
+    }
+    if let Ok(value) = serde_json::from_str(text) {
+        return Some(value);
+    }
+    let text = text
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    let text = text.get(start..=end)?;
+    serde_json::from_str(text)
+        .ok()
+        .or_else(|| serde_json::from_str(&TRAILING_COMMA.replace_all(text, "$1")).ok())
+}
+
+struct Checker<'a> {
+    root: &'a Value,
+}
+
+impl<'a> Checker<'a> {
+    /// 依 schema 檢查並修正值
+    fn conform(
+        &self,
+        value: Value,
+        schema: &'a Value,
+        path: &str,
+        depth: usize,
+    ) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("{} 的 schema 巢狀過深", path));
+        }
+        let schema = self.resolve(schema, path)?;
+
+        if let Some(options) = schema
+            .get("anyOf")
+            .or_else(|| schema.get("oneOf"))
+            .and_then(Value::as_array)
+        {
+            let mut last_error = None;
+            for option in options {
+                match self.conform(value.clone(), option, path, depth + 1) {
+                    Ok(value) => return Ok(value),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            return Err(last_error.unwrap_or_else(|| format!("{} 不符合任何允許的格式", path)));
+        }
+
+        let types = schema_types(schema);
+        let value = if types.is_empty() {
+            value
+        } else {
+            self.conform_type(value, schema, &types, path, depth)?
+        };
+
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array)
+            && !allowed.contains(&value)
+        {
+            return Err(format!("{} 的值 {} 不在允許的值中", path, value));
+        }
+        if let Some(expected) = schema.get("const")
+            && expected != &value
+        {
+            return Err(format!("{} 的值應為 {}", path, expected));
+        }
+        Ok(value)
+    }
+
+    /// 展開 #/ 開頭的 $ref
+    fn resolve(&self, schema: &'a Value, path: &str) -> Result<&'a Value, String> {
+        let mut schema = schema;
+        for _ in 0..MAX_DEPTH {
+            let Some(reference) = schema.get("$ref").and_then(Value::as_str) else {
+                return Ok(schema);
+            };
+            schema = reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.root.pointer(pointer))
+                .ok_or_else(|| format!("{} 的 $ref 無法解析: {}", path, reference))?;
+        }
+        Err(format!("{} 的 $ref 巢狀過深", path))
+    }
+
+    fn conform_type(
+        &self,
+        value: Value,
+        schema: &'a Value,
+        types: &[&str],
+        path: &str,
+        depth: usize,
+    ) -> Result<Value, String> {
+        if value.is_null() && types.contains(&"null") {
+            return Ok(value);
+        }
+        for ty in types {
+            if let Some(value) = coerce(&value, ty) {
+                return match *ty {
+                    "object" => self.conform_object(value, schema, path, depth),
+                    "array" => self.conform_array(value, schema, path, depth),
+                    _ => Ok(value),
+                };
+            }
+        }
+        Err(format!(
+            "{} 應為 {}，實際為 {}",
+            path,
+            types.join(" 或 "),
+            value
+        ))
+    }
+
+    fn conform_object(
+        &self,
+        value: Value,
+        schema: &'a Value,
+        path: &str,
+        depth: usize,
+    ) -> Result<Value, String> {
+        let Value::Object(mut object) = value else {
+            return Ok(value);
+        };
+        let empty = Map::new();
+        let properties = schema
+            .get("properties")
+            .and_then(Value::as_object)
+            .unwrap_or(&empty);
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|names| names.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        let mut result = Map::new();
+        for (name, property) in properties {
+            let property_path = format!("{}.{}", path, name);
+            match object.remove(name) {
+                Some(value) => {
+                    let value = self.conform(value, property, &property_path, depth + 1)?;
+                    result.insert(name.clone(), value);
+                }
+                None if required.contains(&name.as_str()) => {
+                    // 可為 null 的必填欄位以 null 補上
+                    let nullable = self
+                        .conform(Value::Null, property, &property_path, depth + 1)
+                        .is_ok();
+                    if !nullable {
+                        return Err(format!("{} 缺少必填欄位", property_path));
+                    }
+                    result.insert(name.clone(), Value::Null);
+                }
+                None => {}
+            }
+        }
+
+        // strict 模式下未設定 additionalProperties 視為不允許多餘欄位
+        for (name, value) in object {
+            match schema.get("additionalProperties") {
+                Some(Value::Bool(true)) => {
+                    result.insert(name, value);
+                }
+                Some(extra) if extra.is_object() => {
+                    let property_path = format!("{}.{}", path, name);
+                    let value = self.conform(value, extra, &property_path, depth + 1)?;
+                    result.insert(name, value);
+                }
+                _ => debug!("🔧 移除工具調用參數中的多餘欄位: {}.{}", path, name),
+            }
+        }
+        Ok(Value::Object(result))
+    }
+
+    fn conform_array(
+        &self,
+        value: Value,
+        schema: &'a Value,
+        path: &str,
+        depth: usize,
+    ) -> Result<Value, String> {
+        let Value::Array(items) = value else {
+            return Ok(value);
+        };
+        let Some(item_schema) = schema.get("items") else {
+            return Ok(Value::Array(items));
+        };
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| {
+                self.conform(
+                    item,
+                    item_schema,
+                    &format!("{}[{}]", path, index),
+                    depth + 1,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array)
+    }
+}
+
+/// schema 允許的類型，未指定時返回空
+fn schema_types(schema: &Value) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Value::String(ty)) => vec![ty.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// 將值轉換為指定類型，無法轉換時返回 None
+fn coerce(value: &Value, ty: &str) -> Option<Value> {
+    match (ty, value) {
+        ("object", Value::Object(_)) | ("array", Value::Array(_)) => Some(value.clone()),
+        ("object", Value::String(s)) => serde_json::from_str(s).ok().filter(Value::is_object),
+        ("array", Value::String(s)) => serde_json::from_str(s).ok().filter(Value::is_array),
+        ("string", Value::String(_)) => Some(value.clone()),
+        ("string", Value::Number(n)) => Some(Value::String(n.to_string())),
+        ("string", Value::Bool(b)) => Some(Value::String(b.to_string())),
+        ("integer", Value::Number(n)) if n.is_i64() || n.is_u64() => Some(value.clone()),
+        ("integer", Value::Number(n)) => n
+            .as_f64()
+            .filter(|f| f.fract() == 0.0 && f.abs() < i64::MAX as f64)
+            .map(|f| Value::from(f as i64)),
+        ("integer", Value::String(s)) => s.trim().parse::<i64>().ok().map(Value::from),
+        ("number", Value::Number(_)) => Some(value.clone()),
+        ("number", Value::String(s)) => s
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number),
+        ("boolean", Value::Bool(_)) => Some(value.clone()),
+        ("boolean", Value::String(s)) => match s.trim().to_lowercase().as_str() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        ("null", Value::Null) => Some(Value::Null),
+        _ => None,
+    }
+}
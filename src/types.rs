@@ -1,8 +1,8 @@
-use poe_api_process::types::{ChatTool, ChatToolCall};
+use poe_api_process::types::{ChatTool, ChatToolCall, FunctionDefinition, FunctionParameters};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub model: String,
     pub messages: Vec<Message>,
@@ -14,7 +14,10 @@ pub struct ChatCompletionRequest {
     pub stop: Option<Vec<String>>,
     pub stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tools: Option<Vec<ChatTool>>,
+    pub tools: Option<Vec<RequestTool>>,
+    /// 是否允許同一回合返回多個工具調用，false 時只返回第一個
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
     pub stream_options: Option<StreamOptions>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_effort: Option<String>,
@@ -22,39 +25,182 @@ pub struct ChatCompletionRequest {
     pub thinking: Option<ThinkingConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extra_body: Option<ExtraBody>,
+    /// 直接傳給 Poe 機器人的自訂參數
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poe: Option<serde_json::Map<String, serde_json::Value>>,
+    /// 是否儲存此次聊天完成記錄，可透過 GET /v1/chat/completions/{id} 取回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+    /// 終端使用者識別，可用於對應 Poe 對話
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Predicted Outputs，預設忽略；模型設定 prediction_prefill 時作為助手預填內容
+    /// 保留原始 JSON，格式不符時不會導致請求解析失敗
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prediction: Option<serde_json::Value>,
+    /// 輸出模態，包含 "audio" 時音訊附件以 base64 放入 message.audio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalities: Option<Vec<String>>,
+    /// 音訊輸出參數，Poe 機器人自行決定聲音與格式，僅記錄於日誌
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioParams>,
+    /// 其餘未識別的頂層欄位（top_p、presence_penalty 等），不會轉發，僅供參數策略檢查
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+    /// 讀取請求時暫存至磁碟的附件，請求結束時刪除未上傳的檔案
+    #[serde(skip)]
+    pub spooled: crate::spool::SpooledFiles,
+}
+
+impl ChatCompletionRequest {
+    /// 請求中是否帶有指定的頂層參數
+    pub fn has_param(&self, name: &str) -> bool {
+        match name {
+            "temperature" => self.temperature.is_some(),
+            "logit_bias" => self.logit_bias.is_some(),
+            "stop" => self.stop.is_some(),
+            "tools" => self.tools.is_some(),
+            "parallel_tool_calls" => self.parallel_tool_calls.is_some(),
+            "reasoning_effort" => self.reasoning_effort.is_some(),
+            "thinking" => self.thinking.is_some(),
+            "prediction" => self.prediction.is_some(),
+            "user" => self.user.is_some(),
+            "modalities" => self.modalities.is_some(),
+            "audio" => self.audio.is_some(),
+            other => self.other.get(other).is_some_and(|v| !v.is_null()),
+        }
+    }
+
+    /// 是否要求音訊輸出 (modalities 包含 "audio")
+    pub fn wants_audio(&self) -> bool {
+        self.modalities
+            .as_ref()
+            .is_some_and(|modalities| modalities.iter().any(|m| m.eq_ignore_ascii_case("audio")))
+    }
+
+    /// 設定 strict: true 的工具及其參數 JSON Schema
+    pub fn strict_tool_schemas(&self) -> HashMap<String, serde_json::Value> {
+        self.tools
+            .iter()
+            .flatten()
+            .filter(|tool| tool.function.strict)
+            .map(|tool| {
+                let schema = tool.function.parameters.clone().unwrap_or_default();
+                (tool.function.name.clone(), schema)
+            })
+            .collect()
+    }
+
+    /// 移除指定的頂層參數
+    pub fn remove_param(&mut self, name: &str) {
+        match name {
+            "temperature" => self.temperature = None,
+            "logit_bias" => self.logit_bias = None,
+            "stop" => self.stop = None,
+            "tools" => self.tools = None,
+            "parallel_tool_calls" => self.parallel_tool_calls = None,
+            "reasoning_effort" => self.reasoning_effort = None,
+            "thinking" => self.thinking = None,
+            "prediction" => self.prediction = None,
+            "user" => self.user = None,
+            other => {
+                self.other.remove(other);
+            }
+        }
+    }
+}
+
+/// 請求中的工具定義，保留 strict 與完整的參數 JSON Schema
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RequestTool {
+    pub r#type: String,
+    pub function: RequestFunction,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RequestFunction {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub parameters: Option<serde_json::Value>,
+    /// 是否要求工具調用參數嚴格符合 parameters
+    #[serde(default)]
+    pub strict: bool,
 }
 
-#[derive(Deserialize)]
+impl RequestTool {
+    /// 轉換為傳給 Poe 的工具定義
+    pub fn to_poe_tool(&self) -> ChatTool {
+        let parameters = self
+            .function
+            .parameters
+            .as_ref()
+            .map(|schema| FunctionParameters {
+                r#type: schema["type"].as_str().unwrap_or("object").to_string(),
+                properties: schema
+                    .get("properties")
+                    .cloned()
+                    .unwrap_or_else(|| serde_json::json!({})),
+                required: schema["required"]
+                    .as_array()
+                    .map(|required| {
+                        required
+                            .iter()
+                            .filter_map(|name| name.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            });
+        ChatTool {
+            r#type: self.r#type.clone(),
+            function: FunctionDefinition {
+                name: self.function.name.clone(),
+                description: self.function.description.clone(),
+                parameters,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct StreamOptions {
     pub include_usage: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ThinkingConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub budget_tokens: Option<i32>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ExtraBody {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub google: Option<GoogleConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poe: Option<serde_json::Map<String, serde_json::Value>>,
+    /// 其餘未識別的欄位，視為 Poe 機器人參數
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct GoogleConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking_config: Option<GoogleThinkingConfig>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct GoogleThinkingConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking_budget: Option<i32>,
 }
 
 // 定義支援 OpenAI content 格式的 enum (String 或陣列)
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum OpenAiContent {
     Text(String),
@@ -62,7 +208,7 @@ pub enum OpenAiContent {
 }
 
 // 定義 OpenAI content 陣列內的項目類型
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum OpenAiContentItem {
     #[serde(rename = "text")]
@@ -72,7 +218,7 @@ pub enum OpenAiContentItem {
 }
 
 // 定義 image_url 的內容結構
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ImageUrlContent {
     pub url: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -81,7 +227,7 @@ pub struct ImageUrlContent {
 }
 
 // 更新 Message 結構使用新的 OpenAiContent
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Message {
     pub role: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -116,9 +262,25 @@ pub struct CompletionMessage {
     pub content: String,
     pub refusal: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<Annotation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ChatToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<ImageOutput>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub videos: Option<Vec<VideoOutput>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<MessageMetadata>,
+}
+
+/// 訊息的擴充資訊 (SUGGESTED_REPLIES 啟用時返回機器人的建議回覆)
+#[derive(Serialize, Clone, Debug)]
+pub struct MessageMetadata {
+    pub suggested_replies: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -143,9 +305,109 @@ pub struct Delta {
     pub content: Option<String>,
     pub refusal: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_calls: Option<Vec<ChatToolCall>>,
+    pub annotations: Option<Vec<Annotation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<ImageOutput>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub videos: Option<Vec<VideoOutput>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<MessageMetadata>,
+}
+
+// 串流中的工具調用，index 用於區分同一回合的多個調用
+#[derive(Serialize, Clone, Debug)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    #[serde(flatten)]
+    pub call: ChatToolCall,
+}
+
+// 引用註解（OpenAI 的 url_citation），位置以正文的字元數計算
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Annotation {
+    pub r#type: String,
+    pub url_citation: UrlCitation,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct UrlCitation {
+    pub start_index: usize,
+    pub end_index: usize,
+    pub url: String,
+    pub title: String,
+}
+
+impl Annotation {
+    /// 建立 url_citation 註解，標題使用網址的主機名稱
+    pub fn url_citation(start_index: usize, end_index: usize, url: &str) -> Self {
+        let host = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let title = host.split(['/', '?', '#']).next().unwrap_or(host);
+        Self {
+            r#type: "url_citation".to_string(),
+            url_citation: UrlCitation {
+                start_index,
+                end_index,
+                url: url.to_string(),
+                title: title.to_string(),
+            },
+        }
+    }
+}
+
+// 圖片輸出（與 OpenRouter 的 message.images 格式相容）
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageOutput {
+    pub r#type: String,
+    pub image_url: ImageOutputUrl,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageOutputUrl {
+    pub url: String,
+}
+
+// 影片輸出（OpenAI 無對應格式，沿用 message.images 的結構）
+#[derive(Serialize, Clone, Debug)]
+pub struct VideoOutput {
+    pub r#type: String,
+    pub video_url: VideoOutputUrl,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct VideoOutputUrl {
+    pub url: String,
+    pub mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+}
+
+// 音訊輸出（OpenAI 的 message.audio），未要求音訊模態時以 url 提供連結而非 data
+#[derive(Serialize, Clone, Debug)]
+pub struct AudioOutput {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub format: String,
+    pub expires_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<String>,
+}
+
+// 請求的音訊輸出參數
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AudioParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voice: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -171,6 +433,247 @@ pub(crate) struct Config {
     pub(crate) api_token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) use_v1_api: Option<bool>,
+    // 串流結尾片段的相容性設定（全域）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stream_compat: Option<StreamCompatConfig>,
+    // 依 API Key 覆蓋的串流相容性設定
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) key_stream_compat: Option<std::collections::HashMap<String, StreamCompatConfig>>,
+    // Poe 無法支援的請求參數的處理策略（全域），可由模型設定覆蓋
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) param_policy: Option<std::collections::HashMap<String, ParamPolicy>>,
+    // 移除正文中的腳註標記與結尾的來源列表（全域），可由模型設定覆蓋
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) strip_footnotes: Option<bool>,
+    // 依 API Key 設定的優先級，併發已滿時決定排隊順序
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) key_priority: Option<std::collections::HashMap<String, Priority>>,
+    // 對話歷史超出模型 context_length 時的處理方式（全域），可由模型設定覆蓋
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) history_policy: Option<HistoryPolicy>,
+    // history_policy 為 summarize 時產生摘要的機器人
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) history_summary_model: Option<String>,
+    // 可供模型在伺服器端調用的 MCP 伺服器，以名稱對應
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) mcp_servers: Option<std::collections::HashMap<String, McpServerConfig>>,
+    // 依 OpenAI-Project（優先）或 OpenAI-Organization 標頭套用的設定，以專案或組織名稱對應
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) scopes: Option<std::collections::HashMap<String, ScopeConfig>>,
+    // 合併相同的並行非串流請求（全域）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) coalesce: Option<bool>,
+    // 依 API Key 覆蓋的 coalesce
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) key_coalesce: Option<std::collections::HashMap<String, bool>>,
+}
+
+impl Config {
+    /// 模型是否移除腳註標記，模型設定優先於全域設定
+    pub(crate) fn strip_footnotes(&self, model: &str) -> bool {
+        self.models
+            .get(model)
+            .and_then(|model_config| model_config.strip_footnotes)
+            .or(self.strip_footnotes)
+            .unwrap_or(false)
+    }
+
+    /// API Key 是否合併相同的並行請求，API Key 的設定優先於全域設定
+    pub(crate) fn coalesce(&self, access_key: &str) -> bool {
+        self.key_coalesce
+            .as_ref()
+            .and_then(|keys| keys.get(access_key).copied())
+            .or(self.coalesce)
+            .unwrap_or(false)
+    }
+
+    /// 模型的圖片生成結果緩存時間，未設置或為 0 時返回 None
+    pub(crate) fn image_cache_ttl(&self, model: &str) -> Option<std::time::Duration> {
+        self.models
+            .get(model)
+            .and_then(|model_config| model_config.image_cache_ttl)
+            .filter(|ttl| *ttl > 0)
+            .map(std::time::Duration::from_secs)
+    }
+
+    /// 模型的對話歷史處理方式及可使用的 token 預算（context_length 扣除預留的輸出長度）；
+    /// 未設定 context_length 或未啟用 history_policy 時返回 None
+    pub(crate) fn history_limit(&self, model: &str, reserved: u32) -> Option<(HistoryPolicy, u32)> {
+        let model_config = self.models.get(model)?;
+        let policy = model_config
+            .history_policy
+            .or(self.history_policy)
+            .unwrap_or_default();
+        if policy == HistoryPolicy::Off {
+            return None;
+        }
+        let context_length = model_config.context_length?;
+        Some((policy, context_length.saturating_sub(reserved)))
+    }
+}
+
+/// 一個組織或專案的設定
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct ScopeConfig {
+    /// 可見與可用的模型，未設定時不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) models: Option<Vec<String>>,
+    /// 改以此 Token 連線 Poe，只套用於 access_keys 中的 API Key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) api_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) access_keys: Vec<String>,
+}
+
+/// MCP 伺服器設定，command（以 stdio 啟動的子行程）與 url（Streamable HTTP）擇一
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct McpServerConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) command: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) args: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub(crate) env: std::collections::HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) url: Option<String>,
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub(crate) headers: std::collections::HashMap<String, String>,
+}
+
+/// 模型的串流方式
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StreamPolicy {
+    /// 依客戶端的 stream 設定（預設）
+    #[default]
+    Client,
+    /// 總是以 SSE 串流返回
+    Always,
+    /// 總是等待完成後以單一 JSON 返回
+    Never,
+}
+
+/// 由代理在伺服器端執行的內建工具
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BuiltinTool {
+    /// 以 WEB_SEARCH_BACKEND 設定的搜尋服務搜尋網頁
+    WebSearch,
+}
+
+/// 對話歷史超出上下文長度時的處理方式
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HistoryPolicy {
+    /// 照常轉發（預設）
+    #[default]
+    Off,
+    /// 由最舊的非系統訊息開始移除
+    Truncate,
+    /// 以 history_summary_model 將較舊的訊息濃縮為一則摘要
+    Summarize,
+}
+
+/// API Key 的優先級
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Priority {
+    /// 最先放行，隊列已滿時可擠出較低優先級的請求
+    High,
+    /// 預設
+    #[default]
+    Normal,
+    /// 最後放行，隊列已滿時最先被捨棄
+    Low,
+}
+
+impl Priority {
+    pub(crate) const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    /// 隊列索引，數字越小優先級越高
+    pub(crate) fn tier(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+/// 請求參數的處理策略
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ParamPolicy {
+    /// 照常處理（預設）
+    #[default]
+    Forward,
+    /// 移除參數
+    Ignore,
+    /// 移除參數並記錄警告
+    Warn,
+    /// 以 400 拒絕請求
+    Reject,
+}
+
+/// 串流結尾片段的相容性設定，未設置的欄位沿用上一層或預設值
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub(crate) struct StreamCompatConfig {
+    // finish_reason 的位置
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) finish_reason: Option<FinishReasonPlacement>,
+    // 帶 finish_reason 的結尾片段中 delta 的形式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) final_delta: Option<FinalDeltaShape>,
+    // usage 的位置
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) usage: Option<UsagePlacement>,
+    // 是否發送 data: [DONE]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) done_marker: Option<bool>,
+}
+
+impl StreamCompatConfig {
+    /// 以 other 中已設置的欄位覆蓋目前設定
+    pub(crate) fn overlay(&self, other: &StreamCompatConfig) -> StreamCompatConfig {
+        StreamCompatConfig {
+            finish_reason: other.finish_reason.or(self.finish_reason),
+            final_delta: other.final_delta.or(self.final_delta),
+            usage: other.usage.or(self.usage),
+            done_marker: other.done_marker.or(self.done_marker),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FinishReasonPlacement {
+    /// 以獨立的結尾片段發送（預設）
+    #[default]
+    Separate,
+    /// 有剩餘正文時與最後一段正文一起發送
+    WithContent,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FinalDeltaShape {
+    /// delta 中帶有空字串 content（預設）
+    #[default]
+    EmptyContent,
+    /// delta 為空物件 {}
+    Empty,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UsagePlacement {
+    /// 附加在帶 finish_reason 的結尾片段上（預設）
+    #[default]
+    FinalChunk,
+    /// 以 choices 為空陣列的獨立片段在 [DONE] 之前發送（與 OpenAI 一致）
+    SeparateChunk,
+    /// 以 choices 為空陣列的獨立片段在 [DONE] 之後發送
+    AfterDone,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -188,4 +691,79 @@ pub(crate) struct ModelConfig {
     pub(crate) replace_response: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) enable: Option<bool>,
+    // 推理模型（o1/o3 類）不接受取樣參數，轉發前移除
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reasoning: Option<bool>,
+    // 將請求的 prediction 作為助手預填內容，供支援接續回覆的機器人使用
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) prediction_prefill: Option<bool>,
+    // 覆蓋全域 param_policy 的參數處理策略
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) param_policy: Option<std::collections::HashMap<String, ParamPolicy>>,
+    // 覆蓋全域 strip_footnotes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) strip_footnotes: Option<bool>,
+    // 模型的上下文長度（token），供 history_policy 使用
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) context_length: Option<u32>,
+    // 覆蓋全域 history_policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) history_policy: Option<HistoryPolicy>,
+    // 緩存圖片生成結果的秒數，未設置時不緩存
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) image_cache_ttl: Option<u64>,
+    // 提供給模型的 MCP 伺服器名稱，工具調用由代理執行
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) mcp_servers: Option<Vec<String>>,
+    // 由代理執行的內建工具
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) builtin_tools: Option<Vec<BuiltinTool>>,
+    // 覆蓋客戶端的 stream 設定
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stream_policy: Option<StreamPolicy>,
+    // 請求訊息（含內嵌附件）的位元組上限，超過時拒絕請求
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_input_bytes: Option<u64>,
+    // 請求訊息的 token 上限，超過時拒絕請求
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_input_tokens: Option<u32>,
+    // 回覆的 token 上限，超過時截斷並以 finish_reason: length 結束
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_output_tokens: Option<u32>,
+    // /v1/completions 的 prompt 格式，用於拆解為對話訊息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) instruct_template: Option<InstructTemplate>,
+}
+
+/// 文字補全 prompt 的格式：內建格式名稱或自訂的角色標記
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum InstructTemplate {
+    Preset(InstructPreset),
+    Custom(CustomInstructTemplate),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum InstructPreset {
+    /// 整段 prompt 作為一則使用者訊息（預設）
+    Raw,
+    #[serde(rename = "chatml")]
+    ChatMl,
+    Alpaca,
+    Llama3,
+}
+
+/// 自訂格式：各角色訊息開頭的標記，end 為每則訊息結尾的標記
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct CustomInstructTemplate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) system: Option<String>,
+    pub(crate) user: String,
+    pub(crate) assistant: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) end: Option<String>,
+    /// 額外的停止序列
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) stop: Vec<String>,
 }
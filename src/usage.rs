@@ -0,0 +1,574 @@
+//! 每個 API Key 與模型的用量統計 (USAGE_STATS)，以小時為單位累計在儲存資料庫的 usage 樹中
+//!
+//! 鍵為 `{小時起點}:{擁有者雜湊}:{模型}:{標籤雜湊}`，值為該小時的請求數、錯誤數與 token 數。
+//! 請求的 user 與 metadata 作為標籤一併保存，可依標籤篩選與分組；
+//! OpenAI-Organization 與 OpenAI-Project 標頭同樣保存，可依組織與專案篩選與分組。
+//! API Key 只保存雜湊與遮罩後的提示；超過 USAGE_RETENTION_DAYS 天的資料在進入新的小時時清除。
+//! 設定 REDIS_URL 時同時累計在 Redis（每個小時與鍵一個 hash，另以 usage:index 有序集合索引），
+//! 查詢改讀 Redis 以彙整所有實例的用量
+
+use crate::shared::{SharedState, get_shared_state};
+use crate::store::{USAGE_TREE, open_store_tree};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicI64, Ordering};
+use tracing::{debug, error, info};
+
+const HOUR_SECS: i64 = 3600;
+
+struct UsageConfig {
+    retention_days: i64,
+}
+
+static USAGE_CONFIG: LazyLock<Option<UsageConfig>> = LazyLock::new(|| {
+    let enabled = std::env::var("USAGE_STATS")
+        .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+        .unwrap_or(false);
+    if !enabled {
+        return None;
+    }
+    let retention_days = std::env::var("USAGE_RETENTION_DAYS")
+        .ok()
+        .and_then(|s| s.trim().parse::<i64>().ok())
+        .filter(|days| *days > 0)
+        .unwrap_or(90);
+    info!(
+        "{}",
+        tr!(
+            "📈 用量統計已啟用 | 保留: {} 天",
+            "📈 Usage statistics enabled | retention: {} days",
+            retention_days
+        )
+    );
+    Some(UsageConfig { retention_days })
+});
+
+// 最近一次清除過期資料時的小時起點
+static LAST_PRUNE_HOUR: AtomicI64 = AtomicI64::new(0);
+
+/// 是否啟用用量統計
+pub fn usage_enabled() -> bool {
+    USAGE_CONFIG.is_some()
+}
+
+/// 累計的用量
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+pub struct UsageCounters {
+    pub requests: u64,
+    pub errors: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl UsageCounters {
+    fn add(&mut self, other: &UsageCounters) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+
+    fn to_value(self) -> serde_json::Value {
+        json!({
+            "requests": self.requests,
+            "errors": self.errors,
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": self.completion_tokens,
+            "total_tokens": self.prompt_tokens + self.completion_tokens,
+        })
+    }
+}
+
+/// 一個 API Key 與模型在某個小時內的用量
+#[derive(Serialize, Deserialize)]
+struct UsageBucket {
+    hour: i64,
+    owner: String,
+    key_hint: String,
+    model: String,
+    #[serde(default)]
+    user: String,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    organization: String,
+    #[serde(default)]
+    project: String,
+    #[serde(flatten)]
+    counters: UsageCounters,
+}
+
+fn hour_key(hour: i64) -> String {
+    format!("{:012}", hour)
+}
+
+/// 請求時即可確定的用量歸屬，待回應完成或失敗後寫入
+#[derive(Clone)]
+pub struct UsageKey {
+    owner: String,
+    key_hint: String,
+    model: String,
+    user: String,
+    metadata: BTreeMap<String, String>,
+    organization: String,
+    project: String,
+}
+
+impl UsageKey {
+    /// 未啟用用量統計時返回 None
+    pub fn new(owner: String, key_hint: String, model: &str) -> Option<Self> {
+        usage_enabled().then(|| Self {
+            owner,
+            key_hint,
+            model: model.to_string(),
+            user: String::new(),
+            metadata: BTreeMap::new(),
+            organization: String::new(),
+            project: String::new(),
+        })
+    }
+
+    /// 以請求的 user 與 metadata 標記用量
+    pub fn with_tags(
+        mut self,
+        user: Option<&str>,
+        metadata: Option<&HashMap<String, String>>,
+    ) -> Self {
+        self.user = user.unwrap_or_default().to_string();
+        self.metadata = metadata
+            .map(|m| m.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default();
+        self
+    }
+
+    /// 以請求的 OpenAI-Organization 與 OpenAI-Project 標記用量
+    pub fn with_scope(mut self, organization: Option<&str>, project: Option<&str>) -> Self {
+        self.organization = organization.unwrap_or_default().to_string();
+        self.project = project.unwrap_or_default().to_string();
+        self
+    }
+
+    /// 標籤的短雜湊，沒有標籤時為空字串
+    fn tags_hash(&self) -> String {
+        if self.user.is_empty()
+            && self.metadata.is_empty()
+            && self.organization.is_empty()
+            && self.project.is_empty()
+        {
+            return String::new();
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(self.user.as_bytes());
+        for (key, value) in &self.metadata {
+            hasher.update([0]);
+            hasher.update(key.as_bytes());
+            hasher.update([0]);
+            hasher.update(value.as_bytes());
+        }
+        // 沒有組織與專案時維持原有的雜湊，與既有資料合併
+        if !self.organization.is_empty() || !self.project.is_empty() {
+            hasher.update([1]);
+            hasher.update(self.organization.as_bytes());
+            hasher.update([1]);
+            hasher.update(self.project.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())[..16].to_string()
+    }
+
+    /// 記錄一次成功的請求
+    pub fn record(&self, prompt_tokens: u32, completion_tokens: u32) {
+        self.add(UsageCounters {
+            requests: 1,
+            errors: 0,
+            prompt_tokens: prompt_tokens as u64,
+            completion_tokens: completion_tokens as u64,
+        });
+    }
+
+    /// 記錄一次失敗的請求
+    pub fn record_error(&self) {
+        self.add(UsageCounters {
+            requests: 1,
+            errors: 1,
+            ..Default::default()
+        });
+    }
+
+    fn add(&self, counters: UsageCounters) {
+        let Some(tree) = open_store_tree(USAGE_TREE) else {
+            return;
+        };
+        let hour = Utc::now().timestamp().div_euclid(HOUR_SECS) * HOUR_SECS;
+        let key = format!(
+            "{}:{}:{}:{}",
+            hour_key(hour),
+            self.owner,
+            self.model,
+            self.tags_hash()
+        );
+        let result = tree.update_and_fetch(key.as_bytes(), |old| {
+            let mut bucket = old
+                .and_then(|bytes| serde_json::from_slice::<UsageBucket>(bytes).ok())
+                .unwrap_or_else(|| UsageBucket {
+                    hour,
+                    owner: self.owner.clone(),
+                    key_hint: self.key_hint.clone(),
+                    model: self.model.clone(),
+                    user: self.user.clone(),
+                    metadata: self.metadata.clone(),
+                    organization: self.organization.clone(),
+                    project: self.project.clone(),
+                    counters: UsageCounters::default(),
+                });
+            bucket.counters.add(&counters);
+            serde_json::to_vec(&bucket).ok()
+        });
+        if let Err(e) = result {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 寫入用量統計失敗: {}",
+                    "❌ Failed to write usage statistics: {}",
+                    e
+                )
+            );
+            return;
+        }
+        debug!(
+            "📈 記錄用量 | 模型: {} | user: {} | metadata: {:?} | 組織: {} | 專案: {} | 錯誤: {} | tokens: {}",
+            self.model,
+            self.user,
+            self.metadata,
+            self.organization,
+            self.project,
+            counters.errors,
+            counters.prompt_tokens + counters.completion_tokens
+        );
+        let new_hour = LAST_PRUNE_HOUR.swap(hour, Ordering::Relaxed) != hour;
+        if new_hour {
+            prune(&tree, hour);
+        }
+        if let Some(shared) = get_shared_state() {
+            self.share(shared, hour, key, counters, new_hour);
+        }
+    }
+
+    /// 於背景將用量累計到共享狀態
+    fn share(
+        &self,
+        shared: &'static SharedState,
+        hour: i64,
+        name: String,
+        counters: UsageCounters,
+        prune: bool,
+    ) {
+        let Some(config) = USAGE_CONFIG.as_ref() else {
+            return;
+        };
+        let retention_secs = config.retention_days * 24 * HOUR_SECS;
+        let bucket_key = shared.key(&format!("usage:{}", name));
+        let index_key = shared.key("usage:index");
+        let mut pipe = redis::pipe();
+        for (field, value) in [
+            ("requests", counters.requests),
+            ("errors", counters.errors),
+            ("prompt_tokens", counters.prompt_tokens),
+            ("completion_tokens", counters.completion_tokens),
+        ] {
+            if value > 0 {
+                pipe.hincr(&bucket_key, field, value).ignore();
+            }
+        }
+        pipe.hset_multiple(
+            &bucket_key,
+            &[
+                ("hour", hour.to_string()),
+                ("owner", self.owner.clone()),
+                ("key_hint", self.key_hint.clone()),
+                ("model", self.model.clone()),
+                ("user", self.user.clone()),
+                (
+                    "metadata",
+                    serde_json::to_string(&self.metadata).unwrap_or_default(),
+                ),
+                ("organization", self.organization.clone()),
+                ("project", self.project.clone()),
+            ],
+        )
+        .ignore()
+        .expire(&bucket_key, retention_secs)
+        .ignore()
+        .zadd(&index_key, name, hour)
+        .ignore();
+        if prune {
+            pipe.zrembyscore(&index_key, "-inf", format!("({}", hour - retention_secs))
+                .ignore();
+        }
+        tokio::spawn(async move {
+            if let Err(e) = shared.query_pipeline::<()>(&pipe).await {
+                debug!("📈 用量統計寫入共享狀態失敗: {}", e);
+            }
+        });
+    }
+}
+
+/// 每次管線讀取的用量記錄數
+const SHARED_READ_BATCH: usize = 500;
+
+/// 從共享狀態讀取時間範圍內的用量記錄，失敗時返回 None
+async fn load_shared_buckets(shared: &SharedState, query: &UsageQuery) -> Option<Vec<UsageBucket>> {
+    let names: Vec<String> = shared
+        .query(
+            redis::cmd("ZRANGEBYSCORE")
+                .arg(shared.key("usage:index"))
+                .arg(query.from)
+                .arg(format!("({}", query.to)),
+        )
+        .await
+        .ok()?;
+    let mut buckets = Vec::with_capacity(names.len());
+    for batch in names.chunks(SHARED_READ_BATCH) {
+        let mut pipe = redis::pipe();
+        for name in batch {
+            pipe.hgetall(shared.key(&format!("usage:{}", name)));
+        }
+        let replies: Vec<HashMap<String, String>> = shared.query_pipeline(&pipe).await.ok()?;
+        buckets.extend(replies.into_iter().filter_map(parse_shared_bucket));
+    }
+    Some(buckets)
+}
+
+/// 將 HGETALL 的結果轉為用量記錄，已過期的鍵返回 None
+fn parse_shared_bucket(fields: HashMap<String, String>) -> Option<UsageBucket> {
+    let number = |field: &str| {
+        fields
+            .get(field)
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or_default()
+    };
+    Some(UsageBucket {
+        hour: fields.get("hour")?.parse().ok()?,
+        owner: fields.get("owner")?.clone(),
+        key_hint: fields.get("key_hint").cloned().unwrap_or_default(),
+        model: fields.get("model")?.clone(),
+        user: fields.get("user").cloned().unwrap_or_default(),
+        metadata: fields
+            .get("metadata")
+            .and_then(|m| serde_json::from_str(m).ok())
+            .unwrap_or_default(),
+        organization: fields.get("organization").cloned().unwrap_or_default(),
+        project: fields.get("project").cloned().unwrap_or_default(),
+        counters: UsageCounters {
+            requests: number("requests"),
+            errors: number("errors"),
+            prompt_tokens: number("prompt_tokens"),
+            completion_tokens: number("completion_tokens"),
+        },
+    })
+}
+
+/// 讀取時間範圍內的用量記錄：有共享狀態時讀取 Redis，否則讀取本機儲存
+async fn load_buckets(query: &UsageQuery) -> Vec<UsageBucket> {
+    // 未啟用時不為查詢而建立資料庫
+    if !usage_enabled() {
+        return Vec::new();
+    }
+    if let Some(shared) = get_shared_state()
+        && let Some(buckets) = load_shared_buckets(shared, query).await
+    {
+        return buckets;
+    }
+    let Some(tree) = open_store_tree(USAGE_TREE) else {
+        return Vec::new();
+    };
+    let start = hour_key(query.from);
+    let end = hour_key(query.to);
+    tree.range(start.as_bytes()..end.as_bytes())
+        .values()
+        .filter_map(|v| v.ok())
+        .filter_map(|bytes| serde_json::from_slice::<UsageBucket>(&bytes).ok())
+        .collect()
+}
+
+/// 清除超過保留天數的用量資料
+fn prune(tree: &sled::Tree, hour: i64) {
+    let Some(config) = USAGE_CONFIG.as_ref() else {
+        return;
+    };
+    let cutoff = hour_key(hour - config.retention_days * 24 * HOUR_SECS);
+    let mut removed = 0;
+    for key in tree
+        .range(..cutoff.as_bytes())
+        .keys()
+        .filter_map(|k| k.ok())
+    {
+        if tree.remove(key).is_ok() {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        debug!("📈 清除過期的用量統計 | 數量: {}", removed);
+    }
+}
+
+/// 用量查詢條件，時間範圍為 [from, to)，以 bucket_secs 為單位彙整時間序列
+pub struct UsageQuery {
+    pub from: i64,
+    pub to: i64,
+    pub bucket_secs: i64,
+    pub owner: Option<String>,
+    pub model: Option<String>,
+    pub user: Option<String>,
+    pub organization: Option<String>,
+    pub project: Option<String>,
+    /// 需全部符合的 metadata 鍵值
+    pub metadata: HashMap<String, String>,
+    /// 依此 metadata 鍵的值分組
+    pub group_tag: Option<String>,
+}
+
+impl UsageQuery {
+    /// 檢查記錄是否符合篩選條件，skip 指定不套用的條件
+    fn matches(&self, bucket: &UsageBucket, skip: Filter) -> bool {
+        (skip == Filter::Owner || self.owner.as_ref().is_none_or(|o| &bucket.owner == o))
+            && (skip == Filter::Model || self.model.as_ref().is_none_or(|m| &bucket.model == m))
+            && (skip == Filter::User || self.user.as_ref().is_none_or(|u| &bucket.user == u))
+            && (skip == Filter::Organization
+                || self
+                    .organization
+                    .as_ref()
+                    .is_none_or(|o| &bucket.organization == o))
+            && (skip == Filter::Project
+                || self.project.as_ref().is_none_or(|p| &bucket.project == p))
+            && self.metadata.iter().all(|(key, value)| {
+                (skip == Filter::GroupTag && self.group_tag.as_ref() == Some(key))
+                    || bucket.metadata.get(key) == Some(value)
+            })
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Filter {
+    None,
+    Owner,
+    Model,
+    User,
+    Organization,
+    Project,
+    GroupTag,
+}
+
+/// 依總 token 數排序的分組用量，name 為分組名稱的欄位名
+fn breakdown(groups: HashMap<String, UsageCounters>, name: &str) -> Vec<serde_json::Value> {
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|a, b| {
+        let total = |c: &UsageCounters| c.prompt_tokens + c.completion_tokens;
+        total(&b.1)
+            .cmp(&total(&a.1))
+            .then_with(|| b.1.requests.cmp(&a.1.requests))
+            .then_with(|| a.0.cmp(&b.0))
+    });
+    groups
+        .into_iter()
+        .map(|(group, counters)| {
+            let mut value = counters.to_value();
+            value[name] = json!(group);
+            value
+        })
+        .collect()
+}
+
+/// 依查詢條件彙整用量：時間序列，以及依 API Key、模型、user、組織、專案與 metadata 標籤分組的總計
+/// 時間序列套用所有篩選；各分組列表不套用自身維度的篩選，以便比較同一維度的其他值
+pub async fn usage_report(query: &UsageQuery) -> serde_json::Value {
+    let slots = ((query.to - query.from) / query.bucket_secs).max(0) as usize;
+    let mut timeline = vec![UsageCounters::default(); slots];
+    let mut keys: HashMap<String, UsageCounters> = HashMap::new();
+    let mut key_hints: HashMap<String, String> = HashMap::new();
+    let mut models: HashMap<String, UsageCounters> = HashMap::new();
+    let mut users: HashMap<String, UsageCounters> = HashMap::new();
+    let mut organizations: HashMap<String, UsageCounters> = HashMap::new();
+    let mut projects: HashMap<String, UsageCounters> = HashMap::new();
+    let mut tags: HashMap<String, UsageCounters> = HashMap::new();
+
+    for bucket in load_buckets(query).await {
+        if query.matches(&bucket, Filter::Owner) {
+            keys.entry(bucket.owner.clone())
+                .or_default()
+                .add(&bucket.counters);
+            key_hints
+                .entry(bucket.owner.clone())
+                .or_insert_with(|| bucket.key_hint.clone());
+        }
+        if query.matches(&bucket, Filter::Model) {
+            models
+                .entry(bucket.model.clone())
+                .or_default()
+                .add(&bucket.counters);
+        }
+        if query.matches(&bucket, Filter::User) {
+            users
+                .entry(bucket.user.clone())
+                .or_default()
+                .add(&bucket.counters);
+        }
+        if query.matches(&bucket, Filter::Organization) {
+            organizations
+                .entry(bucket.organization.clone())
+                .or_default()
+                .add(&bucket.counters);
+        }
+        if query.matches(&bucket, Filter::Project) {
+            projects
+                .entry(bucket.project.clone())
+                .or_default()
+                .add(&bucket.counters);
+        }
+        if let Some(group_tag) = &query.group_tag
+            && query.matches(&bucket, Filter::GroupTag)
+        {
+            tags.entry(bucket.metadata.get(group_tag).cloned().unwrap_or_default())
+                .or_default()
+                .add(&bucket.counters);
+        }
+        if query.matches(&bucket, Filter::None) {
+            let slot = ((bucket.hour - query.from) / query.bucket_secs) as usize;
+            if let Some(counters) = timeline.get_mut(slot) {
+                counters.add(&bucket.counters);
+            }
+        }
+    }
+
+    let mut keys = breakdown(keys, "owner");
+    for key in keys.iter_mut() {
+        let hint = key_hints
+            .remove(key["owner"].as_str().unwrap_or_default())
+            .unwrap_or_default();
+        key["key_hint"] = json!(hint);
+    }
+
+    json!({
+        "from": query.from,
+        "to": query.to,
+        "bucket_secs": query.bucket_secs,
+        "timeline": timeline
+            .iter()
+            .enumerate()
+            .map(|(i, counters)| {
+                let mut value = counters.to_value();
+                value["time"] = json!(query.from + i as i64 * query.bucket_secs);
+                value
+            })
+            .collect::<Vec<_>>(),
+        "keys": keys,
+        "models": breakdown(models, "model"),
+        "users": breakdown(users, "user"),
+        "organizations": breakdown(organizations, "organization"),
+        "projects": breakdown(projects, "project"),
+        "tags": query.group_tag.as_ref().map(|_| breakdown(tags, "value")),
+    })
+}
@@ -7,8 +7,11 @@ use poe_api_process::FileUploadRequest;
 use salvo::http::StatusCode;
 use sha2::{Digest, Sha256};
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
-use tiktoken_rs::o200k_base;
+use std::sync::LazyLock;
+use tiktoken_rs::tokenizer::{Tokenizer, get_tokenizer};
+use tiktoken_rs::{CoreBPE, o200k_base_singleton};
 use tracing::{debug, error, info, warn};
 
 // 處理消息中的文件/圖片
@@ -24,14 +27,15 @@ pub async fn process_message_images(
     let mut temp_files: Vec<PathBuf> = Vec::new();
 
     // 收集消息中所有需要處理的URL
-    for (msg_idx, message) in messages.iter().enumerate() {
-        if let Some(OpenAiContent::Multi(items)) = &message.content {
-            for (item_idx, item) in items.iter().enumerate() {
+    for (msg_idx, message) in messages.iter_mut().enumerate() {
+        if let Some(OpenAiContent::Multi(items)) = &mut message.content {
+            for (item_idx, item) in items.iter_mut().enumerate() {
                 if let OpenAiContentItem::ImageUrl { image_url } = item {
                     if image_url.url.starts_with("data:") {
-                        // 處理data URL
+                        // 處理data URL：直接移出而非複製，避免大型附件在記憶體中存在多份
+                        // 之後會以緩存或上傳後的 Poe URL 回填
                         debug!("🔍 發現data URL");
-                        data_urls.push(image_url.url.clone());
+                        data_urls.push(std::mem::take(&mut image_url.url));
                         data_url_indices.push((msg_idx, item_idx));
                     } else if !is_poe_cdn_url(&image_url.url) {
                         // 處理需要上傳的外部URL
@@ -56,14 +60,14 @@ pub async fn process_message_images(
             let url = &external_urls[idx];
 
             // 檢查緩存
-            if let Some((poe_url, _)) = crate::cache::get_cached_url(url) {
+            if let Some((poe_url, _)) = crate::cache::lookup_cached_url(url).await {
                 debug!("✅ URL緩存命中: {} -> {}", url, poe_url);
 
-                if let Some(OpenAiContent::Multi(items)) = &mut messages[*msg_idx].content {
-                    if let OpenAiContentItem::ImageUrl { image_url } = &mut items[*item_idx] {
-                        debug!("🔄 從緩存替換URL: {}", poe_url);
-                        image_url.url = poe_url;
-                    }
+                if let Some(OpenAiContent::Multi(items)) = &mut messages[*msg_idx].content
+                    && let OpenAiContentItem::ImageUrl { image_url } = &mut items[*item_idx]
+                {
+                    debug!("🔄 從緩存替換URL: {}", poe_url);
+                    image_url.url = poe_url;
                 }
             } else {
                 // 緩存未命中，需要上傳
@@ -84,7 +88,7 @@ pub async fn process_message_images(
                 })
                 .collect();
 
-            match poe_client.client.upload_files_batch(upload_requests).await {
+            match poe_client.upload_files_batch(upload_requests).await {
                 Ok(responses) => {
                     debug!("✅ 成功上傳 {} 個外部URL", responses.len());
 
@@ -102,25 +106,31 @@ pub async fn process_message_images(
                         // 添加到緩存
                         crate::cache::cache_url(original_url, &response.attachment_url, size_bytes);
 
-                        if let Some(OpenAiContent::Multi(items)) = &mut messages[*msg_idx].content {
-                            if let OpenAiContentItem::ImageUrl { image_url } = &mut items[*item_idx]
-                            {
-                                debug!(
-                                    "🔄 替換URL | 原始: {} | Poe: {}",
-                                    image_url.url, response.attachment_url
-                                );
-                                image_url.url = response.attachment_url.clone();
-                                image_url.mime_type = response.mime_type.clone();
-                            }
+                        if let Some(OpenAiContent::Multi(items)) = &mut messages[*msg_idx].content
+                            && let OpenAiContentItem::ImageUrl { image_url } = &mut items[*item_idx]
+                        {
+                            debug!(
+                                "🔄 替換URL | 原始: {} | Poe: {}",
+                                image_url.url, response.attachment_url
+                            );
+                            image_url.url = response.attachment_url.clone();
+                            image_url.mime_type = response.mime_type.clone();
                         }
                     }
                 }
                 Err(e) => {
-                    error!("❌ 上傳外部URL失敗: {}", e);
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!("上傳外部URL失敗: {}", e),
-                    )));
+                    error!(
+                        "{}",
+                        tr!(
+                            "❌ 上傳外部URL失敗: {}",
+                            "❌ Failed to upload external URL: {}",
+                            e
+                        )
+                    );
+                    return Err(Box::new(std::io::Error::other(format!(
+                        "上傳外部URL失敗: {}",
+                        e
+                    ))));
                 }
             }
         }
@@ -131,33 +141,58 @@ pub async fn process_message_images(
         debug!("🔄 準備處理 {} 個data URL", data_urls.len());
 
         // 分為緩存命中和未命中兩組
-    let mut data_to_upload = Vec::new();
-    let mut data_indices_to_upload = Vec::new();
-    let mut data_mime_types: Vec<Option<String>> = Vec::new();
-    let mut data_hashes = Vec::new();
-
-    for (idx, (msg_idx, item_idx)) in data_url_indices.iter().enumerate() {
-        let data_url = &data_urls[idx];
-        let hash = hash_base64_content(data_url);
+        let mut data_to_upload = Vec::new();
+        let mut data_indices_to_upload = Vec::new();
+        let mut data_mime_types: Vec<Option<String>> = Vec::new();
+        let mut data_hashes = Vec::new();
+        // 讀取請求時已暫存至磁碟的附件，與 data_to_upload 對應
+        let mut data_spooled: Vec<Option<crate::spool::SpooledAttachment>> = Vec::new();
+
+        for (idx, (msg_idx, item_idx)) in data_url_indices.iter().enumerate() {
+            let data_url = &data_urls[idx];
+            let spooled = match data_url.split_once(crate::spool::SPOOL_MARKER) {
+                Some((_, id)) => match crate::spool::take(id) {
+                    Some(attachment) => Some(attachment),
+                    None => {
+                        for path in &temp_files {
+                            let _ = fs::remove_file(path);
+                        }
+                        return Err(Box::new(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "無效的附件暫存引用",
+                        )));
+                    }
+                },
+                None => None,
+            };
+            // 暫存的附件由此負責刪除
+            if let Some(attachment) = &spooled {
+                temp_files.push(attachment.path.clone());
+            }
+            let hash = match &spooled {
+                Some(attachment) => attachment.hash.clone(),
+                None => hash_base64_content(data_url),
+            };
 
             debug!("🔍 計算data URL哈希值 | 哈希頭部: {}...", &hash[..8]);
 
             // 檢查緩存
-            if let Some((poe_url, _)) = crate::cache::get_cached_base64(&hash) {
+            if let Some((poe_url, _)) = crate::cache::lookup_cached_base64(&hash).await {
                 debug!("✅ base64緩存命中 | 哈希: {}... -> {}", &hash[..8], poe_url);
 
-                if let Some(OpenAiContent::Multi(items)) = &mut messages[*msg_idx].content {
-                    if let OpenAiContentItem::ImageUrl { image_url } = &mut items[*item_idx] {
-                        debug!("🔄 從緩存替換base64 | URL: {}", poe_url);
-                        image_url.url = poe_url;
-                    }
+                if let Some(OpenAiContent::Multi(items)) = &mut messages[*msg_idx].content
+                    && let OpenAiContentItem::ImageUrl { image_url } = &mut items[*item_idx]
+                {
+                    debug!("🔄 從緩存替換base64 | URL: {}", poe_url);
+                    image_url.url = poe_url;
                 }
             } else {
                 // 緩存未命中，需要上傳
                 debug!("❌ base64緩存未命中 | 哈希: {}...", &hash[..8]);
-                data_to_upload.push(data_url.clone());
+                data_to_upload.push(idx);
                 data_indices_to_upload.push((idx, (*msg_idx, *item_idx)));
                 data_hashes.push(hash);
+                data_spooled.push(spooled);
             }
         }
 
@@ -166,23 +201,36 @@ pub async fn process_message_images(
             let mut upload_requests = Vec::new();
 
             // 將data URL轉換為臨時文件
-            for data_url in data_to_upload.iter() {
+            for (data_url, spooled) in data_to_upload
+                .iter()
+                .map(|&idx| &data_urls[idx])
+                .zip(&data_spooled)
+            {
                 // 從 data URL 中提取 MIME 類型
                 let mime_type = if data_url.starts_with("data:") {
-                    let parts: Vec<&str> = data_url.split(";base64,").collect();
-                    if !parts.is_empty() {
-                        let mime_part = parts[0].trim_start_matches("data:");
-                        debug!("🔍 提取的 MIME 類型: {}", mime_part);
-                        Some(mime_part.to_string())
-                    } else {
-                        None
-                    }
+                    let header = data_url
+                        .split_once(";base64,")
+                        .or_else(|| data_url.split_once(crate::spool::SPOOL_MARKER))
+                        .map_or(data_url.as_str(), |(header, _)| header);
+                    let mime_part = header.trim_start_matches("data:");
+                    debug!("🔍 提取的 MIME 類型: {}", mime_part);
+                    Some(mime_part.to_string())
                 } else {
                     None
                 };
 
                 data_mime_types.push(mime_type.clone());
 
+                // 已暫存的附件直接上傳暫存檔案
+                if let Some(attachment) = spooled {
+                    debug!("📄 使用暫存附件: {}", attachment.path.display());
+                    upload_requests.push(FileUploadRequest::LocalFile {
+                        file: attachment.path.to_string_lossy().to_string(),
+                        mime_type,
+                    });
+                    continue;
+                }
+
                 match handle_data_url_to_temp_file(data_url) {
                     Ok(file_path) => {
                         debug!("📄 創建臨時文件成功: {}", file_path.display());
@@ -193,11 +241,26 @@ pub async fn process_message_images(
                         temp_files.push(file_path);
                     }
                     Err(e) => {
-                        error!("❌ 處理data URL失敗: {}", e);
+                        error!(
+                            "{}",
+                            tr!(
+                                "❌ 處理data URL失敗: {}",
+                                "❌ Failed to process data URL: {}",
+                                e
+                            )
+                        );
                         // 清理已創建的臨時文件
                         for path in &temp_files {
                             if let Err(e) = fs::remove_file(path) {
-                                warn!("⚠️ 無法刪除臨時文件 {}: {}", path.display(), e);
+                                warn!(
+                                    "{}",
+                                    tr!(
+                                        "⚠️ 無法刪除臨時文件 {}: {}",
+                                        "⚠️ Failed to delete temporary file {}: {}",
+                                        path.display(),
+                                        e
+                                    )
+                                );
                             }
                         }
                         return Err(Box::new(std::io::Error::new(
@@ -210,21 +273,22 @@ pub async fn process_message_images(
 
             // 上傳臨時文件
             if !upload_requests.is_empty() {
-                match poe_client.client.upload_files_batch(upload_requests).await {
+                match poe_client.upload_files_batch(upload_requests).await {
                     Ok(responses) => {
                         debug!("✅ 成功上傳 {} 個臨時文件", responses.len());
 
                         // 更新緩存並保存URL映射
                         for (idx, response) in responses.iter().enumerate() {
-                        let (_, (msg_idx, item_idx)) = data_indices_to_upload[idx];
-                        let hash = &data_hashes[idx];
-                        let data_url = &data_to_upload[idx];
-                        let original_mime = data_mime_types
-                            .get(idx)
-                            .and_then(|m| m.clone());
+                            let (_, (msg_idx, item_idx)) = data_indices_to_upload[idx];
+                            let hash = &data_hashes[idx];
+                            let data_url = &data_urls[data_to_upload[idx]];
+                            let original_mime = data_mime_types.get(idx).and_then(|m| m.clone());
 
-                        // 估算大小
-                        let size = crate::cache::estimate_base64_size(data_url);
+                            // 估算大小
+                            let size = match &data_spooled[idx] {
+                                Some(attachment) => attachment.size,
+                                None => crate::cache::estimate_base64_size(data_url),
+                            };
 
                             // 添加到緩存
                             crate::cache::cache_base64(hash, &response.attachment_url, size);
@@ -237,43 +301,62 @@ pub async fn process_message_images(
 
                             if let Some(OpenAiContent::Multi(items)) =
                                 &mut messages[msg_idx].content
-                            {
-                                if let OpenAiContentItem::ImageUrl { image_url } =
+                                && let OpenAiContentItem::ImageUrl { image_url } =
                                     &mut items[item_idx]
-                                {
-                                    debug!("🔄 替換data URL | Poe: {}", response.attachment_url);
-                                    image_url.url = response.attachment_url.clone();
-                                    image_url.mime_type = response
-                                        .mime_type
-                                        .clone()
-                                        .or_else(|| original_mime.clone());
-                                }
+                            {
+                                debug!("🔄 替換data URL | Poe: {}", response.attachment_url);
+                                image_url.url = response.attachment_url.clone();
+                                image_url.mime_type =
+                                    response.mime_type.clone().or_else(|| original_mime.clone());
                             }
                         }
                     }
                     Err(e) => {
-                        error!("❌ 上傳臨時文件失敗: {}", e);
+                        error!(
+                            "{}",
+                            tr!(
+                                "❌ 上傳臨時文件失敗: {}",
+                                "❌ Failed to upload temporary file: {}",
+                                e
+                            )
+                        );
                         // 清理臨時文件
                         for path in &temp_files {
                             if let Err(e) = fs::remove_file(path) {
-                                warn!("⚠️ 無法刪除臨時文件 {}: {}", path.display(), e);
+                                warn!(
+                                    "{}",
+                                    tr!(
+                                        "⚠️ 無法刪除臨時文件 {}: {}",
+                                        "⚠️ Failed to delete temporary file {}: {}",
+                                        path.display(),
+                                        e
+                                    )
+                                );
                             }
                         }
-                        return Err(Box::new(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("上傳臨時文件失敗: {}", e),
-                        )));
+                        return Err(Box::new(std::io::Error::other(format!(
+                            "上傳臨時文件失敗: {}",
+                            e
+                        ))));
                     }
                 }
             }
+        }
 
-            // 清理臨時文件
-            for path in &temp_files {
-                if let Err(e) = fs::remove_file(path) {
-                    warn!("⚠️ 無法刪除臨時文件 {}: {}", path.display(), e);
-                } else {
-                    debug!("🗑️ 已刪除臨時文件: {}", path.display());
-                }
+        // 清理臨時文件
+        for path in &temp_files {
+            if let Err(e) = fs::remove_file(path) {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 無法刪除臨時文件 {}: {}",
+                        "⚠️ Failed to delete temporary file {}: {}",
+                        path.display(),
+                        e
+                    )
+                );
+            } else {
+                debug!("🗑️ 已刪除臨時文件: {}", path.display());
             }
         }
     }
@@ -284,14 +367,12 @@ pub async fn process_message_images(
         let last_bot_idx = messages
             .iter()
             .enumerate()
-            .filter(|(_, msg)| msg.role == "assistant")
-            .last()
+            .rfind(|(_, msg)| msg.role == "assistant")
             .map(|(i, _)| i);
         let last_user_idx = messages
             .iter()
             .enumerate()
-            .filter(|(_, msg)| msg.role == "user")
-            .last()
+            .rfind(|(_, msg)| msg.role == "user")
             .map(|(i, _)| i);
 
         if let (Some(bot_idx), Some(user_idx)) = (last_bot_idx, last_user_idx) {
@@ -381,9 +462,26 @@ pub fn get_text_from_openai_content(content: &Option<OpenAiContent>) -> String {
     }
 }
 
+/// Poe CDN 連結前綴列表，可透過 POE_CDN_URL_PREFIXES 以逗號分隔覆蓋
+static POE_CDN_URL_PREFIXES: LazyLock<Vec<String>> = LazyLock::new(|| {
+    let prefixes: Vec<String> = std::env::var("POE_CDN_URL_PREFIXES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if prefixes.is_empty() {
+        vec!["https://pfst.cf2.poecdn.net".to_string()]
+    } else {
+        prefixes
+    }
+});
+
 // 檢查URL是否為Poe CDN連結
 pub fn is_poe_cdn_url(url: &str) -> bool {
-    url.starts_with("https://pfst.cf2.poecdn.net")
+    POE_CDN_URL_PREFIXES
+        .iter()
+        .any(|prefix| url.starts_with(prefix.as_str()))
 }
 
 // 從消息中提取Poe CDN連結
@@ -450,38 +548,69 @@ pub fn handle_data_url_to_temp_file(data_url: &str) -> Result<PathBuf, String> {
     // 4. 根據 MIME 類型決定檔案擴充名
     let file_ext = mime_type_to_extension(mime_type).unwrap_or("bin");
     debug!("📄 使用檔案擴充名: {}", file_ext);
-    // 5. 解碼 base64 資料 (僅使用 BASE64_STANDARD)
     let base64_data = parts[1];
     debug!("🔢 Base64 資料長度: {}", base64_data.len());
-    let decoded = match BASE64_STANDARD.decode(base64_data) {
-        Ok(data) => {
-            debug!("✅ Base64 解碼成功 | 資料大小: {} 位元組", data.len());
-            data
-        }
-        Err(e) => {
-            error!("❌ Base64 解碼失敗: {}", e);
-            return Err(format!("Base64 解碼失敗: {}", e));
-        }
-    };
-    // 6. 建立臨時檔案
+    // 5. 建立臨時檔案
     let temp_dir = std::env::temp_dir();
     let file_name = format!("poe2openai_{}.{}", nanoid!(16), file_ext);
     let file_path = temp_dir.join(&file_name);
-    // 7. 寫入資料到臨時檔案
-    match fs::write(&file_path, &decoded) {
-        Ok(_) => {
-            debug!("✅ 成功寫入臨時檔案: {}", file_path.display());
+    let file = match fs::File::create(&file_path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 建立臨時檔案失敗: {}",
+                    "❌ Failed to create temporary file: {}",
+                    e
+                )
+            );
+            return Err(format!("寫入臨時檔案失敗: {}", e));
+        }
+    };
+    // 6. 分塊解碼 base64 並寫入臨時檔案，不在記憶體中保留完整解碼結果 (僅使用 BASE64_STANDARD)
+    let mut decoder = base64::read::DecoderReader::new(base64_data.as_bytes(), &BASE64_STANDARD);
+    let mut writer = std::io::BufWriter::new(file);
+    let result = std::io::copy(&mut decoder, &mut writer).and_then(|size| {
+        writer.flush()?;
+        Ok(size)
+    });
+    match result {
+        Ok(size) => {
+            debug!(
+                "✅ 成功寫入臨時檔案: {} | 資料大小: {} 位元組",
+                file_path.display(),
+                size
+            );
             Ok(file_path)
         }
         Err(e) => {
-            error!("❌ 寫入臨時檔案失敗: {}", e);
-            Err(format!("寫入臨時檔案失敗: {}", e))
+            error!(
+                "{}",
+                tr!(
+                    "❌ Base64 解碼或寫入臨時檔案失敗: {}",
+                    "❌ Failed to decode base64 or write temporary file: {}",
+                    e
+                )
+            );
+            if let Err(e) = fs::remove_file(&file_path) {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ 無法刪除臨時文件 {}: {}",
+                        "⚠️ Failed to delete temporary file {}: {}",
+                        file_path.display(),
+                        e
+                    )
+                );
+            }
+            Err(format!("Base64 解碼或寫入臨時檔案失敗: {}", e))
         }
     }
 }
 
 // 從MIME類型獲取文件擴展名
-fn mime_type_to_extension(mime_type: &str) -> Option<&str> {
+pub fn mime_type_to_extension(mime_type: &str) -> Option<&str> {
     match mime_type {
         "image/jpeg" | "image/jpg" => Some("jpeg"),
         "image/png" => Some("png"),
@@ -508,6 +637,31 @@ fn mime_type_to_extension(mime_type: &str) -> Option<&str> {
     }
 }
 
+/// Poe 回報帳戶點數不足時的錯誤訊息
+const INSUFFICIENT_POINTS_MESSAGES: [&str; 2] = [
+    "This bot needs more points to answer your request.",
+    "You do not have enough points to message this bot.",
+];
+
+/// 返回給客戶端的點數不足說明，提示不要自動重試
+const INSUFFICIENT_QUOTA_MESSAGE: &str = "The upstream Poe account has run out of compute points. \
+Retrying will not help until the operator adds points; please contact the service administrator.";
+
+/// 管理員中止生成時注入的錯誤事件內容
+pub const REQUEST_CANCELLED_MESSAGE: &str =
+    "The request was cancelled by the service administrator.";
+
+/// 串流中 ReplaceResponse 改寫了已發送的內容時的錯誤內容
+pub const CONTENT_REWRITTEN_MESSAGE: &str = "The bot rewrote content that was already streamed. \
+The stream was ended because sent content cannot be corrected; please retry the request.";
+
+/// 上游錯誤是否為 Poe 帳戶點數不足
+pub fn is_insufficient_points(error_text: &str) -> bool {
+    INSUFFICIENT_POINTS_MESSAGES
+        .iter()
+        .any(|message| error_text.contains(message))
+}
+
 pub fn convert_poe_error_to_openai(
     error_text: &str,
     allow_retry: bool,
@@ -516,7 +670,29 @@ pub fn convert_poe_error_to_openai(
         "🔄 轉換錯誤響應 | 錯誤文本: {}, 允許重試: {}",
         error_text, allow_retry
     );
-    let (status, error_type, code) = if error_text.contains("Internal server error") {
+    if is_insufficient_points(error_text) {
+        debug!("📋 錯誤轉換結果 | 狀態碼: 429 | 錯誤類型: insufficient_quota");
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            OpenAIErrorResponse {
+                error: OpenAIError {
+                    message: INSUFFICIENT_QUOTA_MESSAGE.to_string(),
+                    r#type: "insufficient_quota".to_string(),
+                    code: "insufficient_quota".to_string(),
+                    param: None,
+                },
+            },
+        );
+    }
+    let (status, error_type, code) = if error_text == REQUEST_CANCELLED_MESSAGE {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "server_error",
+            "request_cancelled",
+        )
+    } else if error_text == CONTENT_REWRITTEN_MESSAGE {
+        (StatusCode::BAD_GATEWAY, "server_error", "content_rewritten")
+    } else if error_text.contains("Internal server error") {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             "internal_error",
@@ -571,6 +747,32 @@ pub fn format_duration(duration: std::time::Duration) -> String {
     }
 }
 
+/// 讀取環境變數；未設定時改讀 `{name}_FILE` 指向的檔案（Docker/Kubernetes secrets 慣例）
+pub fn get_env_secret(name: &str) -> Option<String> {
+    if let Ok(value) = std::env::var(name) {
+        return Some(value);
+    }
+    let path = std::env::var(format!("{}_FILE", name))
+        .ok()
+        .filter(|path| !path.trim().is_empty())?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => Some(contents.trim_end_matches(['\r', '\n']).to_string()),
+        Err(e) => {
+            error!(
+                "{}",
+                tr!(
+                    "❌ 無法讀取 {}_FILE 指定的檔案 {}: {}",
+                    "❌ Failed to read file referenced by {}_FILE {}: {}",
+                    name,
+                    path,
+                    e
+                )
+            );
+            None
+        }
+    }
+}
+
 pub fn get_config_path(filename: &str) -> PathBuf {
     let config_dir = std::env::var("CONFIG_DIR").unwrap_or_else(|_| "./".to_string());
     let mut path = PathBuf::from(config_dir);
@@ -585,16 +787,35 @@ pub fn load_config_from_yaml() -> Result<Config, String> {
         match std::fs::read_to_string(path) {
             Ok(contents) => match serde_yaml::from_str::<Config>(&contents) {
                 Ok(config) => {
-                    info!("✅ 成功讀取並解析 {}", path_str);
+                    info!(
+                        "{}",
+                        tr!("✅ 成功讀取並解析 {}", "✅ Loaded and parsed {}", path_str)
+                    );
                     Ok(config)
                 }
                 Err(e) => {
-                    error!("❌ 解析 {} 失敗: {}", path_str, e);
+                    error!(
+                        "{}",
+                        tr!(
+                            "❌ 解析 {} 失敗: {}",
+                            "❌ Failed to parse {}: {}",
+                            path_str,
+                            e
+                        )
+                    );
                     Err(format!("解析 {} 失敗: {}", path_str, e))
                 }
             },
             Err(e) => {
-                error!("❌ 讀取 {} 失敗: {}", path_str, e);
+                error!(
+                    "{}",
+                    tr!(
+                        "❌ 讀取 {} 失敗: {}",
+                        "❌ Failed to read {}: {}",
+                        path_str,
+                        e
+                    )
+                );
                 Err(format!("讀取 {} 失敗: {}", path_str, e))
             }
         }
@@ -607,23 +828,46 @@ pub fn load_config_from_yaml() -> Result<Config, String> {
             custom_models: None,
             api_token: None,
             use_v1_api: None,
+            stream_compat: None,
+            key_stream_compat: None,
+            key_priority: None,
+            history_policy: None,
+            history_summary_model: None,
+            mcp_servers: None,
+            scopes: None,
+            coalesce: None,
+            key_coalesce: None,
+            param_policy: None,
+            strip_footnotes: None,
         })
     }
 }
 
 /// 計算文本的 token 數量
 pub fn count_tokens(text: &str) -> u32 {
-    let bpe = match o200k_base() {
-        Ok(bpe) => bpe,
-        Err(e) => {
-            error!("❌ 無法初始化 BPE 編碼器: {}", e);
-            return 0;
-        }
-    };
-    let tokens = bpe.encode_with_special_tokens(text);
+    let tokens = o200k_base_singleton().encode_with_special_tokens(text);
     tokens.len() as u32
 }
 
+/// 依模型名稱選擇編碼器，無法識別時使用與用量計算相同的 o200k_base
+/// 返回 (編碼器名稱, 編碼器)
+pub fn get_tokenizer_for_model(model: Option<&str>) -> (&'static str, &'static CoreBPE) {
+    let tokenizer = model.and_then(|m| get_tokenizer(&m.to_lowercase()));
+    match tokenizer {
+        Some(Tokenizer::Cl100kBase) => ("cl100k_base", tiktoken_rs::cl100k_base_singleton()),
+        Some(Tokenizer::P50kBase) => ("p50k_base", tiktoken_rs::p50k_base_singleton()),
+        Some(Tokenizer::P50kEdit) => ("p50k_edit", tiktoken_rs::p50k_edit_singleton()),
+        Some(Tokenizer::R50kBase) | Some(Tokenizer::Gpt2) => {
+            ("r50k_base", tiktoken_rs::r50k_base_singleton())
+        }
+        Some(Tokenizer::O200kHarmony) => ("o200k_harmony", tiktoken_rs::o200k_harmony_singleton()),
+        Some(Tokenizer::O200kBase) | None => ("o200k_base", o200k_base_singleton()),
+    }
+}
+
+/// 每張輸入圖片的估算 token 數（與 OpenAI low detail 圖片相同）
+const IMAGE_PROMPT_TOKENS: u32 = 85;
+
 /// 計算消息列表的 token 數量
 pub fn count_message_tokens(messages: &[Message]) -> u32 {
     let mut total_tokens = 0;
@@ -633,6 +877,18 @@ pub fn count_message_tokens(messages: &[Message]) -> u32 {
         // 計算內容的 token 數
         let content_text = get_text_from_openai_content(&message.content);
         total_tokens += count_tokens(&content_text);
+        // 圖片無法在本地精確計算，以固定值估算
+        if let Some(OpenAiContent::Multi(items)) = &message.content {
+            let images = items
+                .iter()
+                .filter(|item| matches!(item, OpenAiContentItem::ImageUrl { .. }))
+                .count() as u32;
+            total_tokens += images * IMAGE_PROMPT_TOKENS;
+        }
+        // 助手先前的工具調用
+        if let Some(tool_calls) = &message.tool_calls {
+            total_tokens += count_tool_call_tokens(tool_calls);
+        }
     }
     // 添加消息格式的額外 token
     total_tokens += 2; // 消息格式的開始和結束標記
@@ -644,6 +900,14 @@ pub fn count_completion_tokens(completion: &str) -> u32 {
     count_tokens(completion)
 }
 
+/// 計算工具調用（名稱與參數）的 token 數量
+pub fn count_tool_call_tokens(tool_calls: &[poe_api_process::types::ChatToolCall]) -> u32 {
+    tool_calls
+        .iter()
+        .map(|call| count_tokens(&call.function.name) + count_tokens(&call.function.arguments))
+        .sum()
+}
+
 /// 計算 base64 字符串的 SHA256 哈希
 pub fn hash_base64_content(base64_str: &str) -> String {
     // 提取純base64部分，去除MIME類型前綴
@@ -685,7 +949,7 @@ pub fn hash_base64_content(base64_str: &str) -> String {
 /// 根據 URL 推斷 MIME 類型
 pub fn infer_mime_from_url(url: &str) -> Option<String> {
     let without_query = url.split('?').next().unwrap_or(url).to_lowercase();
-    let ext = without_query.split('.').last().unwrap_or("");
+    let ext = without_query.split('.').next_back().unwrap_or("");
     let mime = match ext {
         "jpg" | "jpeg" => Some("image/jpeg"),
         "png" => Some("image/png"),
@@ -713,7 +977,7 @@ pub fn infer_mime_from_url(url: &str) -> Option<String> {
 /// 根據 URL 或 MIME 類型生成檔名
 pub fn filename_from_url(url: &str, mime_type: Option<&str>) -> Option<String> {
     let without_query = url.split('?').next().unwrap_or(url);
-    let last = without_query.split('/').last().unwrap_or("").trim();
+    let last = without_query.split('/').next_back().unwrap_or("").trim();
     if !last.is_empty() && !last.ends_with("image") && !last.ends_with("base") {
         return Some(last.to_string());
     }
@@ -739,9 +1003,11 @@ pub fn filename_from_url(url: &str, mime_type: Option<&str>) -> Option<String> {
 }
 
 /// 處理消息內容，根據請求參數添加相應的後綴
+/// strip_sampling 為 true 時（推理模型）略過自訂參數中的取樣參數
 pub fn process_message_content_with_suffixes(
     content: &str,
     chat_request: &crate::types::ChatCompletionRequest,
+    strip_sampling: bool,
 ) -> String {
     let mut processed_content = content.to_string();
 
@@ -799,16 +1065,116 @@ pub fn process_message_content_with_suffixes(
             debug!("🎯 添加 reasoning_effort 後綴: {}", suffix);
             processed_content.push_str(&suffix);
         } else {
-            warn!("⚠️ 無效的 reasoning_effort 值: {}", effort);
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無效的 reasoning_effort 值: {}",
+                    "⚠️ Invalid reasoning_effort value: {}",
+                    effort
+                )
+            );
+        }
+    }
+
+    // 處理透過 extra_body / poe 傳入的自訂機器人參數
+    for (key, value) in collect_poe_parameters(chat_request) {
+        if strip_sampling && SAMPLING_PARAMETERS.contains(&key.as_str()) {
+            debug!("🧠 推理模型不支援取樣參數，已移除: {}", key);
+            continue;
+        }
+        match format_poe_parameter(key, value) {
+            Some(suffix) if suffix.is_empty() => {}
+            Some(suffix) => {
+                debug!("🧩 添加自訂參數後綴: {}", suffix);
+                processed_content.push_str(&suffix);
+            }
+            None => warn!(
+                "{}",
+                tr!(
+                    "⚠️ 無效的自訂參數，已忽略: {}",
+                    "⚠️ Ignoring invalid custom parameter: {}",
+                    key
+                )
+            ),
         }
     }
 
     processed_content
 }
 
+/// 推理模型不接受的取樣參數
+const SAMPLING_PARAMETERS: [&str; 5] = [
+    "temperature",
+    "top_p",
+    "frequency_penalty",
+    "presence_penalty",
+    "logit_bias",
+];
+
+/// 收集 extra_body（不含 google）、extra_body.poe 及頂層 poe 中的自訂參數
+/// 同名參數以後出現者為準
+fn collect_poe_parameters(
+    chat_request: &crate::types::ChatCompletionRequest,
+) -> Vec<(&String, &serde_json::Value)> {
+    let mut params: Vec<(&String, &serde_json::Value)> = Vec::new();
+    let sources = [
+        chat_request.extra_body.as_ref().map(|e| &e.other),
+        chat_request
+            .extra_body
+            .as_ref()
+            .and_then(|e| e.poe.as_ref()),
+        chat_request.poe.as_ref(),
+    ];
+    for map in sources.into_iter().flatten() {
+        for (key, value) in map {
+            params.retain(|(k, _)| *k != key);
+            params.push((key, value));
+        }
+    }
+    params
+}
+
+/// 將單個自訂參數轉換為 ` --key value` 形式的後綴
+/// 布林值 true 僅添加 `--key`，false 與 null 不添加；值含換行等控制字元時視為無效
+fn format_poe_parameter(key: &str, value: &serde_json::Value) -> Option<String> {
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return None;
+    }
+    let suffix = match value {
+        serde_json::Value::Null | serde_json::Value::Bool(false) => String::new(),
+        serde_json::Value::Bool(true) => format!(" --{}", key),
+        serde_json::Value::String(s) => format!(" --{} {}", key, quote_poe_value(s)?),
+        other => format!(" --{} {}", key, quote_poe_value(&other.to_string())?),
+    };
+    Some(suffix)
+}
+
+/// 參數值含空白、引號、反斜線或以 - 開頭時以雙引號包住並轉義，避免值被解析成其他參數
+fn quote_poe_value(value: &str) -> Option<String> {
+    if value.chars().any(char::is_control) {
+        return None;
+    }
+    if !value.is_empty()
+        && !value.starts_with('-')
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\\')
+    {
+        return Some(value.to_string());
+    }
+    Some(format!(
+        "\"{}\"",
+        value.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
+
 /// 過濾掉只有 name 字段的 tools，這些 tools 不應該傳遞給 poe_api_process
 pub fn filter_tools_for_poe(
-    tools: &Option<Vec<poe_api_process::types::ChatTool>>,
+    tools: &Option<Vec<crate::types::RequestTool>>,
 ) -> Option<Vec<poe_api_process::types::ChatTool>> {
     if let Some(tools_vec) = tools {
         let filtered_tools: Vec<_> = tools_vec
@@ -821,7 +1187,7 @@ pub fn filter_tools_for_poe(
                     .map(|desc| !desc.is_empty())
                     .unwrap_or(false)
             })
-            .cloned()
+            .map(|tool| tool.to_poe_tool())
             .collect();
 
         if filtered_tools.is_empty() {
@@ -843,20 +1209,82 @@ pub fn filter_tools_for_poe(
 /// 從工具消息中提取 tool_call_id
 pub fn extract_tool_call_id(content: &str) -> Option<String> {
     // 嘗試解析 JSON 格式的內容
-    if let Ok(json) = serde_json::from_str::<serde_json::Value>(content) {
-        if let Some(tool_call_id) = json.get("tool_call_id").and_then(|v| v.as_str()) {
-            return Some(tool_call_id.to_string());
-        }
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(content)
+        && let Some(tool_call_id) = json.get("tool_call_id").and_then(|v| v.as_str())
+    {
+        return Some(tool_call_id.to_string());
     }
     // 嘗試使用簡單的文本解析
-    if let Some(start) = content.find("tool_call_id") {
-        if let Some(id_start) = content[start..].find('"') {
-            if let Some(id_end) = content[start + id_start + 1..].find('"') {
-                return Some(
-                    content[start + id_start + 1..start + id_start + 1 + id_end].to_string(),
-                );
-            }
-        }
+    if let Some(start) = content.find("tool_call_id")
+        && let Some(id_start) = content[start..].find('"')
+        && let Some(id_end) = content[start + id_start + 1..].find('"')
+    {
+        return Some(content[start + id_start + 1..start + id_start + 1 + id_end].to_string());
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn poe_parameter_plain_values() {
+        assert_eq!(
+            format_poe_parameter("aspect", &json!("16:9")).as_deref(),
+            Some(" --aspect 16:9")
+        );
+        assert_eq!(
+            format_poe_parameter("steps", &json!(30)).as_deref(),
+            Some(" --steps 30")
+        );
+        assert_eq!(
+            format_poe_parameter("hd", &json!(true)).as_deref(),
+            Some(" --hd")
+        );
+        assert_eq!(
+            format_poe_parameter("hd", &json!(false)).as_deref(),
+            Some("")
+        );
+        assert_eq!(format_poe_parameter("bad key", &json!("x")), None);
+    }
+
+    #[test]
+    fn poe_parameter_values_with_spaces_are_quoted() {
+        assert_eq!(
+            format_poe_parameter("style", &json!("oil painting")).as_deref(),
+            Some(r#" --style "oil painting""#)
+        );
+        assert_eq!(
+            format_poe_parameter("style", &json!(r#"say "hi" \ bye"#)).as_deref(),
+            Some(r#" --style "say \"hi\" \\ bye""#)
+        );
+        assert_eq!(
+            format_poe_parameter("style", &json!("")).as_deref(),
+            Some(r#" --style """#)
+        );
+        assert_eq!(
+            format_poe_parameter("tags", &json!(["a b", "c"])).as_deref(),
+            Some(r#" --tags "[\"a b\",\"c\"]""#)
+        );
+    }
+
+    #[test]
+    fn poe_parameter_injection_is_neutralized() {
+        // 值中的 -- 不能成為額外的參數
+        assert_eq!(
+            format_poe_parameter("style", &json!("x --aspect 1:1")).as_deref(),
+            Some(r#" --style "x --aspect 1:1""#)
+        );
+        assert_eq!(
+            format_poe_parameter("style", &json!("--aspect")).as_deref(),
+            Some(r#" --style "--aspect""#)
+        );
+        assert_eq!(
+            format_poe_parameter("style", &json!("a\nignore previous")),
+            None
+        );
+        assert_eq!(format_poe_parameter("style", &json!("a\r\tb")), None);
+    }
+}
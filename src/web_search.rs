@@ -0,0 +1,201 @@
+//! 內建的網頁搜尋工具 (models.yaml 的 builtin_tools: [web_search])
+//!
+//! 供沒有內建瀏覽能力的機器人使用，由代理以 WEB_SEARCH_BACKEND 指定的搜尋服務執行：
+//! searxng（WEB_SEARCH_URL 為 SearxNG 實例位址，需啟用 JSON 格式）或 brave（BRAVE_SEARCH_API_KEY）
+
+use crate::poe_client::shared_http_client;
+use crate::utils::get_env_secret;
+use poe_api_process::types::{ChatTool, FunctionDefinition, FunctionParameters};
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use tracing::{debug, info, warn};
+
+pub(crate) const WEB_SEARCH_TOOL: &str = "web_search";
+
+const BRAVE_SEARCH_URL: &str = "https://api.search.brave.com/res/v1/web/search";
+
+enum SearchBackend {
+    Searxng { url: String },
+    Brave { url: String, api_key: String },
+}
+
+struct WebSearchConfig {
+    backend: SearchBackend,
+    max_results: usize,
+}
+
+static WEB_SEARCH_CONFIG: LazyLock<Option<WebSearchConfig>> = LazyLock::new(|| {
+    let backend_name = std::env::var("WEB_SEARCH_BACKEND")
+        .map(|v| v.trim().to_lowercase())
+        .unwrap_or_default();
+    let url = std::env::var("WEB_SEARCH_URL")
+        .ok()
+        .map(|v| v.trim().trim_end_matches('/').to_string())
+        .filter(|v| !v.is_empty());
+    let backend = match backend_name.as_str() {
+        "" => return None,
+        "searxng" => match url {
+            Some(url) => SearchBackend::Searxng { url },
+            None => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ WEB_SEARCH_BACKEND 為 searxng 但未設定 WEB_SEARCH_URL，網頁搜尋不可用",
+                        "⚠️ WEB_SEARCH_BACKEND is searxng but WEB_SEARCH_URL is not set, web search is unavailable"
+                    )
+                );
+                return None;
+            }
+        },
+        "brave" => match get_env_secret("BRAVE_SEARCH_API_KEY").filter(|k| !k.trim().is_empty()) {
+            Some(api_key) => SearchBackend::Brave {
+                url: url.unwrap_or_else(|| BRAVE_SEARCH_URL.to_string()),
+                api_key: api_key.trim().to_string(),
+            },
+            None => {
+                warn!(
+                    "{}",
+                    tr!(
+                        "⚠️ WEB_SEARCH_BACKEND 為 brave 但未設定 BRAVE_SEARCH_API_KEY，網頁搜尋不可用",
+                        "⚠️ WEB_SEARCH_BACKEND is brave but BRAVE_SEARCH_API_KEY is not set, web search is unavailable"
+                    )
+                );
+                return None;
+            }
+        },
+        other => {
+            warn!(
+                "{}",
+                tr!(
+                    "⚠️ 不支援的 WEB_SEARCH_BACKEND: {}，可選 searxng 或 brave",
+                    "⚠️ Unsupported WEB_SEARCH_BACKEND: {}, expected searxng or brave",
+                    other
+                )
+            );
+            return None;
+        }
+    };
+    let max_results = std::env::var("WEB_SEARCH_MAX_RESULTS")
+        .ok()
+        .and_then(|s| s.trim().parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(5);
+    info!(
+        "{}",
+        tr!(
+            "🔎 網頁搜尋工具: {} | 結果數: {}",
+            "🔎 Web search tool: {} | results: {}",
+            backend_name,
+            max_results
+        )
+    );
+    Some(WebSearchConfig {
+        backend,
+        max_results,
+    })
+});
+
+/// 是否已設定搜尋服務
+pub(crate) fn web_search_available() -> bool {
+    WEB_SEARCH_CONFIG.is_some()
+}
+
+/// 提供給機器人的工具定義
+pub(crate) fn web_search_tool() -> ChatTool {
+    ChatTool {
+        r#type: "function".to_string(),
+        function: FunctionDefinition {
+            name: WEB_SEARCH_TOOL.to_string(),
+            description: Some(
+                "Search the web for up-to-date information. Returns the top results with title, URL and snippet."
+                    .to_string(),
+            ),
+            parameters: Some(FunctionParameters {
+                r#type: "object".to_string(),
+                properties: json!({
+                    "query": {"type": "string", "description": "The search query"}
+                }),
+                required: vec!["query".to_string()],
+            }),
+        },
+    }
+}
+
+/// 執行搜尋並以文字列出結果
+pub(crate) async fn web_search(arguments: &Value) -> Result<String, String> {
+    let config = WEB_SEARCH_CONFIG
+        .as_ref()
+        .ok_or("web search is not configured")?;
+    let query = arguments["query"]
+        .as_str()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .ok_or("missing query")?;
+    debug!("🔎 網頁搜尋: {}", query);
+
+    // (標題, 網址, 摘要)
+    let results: Vec<(String, String, String)> = match &config.backend {
+        SearchBackend::Searxng { url } => {
+            let body = get_json(
+                shared_http_client()
+                    .get(format!("{}/search", url))
+                    .query(&[("q", query), ("format", "json")]),
+            )
+            .await?;
+            body["results"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|item| result_fields(item, "content"))
+                .collect()
+        }
+        SearchBackend::Brave { url, api_key } => {
+            let body = get_json(
+                shared_http_client()
+                    .get(url)
+                    .header("X-Subscription-Token", api_key)
+                    .query(&[
+                        ("q", query.to_string()),
+                        ("count", config.max_results.to_string()),
+                    ]),
+            )
+            .await?;
+            body["web"]["results"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|item| result_fields(item, "description"))
+                .collect()
+        }
+    };
+    if results.is_empty() {
+        return Ok(format!("No results found for \"{}\".", query));
+    }
+    Ok(results
+        .into_iter()
+        .take(config.max_results)
+        .enumerate()
+        .map(|(index, (title, url, snippet))| {
+            format!("{}. {}\n{}\n{}", index + 1, title, url, snippet)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n"))
+}
+
+fn result_fields(item: &Value, snippet_field: &str) -> (String, String, String) {
+    let field = |name: &str| item[name].as_str().unwrap_or_default().trim().to_string();
+    (field("title"), field("url"), field(snippet_field))
+}
+
+async fn get_json(request: reqwest::RequestBuilder) -> Result<Value, String> {
+    let response = request
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("search backend returned HTTP {}", status));
+    }
+    response.json().await.map_err(|e| e.to_string())
+}